@@ -1,7 +1,32 @@
+use noria::{DataType, TableOperation};
 use std::future::Future;
 use tower_util::ServiceExt;
 use trawler::{StoryId, UserId, Vote};
 
+/// What to do about a vote, given the user's existing vote on the same comment.
+#[derive(Debug, PartialEq)]
+enum VoteAction {
+    /// The user has not voted on the comment yet.
+    Insert,
+    /// The user has already cast the same vote.
+    Skip,
+    /// The user is flipping their vote, so their old vote (with the given id) must go.
+    Replace(DataType),
+}
+
+/// Decide what to do about a vote with the given polarity, given the `(id, vote)` pairs of the
+/// user's existing votes on the comment.
+fn vote_action<I>(existing: I, polarity: i32) -> VoteAction
+where
+    I: IntoIterator<Item = (DataType, DataType)>,
+{
+    match existing.into_iter().next() {
+        None => VoteAction::Insert,
+        Some((_, vote)) if vote == DataType::from(polarity) => VoteAction::Skip,
+        Some((id, _)) => VoteAction::Replace(id),
+    }
+}
+
 pub(crate) async fn handle<F>(
     c: F,
     acting_as: Option<UserId>,
@@ -23,9 +48,13 @@ where
         .await?
         .unwrap();
 
-    let sid = comment.take("story_id").unwrap();
-    let comment = comment.take("id").unwrap();
-    let _ = c
+    let sid = comment
+        .try_take("story_id")
+        .ok_or_else(|| failure::format_err!("comment_vote_1 has no story_id column"))?;
+    let comment = comment
+        .try_take("id")
+        .ok_or_else(|| failure::format_err!("comment_vote_1 has no id column"))?;
+    let existing = c
         .view("comment_vote_2")
         .await?
         .ready_oneshot()
@@ -33,7 +62,20 @@ where
         .lookup(&[user.into(), sid.clone(), comment.clone()], true)
         .await?;
 
-    // TODO: do something else if user has already voted
+    let polarity = match v {
+        Vote::Up => 1,
+        Vote::Down => 0,
+    };
+    let action = vote_action(
+        existing
+            .iter()
+            .map(|vote| (vote["id"].clone(), vote["vote"].clone())),
+        polarity,
+    );
+    if action == VoteAction::Skip {
+        return Ok((c, false));
+    }
+
     // TODO: technically need to re-load comment under transaction
 
     // NOTE: MySQL technically does everything inside this and_then in a transaction,
@@ -44,12 +86,32 @@ where
         "user_id" => user,
         "story_id" => sid,
         "comment_id" => comment,
-        "vote" => match v {
-            Vote::Up => 1,
-            Vote::Down => 0,
-        },
+        "vote" => polarity,
     );
-    votes.insert(vote).await?;
+    if let VoteAction::Replace(old) = action {
+        votes
+            .perform_all(vec![
+                TableOperation::Delete { key: vec![old] },
+                TableOperation::Insert(vote),
+            ])
+            .await?;
+    } else {
+        votes.insert(vote).await?;
+    }
 
     Ok((c, false))
 }
+
+#[test]
+fn vote_dedup() {
+    // first vote
+    assert_eq!(vote_action(vec![], 1), VoteAction::Insert);
+    // repeated same vote
+    assert_eq!(vote_action(vec![(7.into(), 1.into())], 1), VoteAction::Skip);
+    assert_eq!(vote_action(vec![(7.into(), 0.into())], 0), VoteAction::Skip);
+    // flipped vote
+    assert_eq!(
+        vote_action(vec![(7.into(), 1.into())], 0),
+        VoteAction::Replace(7.into())
+    );
+}
@@ -18,29 +18,58 @@ use trawler::{LobstersRequest, TrawlerRequest};
 const SCHEMA: &'static str = include_str!("schema.sql");
 const QUERIES: &'static str = include_str!("queries.sql");
 
-struct NoriaTrawlerBuilder(Option<ZookeeperAuthority>);
+/// Connects to Noria through the given authority, with a pool of at most the given number of
+/// connections.
+struct NoriaTrawlerBuilder(Option<ZookeeperAuthority>, usize);
 
 type Conn = Arc<NoriaConnection>;
 
 struct NoriaTrawler {
-    noria: Arc<NoriaConnection>,
+    ch: ControllerHandle<ZookeeperAuthority>,
+    /// The first connection made, whose view and table handles all other connections share.
+    first: Conn,
+    pool: Arc<ConnPool<Conn>>,
 }
 
 struct NoriaConnection {
     ch: Mutex<ControllerHandle<ZookeeperAuthority>>,
-    views: ConcurrentHashMap<Cow<'static, str>, noria::View>,
-    tables: ConcurrentHashMap<Cow<'static, str>, noria::Table>,
+    views: Arc<ConcurrentHashMap<Cow<'static, str>, noria::View>>,
+    tables: Arc<ConcurrentHashMap<Cow<'static, str>, noria::Table>>,
 }
 
 impl NoriaConnection {
+    fn new(ch: ControllerHandle<ZookeeperAuthority>) -> Self {
+        NoriaConnection {
+            ch: Mutex::new(ch),
+            views: Arc::new(ConcurrentHashMap::new()),
+            tables: Arc::new(ConcurrentHashMap::new()),
+        }
+    }
+
+    /// Make another connection through `ch` that shares this one's view and table handles, so
+    /// that they needn't be fetched again.
+    fn sharing(&self, ch: ControllerHandle<ZookeeperAuthority>) -> Self {
+        NoriaConnection {
+            ch: Mutex::new(ch),
+            views: Arc::clone(&self.views),
+            tables: Arc::clone(&self.tables),
+        }
+    }
+
     async fn view(&self, view: &'static str) -> Result<noria::View, failure::Error> {
         if let Some(v) = self.views.pin().get(view) {
             return Ok(v.clone());
         }
 
         // not there -- we'll need to lock our connection to the controller
-        let mut ctrl = self.ch.lock().await;
-        let handle = ctrl.view(view).await?;
+        let handle = self.ch.lock().await.view(view).await;
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(e) if !self.list_views().await?.iter().any(|v| v == view) => {
+                return Err(e.context(format!("no view named {}", view)).into());
+            }
+            Err(e) => return Err(e),
+        };
         let _ = self.views.pin().insert(Cow::Borrowed(view), handle.clone());
         Ok(handle)
     }
@@ -51,17 +80,35 @@ impl NoriaConnection {
         }
 
         // not there -- we'll need to lock our connection to the controller
-        let mut ctrl = self.ch.lock().await;
-        let handle = ctrl.table(table).await?;
+        let handle = self.ch.lock().await.table(table).await;
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(e) if !self.list_tables().await?.iter().any(|t| t == table) => {
+                return Err(e.context(format!("no table named {}", table)).into());
+            }
+            Err(e) => return Err(e),
+        };
         let _ = self
             .tables
             .pin()
             .insert(Cow::Borrowed(table), handle.clone());
         Ok(handle)
     }
+
+    /// The names of all the views currently installed.
+    async fn list_views(&self) -> Result<Vec<String>, failure::Error> {
+        self.ch.lock().await.list_views().await
+    }
+
+    /// The names of all the tables currently installed.
+    async fn list_tables(&self) -> Result<Vec<String>, failure::Error> {
+        self.ch.lock().await.list_tables().await
+    }
 }
 
 mod endpoints;
+mod pool;
+use pool::ConnPool;
 
 impl Service<bool> for NoriaTrawlerBuilder {
     type Response = NoriaTrawler;
@@ -72,6 +119,7 @@ impl Service<bool> for NoriaTrawlerBuilder {
     }
     fn call(&mut self, priming: bool) -> Self::Future {
         let zk = self.0.take().unwrap();
+        let max_conns = self.1;
         Box::pin(async move {
             let mut c = ControllerHandle::new(zk).await?;
 
@@ -83,18 +131,23 @@ impl Service<bool> for NoriaTrawlerBuilder {
                 c.extend_recipe(QUERIES).await?;
             }
 
-            let tables = ConcurrentHashMap::new();
+            let first = Arc::new(NoriaConnection::new(c.clone()));
             for (table, _) in c.inputs().await? {
                 let handle = c.table(&table).await?;
-                tables.pin().insert(Cow::Owned(table), handle);
+                first.tables.pin().insert(Cow::Owned(table), handle);
             }
 
+            // seed the pool with the connection whose tables are loaded already. further
+            // connections are made on demand, as concurrent requests need them.
+            let pool = ConnPool::new(max_conns);
+            pool.checkout(|| async { Ok(Arc::clone(&first)) })
+                .await?
+                .checkin();
+
             Ok(NoriaTrawler {
-                noria: Arc::new(NoriaConnection {
-                    ch: Mutex::new(c),
-                    views: ConcurrentHashMap::new(),
-                    tables,
-                }),
+                ch: c,
+                first,
+                pool: Arc::new(pool),
             })
         })
     }
@@ -116,8 +169,9 @@ impl Service<TrawlerRequest> for NoriaTrawler {
             ..
         }: TrawlerRequest,
     ) -> Self::Future {
-        let c = Arc::clone(&self.noria);
-        let c = futures_util::future::ready(Ok::<_, Self::Error>(c));
+        let ch = self.ch.clone();
+        let first = Arc::clone(&self.first);
+        let pool = Arc::clone(&self.pool);
 
         // TODO: traffic management
         // https://github.com/lobsters/lobsters/blob/master/app/controllers/application_controller.rb#L37
@@ -153,6 +207,11 @@ impl Service<TrawlerRequest> for NoriaTrawler {
         */
 
         Box::pin(async move {
+            let conn = pool
+                .checkout(|| async move { Ok(Arc::new(first.sharing(ch))) })
+                .await?;
+            let c = futures_util::future::ready(Ok::<_, Self::Error>(Arc::clone(&conn)));
+
             let inner = async move {
                 let (c, with_notifications) = match req {
                     LobstersRequest::User(uid) => endpoints::user::handle(c, acting_as, uid).await,
@@ -198,17 +257,22 @@ impl Service<TrawlerRequest> for NoriaTrawler {
                 // notifications
                 if let Some(uid) = acting_as {
                     if with_notifications && !priming {
-                        endpoints::notifications(c, uid).await?;
+                        return endpoints::notifications(c, uid).await;
                     }
                 }
 
-                Ok(())
+                Ok(c)
             };
 
             // XXX: there may be particular errors we want to ignore here that relate to there
             // being outstanding requests during exit.
             match inner.await {
-                Ok(()) => Ok(()),
+                Ok(_) => {
+                    conn.checkin();
+                    Ok(())
+                }
+                // the connection may be in a bad state, so it is dropped rather than handed out
+                // again
                 Err(e) => Err(e),
             }
         })
@@ -310,5 +374,8 @@ fn main() {
         wl.with_histogram(h);
     }
 
-    wl.run(NoriaTrawlerBuilder(Some(zk)), args.is_present("prime"));
+    wl.run(
+        NoriaTrawlerBuilder(Some(zk), in_flight),
+        args.is_present("prime"),
+    );
 }
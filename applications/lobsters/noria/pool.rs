@@ -0,0 +1,139 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// A bounded set of reusable connections.
+///
+/// At most `max` connections are checked out at any one time; further checkouts wait until one
+/// is checked back in or discarded. New connections are only made when no idle one is available.
+pub(crate) struct ConnPool<T> {
+    idle: Mutex<Vec<T>>,
+    available: Semaphore,
+}
+
+/// A connection checked out of a `ConnPool`.
+///
+/// Unless it is checked back in with `Checkout::checkin`, the connection is discarded when this
+/// is dropped, making room for a new one to be made.
+pub(crate) struct Checkout<'a, T> {
+    pool: &'a ConnPool<T>,
+    conn: Option<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T> ConnPool<T> {
+    pub(crate) fn new(max: usize) -> Self {
+        ConnPool {
+            idle: Mutex::new(Vec::with_capacity(max)),
+            available: Semaphore::new(max),
+        }
+    }
+
+    /// Take an idle connection from the pool, or make a new one with `connect` if there are none.
+    ///
+    /// If `connect` fails, its error is returned and the slot is given back to the pool.
+    pub(crate) async fn checkout<F, Fut>(
+        &self,
+        connect: F,
+    ) -> Result<Checkout<'_, T>, failure::Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, failure::Error>>,
+    {
+        let permit = self.available.acquire().await;
+
+        let idle = self.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(c) => c,
+            None => connect().await?,
+        };
+        Ok(Checkout {
+            pool: self,
+            conn: Some(conn),
+            _permit: permit,
+        })
+    }
+}
+
+impl<'a, T> Checkout<'a, T> {
+    /// Return the connection to the pool so that it can be reused.
+    pub(crate) fn checkin(mut self) {
+        let c = self.conn.take().unwrap();
+        self.pool.idle.lock().unwrap().push(c);
+    }
+}
+
+impl<'a, T> Deref for Checkout<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reuses_connections_within_cap() {
+        let pool = Arc::new(ConnPool::new(3));
+        let made = Arc::new(AtomicUsize::new(0));
+        let out = Arc::new(AtomicUsize::new(0));
+        let max_out = Arc::new(AtomicUsize::new(0));
+
+        let requests: Vec<_> = (0..20)
+            .map(|i| {
+                let (pool, made, out, max_out) = (
+                    Arc::clone(&pool),
+                    Arc::clone(&made),
+                    Arc::clone(&out),
+                    Arc::clone(&max_out),
+                );
+                tokio::spawn(async move {
+                    let c = pool
+                        .checkout(|| async { Ok(made.fetch_add(1, Ordering::SeqCst)) })
+                        .await
+                        .unwrap();
+                    let now_out = out.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_out.fetch_max(now_out, Ordering::SeqCst);
+                    tokio::time::delay_for(Duration::from_millis(5)).await;
+                    out.fetch_sub(1, Ordering::SeqCst);
+
+                    // every fourth request fails, and its connection must not be reused
+                    if i % 4 != 0 {
+                        c.checkin();
+                    }
+                })
+            })
+            .collect();
+        for r in requests {
+            r.await.unwrap();
+        }
+
+        assert!(max_out.load(Ordering::SeqCst) <= 3);
+        // connections are only made to replace discarded ones, or while the pool is filling up
+        assert!(made.load(Ordering::SeqCst) <= 3 + 5);
+        assert!(pool.idle.lock().unwrap().len() <= 3);
+    }
+
+    #[tokio::test]
+    async fn failed_connect_frees_slot() {
+        let pool = ConnPool::<usize>::new(1);
+        assert!(pool
+            .checkout(|| async { Err(failure::format_err!("refused")) })
+            .await
+            .is_err());
+        assert_eq!(*pool.checkout(|| async { Ok(1) }).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_checkout_is_discarded() {
+        let pool = ConnPool::<usize>::new(1);
+        drop(pool.checkout(|| async { Ok(1) }).await.unwrap());
+        assert_eq!(*pool.checkout(|| async { Ok(2) }).await.unwrap(), 2);
+    }
+}
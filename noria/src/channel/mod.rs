@@ -25,6 +25,8 @@ pub mod tcp;
 pub use self::tcp::{DualTcpStream, TcpSender};
 
 pub const CONNECTION_FROM_BASE: u8 = 1;
+/// Sent by domains, followed by the version of the encoding of what they will send, as a
+/// little-endian `u16`.
 pub const CONNECTION_FROM_DOMAIN: u8 = 2;
 
 pub struct Remote;
@@ -35,6 +37,7 @@ pub struct DomainConnectionBuilder<D, T> {
     addr: SocketAddr,
     chan: Option<tokio::sync::mpsc::UnboundedSender<T>>,
     is_for_base: bool,
+    version: u16,
     _marker: D,
 }
 
@@ -67,6 +70,7 @@ impl<T> DomainConnectionBuilder<Remote, T> {
             chan: None,
             addr,
             is_for_base: true,
+            version: 0,
             _marker: Remote,
         }
     }
@@ -106,11 +110,12 @@ where
         let mut s = TcpSender::connect_from(self.sport, &self.addr)?;
         {
             let s = s.get_mut();
-            s.write_all(&[if self.is_for_base {
-                CONNECTION_FROM_BASE
+            if self.is_for_base {
+                s.write_all(&[CONNECTION_FROM_BASE])?;
             } else {
-                CONNECTION_FROM_DOMAIN
-            }])?;
+                s.write_all(&[CONNECTION_FROM_DOMAIN])?;
+                s.write_all(&self.version.to_le_bytes())?;
+            }
             s.flush()?;
         }
 
@@ -155,6 +160,7 @@ where
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                version: self.version,
                 _marker: Remote,
             }
             .build_async()
@@ -171,6 +177,7 @@ where
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                version: self.version,
                 _marker: Remote,
             }
             .build_sync()
@@ -265,6 +272,8 @@ struct ChannelCoordinatorInner<K: Eq + Hash + Clone, T> {
 
 pub struct ChannelCoordinator<K: Eq + Hash + Clone, T> {
     inner: RwLock<ChannelCoordinatorInner<K, T>>,
+    /// Version of the encoding of `T` announced on remote connections.
+    version: u16,
 }
 
 impl<K: Eq + Hash + Clone, T> Default for ChannelCoordinator<K, T> {
@@ -275,11 +284,18 @@ impl<K: Eq + Hash + Clone, T> Default for ChannelCoordinator<K, T> {
 
 impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
     pub fn new() -> Self {
+        Self::with_version(0)
+    }
+
+    /// Make a coordinator whose remote connections announce that they send `T`s encoded with the
+    /// given version.
+    pub fn with_version(version: u16) -> Self {
         Self {
             inner: RwLock::new(ChannelCoordinatorInner {
                 addrs: Default::default(),
                 locals: Default::default(),
             }),
+            version,
         }
     }
 
@@ -328,6 +344,7 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
             addr: *inner.addrs.get(key)?,
             chan: inner.locals.get(key).cloned(),
             is_for_base: false,
+            version: self.version,
             _marker: MaybeLocal,
         })
     }
@@ -1,8 +1,9 @@
 use crate::consensus::{self, Authority};
-use crate::debug::stats;
+use crate::debug::{replay, stats};
+use crate::internal::DomainIndex;
 use crate::table::{Table, TableBuilder, TableRpc};
 use crate::view::{View, ViewBuilder, ViewRpc};
-use crate::ActivationResult;
+use crate::{ActivationResult, DataType};
 use failure::{self, ResultExt};
 use futures_util::future;
 use petgraph::graph::NodeIndex;
@@ -287,6 +288,22 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         }
     }
 
+    /// List the names of all known base tables, in order.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn list_tables(&mut self) -> impl Future<Output = Result<Vec<String>, failure::Error>> {
+        let inputs = self.inputs();
+        async move { Ok(inputs.await?.into_iter().map(|(t, _)| t).collect()) }
+    }
+
+    /// List the names of all known external views, in order.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn list_views(&mut self) -> impl Future<Output = Result<Vec<String>, failure::Error>> {
+        let outputs = self.outputs();
+        async move { Ok(outputs.await?.into_iter().map(|(v, _)| v).collect()) }
+    }
+
     /// Obtain a `View` that allows you to query the given external view.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -373,6 +390,153 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("get_statistics", (), "failed to get stats")
     }
 
+    /// Count the processing statistics of the given node, or of every node if `None`, from zero
+    /// again.
+    ///
+    /// Statistics reported after this only cover the work done since. A node's memory size is
+    /// not a counter, and is unaffected.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn reset_statistics(
+        &mut self,
+        node: Option<NodeIndex>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("reset_statistics", node, "failed to reset statistics")
+    }
+
+    /// Check that every domain is responsive.
+    ///
+    /// Returns how long each domain took to respond. Domains answer ahead of any data they have
+    /// queued up, so this measures how quickly they are dispatching, not how far behind they are.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn ping_domains(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<(DomainIndex, Duration)>, failure::Error>> {
+        self.rpc("ping_domains", (), "failed to ping domains")
+    }
+
+    /// Shut down every domain, in dataflow order.
+    ///
+    /// A domain only quits once it has processed all the data it received, and every domain that
+    /// sends it data has quit, so in-flight writes are not lost to a downstream domain that has
+    /// already gone away. The controller's domains are unusable after this.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn quit_domains(&mut self) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("quit_domains", (), "failed to quit domains")
+    }
+
+    /// Get the replay paths known by each domain.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn replay_paths(
+        &mut self,
+    ) -> impl Future<Output = Result<replay::ReplayPaths, failure::Error>> {
+        self.rpc("replay_paths", (), "failed to get replay paths")
+    }
+
+    /// Stop the domain that runs the given node from processing writes and replays.
+    ///
+    /// The domain still answers control requests while paused, so it can be inspected. Anything
+    /// it receives in the meantime is processed once `Self::resume_domain` is called.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn pause_domain(
+        &mut self,
+        node: NodeIndex,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("pause_domain", node, "failed to pause domain")
+    }
+
+    /// Resume a domain paused with `Self::pause_domain`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn resume_domain(
+        &mut self,
+        node: NodeIndex,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("resume_domain", node, "failed to resume domain")
+    }
+
+    /// Have the paused domain that runs the given node process the oldest write or replay it has
+    /// held back, and then stay paused.
+    ///
+    /// Fails if the domain is not paused.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn step_domain(
+        &mut self,
+        node: NodeIndex,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("step_domain", node, "failed to step domain")
+    }
+
+    /// Withdraw the request that the domain running the given node made to replay `key` along
+    /// the replay path tagged `tag`, if that replay has not started yet.
+    ///
+    /// A withdrawn key is requested again the next time it is read. The tags of the replay paths
+    /// through each domain are listed by `Self::replay_paths`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn cancel_partial_replay(
+        &mut self,
+        node: NodeIndex,
+        tag: u32,
+        key: Vec<DataType>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "cancel_partial_replay",
+            (node, tag, key),
+            "failed to cancel partial replay",
+        )
+    }
+
+    /// Index the state of the given fully materialized node on `index`.
+    ///
+    /// The index is built from the rows the node already holds, and can be used once this
+    /// returns. Adding an index the node already has does nothing. Fails if the node is not
+    /// materialized, is only partially materialized, or has no such columns.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn add_index(
+        &mut self,
+        node: NodeIndex,
+        index: Vec<usize>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("add_index", (node, index), "failed to add index")
+    }
+
+    /// Throw away the state of the given materialized node so that it is rebuilt from the node's
+    /// ancestors.
+    ///
+    /// Partial state is evicted along with everything computed from it, and lookups that need it
+    /// replay it the same way they would after an eviction. Full state is replayed before this
+    /// returns. With `block`, reads of the node wait for the rebuilt state; otherwise they are
+    /// served from the old state until the rebuild completes. Partial state has no old state to
+    /// serve, so recomputing it fails unless `block` is set. Base tables cannot be recomputed.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn recompute_node(
+        &mut self,
+        node: NodeIndex,
+        block: bool,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("recompute_node", (node, block), "failed to recompute node")
+    }
+
+    /// Get the column sets the given node is indexed on.
+    ///
+    /// A reader reports its lookup key, and a node that is not materialized reports no indices.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn node_indices(
+        &mut self,
+        node: NodeIndex,
+    ) -> impl Future<Output = Result<Vec<Vec<usize>>, failure::Error>> {
+        self.rpc("node_indices", node, "failed to get node indices")
+    }
+
     /// Flush all partial state, evicting all rows present.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -380,6 +544,17 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("flush_partial", (), "failed to flush partial")
     }
 
+    /// Have every domain evict partial state whenever its partial state grows beyond `bytes`
+    /// bytes.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_memory_limit(
+        &mut self,
+        bytes: usize,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("set_memory_limit", bytes, "failed to set memory limit")
+    }
+
     /// Extend the existing recipe with the given set of queries.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
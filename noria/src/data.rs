@@ -2,8 +2,9 @@ use arccstr::ArcCStr;
 
 use chrono::{self, NaiveDateTime};
 
-use nom_sql::Literal;
+use nom_sql::{Literal, SqlType};
 
+use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::{Add, Div, Mul, Sub};
@@ -43,6 +44,77 @@ pub enum DataType {
     Timestamp(NaiveDateTime),
 }
 
+/// The kinds of value a `DataType` can hold, not counting `DataType::None`.
+///
+/// `DataType::Text` and `DataType::TinyText` are both of kind `Text`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataTypeKind {
+    /// A signed 32-bit numeric value.
+    Int,
+    /// An unsigned 32-bit numeric value.
+    UnsignedInt,
+    /// A signed 64-bit numeric value.
+    BigInt,
+    /// An unsigned 64-bit numeric value.
+    UnsignedBigInt,
+    /// A fixed point real value.
+    Real,
+    /// A string-like value.
+    Text,
+    /// A timestamp for date/time types.
+    Timestamp,
+}
+
+impl DataTypeKind {
+    /// The kind of value stored in a column of the given SQL type, if known.
+    pub fn from_sql_type(t: &SqlType) -> Option<Self> {
+        match *t {
+            SqlType::Int(_) => Some(DataTypeKind::Int),
+            SqlType::UnsignedInt(_) => Some(DataTypeKind::UnsignedInt),
+            SqlType::Bigint(_) => Some(DataTypeKind::BigInt),
+            SqlType::UnsignedBigint(_) => Some(DataTypeKind::UnsignedBigInt),
+            SqlType::Real => Some(DataTypeKind::Real),
+            SqlType::Text | SqlType::Varchar(_) => Some(DataTypeKind::Text),
+            SqlType::Timestamp => Some(DataTypeKind::Timestamp),
+            _ => None,
+        }
+    }
+}
+
+/// The reason a `DataType` could not be coerced to another kind of value.
+#[derive(Debug, Clone, PartialEq, Fail)]
+pub enum CoerceError {
+    /// The value cannot be converted to the target kind at all.
+    #[fail(display = "cannot coerce {:?} to {:?}", value, target)]
+    Incompatible {
+        /// The value that was to be coerced.
+        value: DataType,
+        /// The kind of value it was to be coerced to.
+        target: DataTypeKind,
+    },
+    /// A string could not be parsed as the target kind.
+    #[fail(display = "cannot parse {:?} as {:?}", value, target)]
+    Parse {
+        /// The string that was to be parsed.
+        value: String,
+        /// The kind of value it was to be parsed as.
+        target: DataTypeKind,
+    },
+    /// The value is out of range for the target kind.
+    #[fail(display = "{:?} does not fit in {:?}", value, target)]
+    Overflow {
+        /// The value that was to be coerced.
+        value: DataType,
+        /// The kind of value it was to be coerced to.
+        target: DataTypeKind,
+    },
+    /// Converting the value to an integer would drop its fractional part.
+    #[fail(display = "{:?} is not a whole number", _0)]
+    Inexact(DataType),
+}
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -143,6 +215,111 @@ impl DataType {
             _ => false,
         }
     }
+
+    /// The kind of value this is, or `None` for `DataType::None`.
+    pub fn kind(&self) -> Option<DataTypeKind> {
+        match *self {
+            DataType::None => None,
+            DataType::Int(_) => Some(DataTypeKind::Int),
+            DataType::UnsignedInt(_) => Some(DataTypeKind::UnsignedInt),
+            DataType::BigInt(_) => Some(DataTypeKind::BigInt),
+            DataType::UnsignedBigInt(_) => Some(DataTypeKind::UnsignedBigInt),
+            DataType::Real(..) => Some(DataTypeKind::Real),
+            DataType::Text(_) | DataType::TinyText(_) => Some(DataTypeKind::Text),
+            DataType::Timestamp(_) => Some(DataTypeKind::Timestamp),
+        }
+    }
+
+    /// Convert this value into a value of the given kind.
+    ///
+    /// Integers convert to other integer kinds if they fit, and to reals. Reals only convert to
+    /// integers if they are whole numbers. Strings are parsed as numbers or timestamps, and
+    /// numbers and timestamps can be turned into strings. `DataType::None` is left as is.
+    pub fn coerce(&self, target: &DataTypeKind) -> Result<DataType, CoerceError> {
+        let target = *target;
+        if self.is_none() || self.kind() == Some(target) {
+            return Ok(self.clone());
+        }
+
+        let incompatible = || CoerceError::Incompatible {
+            value: self.clone(),
+            target,
+        };
+        let parse_error = |s: &str| CoerceError::Parse {
+            value: s.to_owned(),
+            target,
+        };
+
+        match target {
+            DataTypeKind::Int
+            | DataTypeKind::UnsignedInt
+            | DataTypeKind::BigInt
+            | DataTypeKind::UnsignedBigInt => {
+                let v: i128 = match *self {
+                    DataType::Int(_)
+                    | DataType::UnsignedInt(_)
+                    | DataType::BigInt(_)
+                    | DataType::UnsignedBigInt(_) => self.into(),
+                    DataType::Real(i, 0) => i128::from(i),
+                    DataType::Real(..) => return Err(CoerceError::Inexact(self.clone())),
+                    DataType::Text(_) | DataType::TinyText(_) => {
+                        let s: Cow<'_, str> = self.into();
+                        s.trim().parse().map_err(|_| parse_error(&s))?
+                    }
+                    _ => return Err(incompatible()),
+                };
+
+                let overflow = |_| CoerceError::Overflow {
+                    value: self.clone(),
+                    target,
+                };
+                Ok(match target {
+                    DataTypeKind::Int => DataType::Int(i32::try_from(v).map_err(overflow)?),
+                    DataTypeKind::UnsignedInt => {
+                        DataType::UnsignedInt(u32::try_from(v).map_err(overflow)?)
+                    }
+                    DataTypeKind::BigInt => DataType::BigInt(i64::try_from(v).map_err(overflow)?),
+                    DataTypeKind::UnsignedBigInt => {
+                        DataType::UnsignedBigInt(u64::try_from(v).map_err(overflow)?)
+                    }
+                    _ => unreachable!(),
+                })
+            }
+            DataTypeKind::Real => match *self {
+                DataType::Int(_)
+                | DataType::UnsignedInt(_)
+                | DataType::BigInt(_)
+                | DataType::UnsignedBigInt(_) => {
+                    let v: i128 = self.into();
+                    Ok(DataType::from(v as f64))
+                }
+                DataType::Text(_) | DataType::TinyText(_) => {
+                    let s: Cow<'_, str> = self.into();
+                    match s.trim().parse::<f64>() {
+                        Ok(f) if f.is_finite() => Ok(DataType::from(f)),
+                        _ => Err(parse_error(&s)),
+                    }
+                }
+                _ => Err(incompatible()),
+            },
+            DataTypeKind::Text => match *self {
+                DataType::Timestamp(ts) => {
+                    Ok(DataType::from(ts.format(TIMESTAMP_FORMAT).to_string()))
+                }
+                DataType::Text(_) | DataType::TinyText(_) | DataType::None => unreachable!(),
+                _ => Ok(DataType::from(self.to_string())),
+            },
+            DataTypeKind::Timestamp => match *self {
+                DataType::Text(_) | DataType::TinyText(_) => {
+                    let s: Cow<'_, str> = self.into();
+                    NaiveDateTime::parse_from_str(s.trim(), TIMESTAMP_FORMAT)
+                        .map(DataType::Timestamp)
+                        .map_err(|_| parse_error(&s))
+                }
+                _ => Err(incompatible()),
+            },
+        }
+    }
 }
 
 impl PartialEq for DataType {
@@ -241,15 +418,49 @@ impl Ord for DataType {
             (&DataType::Timestamp(tsa), &DataType::Timestamp(ref tsb)) => tsa.cmp(tsb),
             (&DataType::None, &DataType::None) => Ordering::Equal,
 
-            // order Ints, Reals, Text, Timestamps, None
-            (&DataType::Int(..), _)
-            | (&DataType::UnsignedInt(..), _)
-            | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
-            (&DataType::Real(..), _) => Ordering::Greater,
-            (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
-            (&DataType::Timestamp(..), _) => Ordering::Greater,
-            (&DataType::None, _) => Ordering::Greater,
+            // order None, Ints, Reals, Text, Timestamps
+            _ => self.type_order().cmp(&other.type_order()),
+        }
+    }
+}
+
+impl DataType {
+    /// Position of this value's type in the ordering between values of different types.
+    fn type_order(&self) -> u8 {
+        match *self {
+            DataType::None => 0,
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => 1,
+            DataType::Real(..) => 2,
+            DataType::Text(..) | DataType::TinyText(..) => 3,
+            DataType::Timestamp(..) => 4,
+        }
+    }
+
+    /// Compare two values for equality the way SQL's `=` does.
+    ///
+    /// Unlike `==`, which treats `DataType::None` as equal to itself so that NULLs can be stored,
+    /// grouped, and indexed, this follows SQL's three-valued logic: if either side is NULL, the
+    /// result is unknown (`None`).
+    pub fn sql_eq(&self, other: &DataType) -> Option<bool> {
+        if self.is_none() || other.is_none() {
+            None
+        } else {
+            Some(self == other)
+        }
+    }
+
+    /// Compare two values the way SQL's `<` and `>` do.
+    ///
+    /// As with `sql_eq`, comparing against NULL yields `None`. `Ord` instead places
+    /// `DataType::None` before all other values, so that NULLs sort first in indexes.
+    pub fn sql_cmp(&self, other: &DataType) -> Option<Ordering> {
+        if self.is_none() || other.is_none() {
+            None
+        } else {
+            Some(self.cmp(other))
         }
     }
 }
@@ -1102,4 +1313,126 @@ mod tests {
         assert_ne!(hash(&long), hash(&time));
         assert_ne!(hash(&long), hash(&shrt6));
     }
+
+    #[test]
+    fn coerce_compatible() {
+        let coerce = |dt: DataType, target| dt.coerce(&target).unwrap();
+
+        assert_eq!(coerce("42".into(), DataTypeKind::Int), DataType::Int(42));
+        assert_eq!(
+            coerce(" 42 ".into(), DataTypeKind::UnsignedBigInt),
+            DataType::UnsignedBigInt(42)
+        );
+        assert_eq!(
+            coerce(DataType::Int(7), DataTypeKind::BigInt),
+            DataType::BigInt(7)
+        );
+        assert_eq!(
+            coerce(DataType::Int(7), DataTypeKind::Real),
+            DataType::from(7.0)
+        );
+        assert_eq!(
+            coerce(DataType::from(3.0), DataTypeKind::Int),
+            DataType::Int(3)
+        );
+        assert_eq!(
+            coerce("2.5".into(), DataTypeKind::Real),
+            DataType::from(2.5)
+        );
+        assert_eq!(coerce(DataType::Int(42), DataTypeKind::Text), "42".into());
+        assert_eq!(
+            coerce("2020-01-02 03:04:05".into(), DataTypeKind::Timestamp),
+            DataType::Timestamp(NaiveDateTime::from_timestamp(1_577_934_245, 0))
+        );
+        assert_eq!(coerce(DataType::None, DataTypeKind::Int), DataType::None);
+    }
+
+    #[test]
+    fn coerce_errors() {
+        assert_eq!(
+            DataType::from("forty-two").coerce(&DataTypeKind::Int),
+            Err(CoerceError::Parse {
+                value: "forty-two".into(),
+                target: DataTypeKind::Int,
+            })
+        );
+        assert_eq!(
+            DataType::BigInt(1 << 40).coerce(&DataTypeKind::Int),
+            Err(CoerceError::Overflow {
+                value: DataType::BigInt(1 << 40),
+                target: DataTypeKind::Int,
+            })
+        );
+        assert_eq!(
+            DataType::Int(-1).coerce(&DataTypeKind::UnsignedInt),
+            Err(CoerceError::Overflow {
+                value: DataType::Int(-1),
+                target: DataTypeKind::UnsignedInt,
+            })
+        );
+        assert_eq!(
+            DataType::from(2.5).coerce(&DataTypeKind::BigInt),
+            Err(CoerceError::Inexact(DataType::from(2.5)))
+        );
+        assert!(DataType::Int(1).coerce(&DataTypeKind::Timestamp).is_err());
+    }
+
+    #[test]
+    fn null_semantics() {
+        use std::cmp::Ordering;
+
+        let null = DataType::None;
+        let one = DataType::Int(1);
+
+        // NULLs are equal to each other for indexing, but not under SQL's `=`
+        assert_eq!(null, DataType::None);
+        assert_eq!(null.sql_eq(&DataType::None), None);
+        assert_eq!(null.sql_eq(&one), None);
+        assert_eq!(one.sql_eq(&null), None);
+        assert_eq!(one.sql_eq(&DataType::BigInt(1)), Some(true));
+        assert_eq!(one.sql_cmp(&null), None);
+        assert_eq!(one.sql_cmp(&DataType::Int(2)), Some(Ordering::Less));
+
+        // NULL sorts before every other value, regardless of which side it is on
+        let others = vec![
+            one.clone(),
+            DataType::UnsignedBigInt(1),
+            DataType::from(1.5),
+            DataType::from("a"),
+            DataType::from("a long string that is not tiny"),
+            DataType::Timestamp(NaiveDateTime::from_timestamp(0, 0)),
+        ];
+        for v in &others {
+            assert_eq!(null.cmp(v), Ordering::Less);
+            assert_eq!(v.cmp(&null), Ordering::Greater);
+        }
+
+        // and the ordering between types is antisymmetric
+        for a in &others {
+            for b in &others {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse(), "{:?} vs {:?}", a, b);
+            }
+        }
+
+        let mut sorted = vec![
+            DataType::from("b"),
+            DataType::Int(2),
+            DataType::None,
+            DataType::from(0.5),
+            DataType::None,
+            DataType::Int(-1),
+        ];
+        sorted.sort();
+        assert_eq!(
+            sorted,
+            vec![
+                DataType::None,
+                DataType::None,
+                DataType::Int(-1),
+                DataType::Int(2),
+                DataType::from(0.5),
+                DataType::from("b"),
+            ]
+        );
+    }
 }
@@ -1,2 +1,4 @@
+/// Types related to replay paths.
+pub mod replay;
 /// Types related to graph statistics.
 pub mod stats;
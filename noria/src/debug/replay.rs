@@ -0,0 +1,31 @@
+use crate::internal::*;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+/// The kind of endpoint that triggers replays along a replay path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayTrigger {
+    /// The path is only used for full replays.
+    None,
+    /// Replays are requested by a domain further down the path, and start in this domain.
+    Start,
+    /// Replays are requested by this domain, and start in a domain further up the path.
+    End,
+    /// Replays both start and end within this domain.
+    Local,
+}
+
+/// A replay path as known by a single domain shard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayPathInfo {
+    /// The tag that identifies this replay path.
+    pub tag: u32,
+    /// The nodes in this domain that the path goes through, in order, along with the columns
+    /// that the replayed keys are on at each node (if the path is partial).
+    pub path: Vec<(NodeIndex, Option<Vec<usize>>)>,
+    /// What triggers replays along this path.
+    pub trigger: ReplayTrigger,
+}
+
+/// The replay paths known by each domain shard.
+pub type ReplayPaths = Vec<((DomainIndex, usize), Vec<ReplayPathInfo>)>;
@@ -3,6 +3,7 @@ use crate::MaterializationStatus;
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::time::Duration;
 
 type DomainMap = HashMap<(DomainIndex, usize), (DomainStats, HashMap<NodeIndex, NodeStats>)>;
 
@@ -19,8 +20,72 @@ pub struct DomainStats {
     pub total_replay_time: u64,
     /// Total wall-clock time spent processing forward updates in this domain.
     pub total_forward_time: u64,
+    /// Total wall-clock time spent processing forward updates in the nodes of this domain.
+    pub total_process_time: u64,
+    /// Total wall-clock time spent processing replays in the nodes of this domain.
+    pub total_replay_process_time: u64,
     /// Total wall-clock time spent waiting for work in this domain.
     pub wait_time: u64,
+    /// Total memory size of the state of all nodes in this domain, in bytes.
+    pub total_mem_size: u64,
+    /// Number of packets this domain failed to send to another domain.
+    pub dropped_packets: u64,
+    /// How many of `dropped_packets` were control packets.
+    pub dropped_control_packets: u64,
+    /// Number of packets received over remote connections that failed their checksum, and were
+    /// dropped.
+    pub corrupt_packets: u64,
+    /// Number of packets that arrived along a link after one that was sent later than them.
+    ///
+    /// Only counted if the domain checks link order, and only in release builds; debug builds
+    /// panic instead.
+    pub misordered_packets: u64,
+    /// Number of writes to base tables rejected by their ingress rate limit.
+    pub rejected_writes: u64,
+    /// Latency of partial replays requested by this domain, keyed by replay path tag.
+    pub replay_latency: HashMap<u32, ReplayLatency>,
+}
+
+/// Latency of the partial replays along a single replay path.
+///
+/// Measured from when a replay is requested until the last piece for the key is processed.
+/// All times are in nanoseconds.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ReplayLatency {
+    /// Number of replayed keys that have completed.
+    pub completed: u64,
+    /// Number of requested keys whose replay has not yet completed.
+    pub in_flight: u64,
+    /// Shortest time taken by a completed replay.
+    pub min: u64,
+    /// Longest time taken by a completed replay.
+    pub max: u64,
+    /// Sum of the time taken by all completed replays.
+    pub total: u64,
+}
+
+impl ReplayLatency {
+    /// Account for a replay that completed after `elapsed`.
+    pub fn record(&mut self, elapsed: Duration) {
+        let ns = elapsed.as_nanos() as u64;
+        if self.completed == 0 || ns < self.min {
+            self.min = ns;
+        }
+        if ns > self.max {
+            self.max = ns;
+        }
+        self.total += ns;
+        self.completed += 1;
+    }
+
+    /// Average time taken by a completed replay.
+    pub fn avg(&self) -> u64 {
+        if self.completed == 0 {
+            0
+        } else {
+            self.total / self.completed
+        }
+    }
 }
 
 /// Statistics about a node.
@@ -34,6 +99,12 @@ pub struct NodeStats {
     pub process_time: u64,
     /// Total thread time elapsed while processing in this node.
     pub process_ptime: u64,
+    /// Number of forward updates processed by this node.
+    pub process_count: u64,
+    /// Total wall-clock time elapsed while processing replays in this node.
+    pub replay_process_time: u64,
+    /// Number of replay pieces processed by this node.
+    pub replay_process_count: u64,
     /// Total memory size of this node's state.
     pub mem_size: u64,
     /// The materialization type of this node's state.
@@ -219,6 +219,7 @@ pub mod results {
 
 /// Noria errors.
 pub mod error {
+    pub use crate::data::CoerceError;
     pub use crate::table::TableError;
     pub use crate::view::ViewError;
 }
@@ -276,7 +277,7 @@ impl<T> From<T> for Tagged<T> {
 }
 
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
-pub use crate::data::{DataType, Modification, Operation, TableOperation};
+pub use crate::data::{DataType, DataTypeKind, Modification, Operation, TableOperation};
 pub use crate::table::Table;
 pub use crate::view::View;
 
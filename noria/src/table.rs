@@ -277,6 +277,8 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    pub source_offset: Option<u64>,
+    pub request_ids: Vec<u64>,
 }
 
 impl fmt::Debug for Input {
@@ -284,6 +286,8 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("source_offset", &self.source_offset)
+            .field("request_ids", &self.request_ids)
             .finish()
     }
 }
@@ -517,12 +521,16 @@ impl Table {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                source_offset: i.source_offset,
+                                request_ids: i.request_ids.clone(),
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            source_offset: i.source_offset,
+                            request_ids: i.request_ids.clone(),
                         })
                     };
                     let request = Tagged::from(p);
@@ -592,6 +600,30 @@ impl Table {
         &self.columns
     }
 
+    /// Get the columns of this base table along with the kind of value each one holds.
+    ///
+    /// Kinds are taken from the table's schema, and are `None` if the schema is not known or
+    /// uses a type with no matching `DataTypeKind`. Rows passed to `insert` must have exactly one
+    /// value per column, in this order.
+    ///
+    /// Note that this will *not* be updated if the underlying recipe changes and adds or removes
+    /// columns!
+    pub fn column_types(&self) -> Vec<(String, Option<DataTypeKind>)> {
+        self.columns
+            .iter()
+            .map(|col| {
+                let kind = self.schema.as_ref().and_then(|schema| {
+                    schema
+                        .fields
+                        .iter()
+                        .find(|f| &f.column.name == col)
+                        .and_then(|f| DataTypeKind::from_sql_type(&f.sql_type))
+                });
+                (col.clone(), kind)
+            })
+            .collect()
+    }
+
     /// Get the schema that was used to create this base table.
     ///
     /// Note that this will *not* be updated if the underlying recipe changes and adds or removes
@@ -694,6 +726,8 @@ impl Table {
         Input {
             dst: self.node,
             data: ops,
+            source_offset: None,
+            request_ids: Vec::new(),
         }
     }
 
@@ -718,6 +752,60 @@ impl Table {
             .await
     }
 
+    /// Insert a single row of data into this base table, tagged with its offset in an upstream
+    /// source (such as a log the rows are being replicated from).
+    ///
+    /// The offset is carried through the dataflow alongside the resulting updates, and readers
+    /// remember the highest offset they have applied; see `View::applied_offset`.
+    ///
+    /// Offsets must fit in an `i64`.
+    pub async fn insert_at<V>(&mut self, u: V, offset: u64) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        assert!(
+            offset <= i64::max_value() as u64,
+            "source offsets must fit in an i64"
+        );
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        let mut i = self.prep_records(vec![TableOperation::Insert(u.into())]);
+        i.source_offset = Some(offset);
+        Ok(self.input(i).await?.v)
+    }
+
+    /// Insert a single row of data into this base table, tagged with the id of the client request
+    /// it belongs to.
+    ///
+    /// The id is carried through the dataflow alongside the resulting updates, and is handed to
+    /// any streamers on downstream readers with the updates it caused. If the write is batched
+    /// with others along the way, the batch carries the ids of all the tagged writes in it.
+    pub async fn insert_with_request_id<V>(
+        &mut self,
+        u: V,
+        request_id: u64,
+    ) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        let mut i = self.prep_records(vec![TableOperation::Insert(u.into())]);
+        i.request_ids.push(request_id);
+        Ok(self.input(i).await?.v)
+    }
+
+    /// Insert many rows of data into this base table.
+    ///
+    /// All the rows are sent in a single request to each shard of the table. If any of the rows
+    /// has the wrong number of columns, an error is returned and none of them are inserted.
+    pub async fn insert_many(&mut self, rows: Vec<Vec<DataType>>) -> Result<(), TableError> {
+        self.quick_n_dirty(
+            rows.into_iter()
+                .map(TableOperation::Insert)
+                .collect::<Vec<_>>(),
+        )
+        .await
+    }
+
     /// Perform multiple operation on this base table.
     pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
     where
@@ -793,4 +881,39 @@ impl Table {
         }])
         .await
     }
+
+    /// Replace the row whose `key_cols` match those of `row` with `row`, or insert `row` if there
+    /// is no such row.
+    ///
+    /// `key_cols` must be the table's primary key. The delete and the insert are sent in the same
+    /// packet, so the base table applies them together and no reader observes one without the
+    /// other. Note that this does not make a preceding read and the upsert atomic: of two
+    /// concurrent upserts to the same key, whichever reaches the base table last wins.
+    pub async fn upsert(
+        &mut self,
+        key_cols: &[usize],
+        row: Vec<DataType>,
+    ) -> Result<(), TableError> {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "upserts can only be applied to base nodes with key columns"
+        );
+        assert_eq!(
+            key_cols,
+            &self.key[..],
+            "upserts must be keyed on the table's primary key"
+        );
+
+        let ncols = self.columns.len();
+        if row.len() != ncols {
+            return Err(TableError::WrongColumnCount(ncols, row.len()));
+        }
+
+        let key = key_cols.iter().map(|&c| row[c].clone()).collect();
+        self.quick_n_dirty(vec![
+            TableOperation::Delete { key },
+            TableOperation::Insert(row),
+        ])
+        .await
+    }
 }
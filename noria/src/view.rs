@@ -20,6 +20,9 @@ use tower_buffer::Buffer;
 use tower_limit::concurrency::ConcurrencyLimit;
 use tower_service::Service;
 
+/// How many rows `View::lookup_stream` reads from the view at a time.
+const STREAM_PAGE_SIZE: usize = 1024;
+
 type Transport = AsyncBincodeStream<
     tokio::net::TcpStream,
     Tagged<ReadReply>,
@@ -70,6 +73,23 @@ pub enum ViewError {
     /// The given view is not yet available.
     #[fail(display = "the view is not yet available")]
     NotYetAvailable,
+    /// The given view is partially materialized, and so cannot answer range queries.
+    #[fail(display = "the view does not support range queries")]
+    NotRangeIndexed,
+    /// The given view cannot answer lookups on a prefix of its key, because its key is a single
+    /// column, or because it is partially materialized and its sources cannot list the keys that
+    /// start with a prefix.
+    #[fail(display = "the view does not support lookups on a prefix of its key")]
+    NotPrefixIndexed,
+    /// A lookup was given no key columns.
+    #[fail(display = "a lookup key needs at least one column")]
+    EmptyKey,
+    /// A lookup key could not be converted to the type of the view's key column.
+    #[fail(display = "{}", _0)]
+    Coerce(#[cause] CoerceError),
+    /// The view has no column with the given index.
+    #[fail(display = "the view has no column {}", _0)]
+    NoSuchColumn(usize),
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -93,11 +113,80 @@ pub enum ReadQuery {
         /// Whether to block if a partial replay is triggered
         block: bool,
     },
+    /// Count the rows for a key in a leaf view
+    Count {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to count the rows of
+        key: Vec<DataType>,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
+    /// Read the first rows for a key in a leaf view, ordered by one of the columns
+    TopN {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read the rows of
+        key: Vec<DataType>,
+        /// Column to order the rows by
+        order_col: usize,
+        /// How many rows to read
+        n: usize,
+        /// Whether the rows with the largest values come first
+        descending: bool,
+    },
+    /// Read the rows for a key in a leaf view only if they are materialized, without replaying
+    /// them if they are not
+    Peek {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read the rows of
+        key: Vec<DataType>,
+    },
+    /// Read the next rows for a key in a leaf view, ordered by all their columns
+    Page {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read the rows of
+        key: Vec<DataType>,
+        /// The last row already read, and how many copies of it have been read
+        after: Option<(Vec<DataType>, usize)>,
+        /// How many rows to read
+        n: usize,
+    },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Read all keys in a half-open range from a leaf view
+    Range {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Smallest key to include
+        start: Vec<DataType>,
+        /// Smallest key to exclude
+        end: Vec<DataType>,
+    },
+    /// Read all keys that start with the given columns from a leaf view
+    Prefix {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Leading key columns to match
+        prefix: Vec<DataType>,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
+    /// Read every row currently materialized in a leaf view
+    All {
+        /// Where to read from
+        target: (NodeIndex, usize),
+    },
+    /// Read the highest source offset applied to a leaf view
+    AppliedOffset {
+        /// Where to read from
+        target: (NodeIndex, usize),
+    },
 }
 
 #[doc(hidden)]
@@ -105,8 +194,25 @@ pub enum ReadQuery {
 pub enum ReadReply {
     /// Errors if view isn't ready yet.
     Normal(Result<Vec<Vec<Vec<DataType>>>, ()>),
+    /// Number of rows for the key.
+    /// Errors if view isn't ready yet.
+    Count(Result<usize, ()>),
     /// Read size of view
     Size(usize),
+    /// Rows for each key in the range, or `None` if the view cannot answer range queries.
+    /// Errors if view isn't ready yet.
+    Range(Result<Option<Vec<(Vec<DataType>, Vec<Vec<DataType>>)>>, ()>),
+    /// Rows for each key with the prefix, or `None` if the view cannot answer prefix queries.
+    /// Errors if view isn't ready yet.
+    Prefix(Result<Option<Vec<Vec<Vec<DataType>>>>, ()>),
+    /// Rows for each materialized key.
+    /// Errors if view isn't ready yet.
+    All(Result<Vec<Vec<Vec<DataType>>>, ()>),
+    /// Highest source offset applied to the view, if any.
+    AppliedOffset(Option<u64>),
+    /// Rows for the key, or `None` if the key is not materialized.
+    /// Errors if view isn't ready yet.
+    Peek(Result<Option<Vec<Vec<DataType>>>, ()>),
 }
 
 #[doc(hidden)]
@@ -115,6 +221,7 @@ pub struct ViewBuilder {
     pub node: NodeIndex,
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
+    pub key: Option<Vec<usize>>,
     pub shards: Vec<SocketAddr>,
 }
 
@@ -129,6 +236,7 @@ impl ViewBuilder {
         let columns = self.columns.clone();
         let shards = self.shards.clone();
         let schema = self.schema.clone();
+        let key = self.key.clone();
 
         let mut addrs = Vec::with_capacity(shards.len());
         let mut conns = Vec::with_capacity(shards.len());
@@ -171,6 +279,8 @@ impl ViewBuilder {
         Ok(View {
             node,
             schema,
+            key,
+            coerce_keys: false,
             columns: Arc::from(columns),
             shard_addrs: addrs,
             shards: conns,
@@ -188,6 +298,8 @@ pub struct View {
     node: NodeIndex,
     columns: Arc<[String]>,
     schema: Option<Vec<ColumnSpecification>>,
+    key: Option<Vec<usize>>,
+    coerce_keys: bool,
 
     shards: Vec<ViewRpc>,
     shard_addrs: Vec<SocketAddr>,
@@ -264,9 +376,12 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
         }
         assert!(keys.iter().all(|k| k.len() == 1));
         let mut shard_queries = vec![Vec::new(); self.shards.len()];
-        for key in keys {
+        // where in the response each shard's results should end up
+        let mut shard_positions = vec![Vec::new(); self.shards.len()];
+        for (i, key) in keys.into_iter().enumerate() {
             let shard = crate::shard_by(&key[0], self.shards.len());
             shard_queries[shard].push(key);
+            shard_positions[shard].push(i);
         }
 
         let node = self.node;
@@ -274,8 +389,8 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
             self.shards
                 .iter_mut()
                 .enumerate()
-                .zip(shard_queries.into_iter())
-                .filter_map(|((shardi, shard), shard_queries)| {
+                .zip(shard_queries.into_iter().zip(shard_positions))
+                .filter_map(|((shardi, shard), (shard_queries, positions))| {
                     if shard_queries.is_empty() {
                         // poll_ready reserves a sender slot which we have to release
                         // we do that by dropping the old handle and replacing it with a clone
@@ -283,10 +398,10 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         *shard = shard.clone();
                         None
                     } else {
-                        Some(((shardi, shard), shard_queries))
+                        Some(((shardi, shard), shard_queries, positions))
                     }
                 })
-                .map(move |((shardi, shard), shard_queries)| {
+                .map(move |((shardi, shard), shard_queries, positions)| {
                     let request = Tagged::from(ReadQuery::Normal {
                         target: (node, shardi),
                         keys: shard_queries,
@@ -313,12 +428,15 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                                 _ => unreachable!(),
                             }
                         })
+                        .map_ok(move |rows| positions.into_iter().zip(rows).collect::<Vec<_>>())
                 })
                 .collect::<FuturesUnordered<_>>()
                 .try_concat()
-                .map_ok(move |rows| {
+                .map_ok(move |mut rows| {
+                    // shards respond in any order, but results must line up with the keys
+                    rows.sort_unstable_by_key(|&(i, _)| i);
                     rows.into_iter()
-                        .map(|rows| Results::new(rows, Arc::clone(&columns)))
+                        .map(|(_, rows)| Results::new(rows, Arc::clone(&columns)))
                         .collect()
                 }),
         )
@@ -337,6 +455,32 @@ impl View {
         self.schema.as_deref()
     }
 
+    /// Convert lookup keys to the types of this view's key columns before sending them.
+    ///
+    /// Without this, a key must have exactly the type stored in the view to match; looking up
+    /// `"42"` in a view keyed by an integer column finds nothing. With it, lookups fail with
+    /// `ViewError::Coerce` if a key cannot be converted. Disabled by default.
+    pub fn coerce_keys(&mut self, coerce: bool) {
+        self.coerce_keys = coerce;
+    }
+
+    fn coerce_key(&self, key: Vec<DataType>) -> Result<Vec<DataType>, ViewError> {
+        let (schema, cols) = match (&self.schema, &self.key) {
+            (Some(schema), Some(cols)) if self.coerce_keys => (schema, cols),
+            _ => return Ok(key),
+        };
+
+        key.into_iter()
+            .zip(cols)
+            .map(
+                |(k, &col)| match DataTypeKind::from_sql_type(&schema[col].sql_type) {
+                    Some(kind) => k.coerce(&kind).map_err(ViewError::Coerce),
+                    None => Ok(k),
+                },
+            )
+            .collect()
+    }
+
     /// Get the current size of this view.
     ///
     /// Note that you must also continue to poll this `View` for the returned future to resolve.
@@ -367,29 +511,432 @@ impl View {
         Ok(nrows)
     }
 
+    /// Get the highest source offset whose writes are visible in this view.
+    ///
+    /// Offsets are supplied by clients with `Table::insert_at`; `None` is returned if no write
+    /// carrying an offset has reached the view yet. For a sharded view, this is the highest offset
+    /// seen by any shard.
+    ///
+    /// Note that you must also continue to poll this `View` for the returned future to resolve.
+    pub async fn applied_offset(&mut self) -> Result<Option<u64>, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let node = self.node;
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .map(|(shardi, shard)| {
+                shard.call(Tagged::from(ReadQuery::AppliedOffset {
+                    target: (node, shardi),
+                }))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut applied = None;
+        while let Some(reply) = rsps.next().await.transpose()? {
+            if let ReadReply::AppliedOffset(offset) = reply.v {
+                applied = applied.max(offset);
+            } else {
+                unreachable!();
+            }
+        }
+
+        Ok(applied)
+    }
+
     /// Retrieve the query results for the given parameter values.
     ///
+    /// The results for each key are returned in the same order as `keys`.
+    ///
     /// The method will block if the results are not yet available only when `block` is `true`.
     /// If `block` is false, misses will be returned as empty results. Any requested keys that have
     /// missing state will be backfilled (asynchronously if `block` is `false`).
+    ///
+    /// As with SQL's `=`, a key that contains `DataType::None` (NULL) matches no rows, even rows
+    /// that have NULL in the key column. Such keys are not sent to the view at all.
     pub async fn multi_lookup(
         &mut self,
         keys: Vec<Vec<DataType>>,
         block: bool,
     ) -> Result<Vec<Results>, ViewError> {
-        future::poll_fn(|cx| self.poll_ready(cx)).await?;
-        self.call((keys, block)).await
+        let keys = keys
+            .into_iter()
+            .map(|key| self.coerce_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let null = |key: &Vec<DataType>| key.iter().any(DataType::is_none);
+        let nulls: Vec<_> = keys.iter().map(null).collect();
+        let keys: Vec<_> = keys.into_iter().filter(|key| !null(key)).collect();
+
+        let mut found = if keys.is_empty() {
+            Vec::new()
+        } else {
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            self.call((keys, block)).await?
+        }
+        .into_iter();
+
+        Ok(nulls
+            .into_iter()
+            .map(|null| {
+                if null {
+                    Results::new(Vec::new(), Arc::clone(&self.columns))
+                } else {
+                    found.next().unwrap()
+                }
+            })
+            .collect())
     }
 
     /// Retrieve the query results for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
+    ///
+    /// If the view is keyed on several columns, `key` may give just the leading ones, and all rows
+    /// whose key starts with them are returned. A partially materialized view replays every key
+    /// that starts with them, which needs its sources to be able to list those keys; if they
+    /// cannot, `ViewError::NotPrefixIndexed` is returned.
     pub async fn lookup(&mut self, key: &[DataType], block: bool) -> Result<Results, ViewError> {
+        if key.is_empty() {
+            return Err(ViewError::EmptyKey);
+        }
+        if let Some(ref cols) = self.key {
+            if key.len() < cols.len() {
+                return self.lookup_prefix(key, block).await;
+            }
+        }
+
         // TODO: Optimized version of this function?
         let rs = self.multi_lookup(vec![Vec::from(key)], block).await?;
         Ok(rs.into_iter().next().unwrap())
     }
 
+    /// Retrieve the query results for the given parameter value, but only if they are already
+    /// materialized.
+    ///
+    /// Unlike a non-blocking `lookup`, a key that is missing from a partially materialized view
+    /// is not replayed; `None` is returned and the view is left as it was. This is useful for
+    /// finding out which keys are cached without changing the answer.
+    pub async fn lookup_if_present(
+        &mut self,
+        key: &[DataType],
+    ) -> Result<Option<Results>, ViewError> {
+        if key.iter().any(DataType::is_none) {
+            // NULL never matches, just like in `lookup`
+            return Ok(Some(Results::new(Vec::new(), Arc::clone(&self.columns))));
+        }
+        let key = self.coerce_key(Vec::from(key))?;
+
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shard = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let reply = self.shards[shard]
+            .call(Tagged::from(ReadQuery::Peek {
+                target: (self.node, shard),
+                key,
+            }))
+            .await?;
+        match reply.v {
+            ReadReply::Peek(Ok(rows)) => {
+                Ok(rows.map(|rows| Results::new(rows, Arc::clone(&self.columns))))
+            }
+            ReadReply::Peek(Err(())) => Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn lookup_prefix(
+        &mut self,
+        prefix: &[DataType],
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        let prefix = self.coerce_key(Vec::from(prefix))?;
+        if prefix.iter().any(DataType::is_none) {
+            // NULL never matches, just like for full keys
+            return Ok(Results::new(Vec::new(), Arc::clone(&self.columns)));
+        }
+
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let node = self.node;
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .map(|(shardi, shard)| {
+                shard.call(Tagged::from(ReadQuery::Prefix {
+                    target: (node, shardi),
+                    prefix: prefix.clone(),
+                    block,
+                }))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut rows = Vec::new();
+        while let Some(reply) = rsps.next().await.transpose()? {
+            match reply.v {
+                ReadReply::Prefix(Ok(Some(rs))) => rows.extend(rs.into_iter().flatten()),
+                ReadReply::Prefix(Ok(None)) => return Err(ViewError::NotPrefixIndexed),
+                ReadReply::Prefix(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Results::new(rows, Arc::clone(&self.columns)))
+    }
+
+    /// Retrieve the number of rows for the given parameter value.
+    ///
+    /// The rows are counted by the reader, so they are not sent over the network. Like
+    /// `lookup`, this triggers a replay if the key is missing, and only waits for it to complete
+    /// if `block` is `true`. Missing keys count as zero rows otherwise.
+    pub async fn count(&mut self, key: &[DataType], block: bool) -> Result<usize, ViewError> {
+        let key = self.coerce_key(Vec::from(key))?;
+        if key.iter().any(DataType::is_none) {
+            // NULL never matches, just like in `lookup`
+            return Ok(0);
+        }
+
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shard = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let reply = self.shards[shard]
+            .call(Tagged::from(ReadQuery::Count {
+                target: (self.node, shard),
+                key,
+                block,
+            }))
+            .await?;
+        match reply.v {
+            ReadReply::Count(Ok(n)) => Ok(n),
+            ReadReply::Count(Err(())) => Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Retrieve the first `n` rows for the given parameter value, ordered by column `order_col`.
+    ///
+    /// The rows are ordered and cut down by the reader, so only the `n` rows are sent over the
+    /// network. Rows with the same value in `order_col` are ordered by their remaining columns,
+    /// so repeated calls return them in the same order. Like a blocking `lookup`, this triggers a
+    /// replay if the key is missing, and waits for it to complete.
+    ///
+    /// Fails with `ViewError::NoSuchColumn` if the view has no column `order_col`.
+    pub async fn top_n(
+        &mut self,
+        key: &[DataType],
+        order_col: usize,
+        n: usize,
+        descending: bool,
+    ) -> Result<Vec<Row>, ViewError> {
+        if order_col >= self.columns.len() {
+            return Err(ViewError::NoSuchColumn(order_col));
+        }
+        if key.iter().any(DataType::is_none) {
+            // NULL never matches, just like in `lookup`
+            return Ok(Vec::new());
+        }
+        let key = self.coerce_key(Vec::from(key))?;
+
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let shard = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let reply = self.shards[shard]
+            .call(Tagged::from(ReadQuery::TopN {
+                target: (self.node, shard),
+                key,
+                order_col,
+                n,
+                descending,
+            }))
+            .await?;
+        match reply.v {
+            ReadReply::Normal(Ok(mut rows)) => {
+                Ok(Results::new(rows.pop().unwrap(), Arc::clone(&self.columns))
+                    .into_iter()
+                    .collect())
+            }
+            ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Send every row for the given parameter value to `sink`, a page at a time.
+    ///
+    /// Only one page of rows is read from the view at a time, so keys with very many rows can be
+    /// consumed without holding all of them in memory. Rows are sent ordered by all their columns.
+    /// Like a blocking `lookup`, this triggers a replay if the key is missing, and waits for it to
+    /// complete. Each page is read separately, so rows written while the stream is in progress
+    /// may or may not be included. `sink` is dropped once all the rows have been sent, or as soon
+    /// as its receiver is dropped.
+    pub async fn lookup_stream(
+        &mut self,
+        key: &[DataType],
+        mut sink: tokio::sync::mpsc::Sender<Row>,
+    ) -> Result<(), ViewError> {
+        if key.iter().any(DataType::is_none) {
+            // NULL never matches, just like in `lookup`
+            return Ok(());
+        }
+        let key = self.coerce_key(Vec::from(key))?;
+
+        let shard = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let mut after: Option<(Vec<DataType>, usize)> = None;
+        loop {
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            let reply = self.shards[shard]
+                .call(Tagged::from(ReadQuery::Page {
+                    target: (self.node, shard),
+                    key: key.clone(),
+                    after: after.clone(),
+                    n: STREAM_PAGE_SIZE,
+                }))
+                .await?;
+            let rows = match reply.v {
+                ReadReply::Normal(Ok(mut rows)) => rows.pop().unwrap(),
+                ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            };
+
+            let done = rows.len() < STREAM_PAGE_SIZE;
+            if let Some(last) = rows.last() {
+                let copies = rows.iter().rev().take_while(|&r| r == last).count();
+                after = match after {
+                    Some((prev, seen)) if prev == *last => Some((prev, seen + copies)),
+                    _ => Some((last.clone(), copies)),
+                };
+            }
+
+            for row in Results::new(rows, Arc::clone(&self.columns)) {
+                if sink.send(row).await.is_err() {
+                    // no one is listening any more
+                    return Ok(());
+                }
+            }
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Retrieve all rows whose key falls in the half-open range `[start, end)`, ordered by key.
+    ///
+    /// Keys are compared column by column, with NULL sorting before every other value.
+    ///
+    /// Readers keep no ordered index, so each shard scans all of its keys to answer the query, and
+    /// the cost grows with the size of the view rather than that of the range. For the same
+    /// reason, only fully materialized views can answer range queries; partially materialized
+    /// ones return `ViewError::NotRangeIndexed`.
+    ///
+    /// If `block` is `true` and the view is not yet ready to be read, this waits until it is.
+    /// Otherwise, `ViewError::NotYetAvailable` is returned.
+    pub async fn lookup_range(
+        &mut self,
+        start: &[DataType],
+        end: &[DataType],
+        block: bool,
+    ) -> Result<Vec<Row>, ViewError> {
+        let mut hits = Vec::new();
+        'retry: loop {
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+            let node = self.node;
+            let mut rsps = self
+                .shards
+                .iter_mut()
+                .enumerate()
+                .map(|(shardi, shard)| {
+                    shard.call(Tagged::from(ReadQuery::Range {
+                        target: (node, shardi),
+                        start: Vec::from(start),
+                        end: Vec::from(end),
+                    }))
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            hits.clear();
+            while let Some(reply) = rsps.next().await.transpose()? {
+                match reply.v {
+                    ReadReply::Range(Ok(Some(rows))) => hits.extend(rows),
+                    ReadReply::Range(Ok(None)) => return Err(ViewError::NotRangeIndexed),
+                    ReadReply::Range(Err(())) if block => {
+                        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+                        continue 'retry;
+                    }
+                    ReadReply::Range(Err(())) => return Err(ViewError::NotYetAvailable),
+                    _ => unreachable!(),
+                }
+            }
+            break;
+        }
+
+        // each shard holds a different subset of the keys
+        hits.sort_by(|a, b| a.0.cmp(&b.0));
+        let rows = hits.into_iter().flat_map(|(_, rows)| rows).collect();
+        Ok(Results::new(rows, Arc::clone(&self.columns))
+            .into_iter()
+            .collect())
+    }
+
+    /// Retrieve every row currently materialized in this view, in no particular order.
+    ///
+    /// Each shard is read from a single, consistent version of its state. For partially
+    /// materialized views, only the keys that happen to be materialized are returned, and no
+    /// replays are triggered for the rest.
+    pub async fn dump(&mut self) -> Result<Vec<Row>, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let node = self.node;
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .map(|(shardi, shard)| {
+                shard.call(Tagged::from(ReadQuery::All {
+                    target: (node, shardi),
+                }))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut rows = Vec::new();
+        while let Some(reply) = rsps.next().await.transpose()? {
+            match reply.v {
+                ReadReply::All(Ok(rs)) => rows.extend(rs.into_iter().flatten()),
+                ReadReply::All(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Results::new(rows, Arc::clone(&self.columns))
+            .into_iter()
+            .collect())
+    }
+
     /// Retrieve the first query result for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
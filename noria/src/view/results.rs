@@ -331,4 +331,31 @@ impl Row {
         let index = self.columns.iter().position(|col| col == field)?;
         Some(std::mem::replace(&mut self.row[index], DataType::None))
     }
+
+    /// Remove the value for the field of the result by the given name, leaving `DataType::None`
+    /// in its place.
+    ///
+    /// Returns `None` if the given field does not exist, so that callers can report a missing
+    /// field as an error rather than unwrap the result of [`Row::take`].
+    pub fn try_take(&mut self, field: &str) -> Option<DataType> {
+        self.take(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_take() {
+        let columns: Arc<[String]> = Arc::from(vec!["id".to_owned(), "story_id".to_owned()]);
+        let mut row = Row::new(vec![1.into(), 2.into()], &columns);
+
+        assert_eq!(row.try_take("votes"), None);
+        assert_eq!(row.try_take("story_id"), Some(2.into()));
+        assert_eq!(row.take("id"), Some(1.into()));
+
+        // taken fields are left behind as NULL
+        assert_eq!(row, vec![DataType::None, DataType::None]);
+    }
 }
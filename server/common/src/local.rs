@@ -5,15 +5,31 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Link {
     pub src: LocalNodeIndex,
     pub dst: LocalNodeIndex,
+    /// Position of the packet among those the sending replica has sent along this link, if it
+    /// was sent to another replica.
+    ///
+    /// The receiving end uses it to check that packets along a link are neither lost nor
+    /// reordered. It is transport bookkeeping, and is ignored when comparing links.
+    pub seq: Option<u64>,
 }
 
 impl Link {
     pub fn new(src: LocalNodeIndex, dst: LocalNodeIndex) -> Self {
-        Link { src, dst }
+        Link {
+            src,
+            dst,
+            seq: None,
+        }
+    }
+}
+
+impl PartialEq for Link {
+    fn eq(&self, other: &Self) -> bool {
+        self.src == other.src && self.dst == other.dst
     }
 }
 
@@ -1,5 +1,8 @@
 use noria::DataType;
 use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter;
 use std::ops::{Deref, DerefMut};
 
 /// A record is a single positive or negative data record with an associated time stamp.
@@ -136,6 +139,82 @@ impl Records {
     {
         self.has(q, false)
     }
+
+    /// Count the number of positive and negative records, returned as `(inserts, deletes)`.
+    pub fn polarity_counts(&self) -> (usize, usize) {
+        self.iter().fold((0, 0), |(pos, neg), r| {
+            if r.is_positive() {
+                (pos + 1, neg)
+            } else {
+                (pos, neg + 1)
+            }
+        })
+    }
+
+    /// Compare the net effect of these records with that of `other`.
+    ///
+    /// Each positive record adds a copy of its row and each negative record takes one away, so
+    /// records that cancel each other out, or that come in a different order, make no difference.
+    pub fn diff(&self, other: &Records) -> RecordsDiff {
+        let mut net = BTreeMap::new();
+        for (rs, sign) in &[(self, -1), (other, 1)] {
+            for r in rs.iter() {
+                let n = net.entry(r.rec()).or_insert(0isize);
+                if r.is_positive() {
+                    *n += sign;
+                } else {
+                    *n -= sign;
+                }
+            }
+        }
+
+        let mut diff = RecordsDiff::default();
+        for (row, n) in net {
+            let rows = if n > 0 {
+                &mut diff.added
+            } else {
+                &mut diff.removed
+            };
+            rows.extend(iter::repeat(row.to_vec()).take(n.abs() as usize));
+        }
+        diff
+    }
+}
+
+/// The rows by which the net effect of one set of records differs from another's.
+///
+/// See `Records::diff`. Rows appear once for every copy by which they differ, in sorted order.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct RecordsDiff {
+    /// Rows the other records leave more copies of.
+    pub added: Vec<Vec<DataType>>,
+    /// Rows the other records leave fewer copies of.
+    pub removed: Vec<Vec<DataType>>,
+}
+
+impl RecordsDiff {
+    /// Returns true if both sets of records have the same net effect.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for RecordsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &(sign, ref rows) in &[('-', &self.removed), ('+', &self.added)] {
+            for row in rows.iter() {
+                write!(f, "{} [", sign)?;
+                for (i, v) in row.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                writeln!(f, "]")?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Deref for Records {
@@ -174,3 +253,63 @@ impl Into<Records> for Vec<(Vec<DataType>, bool)> {
         Records(self.into_iter().map(Into::into).collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polarity_counts() {
+        assert_eq!(Records::default().polarity_counts(), (0, 0));
+
+        let rs: Records = vec![
+            (vec![1.into()], true),
+            (vec![2.into()], false),
+            (vec![3.into()], true),
+        ]
+        .into();
+        assert_eq!(rs.polarity_counts(), (2, 1));
+    }
+
+    #[test]
+    fn diff() {
+        let row = |i: i32, s: &str| -> Vec<DataType> { vec![i.into(), s.into()] };
+        let expected: Records = vec![
+            (row(1, "a"), true),
+            (row(2, "b"), true),
+            (row(3, "c"), false),
+        ]
+        .into();
+
+        // the same net effect, in a different order and with a record that is cancelled out
+        let same: Records = vec![
+            (row(5, "e"), true),
+            (row(3, "c"), false),
+            (row(2, "b"), true),
+            (row(5, "e"), false),
+            (row(1, "a"), true),
+        ]
+        .into();
+        assert!(expected.diff(&same).is_empty());
+        assert_eq!(expected.diff(&same).to_string(), "");
+
+        // one copy of [1, "a"] too many, and a deletion of [4, "d"] that shouldn't be there
+        let actual: Records = vec![
+            (row(2, "b"), true),
+            (row(1, "a"), true),
+            (row(1, "a"), true),
+            (row(3, "c"), false),
+            (row(4, "d"), false),
+        ]
+        .into();
+        let diff = expected.diff(&actual);
+        assert_eq!(diff.added, vec![row(1, "a")]);
+        assert_eq!(diff.removed, vec![row(4, "d")]);
+        assert_eq!(diff.to_string(), "- [4, \"d\"]\n+ [1, \"a\"]\n");
+
+        // and the other way around
+        let diff = actual.diff(&expected);
+        assert_eq!(diff.added, vec![row(4, "d")]);
+        assert_eq!(diff.removed, vec![row(1, "a")]);
+    }
+}
@@ -3,7 +3,11 @@ use common::SizeOf;
 use fnv::FnvBuildHasher;
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
@@ -63,26 +67,211 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    // only a key of several columns has prefixes to look up
+    let prefixes = if key.len() > 1 {
+        Some(Arc::new(PrefixIndex::new(trigger.is_some())))
+    } else {
+        None
+    };
+
+    let rebuilding = Arc::new(AtomicBool::new(false));
     let w = WriteHandle {
         partial: trigger.is_some(),
+        rebuilding: rebuilding.clone(),
+        prefixes: prefixes.clone(),
         handle: w,
         key: Vec::from(key),
         cols,
         contiguous,
         mem_size: 0,
+        applied_offset: None,
+        unrecorded: false,
+        emptied: Vec::new(),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
+        rebuilding,
+        prefixes,
         key: Vec::from(key),
     };
 
     (r, w)
 }
 
+/// The keys that start with each prefix of a key of several columns.
+///
+/// Without these, a lookup on a prefix would have to go through every key in the map. Keeping
+/// them costs every write, though, so they are only kept once a prefix lookup has happened.
+struct PrefixIndex {
+    /// Set by the first prefix lookup. Until then, writes leave `prefixes` alone.
+    wanted: AtomicBool,
+    prefixes: RwLock<Prefixes>,
+}
+
+impl PrefixIndex {
+    fn new(partial: bool) -> Self {
+        PrefixIndex {
+            wanted: AtomicBool::new(false),
+            prefixes: RwLock::new(Prefixes {
+                partial,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The prefixes to keep up to date, if a prefix lookup has asked for them to be kept.
+    fn kept(&self) -> Option<RwLockWriteGuard<'_, Prefixes>> {
+        if self.wanted.load(Ordering::SeqCst) {
+            Some(self.prefixes.write().unwrap())
+        } else {
+            None
+        }
+    }
+
+    /// Start keeping the keys of fully materialized state, listing those already in `map`.
+    fn build(&self, map: &multir::Handle) -> RwLockReadGuard<'_, Prefixes> {
+        if !self.wanted.load(Ordering::SeqCst) {
+            self.wanted.store(true, Ordering::SeqCst);
+        }
+        let built = self.prefixes.read().unwrap().built;
+        if !built {
+            let mut prefixes = self.prefixes.write().unwrap();
+            if !prefixes.built {
+                // the writer may already have added keys that aren't visible yet, so keep those
+                for key in map.keys() {
+                    prefixes.insert(&key);
+                }
+                prefixes.built = true;
+            }
+        }
+        self.prefixes.read().unwrap()
+    }
+
+    /// Bytes held by the kept keys.
+    fn size(&self) -> u64 {
+        self.prefixes.read().unwrap().size
+    }
+}
+
+fn key_size(key: &[DataType]) -> u64 {
+    mem::size_of::<Vec<DataType>>() as u64 + key.iter().map(SizeOf::deep_size_of).sum::<u64>()
+}
+
+/// The keys known for each prefix, once kept.
+#[derive(Default)]
+struct Prefixes {
+    /// Known keys, by each of their prefixes.
+    keys: HashMap<Vec<DataType>, HashSet<Vec<DataType>>>,
+    /// Bytes held by `keys`.
+    size: u64,
+    /// Whether the state is partially materialized.
+    partial: bool,
+    /// In fully materialized state, whether `keys` lists every key in the map.
+    built: bool,
+    /// Prefixes for which `keys` lists every key that exists upstream.
+    ///
+    /// Only used in partial state, where a key is only known once it has been filled or its rows
+    /// have been seen, and so some keys for a prefix may not be known at all.
+    complete: HashSet<Vec<DataType>>,
+    /// Prefixes whose keys have been asked for, but not yet been listed.
+    pending: HashSet<Vec<DataType>>,
+    /// Set if upstream cannot list the keys for a prefix.
+    unsupported: bool,
+}
+
+impl Prefixes {
+    /// Whether the keys that start with `prefix` are kept.
+    ///
+    /// Fully materialized state keeps them all. Partial state only keeps the keys of prefixes
+    /// that have been or are being listed, since any other prefix is listed afresh when it is
+    /// looked up.
+    fn tracks(&self, prefix: &[DataType]) -> bool {
+        !self.partial || self.complete.contains(prefix) || self.pending.contains(prefix)
+    }
+
+    fn insert(&mut self, key: &[DataType]) {
+        for i in 1..key.len() {
+            let prefix = &key[..i];
+            if !self.tracks(prefix) {
+                continue;
+            }
+            if let Some(keys) = self.keys.get_mut(prefix) {
+                if !keys.contains(key) {
+                    keys.insert(Vec::from(key));
+                    self.size += key_size(key);
+                }
+            } else {
+                let mut keys = HashSet::new();
+                keys.insert(Vec::from(key));
+                self.keys.insert(Vec::from(prefix), keys);
+                self.size += key_size(prefix) + key_size(key);
+            }
+        }
+    }
+
+    /// Stop listing `key`, since it no longer exists.
+    fn forget(&mut self, key: &[DataType]) {
+        for i in 1..key.len() {
+            let prefix = &key[..i];
+            if let Some(keys) = self.keys.get_mut(prefix) {
+                if keys.remove(key) {
+                    self.size -= key_size(key);
+                }
+                if keys.is_empty() {
+                    self.keys.remove(prefix);
+                    self.size -= key_size(prefix);
+                }
+            }
+        }
+    }
+
+    /// Stop listing `key`, which may still exist upstream.
+    fn remove(&mut self, key: &[DataType]) {
+        self.forget(key);
+        for i in 1..key.len() {
+            let prefix = &key[..i];
+            if self.complete.remove(prefix) && !self.pending.contains(prefix) {
+                // the prefix has to be listed again before it can be looked up, and that will
+                // list the rest of its keys too
+                if let Some(keys) = self.keys.remove(prefix) {
+                    self.size -= key_size(prefix) + keys.iter().map(|k| key_size(k)).sum::<u64>();
+                }
+            }
+        }
+    }
+
+    /// List exactly the keys in `keys`.
+    fn rebuild(&mut self, keys: Vec<Vec<DataType>>) {
+        self.keys.clear();
+        self.size = 0;
+        for key in keys {
+            self.insert(&key);
+        }
+        self.built = true;
+    }
+}
+
 mod multir;
 mod multiw;
 
+/// Pass the records of every entry in `map` to `then`.
+///
+/// All entries are read from the same version of the map, so concurrent writes are either fully
+/// included or not at all. Fails if the map has been destroyed.
+fn find_all_and<K, F, T>(
+    map: &evmap::ReadHandle<K, Vec<DataType>, i64, FnvBuildHasher>,
+    mut then: F,
+) -> Result<Vec<T>, ()>
+where
+    K: Eq + Hash,
+    F: FnMut(&evmap::Values<Vec<DataType>, FnvBuildHasher>) -> T,
+{
+    let map = map.read();
+    map.meta().ok_or(())?;
+    Ok(map.iter().map(|(_, rs)| then(rs)).collect())
+}
+
 fn key_to_single(k: Key) -> Cow<DataType> {
     assert_eq!(k.len(), 1);
     match k {
@@ -111,6 +300,13 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    applied_offset: Option<u64>,
+    rebuilding: Arc<AtomicBool>,
+    prefixes: Option<Arc<PrefixIndex>>,
+    /// Whether records were added since the last swap without their keys being kept.
+    unrecorded: bool,
+    /// Keys that lost records since the last swap, and may have no records left.
+    emptied: Vec<Vec<DataType>>,
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -130,6 +326,9 @@ impl<'a> MutWriteHandleEntry<'a> {
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            if let Some(mut prefixes) = self.handle.prefixes.as_ref().and_then(|p| p.kept()) {
+                prefixes.insert(&self.key);
+            }
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -146,6 +345,9 @@ impl<'a> MutWriteHandleEntry<'a> {
             .map(|r| r.0.unwrap_or(0))
             .unwrap_or(0);
         self.handle.mem_size = self.handle.mem_size.checked_sub(size as usize).unwrap();
+        if let Some(mut prefixes) = self.handle.prefixes.as_ref().and_then(|p| p.kept()) {
+            prefixes.remove(&self.key);
+        }
         self.handle.handle.empty(self.key)
     }
 }
@@ -230,6 +432,24 @@ impl WriteHandle {
 
     pub(crate) fn swap(&mut self) {
         self.handle.refresh();
+
+        if let Some(ref index) = self.prefixes {
+            if let Some(mut prefixes) = index.kept() {
+                if self.unrecorded && !prefixes.partial {
+                    // the first prefix lookup may have listed the keys before these records were
+                    // visible, so list them all again
+                    prefixes.rebuild(self.handle.keys());
+                }
+                for key in self.emptied.drain(..) {
+                    if let Some((None, _)) =
+                        self.handle.meta_get_and(Cow::Borrowed(&key[..]), |_| ())
+                    {
+                        prefixes.forget(&key);
+                    }
+                }
+            }
+            self.unrecorded = false;
+        }
     }
 
     /// Add a new set of records to the backlog.
@@ -239,7 +459,25 @@ impl WriteHandle {
     where
         I: IntoIterator<Item = Record>,
     {
-        let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
+        let mem_delta = match self.prefixes.as_ref().and_then(|p| p.kept()) {
+            Some(mut prefixes) => {
+                let (key, contiguous) = (&self.key[..], self.contiguous);
+                let emptied = &mut self.emptied;
+                let rs = rs.into_iter().inspect(|r| match *r {
+                    Record::Positive(ref r) => {
+                        prefixes.insert(&key_from_record(key, contiguous, &r[..]));
+                    }
+                    Record::Negative(ref r) => {
+                        emptied.push(key_from_record(key, contiguous, &r[..]).into_owned());
+                    }
+                });
+                self.handle.add(key, self.cols, rs)
+            }
+            None => {
+                self.unrecorded = true;
+                self.handle.add(&self.key[..], self.cols, rs)
+            }
+        };
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
         } else if mem_delta < 0 {
@@ -250,10 +488,112 @@ impl WriteHandle {
         }
     }
 
+    /// Record that updates up to source offset `offset` have been added to the backlog.
+    ///
+    /// The highest offset seen so far is stored in the map's meta value, and so is made visible
+    /// to readers together with the records by the next call to `swap()`.
+    pub(crate) fn applied(&mut self, offset: u64) {
+        if self.applied_offset.map(|o| o >= offset).unwrap_or(false) {
+            return;
+        }
+        debug_assert!(offset <= i64::max_value() as u64);
+        self.applied_offset = Some(offset);
+        self.handle.set_meta(offset as i64);
+    }
+
     pub(crate) fn is_partial(&self) -> bool {
         self.partial
     }
 
+    /// Note that `record` exists upstream, even though its key is a hole.
+    ///
+    /// Its key is then listed for its prefixes, so that prefix lookups know to replay it.
+    pub(crate) fn saw_hole(&mut self, record: &[DataType]) {
+        if let Some(mut prefixes) = self.prefixes.as_ref().and_then(|p| p.kept()) {
+            let key = key_from_record(&self.key[..], self.contiguous, record);
+            prefixes.insert(&key);
+        }
+    }
+
+    /// Start listing the keys that start with `prefix`.
+    ///
+    /// Returns `false` if they are already listed, are being listed, or cannot be listed. Either
+    /// way, `prefix_keys` then returns the keys once they have been listed.
+    pub(crate) fn start_prefix(&mut self, prefix: &[DataType]) -> bool {
+        match self.prefixes {
+            Some(ref index) => {
+                index.wanted.store(true, Ordering::SeqCst);
+                let mut prefixes = index.prefixes.write().unwrap();
+                if prefixes.unsupported || prefixes.complete.contains(prefix) {
+                    return false;
+                }
+                prefixes.pending.insert(Vec::from(prefix))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns every key that starts with `prefix`, if they have all been listed.
+    pub(crate) fn prefix_keys(&self, prefix: &[DataType]) -> Option<Vec<Vec<DataType>>> {
+        let prefixes = self.prefixes.as_ref()?.prefixes.read().unwrap();
+        if self.partial && !prefixes.complete.contains(prefix) {
+            return None;
+        }
+        Some(
+            prefixes
+                .keys
+                .get(prefix)
+                .map(|keys| keys.iter().cloned().collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// List the keys that exist upstream for a prefix passed to `start_prefix`.
+    ///
+    /// `None` means upstream could not list them, and prefix lookups are then no longer
+    /// supported.
+    pub(crate) fn fill_prefix(&mut self, prefix: &[DataType], keys: Option<Vec<Vec<DataType>>>) {
+        if let Some(ref index) = self.prefixes {
+            let mut prefixes = index.prefixes.write().unwrap();
+            if !prefixes.pending.remove(prefix) {
+                return;
+            }
+            match keys {
+                Some(keys) => {
+                    prefixes.complete.insert(Vec::from(prefix));
+                    for key in keys {
+                        prefixes.insert(&key);
+                    }
+                }
+                None => prefixes.unsupported = true,
+            }
+        }
+    }
+
+    /// Returns every key visible to readers as of the last `swap()`.
+    pub(crate) fn keys(&self) -> Vec<Vec<DataType>> {
+        self.handle.keys()
+    }
+
+    /// Mark whether the state is being rebuilt from scratch.
+    ///
+    /// While it is, readers see every key as a hole, even in fully materialized state, so that
+    /// blocking lookups wait for the rebuilt rows instead of seeing a half-built view.
+    pub(crate) fn set_rebuilding(&mut self, rebuilding: bool) {
+        self.rebuilding.store(rebuilding, Ordering::SeqCst);
+    }
+
+    /// Find all entries visible as of the last `swap()`.
+    ///
+    /// The records of each entry are passed to `then` before being returned. For partially
+    /// materialized state, only keys that have been filled are included.
+    pub(crate) fn try_find_all_and<F, T>(&self, then: F) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        self.handle.find_all_and(then)
+    }
+
     /// Evict `count` randomly selected keys from state and return them along with the number of
     /// bytes that will be freed once the underlying `evmap` applies the operation.
     pub(crate) fn evict_random_key(&mut self, rng: &mut ThreadRng) -> u64 {
@@ -265,9 +605,12 @@ impl WriteHandle {
 
             match self.handle.empty_at_index(rng.gen()) {
                 None => (),
-                Some(vs) => {
+                Some((key, vs)) => {
                     let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
                     bytes_to_be_freed += size;
+                    if let Some(mut prefixes) = self.prefixes.as_ref().and_then(|p| p.kept()) {
+                        prefixes.remove(&key);
+                    }
                 }
             }
             self.mem_size = self
@@ -287,7 +630,7 @@ impl SizeOf for WriteHandle {
     }
 
     fn deep_size_of(&self) -> u64 {
-        self.mem_size as u64
+        self.mem_size as u64 + self.prefixes.as_ref().map(|p| p.size()).unwrap_or(0)
     }
 }
 
@@ -297,14 +640,23 @@ pub struct SingleReadHandle {
     handle: multir::Handle,
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
     key: Vec<usize>,
+    rebuilding: Arc<AtomicBool>,
+    prefixes: Option<Arc<PrefixIndex>>,
 }
 
 impl SingleReadHandle {
     /// Trigger a replay of a missing key from a partially materialized view.
+    ///
+    /// While fully materialized state is being rebuilt, its missing keys are already on their
+    /// way, so there is nothing to trigger.
     pub fn trigger<'a, I>(&self, keys: I) -> bool
     where
         I: Iterator<Item = &'a [DataType]>,
     {
+        if self.trigger.is_none() && self.rebuilding.load(Ordering::SeqCst) {
+            return true;
+        }
+
         assert!(
             self.trigger.is_some(),
             "tried to trigger a replay for a fully materialized view"
@@ -323,11 +675,16 @@ impl SingleReadHandle {
     /// Note that not all writes will be included with this read -- only those that have been
     /// swapped in by the writer.
     ///
-    /// Holes in partially materialized state are returned as `Ok((None, _))`.
+    /// Holes in partially materialized state are returned as `Ok((None, _))`, as is every key of
+    /// state that is being rebuilt.
     pub fn try_find_and<F, T>(&self, key: &[DataType], mut then: F) -> Result<(Option<T>, i64), ()>
     where
         F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
     {
+        if self.rebuilding.load(Ordering::SeqCst) {
+            return self.handle.meta().ok_or(()).map(|meta| (None, meta));
+        }
+
         self.handle
             .meta_get_and(key, &mut then)
             .ok_or(())
@@ -339,10 +696,111 @@ impl SingleReadHandle {
             })
     }
 
+    /// Find all entries whose key falls in the half-open range `[start, end)`.
+    ///
+    /// Returned records are passed to `then` before being returned, along with their key. Every
+    /// key in the map is looked at to find the ones in the range.
+    ///
+    /// Partially materialized state may have holes anywhere in the range, so it cannot answer
+    /// range queries, and `Ok(None)` is returned.
+    pub fn try_find_range_and<F, T>(
+        &self,
+        start: &[DataType],
+        end: &[DataType],
+        then: F,
+    ) -> Result<Option<Vec<(Vec<DataType>, T)>>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        if self.trigger.is_some() {
+            return Ok(None);
+        }
+
+        self.handle
+            .meta_range_and(start, end, then)
+            .ok_or(())
+            .map(|(records, _)| Some(records))
+    }
+
+    /// Returns whether lookups on `prefix` can be answered.
+    ///
+    /// Only proper, non-empty prefixes of a key of several columns can be looked up. Partially
+    /// materialized state can only answer them if the keys for a prefix can be listed upstream.
+    pub fn supports_prefix(&self, prefix: &[DataType]) -> bool {
+        match self.prefixes {
+            Some(ref index) if !prefix.is_empty() && prefix.len() < self.key.len() => {
+                !index.prefixes.read().unwrap().unsupported
+            }
+            _ => false,
+        }
+    }
+
+    /// Find all entries whose key starts with the given columns.
+    ///
+    /// The records of each entry are passed to `then` before being returned.
+    ///
+    /// In partially materialized state, `Ok(None)` is returned if not every key for the prefix
+    /// is known to be filled. `trigger` then lists the keys, and replays the missing ones. As
+    /// with `try_find_and`, `Ok(None)` is also returned while the state is being rebuilt.
+    pub fn try_find_prefix_and<F, T>(
+        &self,
+        prefix: &[DataType],
+        mut then: F,
+    ) -> Result<Option<Vec<T>>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        self.handle.meta().ok_or(())?;
+        let prefixes = match self.prefixes {
+            Some(ref index) if !self.rebuilding.load(Ordering::SeqCst) => {
+                if self.trigger.is_some() {
+                    // partial state lists the keys of a prefix when it is first looked up
+                    index.prefixes.read().unwrap()
+                } else {
+                    index.build(&self.handle)
+                }
+            }
+            _ => return Ok(None),
+        };
+        if self.trigger.is_some() && !prefixes.complete.contains(prefix) {
+            return Ok(None);
+        }
+
+        let mut records = Vec::new();
+        for key in prefixes.keys.get(prefix).into_iter().flatten() {
+            match self.handle.meta_get_and(key, &mut then) {
+                None => return Err(()),
+                Some((Some(rs), _)) => records.push(rs),
+                Some((None, _)) if self.trigger.is_some() => return Ok(None),
+                Some((None, _)) => {
+                    // not yet visible to readers, or all its records have been removed
+                }
+            }
+        }
+        Ok(Some(records))
+    }
+
+    /// Find all entries currently materialized.
+    ///
+    /// The records of each entry are passed to `then` before being returned. For partially
+    /// materialized state, only keys that have been filled are included.
+    pub fn try_find_all_and<F, T>(&self, then: F) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        self.handle.find_all_and(then)
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
 
+    /// The highest source offset whose updates are visible to readers, if any write carrying an
+    /// offset has reached this reader.
+    pub fn applied_offset(&self) -> Option<u64> {
+        self.handle.meta().filter(|&m| m >= 0).map(|m| m as u64)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.handle.len() == 0
     }
@@ -551,4 +1009,206 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn find_range() {
+        let (r, mut w) = new(2, &[0]);
+        w.add((0..10).map(|i| Record::Positive(vec![i.into(), "a".into()])));
+        w.swap();
+
+        let mut keys: Vec<_> = r
+            .try_find_range_and(&[3.into()], &[7.into()], |rs| rs.len())
+            .unwrap()
+            .unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            (3..7)
+                .map(|i| (vec![i.into()], 1))
+                .collect::<Vec<(Vec<DataType>, usize)>>()
+        );
+
+        let (r, mut w) = new_partial(2, &[0], |_: &mut dyn Iterator<Item = &[DataType]>| true);
+        w.swap();
+        assert_eq!(
+            r.try_find_range_and(&[3.into()], &[7.into()], |rs| rs.len()),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn find_prefix() {
+        let (r, mut w) = new(3, &[0, 1]);
+        w.add((0..10).map(|i| Record::Positive(vec![(i % 2).into(), i.into(), "a".into()])));
+        w.swap();
+
+        let mut rows: Vec<_> = r
+            .try_find_prefix_and(&[1.into()], |rs| rs.iter().next().unwrap()[1].clone())
+            .unwrap()
+            .unwrap();
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![1.into(), 3.into(), 5.into(), 7.into(), 9.into()] as Vec<DataType>
+        );
+
+        // only proper, non-empty prefixes can be looked up
+        assert!(r.supports_prefix(&[0.into()]));
+        assert!(!r.supports_prefix(&[]));
+        assert!(!r.supports_prefix(&[0.into(), 4.into()]));
+        let (r, _) = new(2, &[0]);
+        assert!(!r.supports_prefix(&[0.into()]));
+    }
+
+    #[test]
+    fn find_prefix_keeps_keys_once_looked_up() {
+        let (r, mut w) = new(3, &[0, 1]);
+        let row = |k: i32| vec![1.into(), k.into(), "a".into()];
+        let prefix_size = |w: &WriteHandle| w.prefixes.as_ref().unwrap().size();
+        let count = |r: &SingleReadHandle| {
+            r.try_find_prefix_and(&[1.into()], |rs| rs.len())
+                .unwrap()
+                .unwrap()
+                .len()
+        };
+
+        // no keys are kept until a prefix is looked up
+        w.add(vec![Record::Positive(row(1))]);
+        w.swap();
+        assert_eq!(prefix_size(&w), 0);
+        assert_eq!(w.deep_size_of(), w.mem_size as u64);
+
+        // a key written before the lookup, but only visible after it, is still found
+        w.add(vec![Record::Positive(row(2))]);
+        assert_eq!(count(&r), 1);
+        w.swap();
+        assert_eq!(count(&r), 2);
+        assert_eq!(w.deep_size_of(), w.mem_size as u64 + prefix_size(&w));
+
+        // a key is no longer kept once its last row is removed
+        let two_keys = prefix_size(&w);
+        w.add(vec![Record::Positive(row(3))]);
+        w.swap();
+        assert_eq!(count(&r), 3);
+        w.add(vec![Record::Negative(row(3))]);
+        w.swap();
+        assert_eq!(count(&r), 2);
+        assert_eq!(prefix_size(&w), two_keys);
+    }
+
+    #[test]
+    fn find_prefix_partial() {
+        let (r, mut w) = new_partial(3, &[0, 1], |_: &mut dyn Iterator<Item = &[DataType]>| true);
+        w.swap();
+        let prefix: [DataType; 1] = [1.into()];
+
+        // nothing is known about the prefix until its keys have been listed
+        assert_eq!(r.try_find_prefix_and(&prefix, |rs| rs.len()), Ok(None));
+        assert_eq!(w.prefix_keys(&prefix), None);
+        assert!(w.start_prefix(&prefix));
+        assert!(!w.start_prefix(&prefix));
+
+        let keys: Vec<Vec<DataType>> = vec![vec![1.into(), 1.into()], vec![1.into(), 3.into()]];
+        w.fill_prefix(&prefix, Some(keys.clone()));
+        assert!(!w.start_prefix(&prefix));
+        let mut listed = w.prefix_keys(&prefix).unwrap();
+        listed.sort();
+        assert_eq!(listed, keys);
+
+        // the listed keys are holes until they are replayed
+        assert_eq!(r.try_find_prefix_and(&prefix, |rs| rs.len()), Ok(None));
+        w.mut_with_key(&keys[0][..]).mark_filled();
+        w.mut_with_key(&keys[1][..]).mark_filled();
+        w.add(vec![Record::Positive(vec![1.into(), 1.into(), "a".into()])]);
+        w.swap();
+        let mut counts = r
+            .try_find_prefix_and(&prefix, |rs| rs.len())
+            .unwrap()
+            .unwrap();
+        counts.sort();
+        assert_eq!(counts, vec![0, 1]);
+
+        // so is a key that shows up later
+        w.saw_hole(&[1.into(), 5.into(), "a".into()]);
+        assert_eq!(w.prefix_keys(&prefix).unwrap().len(), 3);
+        assert_eq!(r.try_find_prefix_and(&prefix, |rs| rs.len()), Ok(None));
+
+        // keys are only kept for prefixes that have been listed
+        let prefix_size = |w: &WriteHandle| w.prefixes.as_ref().unwrap().size();
+        let listed_size = prefix_size(&w);
+        w.saw_hole(&[2.into(), 5.into(), "a".into()]);
+        assert_eq!(prefix_size(&w), listed_size);
+
+        // an evicted key may still exist upstream, so the keys have to be listed again
+        w.mut_with_key(&keys[0][..]).mark_hole();
+        assert_eq!(w.prefix_keys(&prefix), None);
+        assert_eq!(prefix_size(&w), 0);
+        assert!(w.start_prefix(&prefix));
+        w.fill_prefix(&prefix, None);
+        assert!(!r.supports_prefix(&prefix));
+    }
+
+    #[test]
+    fn find_range_with_nulls() {
+        let (r, mut w) = new(2, &[0]);
+        w.add((0..5).map(|i| Record::Positive(vec![i.into(), "a".into()])));
+        w.add(vec![Record::Positive(vec![DataType::None, "a".into()])]);
+        w.swap();
+
+        let range = |start: DataType, end: DataType| {
+            let mut keys: Vec<_> = r
+                .try_find_range_and(&[start], &[end], |rs| rs.len())
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k[0].clone())
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        // NULL sorts before every other value, so only ranges starting at NULL include it
+        assert_eq!(range(1.into(), 3.into()), vec![1.into(), 2.into()]);
+        assert_eq!(
+            range(DataType::None, 2.into()),
+            vec![DataType::None, 0.into(), 1.into()]
+        );
+        assert_eq!(range(DataType::None, DataType::None), vec![]);
+    }
+
+    #[test]
+    fn rebuilding_reads_as_holes() {
+        let a = vec![1.into(), "a".into()];
+
+        let (r, mut w) = new(2, &[0]);
+        w.add(vec![Record::Positive(a.clone())]);
+        w.swap();
+        assert_eq!(w.keys(), vec![vec![1.into()]]);
+
+        w.set_rebuilding(true);
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, None);
+        assert!(r.trigger(std::iter::once(&a[0..1])));
+
+        w.set_rebuilding(false);
+        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()).unwrap().0, Some(1));
+    }
+
+    #[test]
+    fn find_all() {
+        let (r, mut w) = new(2, &[0]);
+        w.add((0..10).map(|i| Record::Positive(vec![(i % 5).into(), i.into()])));
+        w.swap();
+
+        let mut rows: Vec<_> = r
+            .try_find_all_and(|rs| rs.iter().cloned().collect::<Vec<_>>())
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        rows.sort();
+        let mut expected: Vec<Vec<DataType>> =
+            (0..10).map(|i| vec![(i % 5).into(), i.into()]).collect();
+        expected.sort();
+        assert_eq!(rows, expected);
+    }
 }
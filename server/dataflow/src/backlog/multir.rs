@@ -18,6 +18,32 @@ impl Handle {
         }
     }
 
+    /// The keys of all entries currently visible to readers.
+    pub(super) fn keys(&self) -> Vec<Vec<DataType>> {
+        match *self {
+            Handle::Single(ref h) => h.read().iter().map(|(k, _)| vec![k.deep_clone()]).collect(),
+            Handle::Double(ref h) => h
+                .read()
+                .iter()
+                .map(|(k, _)| vec![k.0.deep_clone(), k.1.deep_clone()])
+                .collect(),
+            Handle::Many(ref h) => h
+                .read()
+                .iter()
+                .map(|(k, _)| k.iter().map(DataType::deep_clone).collect())
+                .collect(),
+        }
+    }
+
+    /// The map's meta value as of the last refresh, or `None` if the map has been destroyed.
+    pub(super) fn meta(&self) -> Option<i64> {
+        match *self {
+            Handle::Single(ref h) => h.read().meta().cloned(),
+            Handle::Double(ref h) => h.read().meta().cloned(),
+            Handle::Many(ref h) => h.read().meta().cloned(),
+        }
+    }
+
     pub(super) fn meta_get_and<F, T>(&self, key: &[DataType], then: F) -> Option<(Option<T>, i64)>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
@@ -65,4 +91,69 @@ impl Handle {
             }
         }
     }
+
+    /// Find all entries whose key falls in the half-open range `[start, end)`.
+    ///
+    /// Keys are compared column by column. Matching entries are returned along with their key,
+    /// in no particular order.
+    pub(super) fn meta_range_and<F, T>(
+        &self,
+        start: &[DataType],
+        end: &[DataType],
+        mut then: F,
+    ) -> Option<(Vec<(Vec<DataType>, T)>, i64)>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        match *self {
+            Handle::Single(ref h) => {
+                assert_eq!(start.len(), 1);
+                assert_eq!(end.len(), 1);
+                let map = h.read();
+                let m = map.meta().cloned()?;
+                let vs = map
+                    .iter()
+                    .filter(|&(k, _)| &start[0] <= k && k < &end[0])
+                    .map(|(k, rs)| (vec![k.deep_clone()], then(rs)))
+                    .collect();
+                Some((vs, m))
+            }
+            Handle::Double(ref h) => {
+                assert_eq!(start.len(), 2);
+                assert_eq!(end.len(), 2);
+                let (start, end) = ((&start[0], &start[1]), (&end[0], &end[1]));
+                let map = h.read();
+                let m = map.meta().cloned()?;
+                let vs = map
+                    .iter()
+                    .filter(|&(&(ref k1, ref k2), _)| start <= (k1, k2) && (k1, k2) < end)
+                    .map(|(&(ref k1, ref k2), rs)| {
+                        (vec![k1.deep_clone(), k2.deep_clone()], then(rs))
+                    })
+                    .collect();
+                Some((vs, m))
+            }
+            Handle::Many(ref h) => {
+                let map = h.read();
+                let m = map.meta().cloned()?;
+                let vs = map
+                    .iter()
+                    .filter(|&(k, _)| start <= &k[..] && &k[..] < end)
+                    .map(|(k, rs)| (k.iter().map(DataType::deep_clone).collect(), then(rs)))
+                    .collect();
+                Some((vs, m))
+            }
+        }
+    }
+
+    pub(super) fn find_all_and<F, T>(&self, then: F) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        match *self {
+            Handle::Single(ref h) => super::find_all_and(h, then),
+            Handle::Double(ref h) => super::find_all_and(h, then),
+            Handle::Many(ref h) => super::find_all_and(h, then),
+        }
+    }
 }
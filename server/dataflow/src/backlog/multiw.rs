@@ -51,11 +51,52 @@ impl Handle {
     pub fn empty_at_index(
         &mut self,
         index: usize,
-    ) -> Option<&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>> {
+    ) -> Option<(
+        Vec<DataType>,
+        &evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>,
+    )> {
         match *self {
-            Handle::Single(ref mut h) => h.empty_at_index(index).map(|r| r.1),
-            Handle::Double(ref mut h) => h.empty_at_index(index).map(|r| r.1),
-            Handle::Many(ref mut h) => h.empty_at_index(index).map(|r| r.1),
+            Handle::Single(ref mut h) => h
+                .empty_at_index(index)
+                .map(|(k, vs)| (vec![k.deep_clone()], vs)),
+            Handle::Double(ref mut h) => h
+                .empty_at_index(index)
+                .map(|(k, vs)| (vec![k.0.deep_clone(), k.1.deep_clone()], vs)),
+            Handle::Many(ref mut h) => h
+                .empty_at_index(index)
+                .map(|(k, vs)| (k.iter().map(DataType::deep_clone).collect(), vs)),
+        }
+    }
+
+    /// Returns a copy of every key visible to readers as of the last refresh.
+    pub fn keys(&self) -> Vec<Vec<DataType>> {
+        match *self {
+            Handle::Single(ref h) => h.read().iter().map(|(k, _)| vec![k.deep_clone()]).collect(),
+            Handle::Double(ref h) => h
+                .read()
+                .iter()
+                .map(|(k, _)| vec![k.0.deep_clone(), k.1.deep_clone()])
+                .collect(),
+            Handle::Many(ref h) => h
+                .read()
+                .iter()
+                .map(|(k, _)| k.iter().map(DataType::deep_clone).collect())
+                .collect(),
+        }
+    }
+
+    /// Set the meta value that readers will see after the next refresh.
+    pub fn set_meta(&mut self, meta: i64) {
+        match *self {
+            Handle::Single(ref mut h) => {
+                h.set_meta(meta);
+            }
+            Handle::Double(ref mut h) => {
+                h.set_meta(meta);
+            }
+            Handle::Many(ref mut h) => {
+                h.set_meta(meta);
+            }
         }
     }
 
@@ -182,4 +223,15 @@ impl Handle {
         }
         memory_delta
     }
+
+    pub fn find_all_and<F, T>(&self, then: F) -> Result<Vec<T>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, fnv::FnvBuildHasher>) -> T,
+    {
+        match *self {
+            Handle::Single(ref h) => super::find_all_and(h, then),
+            Handle::Double(ref h) => super::find_all_and(h, then),
+            Handle::Many(ref h) => super::find_all_and(h, then),
+        }
+    }
 }
@@ -12,9 +12,12 @@ use std::time;
 use crate::group_commit::GroupCommitQueueSet;
 use crate::payload::{ControlReplyPacket, ReplayPieceContext, SourceSelection};
 use crate::prelude::*;
+use crate::rate_limit::IngressRateLimit;
 use futures_util::{future::FutureExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
+use noria::debug::stats::{DomainStats, NodeStats};
 pub use noria::internal::DomainIndex as Index;
+use rand::seq::SliceRandom;
 use slog::Logger;
 use stream_cancel::Valve;
 
@@ -39,11 +42,55 @@ pub enum ProcessResult {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Config {
     pub concurrent_replays: usize,
+    pub max_pending_replays: Option<usize>,
     pub replay_batch_timeout: time::Duration,
+    /// check that packets along each link are processed in the order they were sent
+    pub check_link_order: bool,
 }
 
 const BATCH_SIZE: usize = 256;
 
+/// Watches the packets a domain processes, for example to log them or to gather metrics.
+///
+/// `on_receive` is called as the domain starts processing each packet, and `on_complete` once it
+/// is done with it. Packets held back while the domain is paused are only observed when they are
+/// eventually processed.
+pub trait PacketObserver: Send + Sync {
+    /// Called before the domain processes `packet`.
+    fn on_receive(&self, packet: &Packet);
+    /// Called after the domain has processed the packet summarized by `packet`.
+    fn on_complete(&self, packet: &PacketSummary);
+    /// Called before `on_receive`; returning true makes the domain drop `packet` unprocessed.
+    ///
+    /// This lets tests inject faults, such as state that has missed an update.
+    fn drop_packet(&self, _packet: &Packet) -> bool {
+        false
+    }
+}
+
+/// What a `PacketObserver` is told about a packet the domain has processed.
+///
+/// Processing consumes the packet, so only these details are kept from when it was received.
+#[derive(Clone, Copy, Debug)]
+pub struct PacketSummary {
+    /// The link the packet travelled along, if any.
+    pub link: Option<Link>,
+    /// The replay path the packet was part of, if any.
+    pub tag: Option<Tag>,
+    /// Whether the packet was a control packet rather than data.
+    pub control: bool,
+}
+
+impl PacketSummary {
+    fn of(packet: &Packet) -> Self {
+        PacketSummary {
+            link: packet.try_link().cloned(),
+            tag: packet.tag(),
+            control: packet.is_control(),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum DomainMode {
     Forwarding,
@@ -78,6 +125,442 @@ struct ReplayPath {
     path: Vec<ReplayPathSegment>,
     notify_done: bool,
     trigger: TriggerEndpoint,
+    priority: u8,
+}
+
+/// Split a state snapshot into batches for a full replay.
+///
+/// Each batch is handed to `send` along with its sequence number and whether it is the last one.
+/// If `send` returns `false`, the replay is abandoned. After every batch that is sent, the number
+/// of records replayed so far is reported on `progress`.
+fn chunk_replay<F, S>(
+    state: Vec<Vec<DataType>>,
+    fix: F,
+    progress: Option<&channel::ChannelSender<usize>>,
+    mut send: S,
+) where
+    F: Fn(Vec<DataType>) -> Vec<DataType>,
+    S: FnMut(usize, Records, bool) -> bool,
+{
+    use itertools::Itertools;
+    use std::iter::FromIterator;
+
+    let iter = state.into_iter().chunks(BATCH_SIZE);
+    let mut iter = iter.into_iter().enumerate().peekable();
+
+    let mut replayed = 0;
+    while let Some((i, chunk)) = iter.next() {
+        let chunk = Records::from_iter(chunk.map(&fix));
+        let len = chunk.len();
+        let last = iter.peek().is_none();
+        if !send(i, chunk, last) {
+            break;
+        }
+
+        replayed += len;
+        if let Some(progress) = progress {
+            // nobody listening for progress is no reason to stop the replay
+            let _ = progress.send(replayed);
+        }
+    }
+}
+
+/// Packets that could not be handed to another domain, either directly or through the replica's
+/// outboxes, and reader replay requests that were rejected because too many replays were pending.
+#[derive(Debug, Default)]
+struct DroppedPackets {
+    data: u64,
+    control: u64,
+}
+
+impl DroppedPackets {
+    /// Send `m` on `tx`, and count it as dropped if the send fails.
+    fn send<S>(&mut self, tx: &mut S, m: Box<Packet>) -> bool
+    where
+        S: channel::Sender<Item = Box<Packet>> + ?Sized,
+    {
+        let control = m.is_control();
+        if tx.send(m).is_ok() {
+            return true;
+        }
+
+        self.count(control);
+        false
+    }
+
+    fn count(&mut self, control: bool) {
+        if control {
+            self.control += 1;
+        } else {
+            self.data += 1;
+        }
+    }
+}
+
+/// The sequence number of the last packet that arrived along each link, for
+/// `Config::check_link_order`.
+#[derive(Debug, Default)]
+struct LinkOrder {
+    last: HashMap<(LocalNodeIndex, LocalNodeIndex), u64>,
+    /// packets that arrived after one sent later than them
+    misordered: u64,
+}
+
+impl LinkOrder {
+    /// Check that a packet along `link` was sent after the last one to arrive along it.
+    ///
+    /// A misordered packet is a bug, so debug builds panic on one. Release builds count it
+    /// instead. Packets that weren't sent by another replica carry no sequence number, and aren't
+    /// checked.
+    fn saw(&mut self, link: &Link) {
+        let seq = match link.seq {
+            Some(seq) => seq,
+            None => return,
+        };
+        match self.last.insert((link.src, link.dst), seq) {
+            Some(last) if seq <= last => {
+                if cfg!(debug_assertions) {
+                    panic!(
+                        "packet {} along {:?} arrived after packet {}",
+                        seq, link, last
+                    );
+                }
+                self.misordered += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A standing request for statistics snapshots, made with `Packet::SubscribeStatistics`.
+struct StatisticsSubscription<T> {
+    interval: time::Duration,
+    next: time::Instant,
+    sink: channel::ChannelSender<T>,
+}
+
+/// Send a snapshot to every subscription that is due at `now`.
+///
+/// Subscriptions whose sink has been dropped are cancelled.
+fn push_statistics<T, F>(
+    subscriptions: &mut Vec<StatisticsSubscription<T>>,
+    now: time::Instant,
+    mut snapshot: F,
+) where
+    F: FnMut() -> T,
+{
+    let mut i = 0;
+    while i < subscriptions.len() {
+        let sub = &mut subscriptions[i];
+        if sub.next <= now {
+            if sub.sink.send(snapshot()).is_err() {
+                subscriptions.swap_remove(i);
+                continue;
+            }
+            sub.next = now + sub.interval;
+        }
+        i += 1;
+    }
+}
+
+/// How many column changes to remember per node when detecting duplicates.
+const SCHEMA_OP_HISTORY: usize = 32;
+
+/// The most recent column changes applied to a node, identified by their `op_id`.
+#[derive(Debug, Default)]
+struct AppliedSchemaOps(VecDeque<u64>);
+
+impl AppliedSchemaOps {
+    /// Remember that `op_id` has been applied.
+    ///
+    /// Returns `false` if it already had been, in which case it should not be applied again.
+    fn insert(&mut self, op_id: u64) -> bool {
+        if self.0.contains(&op_id) {
+            return false;
+        }
+
+        if self.0.len() == SCHEMA_OP_HISTORY {
+            self.0.pop_front();
+        }
+        self.0.push_back(op_id);
+        true
+    }
+}
+
+/// A node's processing times, in nanoseconds, when its statistics were last reset.
+///
+/// The timers themselves keep running, so reported times are relative to these.
+#[derive(Clone, Copy, Debug, Default)]
+struct StatisticsBaseline {
+    process_time: u64,
+    process_ptime: u64,
+    replay_process_time: u64,
+}
+
+/// Find where an entry with the given priority should go in a queue that is ordered by
+/// descending priority, such that it ends up behind all entries of the same or higher priority.
+fn priority_position<T, F>(queue: &VecDeque<T>, priority: u8, priority_of: F) -> usize
+where
+    F: Fn(&T) -> u8,
+{
+    queue
+        .iter()
+        .position(|e| priority_of(e) < priority)
+        .unwrap_or_else(|| queue.len())
+}
+
+/// Replay requests that are waiting for one of the domain's concurrent replay slots, ordered by
+/// descending replay path priority.
+#[derive(Debug)]
+struct ReplayRequestQueue {
+    queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
+    max: Option<usize>,
+}
+
+impl ReplayRequestQueue {
+    fn new(max: Option<usize>) -> Self {
+        ReplayRequestQueue {
+            queue: VecDeque::new(),
+            max,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// True if the queue holds as many requests as it should.
+    ///
+    /// Requests triggered by readers are rejected while the queue is full. Requests caused by
+    /// misses inside the domain are still queued, since the replays that missed cannot finish
+    /// without them.
+    fn is_full(&self) -> bool {
+        self.max.map(|max| self.queue.len() >= max).unwrap_or(false)
+    }
+
+    /// Queue a request behind all queued requests of the same or higher priority.
+    fn push<F>(&mut self, tag: Tag, keys: Vec<Vec<DataType>>, priority: u8, priority_of: F)
+    where
+        F: Fn(Tag) -> u8,
+    {
+        let i = priority_position(&self.queue, priority, |&(t, _)| priority_of(t));
+        self.queue.insert(i, (tag, keys));
+    }
+
+    fn pop_front(&mut self) -> Option<(Tag, Vec<Vec<DataType>>)> {
+        self.queue.pop_front()
+    }
+
+    /// True if a queued request asks for `key` along `tag`.
+    fn contains(&self, tag: Tag, key: &[DataType]) -> bool {
+        self.queue
+            .iter()
+            .any(|&(t, ref keys)| t == tag && keys.iter().any(|k| &k[..] == key))
+    }
+
+    /// Drop `key` from any queued request along `tag`, and any request left without keys.
+    ///
+    /// Returns true if a queued request for the key was found.
+    fn cancel(&mut self, tag: Tag, key: &[DataType]) -> bool {
+        let mut found = false;
+        self.queue.retain(|&mut (t, ref mut keys)| {
+            if t == tag {
+                let before = keys.len();
+                keys.retain(|k| &k[..] != key);
+                found |= keys.len() != before;
+                !keys.is_empty()
+            } else {
+                true
+            }
+        });
+        found
+    }
+}
+
+/// Check that a replay path sent with `Packet::SetupReplayPath` fits the nodes in this domain.
+///
+/// Each node on the path must be a child of the node before it, and the columns the path is keyed
+/// or triggered on must exist on the nodes they are looked up in. Catching this here gives an
+/// error during the migration rather than a panic in the middle of a later replay.
+fn check_replay_path(
+    nodes: &DomainNodes,
+    source: Option<LocalNodeIndex>,
+    path: &[ReplayPathSegment],
+    trigger: &crate::payload::TriggerEndpoint,
+) -> Result<(), String> {
+    use crate::payload::TriggerEndpoint;
+
+    let in_range = |node: LocalNodeIndex, cols: &[usize]| {
+        let width = nodes[node].borrow().fields().len();
+        match cols.iter().find(|&&c| c >= width) {
+            Some(c) => Err(format!(
+                "column {} out of range for node {:?} with {} columns",
+                c, node, width
+            )),
+            None => Ok(()),
+        }
+    };
+
+    if path.is_empty() {
+        return Err("replay path is empty".to_owned());
+    }
+    if let Some(source) = source {
+        if !nodes.contains_key(source) {
+            return Err(format!("replay source {:?} is not in this domain", source));
+        }
+    }
+    for segment in path {
+        if !nodes.contains_key(segment.node) {
+            return Err(format!(
+                "replay path node {:?} is not in this domain",
+                segment.node
+            ));
+        }
+        if let Some(ref key) = segment.partial_key {
+            in_range(segment.node, key)?;
+        }
+    }
+    for hop in path.windows(2) {
+        if !nodes[hop[0].node]
+            .borrow()
+            .children()
+            .contains(&hop[1].node)
+        {
+            return Err(format!(
+                "replay path is not connected: {:?} is not a child of {:?}",
+                hop[1].node, hop[0].node
+            ));
+        }
+    }
+
+    match *trigger {
+        TriggerEndpoint::Start(ref cols) | TriggerEndpoint::Local(ref cols) => match source {
+            Some(source) => in_range(source, cols),
+            None => Err("replay path is triggered at its start, but has no source".to_owned()),
+        },
+        TriggerEndpoint::End(..) | TriggerEndpoint::None => Ok(()),
+    }
+}
+
+/// Add a full index on `index` to `state`, which holds rows that are `width` columns wide.
+///
+/// Returns `false` if the state was already indexed on those columns.
+fn add_full_index(state: &mut dyn State, width: usize, index: &[usize]) -> Result<bool, String> {
+    if index.is_empty() {
+        return Err("cannot index on no columns".to_owned());
+    }
+    if let Some(c) = index.iter().find(|&&c| c >= width) {
+        return Err(format!(
+            "column {} out of range for state with {} columns",
+            c, width
+        ));
+    }
+    if state.is_partial() {
+        return Err("cannot add a full index to partially materialized state".to_owned());
+    }
+    if state.keys().iter().any(|k| &k[..] == index) {
+        return Ok(false);
+    }
+
+    state.add_key(index, None);
+    Ok(true)
+}
+
+/// Remove the full index on `index` from `state`.
+fn drop_full_index(state: &mut dyn State, index: &[usize]) -> Result<(), String> {
+    if state.is_partial() {
+        return Err("cannot drop an index from partially materialized state".to_owned());
+    }
+    match state.keys().iter().position(|k| &k[..] == index) {
+        None => Err("no such index".to_owned()),
+        Some(0) => Err("the first index holds the rows and cannot be dropped".to_owned()),
+        Some(_) if !state.drop_key(index) => Err("the state cannot drop this index".to_owned()),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Make `new` the parent of `node` in place of `old`.
+///
+/// Both the domain's parent and child links and the operator's references to its ancestors are
+/// moved over, so that `node` treats updates from `new` the way it treated those from `old`.
+fn reparent_node(
+    nodes: &DomainNodes,
+    node: LocalNodeIndex,
+    old: LocalNodeIndex,
+    new: LocalNodeIndex,
+) -> Result<(), String> {
+    for &ni in &[node, old, new] {
+        if !nodes.contains_key(ni) {
+            return Err(format!("no node {} in this domain", ni.id()));
+        }
+    }
+    {
+        let n = nodes[node].borrow();
+        if !n.is_internal() {
+            return Err("only operators can be reparented".to_owned());
+        }
+        if !n.parents().contains(&old) {
+            return Err(format!("{} is not a parent", old.id()));
+        }
+        if n.parents().contains(&new) {
+            return Err(format!("{} is already a parent", new.id()));
+        }
+    }
+    let (old_width, new_width) = (
+        nodes[old].borrow().fields().len(),
+        nodes[new].borrow().fields().len(),
+    );
+    if old_width != new_width {
+        return Err(format!(
+            "new parent has {} columns, but the old one has {}",
+            new_width, old_width
+        ));
+    }
+
+    // the operator refers to its ancestors by index pair, so map each parent to itself, except
+    // for the old parent, which maps to the new one
+    let pair = |ni: LocalNodeIndex| {
+        let mut ip = IndexPair::from(nodes[ni].borrow().global_addr());
+        ip.set_local(ni);
+        ip
+    };
+    let mut remap: HashMap<_, _> = nodes[node]
+        .borrow()
+        .parents()
+        .iter()
+        .map(|&p| (nodes[p].borrow().global_addr(), pair(p)))
+        .collect();
+    remap.insert(nodes[old].borrow().global_addr(), pair(new));
+
+    let mut n = nodes[node].borrow_mut();
+    n.replace_parent(old, new);
+    n.on_commit(&remap);
+    nodes[old].borrow_mut().try_remove_child(node);
+    nodes[new].borrow_mut().add_child(node);
+    Ok(())
+}
+
+/// Take the childless `node` out of the domain, along with its state and its parents' links to it.
+fn remove_node(
+    nodes: &mut DomainNodes,
+    state: &mut StateMap,
+    node: LocalNodeIndex,
+) -> Result<(), String> {
+    if !nodes.contains_key(node) {
+        return Err(format!("no node {} in this domain", node.id()));
+    }
+    if !nodes[node].borrow().children().is_empty() {
+        return Err("it still has children".to_owned());
+    }
+
+    let n = nodes.remove(node).unwrap().into_inner();
+    for &p in n.parents() {
+        if let Some(p) = nodes.get(p) {
+            p.borrow_mut().try_remove_child(node);
+        }
+    }
+    state.remove(node);
+    Ok(())
 }
 
 type Hole = (Vec<usize>, Vec<DataType>);
@@ -144,6 +627,7 @@ impl DomainBuilder {
         control_addr: SocketAddr,
         shutdown_valve: &Valve,
         state_size: Arc<AtomicUsize>,
+        packet_observer: Option<Arc<dyn PacketObserver>>,
     ) -> Domain {
         // initially, all nodes are not ready
         let not_ready = self
@@ -164,11 +648,13 @@ impl DomainBuilder {
             persistence_parameters: self.persistence_parameters,
             nodes: self.nodes,
             state: StateMap::default(),
+            rebuilding: StateMap::default(),
             log,
             not_ready,
             mode: DomainMode::Forwarding,
             waiting: Default::default(),
             reader_triggered: Default::default(),
+            prefix_requests: Default::default(),
             replay_paths: Default::default(),
             replay_paths_by_dst: Default::default(),
 
@@ -185,20 +671,45 @@ impl DomainBuilder {
 
             concurrent_replays: 0,
             max_concurrent_replays: self.config.concurrent_replays,
-            replay_request_queue: Default::default(),
+            replay_request_queue: ReplayRequestQueue::new(self.config.max_pending_replays),
             delayed_for_self: Default::default(),
 
             group_commit_queues,
 
             state_size,
+            partial_sizes: Map::new(),
+            partial_state_size: 0,
+            memory_limit: None,
             total_time: Timer::new(),
             total_ptime: Timer::new(),
             wait_time: Timer::new(),
             process_times: TimerSet::new(),
             process_ptimes: TimerSet::new(),
+            process_counts: Map::new(),
+            replay_process_times: TimerSet::new(),
+            replay_process_counts: Map::new(),
 
             total_replay_time: Timer::new(),
             total_forward_time: Timer::new(),
+            replay_started: Default::default(),
+            replay_latencies: Default::default(),
+            dropped_packets: Default::default(),
+            corrupt_packets: 0,
+            link_order: if self.config.check_link_order {
+                Some(LinkOrder::default())
+            } else {
+                None
+            },
+            statistics_subscriptions: Vec::new(),
+            applied_schema_ops: Map::new(),
+            paused: None,
+            quit_after_upstreams: None,
+            upstream_quits: 0,
+            ingress_limits: Map::new(),
+            rejected_writes: 0,
+            stepping: false,
+            statistics_baselines: Map::new(),
+            packet_observer,
         }
     }
 }
@@ -211,6 +722,18 @@ struct TimedPurge {
     keys: HashSet<Vec<DataType>>,
 }
 
+/// A lookup on a prefix of a partial reader's key, waiting for the keys with that prefix to be
+/// listed by the reader's sources.
+#[derive(Debug)]
+struct PrefixRequest {
+    /// the reader's key columns
+    cols: Vec<usize>,
+    /// how many sources have yet to list their keys
+    outstanding: usize,
+    /// the keys listed so far, or `None` if a source could not list its keys
+    keys: Option<HashSet<Vec<DataType>>>,
+}
+
 pub struct Domain {
     index: Index,
     shard: Option<usize>,
@@ -218,6 +741,8 @@ pub struct Domain {
 
     nodes: DomainNodes,
     state: StateMap,
+    /// full state being rebuilt by a recompute, to be swapped in once its replay completes
+    rebuilding: StateMap,
     log: Logger,
 
     not_ready: HashSet<LocalNodeIndex>,
@@ -230,13 +755,14 @@ pub struct Domain {
     waiting: Map<Waiting>,
     replay_paths: HashMap<Tag, ReplayPath>,
     reader_triggered: Map<HashSet<Vec<DataType>>>,
+    prefix_requests: Map<HashMap<Vec<DataType>, PrefixRequest>>,
     timed_purges: VecDeque<TimedPurge>,
 
     replay_paths_by_dst: Map<HashMap<Vec<usize>, Vec<Tag>>>,
 
     concurrent_replays: usize,
     max_concurrent_replays: usize,
-    replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
+    replay_request_queue: ReplayRequestQueue,
 
     shutdown_valve: Valve,
     readers: Readers,
@@ -250,16 +776,55 @@ pub struct Domain {
     group_commit_queues: GroupCommitQueueSet,
 
     state_size: Arc<AtomicUsize>,
+    /// size, in bytes, of the partial state of each node, as of when we last looked at the node
+    partial_sizes: Map<u64>,
+    /// total of `partial_sizes`
+    partial_state_size: u64,
+    /// evict partial state once it grows beyond this many bytes
+    memory_limit: Option<usize>,
     total_time: Timer<SimpleTracker, RealTime>,
     total_ptime: Timer<SimpleTracker, ThreadTime>,
     wait_time: Timer<SimpleTracker, RealTime>,
     process_times: TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
     process_ptimes: TimerSet<LocalNodeIndex, SimpleTracker, ThreadTime>,
+    process_counts: Map<u64>,
+    /// time each node spent processing replays, counted separately from the above
+    replay_process_times: TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
+    replay_process_counts: Map<u64>,
 
     /// time spent processing replays
     total_replay_time: Timer<SimpleTracker, RealTime>,
     /// time spent processing ordinary, forward updates
     total_forward_time: Timer<SimpleTracker, RealTime>,
+    /// when each outstanding partial replay was requested
+    replay_started: HashMap<(Tag, Vec<DataType>), time::Instant>,
+    /// latency of completed partial replays along each replay path
+    replay_latencies: HashMap<Tag, noria::debug::stats::ReplayLatency>,
+    /// packets we failed to send to other domains
+    dropped_packets: DroppedPackets,
+    /// packets received over a remote connection that failed their checksum, and were dropped
+    corrupt_packets: u64,
+    /// where messages along each link are up to, if their order is checked
+    link_order: Option<LinkOrder>,
+    statistics_subscriptions:
+        Vec<StatisticsSubscription<(DomainStats, HashMap<NodeIndex, NodeStats>)>>,
+    /// column changes recently applied to each node, so that retried changes aren't re-applied
+    applied_schema_ops: Map<AppliedSchemaOps>,
+    /// data packets held back since the domain was paused with `Packet::Pause`
+    paused: Option<VecDeque<Box<Packet>>>,
+    /// how many upstream domain shards must quit before we do, once asked to with `QuitDrain`
+    quit_after_upstreams: Option<usize>,
+    /// how many upstream domain shards have told us they quit
+    upstream_quits: usize,
+    /// writes to base tables held back by `Packet::SetIngressRateLimit`
+    ingress_limits: Map<IngressRateLimit>,
+    /// writes dropped because their base table's ingress rate limit rejected them
+    rejected_writes: u64,
+    /// whether we are processing a packet for `Packet::Step`
+    stepping: bool,
+    /// processing times of nodes whose statistics were reset with `Packet::ResetStatistics`
+    statistics_baselines: Map<StatisticsBaseline>,
+    packet_observer: Option<Arc<dyn PacketObserver>>,
 }
 
 impl Domain {
@@ -282,6 +847,11 @@ impl Domain {
             // send a message to the source domain(s) responsible
             // for the chosen tag so they'll start replay.
             let keys = miss_keys.clone(); // :(
+            for key in &keys {
+                self.replay_started
+                    .entry((tag, key.clone()))
+                    .or_insert_with(time::Instant::now);
+            }
             if let TriggerEndpoint::Local(..) = self.replay_paths[&tag].trigger {
                 // *in theory* we could just call self.seed_replay, and everything would be good.
                 // however, then we start recursing, which could get us into sad situations where
@@ -325,6 +895,63 @@ impl Domain {
         }
     }
 
+    /// Ask every source of the partial reader `node` to list its keys that start with `prefix`.
+    ///
+    /// Once they all have, the listed keys that are holes are replayed like any other missed keys.
+    fn request_prefix_keys(&mut self, node: LocalNodeIndex, cols: &[usize], prefix: Vec<DataType>) {
+        let tags = self
+            .replay_paths_by_dst
+            .get(node)
+            .and_then(|candidates| candidates.get(cols))
+            .cloned()
+            .unwrap_or_default();
+        let requester = self.id();
+
+        let mut outstanding = 0;
+        for tag in tags {
+            let request = || {
+                Box::new(Packet::RequestPrefixKeys {
+                    tag,
+                    node,
+                    prefix: prefix.clone(),
+                    requester,
+                })
+            };
+            match self.replay_paths.get_mut(&tag).unwrap().trigger {
+                TriggerEndpoint::Local(..) => {
+                    outstanding += 1;
+                    self.delayed_for_self.push_back(request());
+                }
+                TriggerEndpoint::End {
+                    ref mut options, ..
+                } => {
+                    // the source may be sharded by any column, so ask every shard
+                    for trigger in options {
+                        if self.dropped_packets.send(&mut **trigger, request()) {
+                            outstanding += 1;
+                        }
+                    }
+                }
+                TriggerEndpoint::None | TriggerEndpoint::Start(..) => {
+                    unreachable!("reader replay path does not end at the reader")
+                }
+            }
+        }
+
+        if outstanding == 0 {
+            // we're shutting down
+            return;
+        }
+        self.prefix_requests.entry(node).or_default().insert(
+            prefix,
+            PrefixRequest {
+                cols: Vec::from(cols),
+                outstanding,
+                keys: Some(HashSet::new()),
+            },
+        );
+    }
+
     fn on_replay_miss(
         &mut self,
         miss_in: LocalNodeIndex,
@@ -405,16 +1032,15 @@ impl Domain {
                 );
 
                 for trigger in options {
-                    if trigger
-                        .send(Box::new(Packet::RequestPartialReplay {
+                    // if the send fails, we're shutting down -- it's fine.
+                    self.dropped_packets.send(
+                        &mut **trigger,
+                        Box::new(Packet::RequestPartialReplay {
                             tag,
                             unishard: false, // ask_all is true, so replay is sharded
                             keys: keys.clone(), // sad to clone here
-                        }))
-                        .is_err()
-                    {
-                        // we're shutting down -- it's fine.
-                    }
+                        }),
+                    );
                 }
                 return;
             }
@@ -427,17 +1053,16 @@ impl Domain {
                 "concurrent" => self.concurrent_replays,
             );
 
+            // if any of the sends below fail, we're shutting down -- it's fine.
             if options.len() == 1 {
-                if options[0]
-                    .send(Box::new(Packet::RequestPartialReplay {
+                self.dropped_packets.send(
+                    &mut *options[0],
+                    Box::new(Packet::RequestPartialReplay {
                         tag,
                         keys,
                         unishard: true, // only one option, so only one path
-                    }))
-                    .is_err()
-                {
-                    // we're shutting down -- it's fine.
-                }
+                    }),
+                );
             } else if let Some(key_shard_i) = ask_shard_by_key_i {
                 let mut shards = HashMap::new();
                 for key in keys {
@@ -445,16 +1070,14 @@ impl Domain {
                     shards.entry(shard).or_insert_with(Vec::new).push(key);
                 }
                 for (shard, keys) in shards {
-                    if options[shard]
-                        .send(Box::new(Packet::RequestPartialReplay {
+                    self.dropped_packets.send(
+                        &mut *options[shard],
+                        Box::new(Packet::RequestPartialReplay {
                             tag,
                             keys,
                             unishard: true, // !ask_all, so only one path
-                        }))
-                        .is_err()
-                    {
-                        // we're shutting down -- it's fine.
-                    }
+                        }),
+                    );
                 }
             } else {
                 // would have hit the if further up
@@ -475,7 +1098,118 @@ impl Domain {
                 "keys" => ?keys,
                 "buffered" => self.replay_request_queue.len(),
             );
-            self.replay_request_queue.push_back((tag, keys));
+            let paths = &self.replay_paths;
+            self.replay_request_queue
+                .push(tag, keys, paths[&tag].priority, |t| paths[&t].priority);
+        }
+    }
+
+    /// Forget a request to replay `key` along `tag`, if the replay has not started yet.
+    ///
+    /// Only the domain that asked for the replay can withdraw it, and only while the request is
+    /// still waiting for a replay slot, or buffered to be answered in a batch by this domain
+    /// itself. Once a request has been sent to the domain the replay starts in, it is under way
+    /// and holds a replay slot until its replay arrives, so it is left to finish.
+    ///
+    /// A key filled through a union is requested along every path into it, so it is withdrawn
+    /// from all of those paths or from none. Keys that other replays are waiting on, because
+    /// those replays missed on them, are also left alone, as the waiting replays could otherwise
+    /// never finish.
+    fn cancel_partial_replay(&mut self, tag: Tag, key: &[DataType]) {
+        let (target, cols) = match self.replay_paths.get(&tag) {
+            Some(&ReplayPath {
+                trigger: TriggerEndpoint::End { .. },
+                ref path,
+                ..
+            })
+            | Some(&ReplayPath {
+                trigger: TriggerEndpoint::Local(..),
+                ref path,
+                ..
+            }) => {
+                let last = path.last().unwrap();
+                (last.node, last.partial_key.clone().unwrap())
+            }
+            _ => return,
+        };
+
+        let hole = (cols, key.to_vec());
+        let awaited = self
+            .waiting
+            .get(target)
+            .map(|w| w.redos.contains_key(&hole))
+            .unwrap_or(false);
+        let tags = self
+            .replay_paths_by_dst
+            .get(target)
+            .and_then(|cs| cs.get(&hole.0))
+            .cloned()
+            .unwrap_or_default();
+        let pending = !tags.is_empty() && tags.iter().all(|&t| self.replay_pending(t, key));
+        if awaited || !pending {
+            trace!(self.log, "replay request cannot be cancelled";
+                "tag" => ?tag,
+                "key" => ?key,
+                "awaited" => awaited,
+            );
+            return;
+        }
+
+        for &t in &tags {
+            if let TriggerEndpoint::Local(..) = self.replay_paths[&t].trigger {
+                if let Some(&mut (_, ref mut keys, _)) = self.buffered_replay_requests.get_mut(&t) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.buffered_replay_requests.remove(&t);
+                    }
+                }
+            } else {
+                self.replay_request_queue.cancel(t, key);
+            }
+            self.replay_started.remove(&(t, hole.1.clone()));
+        }
+
+        // so that the key is requested again if it is read again
+        if let Some(triggered) = self.reader_triggered.get_mut(target) {
+            triggered.remove(key);
+        }
+
+        trace!(self.log, "cancelled replay request";
+            "tag" => ?tag,
+            "key" => ?key,
+            "paths" => tags.len(),
+        );
+    }
+
+    /// True if a request to replay `key` along `tag` has been made by this domain, but has not
+    /// been sent on to the domain the replay starts in.
+    fn replay_pending(&self, tag: Tag, key: &[DataType]) -> bool {
+        match self.replay_paths[&tag].trigger {
+            TriggerEndpoint::Local(..) => self
+                .buffered_replay_requests
+                .get(&tag)
+                .map(|&(_, ref keys, _)| keys.contains(key))
+                .unwrap_or(false),
+            TriggerEndpoint::End { .. } => self.replay_request_queue.contains(tag, key),
+            _ => false,
+        }
+    }
+
+    fn add_base_columns(&mut self, node: LocalNodeIndex, fields: Vec<(String, DataType)>) {
+        let mut n = self.nodes[node].borrow_mut();
+        for (field, default) in fields {
+            n.add_column(&field);
+            if let Some(b) = n.get_base_mut() {
+                b.add_column(default);
+            } else if n.is_ingress() {
+                self.ingress_inject
+                    .entry(node)
+                    .or_insert_with(|| (n.fields().len(), Vec::new()))
+                    .1
+                    .push(default);
+            } else {
+                unreachable!("node unrelated to base got AddBaseColumn");
+            }
         }
     }
 
@@ -518,13 +1252,15 @@ impl Domain {
                 "ongoing" => self.concurrent_replays,
                 );
                 debug_assert!(self.concurrent_replays < self.max_concurrent_replays);
-                let mut per_tag = HashMap::new();
+                // requests are sent in the order they were queued in, so that the requests along
+                // higher priority paths go first
+                let mut per_tag: Vec<(Tag, Vec<Vec<DataType>>)> = Vec::new();
                 while self.concurrent_replays < self.max_concurrent_replays {
                     if let Some((tag, mut keys)) = self.replay_request_queue.pop_front() {
-                        per_tag
-                            .entry(tag)
-                            .or_insert_with(Vec::new)
-                            .append(&mut keys);
+                        match per_tag.iter_mut().find(|&&mut (t, _)| t == tag) {
+                            Some(&mut (_, ref mut queued)) => queued.append(&mut keys),
+                            None => per_tag.push((tag, keys)),
+                        }
                     } else {
                         break;
                     }
@@ -587,9 +1323,12 @@ impl Domain {
             assert_eq!(captured.len(), 0);
             self.process_ptimes.stop();
             self.process_times.stop();
+            *self.process_counts.entry(me).or_default() += 1;
 
             if m.is_none() {
                 // no need to deal with our children if we're not sending them anything
+                drop(n);
+                self.resize_partial(me);
                 return;
             }
 
@@ -684,6 +1423,7 @@ impl Domain {
 
             (m, evictions)
         };
+        self.resize_partial(me);
 
         if let Some(evictions) = evictions {
             // now send evictions for all the (tag, [key]) things in evictions
@@ -733,7 +1473,7 @@ impl Domain {
             } else {
                 m.link_mut().src = me;
             }
-            m.link_mut().dst = childi;
+            m.retarget(childi);
 
             self.dispatch(m, executor);
         }
@@ -753,14 +1493,33 @@ impl Domain {
                 self.total_forward_time.stop();
             }
             Packet::ReplayPiece { .. } => {
+                let tag = m.tag();
                 self.total_replay_time.start();
                 self.handle_replay(m, executor);
                 self.total_replay_time.stop();
+                self.resize_replay_path(tag);
+            }
+            Packet::CompressedReplayPiece { .. } => {
+                let tag = m.tag();
+                match m.decompress() {
+                    Ok(m) => {
+                        self.total_replay_time.start();
+                        self.handle_replay(Box::new(m), executor);
+                        self.total_replay_time.stop();
+                        self.resize_replay_path(tag);
+                    }
+                    Err(e) => {
+                        self.saw_corrupt_packet();
+                        warn!(self.log, "dropped corrupted replay piece: {}", e; "tag" => ?tag);
+                    }
+                }
             }
             Packet::Evict { .. } | Packet::EvictKeys { .. } => {
                 self.handle_eviction(m, executor);
             }
             consumed => {
+                // control packets may change state in ways we don't keep track of node by node
+                let recount = consumed.is_control();
                 match consumed {
                     // workaround #16223
                     Packet::AddNode { node, parents } => {
@@ -776,13 +1535,16 @@ impl Domain {
                         }
                         self.nodes.insert(addr, cell::RefCell::new(node));
                         trace!(self.log, "new node incorporated"; "local" => addr.id());
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::NodeAdded(addr))
+                            .unwrap();
                     }
                     Packet::RemoveNodes { nodes } => {
                         for &node in &nodes {
                             self.nodes[node].borrow_mut().remove();
-                            self.state.remove(node);
                             trace!(self.log, "node removed"; "local" => node.id());
                         }
+                        self.forget_nodes(&nodes);
 
                         for node in nodes {
                             for cn in self.nodes.iter_mut() {
@@ -792,37 +1554,142 @@ impl Domain {
                             }
                         }
                     }
+                    Packet::Recompute { node, block } => {
+                        let reply = match self.recompute(node, block, executor) {
+                            Ok(()) => {
+                                info!(self.log, "dropped state for recompute"; "local" => node.id());
+                                ControlReplyPacket::ack()
+                            }
+                            Err(e) => ControlReplyPacket::Nack(e),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::RemoveNode { node } => {
+                        let reply = match self.remove_leaf(node) {
+                            Ok(()) => {
+                                info!(self.log, "node removed"; "local" => node.id());
+                                ControlReplyPacket::ack()
+                            }
+                            Err(e) => ControlReplyPacket::Nack(format!(
+                                "cannot remove {}: {}",
+                                node.id(),
+                                e
+                            )),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
                     Packet::AddBaseColumn {
                         node,
                         field,
                         default,
+                        op_id,
                     } => {
-                        let mut n = self.nodes[node].borrow_mut();
-                        n.add_column(&field);
-                        if let Some(b) = n.get_base_mut() {
-                            b.add_column(default);
-                        } else if n.is_ingress() {
-                            self.ingress_inject
-                                .entry(node)
-                                .or_insert_with(|| (n.fields().len(), Vec::new()))
-                                .1
-                                .push(default);
-                        } else {
-                            unreachable!("node unrelated to base got AddBaseColumn");
+                        if self
+                            .applied_schema_ops
+                            .entry(node)
+                            .or_default()
+                            .insert(op_id)
+                        {
+                            self.add_base_columns(node, vec![(field, default)]);
                         }
                         self.control_reply_tx
                             .send(ControlReplyPacket::ack())
                             .unwrap();
                     }
-                    Packet::DropBaseColumn { node, column } => {
-                        let mut n = self.nodes[node].borrow_mut();
-                        n.get_base_mut()
-                            .expect("told to drop base column from non-base node")
-                            .drop_column(column);
+                    Packet::AddBaseColumns {
+                        node,
+                        fields,
+                        op_id,
+                    } => {
+                        if self
+                            .applied_schema_ops
+                            .entry(node)
+                            .or_default()
+                            .insert(op_id)
+                        {
+                            self.add_base_columns(node, fields);
+                        }
                         self.control_reply_tx
                             .send(ControlReplyPacket::ack())
                             .unwrap();
                     }
+                    Packet::DropBaseColumn {
+                        node,
+                        column,
+                        op_id,
+                    } => {
+                        if self
+                            .applied_schema_ops
+                            .entry(node)
+                            .or_default()
+                            .insert(op_id)
+                        {
+                            let mut n = self.nodes[node].borrow_mut();
+                            n.get_base_mut()
+                                .expect("told to drop base column from non-base node")
+                                .drop_column(column);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::DropBaseColumns {
+                        node,
+                        columns,
+                        op_id,
+                    } => {
+                        if self
+                            .applied_schema_ops
+                            .entry(node)
+                            .or_default()
+                            .insert(op_id)
+                        {
+                            let mut n = self.nodes[node].borrow_mut();
+                            n.get_base_mut()
+                                .expect("told to drop base columns from non-base node")
+                                .drop_columns(&columns);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::RenameBaseColumn {
+                        node,
+                        column,
+                        new_name,
+                    } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        let reply = if n.rename_column(column, &new_name) {
+                            ControlReplyPacket::ack()
+                        } else {
+                            ControlReplyPacket::Nack(format!(
+                                "node {} has no column {} to rename",
+                                n.global_addr().index(),
+                                column
+                            ))
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::SetColumnDefault {
+                        node,
+                        column,
+                        default,
+                    } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        let ni = n.global_addr().index();
+                        let b = n
+                            .get_base_mut()
+                            .expect("told to set column default on non-base node");
+                        let reply = if b.set_default(column, default) {
+                            ControlReplyPacket::ack()
+                        } else {
+                            ControlReplyPacket::Nack(format!(
+                                "node {} has no default for column {}",
+                                ni, column
+                            ))
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
                     Packet::UpdateEgress {
                         node,
                         new_tx,
@@ -838,16 +1705,154 @@ impl Domain {
                             }
                         });
                     }
-                    Packet::UpdateSharder { node, new_txs } => {
+                    Packet::ReplaceEgressTx { node, old_dst, new } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        let mut replaced = false;
+                        n.with_egress_mut(|e| replaced = e.replace_tx(old_dst, new));
+                        let reply = if replaced {
+                            ControlReplyPacket::ack()
+                        } else {
+                            ControlReplyPacket::Nack(format!(
+                                "egress {} does not send to {}",
+                                n.global_addr().index(),
+                                old_dst.index()
+                            ))
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::AddIndex { node, index } => {
+                        let width = self.nodes[node].borrow().fields().len();
+                        let reply = match self.state.get_mut(node) {
+                            Some(state) => add_full_index(&mut **state, width, &index),
+                            None => Err("node has no materialized state".to_owned()),
+                        };
+                        let reply = match reply {
+                            Ok(added) => {
+                                info!(self.log, "added index to running node";
+                                    "local" => node.id(),
+                                    "key" => ?index,
+                                    "new" => added,
+                                );
+                                ControlReplyPacket::ack()
+                            }
+                            Err(e) => ControlReplyPacket::Nack(format!(
+                                "cannot index {} on {:?}: {}",
+                                self.nodes[node].borrow().global_addr().index(),
+                                index,
+                                e
+                            )),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::Reparent {
+                        node,
+                        old_parent,
+                        new_parent,
+                    } => {
+                        let replayed_through = self
+                            .replay_paths
+                            .values()
+                            .any(|p| p.path.iter().any(|s| s.node == node));
+                        let reply = if replayed_through {
+                            Err("a replay path goes through it".to_owned())
+                        } else {
+                            reparent_node(&self.nodes, node, old_parent, new_parent)
+                        };
+                        let reply = match reply {
+                            Ok(()) => {
+                                info!(self.log, "reparented node";
+                                    "local" => node.id(),
+                                    "old" => old_parent.id(),
+                                    "new" => new_parent.id(),
+                                );
+                                ControlReplyPacket::ack()
+                            }
+                            Err(e) => ControlReplyPacket::Nack(format!(
+                                "cannot reparent {}: {}",
+                                node.id(),
+                                e
+                            )),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::GetNodeIndices { node } => {
+                        let indices = match self.nodes[node]
+                            .borrow()
+                            .with_reader(|r| r.key().map(Vec::from))
+                        {
+                            Ok(key) => key.into_iter().collect(),
+                            Err(()) => self.state.get(node).map(|s| s.keys()).unwrap_or_default(),
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::NodeIndices(indices))
+                            .unwrap();
+                    }
+                    Packet::DropIndex { node, index } => {
+                        // operators look up through the indices they suggested for their parents
+                        let looked_up = self.nodes.get(node).map_or(false, |n| {
+                            let parent = n.borrow().global_addr();
+                            self.nodes.values().any(|c| {
+                                let c = c.borrow();
+                                !c.is_dropped()
+                                    && c.suggest_indexes(c.global_addr()).get(&parent)
+                                        == Some(&index)
+                            })
+                        });
+                        let in_use = self.replay_paths.values().any(|p| {
+                            let trigger = match p.trigger {
+                                TriggerEndpoint::Start(ref cols)
+                                | TriggerEndpoint::Local(ref cols) => {
+                                    p.source == Some(node) && cols == &index
+                                }
+                                _ => false,
+                            };
+                            trigger
+                                || p.path.iter().any(|s| {
+                                    s.node == node && s.partial_key.as_ref() == Some(&index)
+                                })
+                        });
+                        let reply = match self.state.get_mut(node) {
+                            _ if in_use => Err("a replay path looks up through it".to_owned()),
+                            _ if looked_up => Err("an operator looks up through it".to_owned()),
+                            Some(state) => drop_full_index(&mut **state, &index),
+                            None => Err("node has no materialized state".to_owned()),
+                        };
+                        let reply = match reply {
+                            Ok(()) => {
+                                info!(self.log, "dropped index from running node";
+                                    "local" => node.id(),
+                                    "key" => ?index,
+                                );
+                                ControlReplyPacket::ack()
+                            }
+                            Err(e) => ControlReplyPacket::Nack(format!(
+                                "cannot drop index {:?} from {}: {}",
+                                index,
+                                self.nodes[node].borrow().global_addr().index(),
+                                e
+                            )),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::UpdateSharder { node, new_txs } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.with_sharder_mut(move |s| {
                             s.add_sharded_child(new_txs.0, new_txs.1);
                         });
                     }
-                    Packet::AddStreamer { node, new_streamer } => {
+                    Packet::AddStreamer {
+                        node,
+                        new_streamer,
+                        send_initial,
+                        filter,
+                        coalesce,
+                    } => {
                         let mut n = self.nodes[node].borrow_mut();
-                        n.with_reader_mut(|r| r.add_streamer(new_streamer).unwrap())
-                            .unwrap();
+                        n.with_reader_mut(|r| {
+                            // the streamer may already have hung up; that's fine
+                            let _ = r.add_streamer(new_streamer, send_initial, filter, coalesce);
+                        })
+                        .unwrap();
                     }
                     Packet::StateSizeProbe { node } => {
                         let row_count = self.state.get(node).map(|r| r.rows()).unwrap_or(0);
@@ -999,80 +2004,102 @@ impl Domain {
                         path,
                         notify_done,
                         trigger,
+                        priority,
+                        compress,
                     } => {
-                        // let coordinator know that we've registered the tagged path
-                        self.control_reply_tx
-                            .send(ControlReplyPacket::ack())
-                            .unwrap();
-
-                        if notify_done {
-                            info!(self.log,
-                                  "told about terminating replay path {:?}",
-                                  path;
-                                  "tag" => tag.id()
+                        if let Err(e) = check_replay_path(&self.nodes, source, &path, &trigger) {
+                            error!(self.log, "rejecting bad replay path {:?}: {}", path, e;
+                                "tag" => tag.id()
                             );
-                        // NOTE: we set self.replaying_to when we first receive a replay with
-                        // this tag
+                            self.control_reply_tx
+                                .send(ControlReplyPacket::Nack(e))
+                                .unwrap();
                         } else {
-                            info!(self.log, "told about replay path {:?}", path; "tag" => tag.id());
-                        }
-
-                        use crate::payload;
-                        let trigger = match trigger {
-                            payload::TriggerEndpoint::None => TriggerEndpoint::None,
-                            payload::TriggerEndpoint::Start(v) => TriggerEndpoint::Start(v),
-                            payload::TriggerEndpoint::Local(v) => TriggerEndpoint::Local(v),
-                            payload::TriggerEndpoint::End(selection, domain) => {
-                                let shard = |shardi| {
-                                    // TODO: make async
-                                    self.channel_coordinator
-                                        .builder_for(&(domain, shardi))
-                                        .unwrap()
-                                        .build_sync()
-                                        .unwrap()
-                                };
+                            // let coordinator know that we've registered the tagged path
+                            self.control_reply_tx
+                                .send(ControlReplyPacket::ack())
+                                .unwrap();
 
-                                let options = match selection {
-                                    SourceSelection::AllShards(nshards)
-                                    | SourceSelection::KeyShard { nshards, .. } => {
-                                        // we may need to send to any of these shards
-                                        (0..nshards).map(shard).collect()
+                            // replay pieces are compressed by the egress as it sends them on
+                            if compress {
+                                if let Some(last) = path.last() {
+                                    let mut n = self.nodes[last.node].borrow_mut();
+                                    if n.is_egress() {
+                                        n.with_egress_mut(|e| e.compress_tag(tag));
                                     }
-                                    SourceSelection::SameShard => vec![shard(self.shard.unwrap())],
-                                };
+                                }
+                            }
+
+                            if notify_done {
+                                info!(self.log,
+                                      "told about terminating replay path {:?}",
+                                      path;
+                                      "tag" => tag.id()
+                                );
+                            // NOTE: we set self.replaying_to when we first receive a replay with
+                            // this tag
+                            } else {
+                                info!(self.log, "told about replay path {:?}", path; "tag" => tag.id());
+                            }
+
+                            use crate::payload;
+                            let trigger = match trigger {
+                                payload::TriggerEndpoint::None => TriggerEndpoint::None,
+                                payload::TriggerEndpoint::Start(v) => TriggerEndpoint::Start(v),
+                                payload::TriggerEndpoint::Local(v) => TriggerEndpoint::Local(v),
+                                payload::TriggerEndpoint::End(selection, domain) => {
+                                    let shard = |shardi| {
+                                        // TODO: make async
+                                        self.channel_coordinator
+                                            .builder_for(&(domain, shardi))
+                                            .unwrap()
+                                            .build_sync()
+                                            .unwrap()
+                                    };
+
+                                    let options = match selection {
+                                        SourceSelection::AllShards(nshards)
+                                        | SourceSelection::KeyShard { nshards, .. } => {
+                                            // we may need to send to any of these shards
+                                            (0..nshards).map(shard).collect()
+                                        }
+                                        SourceSelection::SameShard => {
+                                            vec![shard(self.shard.unwrap())]
+                                        }
+                                    };
 
-                                TriggerEndpoint::End {
-                                    source: selection,
-                                    options,
+                                    TriggerEndpoint::End {
+                                        source: selection,
+                                        options,
+                                    }
                                 }
+                            };
+
+                            if let TriggerEndpoint::End { .. } | TriggerEndpoint::Local(..) =
+                                trigger
+                            {
+                                let last = path.last().unwrap();
+                                self.replay_paths_by_dst
+                                    .entry(last.node)
+                                    .or_insert_with(HashMap::new)
+                                    .entry(last.partial_key.clone().unwrap())
+                                    .or_insert_with(Vec::new)
+                                    .push(tag);
                             }
-                        };
 
-                        if let TriggerEndpoint::End { .. } | TriggerEndpoint::Local(..) = trigger {
-                            let last = path.last().unwrap();
-                            self.replay_paths_by_dst
-                                .entry(last.node)
-                                .or_insert_with(HashMap::new)
-                                .entry(last.partial_key.clone().unwrap())
-                                .or_insert_with(Vec::new)
-                                .push(tag);
+                            self.replay_paths.insert(
+                                tag,
+                                ReplayPath {
+                                    source,
+                                    path,
+                                    notify_done,
+                                    trigger,
+                                    priority: priority.unwrap_or(0),
+                                },
+                            );
                         }
-
-                        self.replay_paths.insert(
-                            tag,
-                            ReplayPath {
-                                source,
-                                path,
-                                notify_done,
-                                trigger,
-                            },
-                        );
                     }
-                    Packet::RequestReaderReplay {
-                        mut keys,
-                        cols,
-                        node,
-                    } => {
+                    Packet::RequestReaderReplay { keys, cols, node } => {
                         self.total_replay_time.start();
                         // the reader could have raced with us filling in the key after some
                         // *other* reader requested it, so let's double check that it indeed still
@@ -1088,6 +2115,11 @@ impl Domain {
                             })
                             .expect("reader replay requested for non-reader node");
 
+                        // lookups on a prefix of the key replay every key with that prefix, once
+                        // those have been listed
+                        let (prefixes, mut keys): (Vec<_>, Vec<_>) =
+                            keys.into_iter().partition(|key| key.len() < cols.len());
+                        let mut unlisted = Vec::new();
                         self.nodes[node]
                             .borrow_mut()
                             .with_reader_mut(|r| {
@@ -1095,6 +2127,16 @@ impl Domain {
                                     .writer_mut()
                                     .expect("reader replay requested for non-materialized reader");
 
+                                for prefix in prefixes {
+                                    match w.prefix_keys(&prefix) {
+                                        Some(prefix_keys) => keys.extend(prefix_keys),
+                                        None if w.start_prefix(&prefix) => unlisted.push(prefix),
+                                        None => {
+                                            // already being listed, or can't be
+                                        }
+                                    }
+                                }
+
                                 keys.retain(|key| {
                                     w.with_key(&key[..])
                                         .try_find_and(|_| ())
@@ -1105,15 +2147,29 @@ impl Domain {
                             })
                             .unwrap();
 
-                        // ensure that we haven't already requested a replay of this key
-                        keys.retain(|key| {
-                            self.reader_triggered
-                                .entry(node)
-                                .or_default()
-                                .insert(key.clone())
-                        });
-                        if !keys.is_empty() {
-                            self.find_tags_and_replay(keys, &cols[..], node);
+                        for prefix in unlisted {
+                            self.request_prefix_keys(node, &cols[..], prefix);
+                        }
+
+                        if !keys.is_empty() && self.replay_request_queue.is_full() {
+                            // the keys are not marked as triggered, so the reader will ask again
+                            warn!(self.log, "rejecting reader replay request, too many pending";
+                                "node" => ?node,
+                                "keys" => keys.len(),
+                                "pending" => self.replay_request_queue.len(),
+                            );
+                            self.dropped_packets.data += 1;
+                        } else {
+                            // ensure that we haven't already requested a replay of this key
+                            keys.retain(|key| {
+                                self.reader_triggered
+                                    .entry(node)
+                                    .or_default()
+                                    .insert(key.clone())
+                            });
+                            if !keys.is_empty() {
+                                self.find_tags_and_replay(keys, &cols[..], node);
+                            }
                         }
                         self.total_replay_time.stop();
                     }
@@ -1134,7 +2190,93 @@ impl Domain {
                         }
                         self.total_replay_time.stop();
                     }
-                    Packet::StartReplay { tag, from } => {
+                    Packet::CancelPartialReplay { tag, key } => {
+                        self.cancel_partial_replay(tag, &key);
+                    }
+                    Packet::RequestPrefixKeys {
+                        tag,
+                        node,
+                        prefix,
+                        requester,
+                    } => {
+                        let keys = match self.replay_paths[&tag] {
+                            ReplayPath {
+                                source: Some(source),
+                                trigger: TriggerEndpoint::Start(ref cols),
+                                ..
+                            }
+                            | ReplayPath {
+                                source: Some(source),
+                                trigger: TriggerEndpoint::Local(ref cols),
+                                ..
+                            } => self
+                                .state
+                                .get(source)
+                                .and_then(|state| state.keys_with_prefix(&cols[..], &prefix)),
+                            _ => None,
+                        };
+
+                        let m = Box::new(Packet::PrefixKeys { node, prefix, keys });
+                        if requester == self.id() {
+                            self.delayed_for_self.push_back(m);
+                        } else {
+                            executor.send(requester, m);
+                        }
+                    }
+                    Packet::PrefixKeys { node, prefix, keys } => {
+                        let listed = match self
+                            .prefix_requests
+                            .get_mut(node)
+                            .and_then(|requests| requests.get_mut(&prefix))
+                        {
+                            Some(request) => {
+                                request.outstanding -= 1;
+                                match (request.keys.as_mut(), keys) {
+                                    (Some(listed), Some(keys)) => listed.extend(keys),
+                                    _ => request.keys = None,
+                                }
+                                request.outstanding == 0
+                            }
+                            None => {
+                                // the reader has been removed
+                                false
+                            }
+                        };
+
+                        if listed {
+                            let request = self
+                                .prefix_requests
+                                .get_mut(node)
+                                .and_then(|requests| requests.remove(&prefix))
+                                .unwrap();
+                            let keys: Option<Vec<_>> =
+                                request.keys.map(|keys| keys.into_iter().collect());
+                            let replay = keys.clone().unwrap_or_default();
+                            self.nodes[node]
+                                .borrow_mut()
+                                .with_reader_mut(|r| {
+                                    if let Some(w) = r.writer_mut() {
+                                        w.fill_prefix(&prefix, keys);
+                                    }
+                                })
+                                .unwrap();
+
+                            if !replay.is_empty() {
+                                self.delayed_for_self.push_back(Box::new(
+                                    Packet::RequestReaderReplay {
+                                        node,
+                                        cols: request.cols,
+                                        keys: replay,
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                    Packet::StartReplay {
+                        tag,
+                        from,
+                        progress,
+                    } => {
                         use std::thread;
                         assert_eq!(self.replay_paths[&tag].source, Some(from));
 
@@ -1179,7 +2321,11 @@ impl Domain {
                             data: Vec::<Record>::new().into(),
                         });
 
-                        if !state.is_empty() {
+                        if state.is_empty() {
+                            if let Some(progress) = progress {
+                                let _ = progress.send(0);
+                            }
+                        } else {
                             let log = self.log.new(o!());
 
                             let added_cols = self.ingress_inject.get(from).cloned();
@@ -1222,8 +2368,6 @@ impl Domain {
                                     link.src
                                 ))
                                 .spawn(move || {
-                                    use itertools::Itertools;
-
                                     // TODO: make async
                                     let mut chunked_replay_tx =
                                         replay_tx_desc.build_sync().unwrap();
@@ -1231,29 +2375,29 @@ impl Domain {
                                     let start = time::Instant::now();
                                     debug!(log, "starting state chunker"; "node" => %link.dst);
 
-                                    let iter = state.into_iter().chunks(BATCH_SIZE);
-                                    let mut iter = iter.into_iter().enumerate().peekable();
-
                                     // process all records in state to completion within domain
                                     // and then forward on tx (if there is one)
-                                    while let Some((i, chunk)) = iter.next() {
-                                        use std::iter::FromIterator;
-                                        let chunk = Records::from_iter(chunk.map(&fix));
-                                        let len = chunk.len();
-                                        let last = iter.peek().is_none();
-                                        let p = Box::new(Packet::ReplayPiece {
-                                            tag,
-                                            link, // to is overwritten by receiver
-                                            context: ReplayPieceContext::Regular { last },
-                                            data: chunk,
-                                        });
-
-                                        trace!(log, "sending batch"; "#" => i, "[]" => len);
-                                        if chunked_replay_tx.send(p).is_err() {
-                                            warn!(log, "replayer noticed domain shutdown");
-                                            break;
-                                        }
-                                    }
+                                    chunk_replay(
+                                        state,
+                                        fix,
+                                        progress.as_ref(),
+                                        |i, chunk, last| {
+                                            let len = chunk.len();
+                                            let p = Box::new(Packet::ReplayPiece {
+                                                tag,
+                                                link, // to is overwritten by receiver
+                                                context: ReplayPieceContext::Regular { last },
+                                                data: chunk,
+                                            });
+
+                                            trace!(log, "sending batch"; "#" => i, "[]" => len);
+                                            if chunked_replay_tx.send(p).is_err() {
+                                                warn!(log, "replayer noticed domain shutdown");
+                                                return false;
+                                            }
+                                            true
+                                        },
+                                    );
 
                                     debug!(log,
                                        "state chunker finished";
@@ -1321,6 +2465,7 @@ impl Domain {
                                     if let Some(ref mut state) = r.writer_mut() {
                                         trace!(self.log, "swapping state"; "local" => node.id());
                                         state.swap();
+                                        state.set_rebuilding(false);
                                         trace!(self.log, "state swapped"; "local" => node.id());
                                     }
                                 })
@@ -1333,106 +2478,138 @@ impl Domain {
                             .unwrap();
                     }
                     Packet::GetStatistics => {
-                        let domain_stats = noria::debug::stats::DomainStats {
-                            total_time: self.total_time.num_nanoseconds(),
-                            total_ptime: self.total_ptime.num_nanoseconds(),
-                            total_replay_time: self.total_replay_time.num_nanoseconds(),
-                            total_forward_time: self.total_forward_time.num_nanoseconds(),
-                            wait_time: self.wait_time.num_nanoseconds(),
+                        let (domain_stats, node_stats) = self.statistics();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Statistics(domain_stats, node_stats))
+                            .unwrap();
+                    }
+                    Packet::SubscribeStatistics { interval_ms, sink } => {
+                        let interval = time::Duration::from_millis(cmp::max(interval_ms, 1));
+                        self.statistics_subscriptions.push(StatisticsSubscription {
+                            interval,
+                            next: time::Instant::now() + interval,
+                            sink,
+                        });
+                    }
+                    Packet::ResetStatistics { node } => {
+                        let reply = match self.reset_statistics(node) {
+                            Ok(()) => ControlReplyPacket::ack(),
+                            Err(e) => ControlReplyPacket::Nack(e),
                         };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::UpdateStateSize => {
+                        self.update_state_sizes();
+                    }
+                    Packet::Ping { sent } => {
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Pong(sent))
+                            .unwrap();
+                    }
+                    Packet::GetReplayPaths => {
+                        use noria::debug::replay::{ReplayPathInfo, ReplayTrigger};
 
-                        let node_stats = self
-                            .nodes
-                            .values()
-                            .filter_map(|nd| {
-                                let n = &*nd.borrow();
-                                let local_index = n.local_addr();
-                                let node_index: NodeIndex = n.global_addr();
-
-                                let time = self.process_times.num_nanoseconds(local_index);
-                                let ptime = self.process_ptimes.num_nanoseconds(local_index);
-                                let mem_size = if n.is_reader() {
-                                    let mut size = 0;
-                                    n.with_reader(|r| size = r.state_size().unwrap_or(0))
-                                        .unwrap();
-                                    size
-                                } else {
-                                    self.state
-                                        .get(local_index)
-                                        .map(|s| s.deep_size_of())
-                                        .unwrap_or(0)
-                                };
-
-                                let mat_state = if !n.is_reader() {
-                                    match self.state.get(local_index) {
-                                        Some(ref s) => {
-                                            if s.is_partial() {
-                                                MaterializationStatus::Partial {
-                                                    beyond_materialization_frontier: n.purge,
-                                                }
-                                            } else {
-                                                MaterializationStatus::Full
-                                            }
-                                        }
-                                        None => MaterializationStatus::Not,
-                                    }
-                                } else {
-                                    n.with_reader(|r| {
-                                        if r.is_partial() {
-                                            MaterializationStatus::Partial {
-                                                beyond_materialization_frontier: n.purge,
-                                            }
-                                        } else {
-                                            MaterializationStatus::Full
-                                        }
+                        let mut paths: Vec<_> = self
+                            .replay_paths
+                            .iter()
+                            .map(|(tag, rp)| ReplayPathInfo {
+                                tag: tag.id(),
+                                path: rp
+                                    .path
+                                    .iter()
+                                    .map(|segment| {
+                                        (
+                                            self.nodes[segment.node].borrow().global_addr(),
+                                            segment.partial_key.clone(),
+                                        )
                                     })
-                                    .unwrap()
-                                };
-
-                                let probe_result = if n.is_internal() {
-                                    n.probe()
-                                } else {
-                                    Default::default()
-                                };
-
-                                if time.is_some() && ptime.is_some() {
-                                    Some((
-                                        node_index,
-                                        noria::debug::stats::NodeStats {
-                                            desc: format!("{:?}", n),
-                                            process_time: time.unwrap(),
-                                            process_ptime: ptime.unwrap(),
-                                            mem_size,
-                                            materialized: mat_state,
-                                            probe_result,
-                                        },
-                                    ))
-                                } else {
-                                    None
-                                }
+                                    .collect(),
+                                trigger: match rp.trigger {
+                                    TriggerEndpoint::None => ReplayTrigger::None,
+                                    TriggerEndpoint::Start(..) => ReplayTrigger::Start,
+                                    TriggerEndpoint::End { .. } => ReplayTrigger::End,
+                                    TriggerEndpoint::Local(..) => ReplayTrigger::Local,
+                                },
                             })
                             .collect();
+                        paths.sort_by_key(|p| p.tag);
 
                         self.control_reply_tx
-                            .send(ControlReplyPacket::Statistics(domain_stats, node_stats))
+                            .send(ControlReplyPacket::ReplayPaths(paths))
                             .unwrap();
                     }
-                    Packet::UpdateStateSize => {
-                        self.update_state_sizes();
+                    Packet::SetMemoryLimit { bytes } => {
+                        self.memory_limit = Some(bytes);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetBatchTimeout { micros } => {
+                        // writes already held are flushed by the new deadline the next time we
+                        // are polled
+                        self.group_commit_queues
+                            .set_flush_timeout(time::Duration::from_micros(micros));
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetIngressRateLimit {
+                        node,
+                        per_sec,
+                        max_held,
+                        reject,
+                    } => {
+                        let reply = match self.nodes.get(node) {
+                            Some(n) if n.borrow().is_base() => {
+                                // held writes are let go at the new rate the next time we are
+                                // polled
+                                let limit = self
+                                    .ingress_limits
+                                    .entry(node)
+                                    .or_insert_with(|| IngressRateLimit::new(per_sec));
+                                limit.set_rate(per_sec);
+                                limit.set_overflow(max_held, reject);
+                                ControlReplyPacket::ack()
+                            }
+                            _ => ControlReplyPacket::Nack(format!(
+                                "no base table {} in this domain",
+                                node.id()
+                            )),
+                        };
+                        self.control_reply_tx.send(reply).unwrap();
+                    }
+                    Packet::Quit | Packet::Pause | Packet::Resume | Packet::Step => {
+                        unreachable!("{:?} is handled by the event loop", consumed)
                     }
-                    Packet::Quit => unreachable!("Quit messages are handled by event loop"),
                     Packet::Spin => {
                         // spinning as instructed
                     }
+                    Packet::FlushAndAck(ack) => {
+                        // whoever asked may have stopped waiting
+                        let _ = ack.send(());
+                    }
                     _ => unreachable!(),
                 }
+                if recount {
+                    self.recount_partial_state();
+                }
             }
         }
 
         if top {
             loop {
-                while let Some(m) = self.delayed_for_self.pop_front() {
+                while let Some(mut m) = self.delayed_for_self.pop_front() {
                     trace!(self.log, "handling local transmission");
+                    if m.is_regular() {
+                        // coalesce back-to-back updates along the same link into one dispatch
+                        while let Some(next) = self.delayed_for_self.pop_front() {
+                            if let Err(next) = m.try_merge(*next) {
+                                self.delayed_for_self.push_front(Box::new(next));
+                                break;
+                            }
+                        }
+                    }
+
                     // we really want this to just use tail recursion.
                     // but alas, the compiler doesn't seem to want to do that.
                     // instead, we ensure that only the topmost call to handle() walks delayed_for_self
@@ -1495,6 +2672,7 @@ impl Domain {
                             }
                         })
                         .unwrap();
+                    self.resize_partial(n);
                 }
 
                 if self.delayed_for_self.is_empty() {
@@ -1813,17 +2991,31 @@ impl Domain {
                         {
                             let had = for_keys.len();
                             let partial_keys = path.last().unwrap().partial_key.as_ref().unwrap();
+                            // replays of keys we discard are no longer in flight
+                            let replay_started = &mut self.replay_started;
+                            let mut discard = |k: &Vec<DataType>, keep: bool| {
+                                if !keep {
+                                    replay_started.remove(&(tag, k.clone()));
+                                }
+                                keep
+                            };
                             if let Some(w) = self.waiting.get(dst) {
                                 // discard all the keys that we aren't waiting for
                                 for_keys.retain(|k| {
-                                    w.redos.contains_key(&(partial_keys.clone(), k.clone()))
+                                    discard(
+                                        k,
+                                        w.redos.contains_key(&(partial_keys.clone(), k.clone())),
+                                    )
                                 });
                             } else if let Some(ref prev) = self.reader_triggered.get(dst) {
                                 // discard all the keys that we aren't waiting for
-                                for_keys.retain(|k| prev.contains(k));
+                                for_keys.retain(|k| discard(k, prev.contains(k)));
                             } else {
                                 // this packet contained no keys that we're waiting for, so it's
                                 // useless to us.
+                                for k in for_keys.iter() {
+                                    discard(k, false);
+                                }
                                 return;
                             }
 
@@ -1908,7 +3100,19 @@ impl Domain {
                             }
                         }
 
+                        // state being rebuilt is filled in on the side, so that lookups keep
+                        // seeing the old state until the replay completes
+                        let rebuild =
+                            i == path.len() - 1 && self.rebuilding.contains_key(segment.node);
+                        if rebuild {
+                            mem::swap(
+                                &mut self.state[segment.node],
+                                &mut self.rebuilding[segment.node],
+                            );
+                        }
+
                         // process the current message in this node
+                        self.replay_process_times.start(segment.node);
                         let (mut misses, lookups, captured) = n.process(
                             &mut m,
                             segment.partial_key.as_ref(),
@@ -1918,6 +3122,14 @@ impl Domain {
                             false,
                             ex,
                         );
+                        self.replay_process_times.stop();
+                        if rebuild {
+                            mem::swap(
+                                &mut self.state[segment.node],
+                                &mut self.rebuilding[segment.node],
+                            );
+                        }
+                        *self.replay_process_counts.entry(segment.node).or_default() += 1;
 
                         // ignore duplicate misses
                         misses.sort_unstable_by(|a, b| {
@@ -2263,7 +3475,7 @@ impl Domain {
                             } else {
                                 m.as_mut().unwrap().link_mut().src = segment.node;
                             }
-                            m.as_mut().unwrap().link_mut().dst = path[i + 1].node;
+                            m.as_mut().unwrap().retarget(path[i + 1].node);
                         }
 
                         // preserve whatever `last` flag that may have been set during processing
@@ -2310,6 +3522,18 @@ impl Domain {
                             unishard: _,
                         } => {
                             assert!(!ignore);
+                            if dst_is_reader || dst_is_target {
+                                for key in &for_keys {
+                                    if let Some(started) =
+                                        self.replay_started.remove(&(tag, key.clone()))
+                                    {
+                                        self.replay_latencies
+                                            .entry(tag)
+                                            .or_default()
+                                            .record(started.elapsed());
+                                    }
+                                }
+                            }
                             if dst_is_reader {
                                 if self.nodes[dst].borrow().beyond_mat_frontier() {
                                     // make sure we eventually evict these from here
@@ -2453,6 +3677,10 @@ impl Domain {
                 // replaying_to is still set, "normal" dispatch calls will continue to be buffered, but
                 // this allows finish_replay to dispatch into the node by overriding replaying_to.
                 self.not_ready.remove(&ni);
+                if let Some(state) = self.rebuilding.remove(ni) {
+                    // the buffered updates must be applied to the rebuilt state
+                    self.state.insert(ni, state);
+                }
                 self.delayed_for_self
                     .push_back(Box::new(Packet::Finish(tag, ni)));
             }
@@ -2541,7 +3769,170 @@ impl Domain {
         }
     }
 
+    /// The tags of the replay paths that end at one of `nodes`.
+    fn tags_ending_at(&self, nodes: &[LocalNodeIndex]) -> HashSet<Tag> {
+        self.replay_paths
+            .iter()
+            .filter(|(_, path)| nodes.contains(&path.path.last().unwrap().node))
+            .map(|(&tag, _)| tag)
+            .collect()
+    }
+
+    /// Drop everything kept for `nodes`, which are being removed, including the replay paths that
+    /// end at them.
+    fn forget_nodes(&mut self, nodes: &[LocalNodeIndex]) {
+        for &node in nodes {
+            self.state.remove(node);
+            self.rebuilding.remove(node);
+            self.prefix_requests.remove(node);
+            self.reader_triggered.remove(node);
+            self.waiting.remove(node);
+            self.replay_paths_by_dst.remove(node);
+        }
+
+        // replays to removed nodes will never finish
+        let tags = self.tags_ending_at(nodes);
+        self.replay_paths.retain(|tag, _| !tags.contains(tag));
+        let replay_paths = &self.replay_paths;
+        self.replay_started
+            .retain(|&(tag, _), _| replay_paths.contains_key(&tag));
+    }
+
+    /// Take the childless `node` out of the domain, along with everything kept for it.
+    ///
+    /// This is refused while a replay to the node is underway, since the replay would come back
+    /// along a path that no longer exists.
+    fn remove_leaf(&mut self, node: LocalNodeIndex) -> Result<(), String> {
+        let tags = self.tags_ending_at(&[node]);
+        let replaying = self
+            .replay_started
+            .keys()
+            .any(|(tag, _)| tags.contains(tag))
+            || self
+                .waiting
+                .values()
+                .any(|w| w.holes.keys().any(|redo| tags.contains(&redo.tag)));
+        if replaying {
+            return Err("a replay to it is still underway".to_owned());
+        }
+
+        remove_node(&mut self.nodes, &mut self.state, node)?;
+        self.not_ready.remove(&node);
+        self.forget_nodes(&[node]);
+        Ok(())
+    }
+
+    /// Throw away the state of `node` so that it is computed anew from its ancestors.
+    ///
+    /// Every filled key of partial state is evicted, along with whatever downstream state was
+    /// derived from it, and is replayed again when next read. Partial state has no old rows to
+    /// serve in the meantime, so it can only be recomputed with `block` set.
+    ///
+    /// Full state is instead refilled by the replays the controller starts once this returns.
+    /// Internal state is rebuilt on the side and swapped in when its replay completes. A reader
+    /// keeps serving its old rows until then, unless `block` is set, in which case reads wait for
+    /// the rebuilt rows.
+    fn recompute(
+        &mut self,
+        node: LocalNodeIndex,
+        block: bool,
+        ex: &mut dyn Executor,
+    ) -> Result<(), String> {
+        let (is_base, is_reader) = match self.nodes.get(node).map(|n| n.borrow()) {
+            Some(ref n) if !n.is_dropped() => (n.is_base(), n.is_reader()),
+            _ => return Err(format!("no node {} in this domain", node.id())),
+        };
+        if is_base {
+            return Err(format!(
+                "cannot recompute {}: base tables have no ancestors",
+                node.id()
+            ));
+        }
+
+        let partial = if is_reader {
+            self.nodes[node]
+                .borrow()
+                .with_reader(|r| r.writer().map(|w| w.is_partial()))
+                .unwrap()
+        } else {
+            self.state.get(node).map(|s| s.is_partial())
+        };
+        let partial =
+            partial.ok_or_else(|| format!("cannot recompute {}: not materialized", node.id()))?;
+        if partial && !block {
+            return Err(format!(
+                "cannot recompute {} without blocking: partial state has no old rows to serve",
+                node.id()
+            ));
+        }
+
+        if partial {
+            let tags: Vec<_> = self
+                .replay_paths_by_dst
+                .get(node)
+                .map(|by_key| by_key.values().flatten().cloned().collect())
+                .unwrap_or_default();
+            for tag in tags {
+                let keys = if is_reader {
+                    self.nodes[node]
+                        .borrow_mut()
+                        .with_reader_mut(|r| {
+                            let w = r.writer_mut().unwrap();
+                            // make every key filled so far visible, so that all are evicted
+                            w.swap();
+                            w.keys()
+                        })
+                        .unwrap()
+                } else {
+                    self.state[node].filled_keys(tag)
+                };
+                if keys.is_empty() {
+                    continue;
+                }
+
+                // evicting a key also evicts whatever was computed from it downstream, so it is
+                // all replayed again from the node's ancestors
+                self.handle_eviction(
+                    Box::new(Packet::EvictKeys {
+                        link: Link::new(node, node),
+                        keys,
+                        tag,
+                    }),
+                    ex,
+                );
+            }
+        } else if is_reader {
+            self.nodes[node]
+                .borrow_mut()
+                .with_reader_mut(|r| {
+                    let w = r.writer_mut().unwrap();
+                    w.swap();
+                    for key in w.keys() {
+                        w.mut_with_key(key).mark_hole();
+                    }
+                    // the emptied keys stay invisible until the rebuilt rows are swapped in
+                    w.set_rebuilding(block);
+                })
+                .unwrap();
+            // the replay snapshot covers any update that arrives before it does
+            self.not_ready.insert(node);
+        } else {
+            let mut state = MemoryState::default();
+            for key in self.state[node].keys() {
+                state.add_key(&key[..], None);
+            }
+            self.rebuilding.insert(node, Box::new(state));
+        }
+        Ok(())
+    }
+
     pub fn handle_eviction(&mut self, m: Box<Packet>, ex: &mut dyn Executor) {
+        self.evict(m, ex);
+        // evictions may reach any node downstream of where they started
+        self.recount_partial_state();
+    }
+
+    fn evict(&mut self, m: Box<Packet>, ex: &mut dyn Executor) {
         #[allow(clippy::too_many_arguments)]
         fn trigger_downstream_evictions(
             log: &Logger,
@@ -2764,6 +4155,16 @@ impl Domain {
         (self.index, self.shard.unwrap_or(0))
     }
 
+    /// Account for a packet that arrived corrupted over a remote connection.
+    pub fn saw_corrupt_packet(&mut self) {
+        self.corrupt_packets += 1;
+    }
+
+    /// Account for a packet this domain produced that could not be sent to another domain.
+    pub fn failed_to_send(&mut self, control: bool) {
+        self.dropped_packets.count(control);
+    }
+
     pub fn booted(&mut self, addr: SocketAddr) {
         info!(self.log, "booted domain"; "nodes" => self.nodes.len());
         self.control_reply_tx
@@ -2771,40 +4172,368 @@ impl Domain {
             .unwrap();
     }
 
-    pub fn update_state_sizes(&mut self) {
-        let total: u64 = self
+    /// Usage statistics for this domain and each of its nodes.
+    fn statistics(&self) -> (DomainStats, HashMap<NodeIndex, NodeStats>) {
+        let node_stats: HashMap<_, _> = self
             .nodes
             .values()
-            .map(|nd| {
+            .filter_map(|nd| {
                 let n = &*nd.borrow();
                 let local_index = n.local_addr();
-
-                if n.is_reader() {
-                    // We are a reader, which has its own kind of state
+                let node_index: NodeIndex = n.global_addr();
+
+                let base = self
+                    .statistics_baselines
+                    .get(local_index)
+                    .cloned()
+                    .unwrap_or_default();
+                let time = self
+                    .process_times
+                    .num_nanoseconds(local_index)
+                    .map(|t| t - base.process_time);
+                let ptime = self
+                    .process_ptimes
+                    .num_nanoseconds(local_index)
+                    .map(|t| t - base.process_ptime);
+                let replay_time = self
+                    .replay_process_times
+                    .num_nanoseconds(local_index)
+                    .map(|t| t - base.replay_process_time);
+                let mem_size = if n.is_reader() {
                     let mut size = 0;
-                    n.with_reader(|r| {
-                        if r.is_partial() {
-                            size = r.state_size().unwrap_or(0)
-                        }
-                    })
-                    .unwrap();
+                    n.with_reader(|r| size = r.state_size().unwrap_or(0))
+                        .unwrap();
                     size
                 } else {
-                    // Not a reader, state is with domain
                     self.state
                         .get(local_index)
-                        .filter(|state| state.is_partial())
                         .map(|s| s.deep_size_of())
                         .unwrap_or(0)
+                };
+
+                let mat_state = if !n.is_reader() {
+                    match self.state.get(local_index) {
+                        Some(ref s) => {
+                            if s.is_partial() {
+                                MaterializationStatus::Partial {
+                                    beyond_materialization_frontier: n.purge,
+                                }
+                            } else {
+                                MaterializationStatus::Full
+                            }
+                        }
+                        None => MaterializationStatus::Not,
+                    }
+                } else {
+                    n.with_reader(|r| {
+                        if r.is_partial() {
+                            MaterializationStatus::Partial {
+                                beyond_materialization_frontier: n.purge,
+                            }
+                        } else {
+                            MaterializationStatus::Full
+                        }
+                    })
+                    .unwrap()
+                };
+
+                let probe_result = if n.is_internal() {
+                    n.probe()
+                } else {
+                    Default::default()
+                };
+
+                if (time.is_some() && ptime.is_some()) || replay_time.is_some() {
+                    let count = |counts: &Map<u64>| counts.get(local_index).cloned().unwrap_or(0);
+                    Some((
+                        node_index,
+                        NodeStats {
+                            desc: format!("{:?}", n),
+                            process_time: time.unwrap_or(0),
+                            process_ptime: ptime.unwrap_or(0),
+                            process_count: count(&self.process_counts),
+                            replay_process_time: replay_time.unwrap_or(0),
+                            replay_process_count: count(&self.replay_process_counts),
+                            mem_size,
+                            materialized: mat_state,
+                            probe_result,
+                        },
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let domain_stats = DomainStats {
+            total_time: self.total_time.num_nanoseconds(),
+            total_ptime: self.total_ptime.num_nanoseconds(),
+            total_replay_time: self.total_replay_time.num_nanoseconds(),
+            total_forward_time: self.total_forward_time.num_nanoseconds(),
+            total_process_time: node_stats.values().map(|ns| ns.process_time).sum(),
+            total_replay_process_time: node_stats.values().map(|ns| ns.replay_process_time).sum(),
+            wait_time: self.wait_time.num_nanoseconds(),
+            total_mem_size: node_stats.values().map(|ns| ns.mem_size).sum(),
+            dropped_packets: self.dropped_packets.data + self.dropped_packets.control,
+            dropped_control_packets: self.dropped_packets.control,
+            corrupt_packets: self.corrupt_packets,
+            misordered_packets: self.link_order.as_ref().map_or(0, |o| o.misordered),
+            rejected_writes: self.rejected_writes,
+            replay_latency: {
+                let mut latency: HashMap<_, _> = self
+                    .replay_latencies
+                    .iter()
+                    .map(|(tag, l)| (tag.id(), l.clone()))
+                    .collect();
+                for &(tag, _) in self.replay_started.keys() {
+                    latency.entry(tag.id()).or_default().in_flight += 1;
+                }
+                latency
+            },
+        };
+
+        (domain_stats, node_stats)
+    }
+
+    /// Count processing statistics from zero again, for one node or for all of them.
+    fn reset_statistics(&mut self, node: Option<LocalNodeIndex>) -> Result<(), String> {
+        let nodes: Vec<_> = match node {
+            Some(node) if self.nodes.contains_key(node) => vec![node],
+            Some(node) => return Err(format!("no node {} in this domain", node.id())),
+            None => self.nodes.iter().map(|(node, _)| node).collect(),
+        };
+
+        // packets are processed one at a time, so none of the timers are running right now
+        for node in nodes {
+            let base = StatisticsBaseline {
+                process_time: self.process_times.num_nanoseconds(node).unwrap_or(0),
+                process_ptime: self.process_ptimes.num_nanoseconds(node).unwrap_or(0),
+                replay_process_time: self.replay_process_times.num_nanoseconds(node).unwrap_or(0),
+            };
+            self.statistics_baselines.insert(node, base);
+            self.process_counts.remove(node);
+            self.replay_process_counts.remove(node);
+        }
+        Ok(())
+    }
+
+    /// Push a statistics snapshot to every subscriber that is due one.
+    fn push_statistics(&mut self) {
+        if self.statistics_subscriptions.is_empty() {
+            return;
+        }
+
+        let mut subscriptions = mem::replace(&mut self.statistics_subscriptions, Vec::new());
+        push_statistics(&mut subscriptions, time::Instant::now(), || {
+            self.statistics()
+        });
+        self.statistics_subscriptions = subscriptions;
+    }
+
+    /// The size, in bytes, of the state of `node` if it is partially materialized, or 0 if not.
+    fn partial_size_of(&self, node: LocalNodeIndex) -> u64 {
+        let n = match self.nodes.get(node) {
+            Some(n) => n.borrow(),
+            None => return 0,
+        };
+
+        if n.is_reader() {
+            // We are a reader, which has its own kind of state
+            let mut size = 0;
+            n.with_reader(|r| {
+                if r.is_partial() {
+                    size = r.state_size().unwrap_or(0)
                 }
             })
-            .sum();
+            .unwrap();
+            size
+        } else {
+            // Not a reader, state is with domain
+            self.state
+                .get(node)
+                .filter(|state| state.is_partial())
+                .map(|s| s.deep_size_of())
+                .unwrap_or(0)
+        }
+    }
+
+    /// Bring the size we keep of the partial state of `node` up to date, after it may have
+    /// changed.
+    fn resize_partial(&mut self, node: LocalNodeIndex) {
+        let size = self.partial_size_of(node);
+        let old = self.partial_sizes.insert(node, size).unwrap_or(0);
+        self.partial_state_size = self.partial_state_size + size - old;
+    }
 
-        self.state_size.store(total as usize, Ordering::Relaxed);
+    /// Bring the size we keep of the partial state of every node along a replay path up to date.
+    fn resize_replay_path(&mut self, tag: Option<Tag>) {
+        let nodes: Vec<_> = match tag.and_then(|tag| self.replay_paths.get(&tag)) {
+            Some(path) => path.path.iter().map(|segment| segment.node).collect(),
+            None => return,
+        };
+        for node in nodes {
+            self.resize_partial(node);
+        }
+    }
+
+    /// Look at the partial state of every node anew, for when it may have changed in ways we
+    /// don't keep track of node by node.
+    fn recount_partial_state(&mut self) {
+        let sizes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(node, _)| (node, self.partial_size_of(node)))
+            .collect();
+        self.partial_sizes = Map::new();
+        self.partial_state_size = 0;
+        for (node, size) in sizes {
+            self.partial_sizes.insert(node, size);
+            self.partial_state_size += size;
+        }
+    }
+
+    pub fn update_state_sizes(&mut self) {
+        self.state_size
+            .store(self.partial_state_size as usize, Ordering::Relaxed);
         // no response sent, as worker will read the atomic
     }
 
+    /// Evict partial state if we have grown beyond our memory limit.
+    ///
+    /// Once above the limit, we keep evicting until we are comfortably below it again, so that we
+    /// don't end up evicting on every subsequent write. Each eviction picks a node at random,
+    /// weighted by the size of its partial state, so that no one node is always the one to lose
+    /// its state.
+    fn enforce_memory_limit(&mut self, executor: &mut dyn Executor) {
+        let limit = match self.memory_limit {
+            Some(limit) => limit as u64,
+            None => return,
+        };
+
+        let mut size = self.partial_state_size;
+        if size <= limit {
+            return;
+        }
+
+        let low_water = limit - limit / 10;
+        debug!(self.log, "partial state exceeds memory limit; evicting";
+               "size" => size, "limit" => limit);
+        let mut rng = rand::thread_rng();
+        while size > low_water {
+            let candidates: Vec<_> = self
+                .partial_sizes
+                .iter()
+                .map(|(node, &size)| (node, size))
+                .filter(|&(_, size)| size > 0)
+                .collect();
+            let (node, node_size) = match candidates.choose_weighted(&mut rng, |&(_, size)| size) {
+                Ok(&victim) => victim,
+                Err(_) => break,
+            };
+            trace!(
+                self.log,
+                "chose to evict from node {:?} with size {}",
+                node,
+                node_size
+            );
+
+            self.handle_eviction(
+                Box::new(Packet::Evict {
+                    node: Some(node),
+                    num_bytes: cmp::min(size - low_water, node_size) as usize,
+                }),
+                executor,
+            );
+
+            if self.partial_state_size >= size {
+                // nothing more we can evict
+                break;
+            }
+            size = self.partial_state_size;
+        }
+    }
+
+    /// Process a packet, passing writes to base tables through group commit first.
+    fn commit_or_handle(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) {
+        if self.group_commit_queues.should_append(&packet, &self.nodes) {
+            if let Some(packet) = self.group_commit_queues.append(packet) {
+                self.handle(packet, executor, true);
+            }
+        } else {
+            self.handle(packet, executor, true);
+        }
+    }
+
+    /// Process the writes held back by ingress rate limits that may now be accepted, or all of
+    /// them if `all` is set.
+    fn release_rate_limited(&mut self, executor: &mut dyn Executor, all: bool) {
+        if self.ingress_limits.is_empty() {
+            return;
+        }
+
+        let now = time::Instant::now();
+        let mut released = Vec::new();
+        let mut idle = Vec::new();
+        for (node, limit) in self.ingress_limits.iter_mut() {
+            if all {
+                released.extend(limit.release_all());
+            } else {
+                while let Some(m) = limit.release(now) {
+                    released.push(m);
+                }
+            }
+            if limit.is_idle() {
+                idle.push(node);
+            }
+        }
+        for node in idle {
+            self.ingress_limits.remove(node);
+        }
+
+        for m in released {
+            self.commit_or_handle(m, executor);
+        }
+    }
+
+    /// Drop a write rejected by its base table's ingress rate limit, but acknowledge it so that
+    /// the client doesn't wait for it forever.
+    fn reject_write(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) {
+        match *packet {
+            Packet::Input {
+                inner,
+                src,
+                senders,
+                ..
+            } => {
+                unsafe { inner.take() };
+                src.into_iter()
+                    .chain(senders)
+                    .for_each(|src| executor.ack(src));
+            }
+            _ => unreachable!(),
+        }
+        self.rejected_writes += 1;
+    }
+
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
+        self.handle_event(executor, event)
+    }
+
+    /// True once the domain has quit after a `Packet::QuitDrain`: it has processed that packet,
+    /// and an `UpstreamQuit` from each of the upstream domain shards it names.
+    pub fn has_quit(&self) -> bool {
+        self.quit_after_upstreams == Some(self.upstream_quits)
+    }
+
+    /// Process the data packets held back since the domain was paused, and stop holding them.
+    fn resume(&mut self, executor: &mut dyn Executor) {
+        // only data packets are held, so none of these can be a Quit
+        for m in self.paused.take().unwrap_or_default() {
+            self.on_event(executor, PollEvent::Process(m));
+        }
+    }
+
+    fn handle_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
         if self.wait_time.is_running() {
             self.wait_time.stop();
         }
@@ -2833,37 +4562,84 @@ impl Domain {
                     }
                 });
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                let opt4 = self
+                    .statistics_subscriptions
+                    .iter()
+                    .map(|sub| {
+                        sub.next
+                            .checked_duration_since(now)
+                            .unwrap_or(time::Duration::from_millis(0))
+                    })
+                    .min();
+
+                let opt5 = self
+                    .ingress_limits
+                    .values()
+                    .filter_map(IngressRateLimit::next_release)
+                    .map(|t| {
+                        t.checked_duration_since(now)
+                            .unwrap_or(time::Duration::from_millis(0))
+                    })
+                    .min();
+
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4).or(opt5);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
+                if let Some(opt5) = opt5 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt5));
+                }
+                if self.paused.is_some() {
+                    // buffered replays, group commits, purges, and rate limited writes all process
+                    // data, so they wait until we're resumed
+                    timeout = opt4;
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
-                if let Packet::Quit = *packet {
-                    return ProcessResult::StopPolling;
-                }
-
-                // TODO: Initialize tracer here, and when flushing group commit
-                // queue.
-                if self.group_commit_queues.should_append(&packet, &self.nodes) {
-                    if let Some(packet) = self.group_commit_queues.append(packet) {
-                        self.handle(packet, executor, true);
+                if !packet.is_control() && !self.has_quit() {
+                    if let Some(ref mut held) = self.paused {
+                        let quit = if let Packet::QuitDrain { .. } = *packet {
+                            true
+                        } else {
+                            false
+                        };
+                        held.push_back(packet);
+                        if quit {
+                            // a domain that is asked to quit must first process everything it
+                            // received, including what it has held back, so it resumes
+                            self.resume(executor);
+                        }
+                        return ProcessResult::Processed;
                     }
-                } else {
-                    self.handle(packet, executor, true);
                 }
 
-                while let Some(m) = self.group_commit_queues.flush_if_necessary() {
-                    self.handle(m, executor, true);
+                match self.packet_observer.clone() {
+                    Some(o) => {
+                        if o.drop_packet(&packet) {
+                            return ProcessResult::Processed;
+                        }
+                        o.on_receive(&packet);
+                        let summary = PacketSummary::of(&packet);
+                        let res = self.process(packet, executor);
+                        o.on_complete(&summary);
+                        res
+                    }
+                    None => self.process(packet, executor),
                 }
-
+            }
+            PollEvent::Timeout if self.paused.is_some() => {
+                self.push_statistics();
                 ProcessResult::Processed
             }
             PollEvent::Timeout => {
+                self.release_rate_limited(executor, false);
                 while let Some(m) = self.group_commit_queues.flush_if_necessary() {
                     self.handle(m, executor, true);
                 }
@@ -2872,6 +4648,8 @@ impl Domain {
                     self.handle(Box::new(Packet::Spin), executor, true);
                 }
 
+                self.push_statistics();
+
                 ProcessResult::Processed
             }
         };
@@ -2880,4 +4658,922 @@ impl Domain {
         }
         res
     }
+
+    /// Process a packet that is not being held back.
+    fn process(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) -> ProcessResult {
+        if let Packet::Quit = *packet {
+            return ProcessResult::StopPolling;
+        }
+
+        if self.has_quit() {
+            // nothing that arrives after we quit is processed. whoever sent a control
+            // packet may be waiting for a reply, so they're told that we're gone.
+            if packet.is_control() {
+                self.control_reply_tx
+                    .send(ControlReplyPacket::Nack("domain has quit".to_owned()))
+                    .unwrap();
+            }
+            return ProcessResult::Processed;
+        }
+
+        match *packet {
+            Packet::Pause => {
+                self.paused.get_or_insert_with(VecDeque::new);
+                self.control_reply_tx
+                    .send(ControlReplyPacket::ack())
+                    .unwrap();
+                return ProcessResult::Processed;
+            }
+            Packet::Resume => {
+                self.resume(executor);
+                self.control_reply_tx
+                    .send(ControlReplyPacket::ack())
+                    .unwrap();
+                return ProcessResult::Processed;
+            }
+            Packet::Step => {
+                let reply = match self.paused.as_mut().map(VecDeque::pop_front) {
+                    None => ControlReplyPacket::Nack("domain is not paused".to_owned()),
+                    Some(None) => ControlReplyPacket::ack(),
+                    Some(Some(m)) => {
+                        let quit = if let Packet::QuitDrain { .. } = *m {
+                            true
+                        } else {
+                            false
+                        };
+                        let held = self.paused.take();
+                        self.stepping = true;
+                        self.on_event(executor, PollEvent::Process(m));
+                        self.stepping = false;
+                        // a stepped input shouldn't sit in group commit until we resume
+                        while let Some(m) = self.group_commit_queues.flush_any() {
+                            self.handle(m, executor, true);
+                        }
+                        self.paused = held;
+                        if quit {
+                            // as when a quit arrives while paused
+                            self.resume(executor);
+                        }
+                        ControlReplyPacket::ack()
+                    }
+                };
+                self.control_reply_tx.send(reply).unwrap();
+                return ProcessResult::Processed;
+            }
+            _ => {}
+        }
+
+        match *packet {
+            Packet::FlushAndAck(..) | Packet::QuitDrain { .. } | Packet::UpstreamQuit => {
+                // inputs held back by rate limits or waiting for group commit were
+                // received before the flush request
+                self.release_rate_limited(executor, true);
+                while let Some(m) = self.group_commit_queues.flush_any() {
+                    self.handle(m, executor, true);
+                }
+            }
+            _ => {}
+        }
+
+        match *packet {
+            Packet::QuitDrain { upstreams } => {
+                self.quit_after_upstreams = Some(upstreams);
+            }
+            Packet::UpstreamQuit => self.upstream_quits += 1,
+            _ => {}
+        }
+        if let Packet::QuitDrain { .. } | Packet::UpstreamQuit = *packet {
+            if self.has_quit() {
+                // everything we will ever receive has been processed, so let our
+                // downstream domains know that nothing more is coming from us. the
+                // replica stops feeding us packets once it has sent this along.
+                let mut downstream: Vec<_> = self
+                    .nodes
+                    .values()
+                    .flat_map(|n| n.borrow().downstream_replicas())
+                    .collect();
+                downstream.sort();
+                downstream.dedup();
+                for addr in downstream {
+                    executor.send(addr, Box::new(Packet::UpstreamQuit));
+                }
+                self.control_reply_tx
+                    .send(ControlReplyPacket::ack())
+                    .unwrap();
+            }
+            return ProcessResult::Processed;
+        }
+
+        if let Some(ref mut order) = self.link_order {
+            if let Some(link) = packet.try_link() {
+                order.saw(link);
+            }
+        }
+
+        // TODO: Initialize tracer here, and when flushing group commit
+        // queue.
+        let limit = match *packet {
+            Packet::Input { .. } => self.ingress_limits.get_mut(packet.dst()),
+            _ => None,
+        };
+        if let Some(limit) = limit {
+            if self.stepping {
+                // a stepped write shouldn't wait for the rate limit, but it mustn't overtake
+                // the writes held before it either
+                let mut released: Vec<_> = limit.release_all().collect();
+                released.push(packet);
+                for m in released {
+                    self.commit_or_handle(m, executor);
+                }
+            } else if let Err(packet) = limit.hold(packet, time::Instant::now()) {
+                self.reject_write(packet, executor);
+            } else {
+                self.release_rate_limited(executor, false);
+            }
+        } else {
+            self.commit_or_handle(packet, executor);
+        }
+
+        while let Some(m) = self.group_commit_queues.flush_if_necessary() {
+            self.handle(m, executor, true);
+        }
+
+        self.enforce_memory_limit(executor);
+        self.push_statistics();
+
+        ProcessResult::Processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_queue_priority() {
+        let entries = [
+            ("low1", 0),
+            ("high1", 2),
+            ("mid", 1),
+            ("low2", 0),
+            ("high2", 2),
+        ];
+        let mut queue = VecDeque::new();
+        for &(name, priority) in &entries {
+            let i = priority_position(&queue, priority, |&(_, p)| p);
+            queue.insert(i, (name, priority));
+        }
+
+        let order: Vec<_> = queue.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(order, vec!["high1", "high2", "mid", "low1", "low2"]);
+    }
+
+    #[test]
+    fn replay_queue_bounded() {
+        let tag = Tag(0);
+        let priority_of = |_| 0;
+
+        let mut queue = ReplayRequestQueue::new(Some(2));
+        for i in 0..2 {
+            assert!(!queue.is_full());
+            queue.push(tag, vec![vec![i.into()]], 0, priority_of);
+        }
+        assert!(queue.is_full());
+        assert_eq!(queue.len(), 2);
+
+        // queued requests are still released in order
+        assert_eq!(queue.pop_front(), Some((tag, vec![vec![0.into()]])));
+        assert!(!queue.is_full());
+        assert_eq!(queue.pop_front(), Some((tag, vec![vec![1.into()]])));
+        assert_eq!(queue.pop_front(), None);
+
+        let mut unbounded = ReplayRequestQueue::new(None);
+        for i in 0..100 {
+            unbounded.push(tag, vec![vec![i.into()]], 0, priority_of);
+        }
+        assert!(!unbounded.is_full());
+    }
+
+    #[test]
+    fn replay_queue_cancel() {
+        let tag = Tag(0);
+        let other = Tag(1);
+        let priority_of = |_| 0;
+
+        let mut queue = ReplayRequestQueue::new(None);
+        queue.push(tag, vec![vec![1.into()], vec![2.into()]], 0, priority_of);
+        queue.push(other, vec![vec![1.into()]], 0, priority_of);
+        queue.push(tag, vec![vec![1.into()]], 0, priority_of);
+        assert!(queue.contains(tag, &[1.into()]));
+        assert!(!queue.contains(other, &[2.into()]));
+
+        // cancelling an unknown key or tag does nothing
+        assert!(!queue.cancel(tag, &[3.into()]));
+        assert!(!queue.cancel(Tag(2), &[1.into()]));
+        assert_eq!(queue.len(), 3);
+
+        // the key is dropped from every request along the tag, and requests left empty go away
+        assert!(queue.cancel(tag, &[1.into()]));
+        assert!(!queue.contains(tag, &[1.into()]));
+        assert_eq!(queue.len(), 2);
+
+        // so no replay is ever requested for the cancelled key
+        assert_eq!(queue.pop_front(), Some((tag, vec![vec![2.into()]])));
+        assert_eq!(queue.pop_front(), Some((other, vec![vec![1.into()]])));
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn replay_path_validation() {
+        use crate::node::NodeType;
+        use crate::payload::TriggerEndpoint;
+
+        let (a, b, c) = unsafe {
+            (
+                LocalNodeIndex::make(0),
+                LocalNodeIndex::make(1),
+                LocalNodeIndex::make(2),
+            )
+        };
+        let mut nodes = DomainNodes::default();
+        for &(ni, width) in &[(a, 2), (b, 2), (c, 1)] {
+            let fields: Vec<_> = (0..width).map(|i| format!("f{}", i)).collect();
+            let n = Node::new("n", fields, NodeType::Ingress);
+            nodes.insert(ni, cell::RefCell::new(n));
+        }
+        nodes[a].borrow_mut().add_child(b);
+        nodes[b].borrow_mut().add_child(c);
+
+        let segment = |node, partial_key| ReplayPathSegment { node, partial_key };
+        let path = vec![segment(a, None), segment(b, Some(vec![1]))];
+
+        assert_eq!(
+            check_replay_path(&nodes, Some(a), &path, &TriggerEndpoint::Start(vec![0, 1])),
+            Ok(())
+        );
+
+        // a trigger column past the end of the source node
+        assert!(
+            check_replay_path(&nodes, Some(a), &path, &TriggerEndpoint::Start(vec![2])).is_err()
+        );
+        // a partial key column past the end of its node
+        let bad_key = vec![
+            segment(a, None),
+            segment(b, Some(vec![0])),
+            segment(c, Some(vec![1])),
+        ];
+        assert!(check_replay_path(&nodes, Some(a), &bad_key, &TriggerEndpoint::None).is_err());
+        // a path that skips a node
+        let gap = vec![segment(a, None), segment(c, None)];
+        assert!(check_replay_path(&nodes, Some(a), &gap, &TriggerEndpoint::None).is_err());
+        // a trigger at the start of a path without a source
+        assert!(check_replay_path(&nodes, None, &path, &TriggerEndpoint::Local(vec![0])).is_err());
+    }
+
+    #[test]
+    fn reparent_union_input() {
+        use crate::node::special::Base;
+        use crate::node::NodeType;
+        use crate::ops::union::Union;
+
+        let mut graph = Graph::new();
+        let source = graph.add_node(Node::new("source", &["x"], NodeType::Source));
+        let mut base = |name: &str| {
+            let ni = graph.add_node(Node::new(name, &["a", "b"], Base::default()));
+            graph.add_edge(source, ni, ());
+            ni
+        };
+        let (a, b, c) = (base("a"), base("b"), base("c"));
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![1, 0]);
+        emits.insert(b, vec![0, 1]);
+        let mut union = Union::new(emits);
+        union.on_connected(&graph);
+        let union: NodeOperator = union.into();
+        let u = graph.add_node(Node::new("u", &["a", "b"], union));
+        graph.add_edge(a, u, ());
+        graph.add_edge(b, u, ());
+
+        let mut remap = HashMap::new();
+        for (i, &ni) in [a, b, c, u].iter().enumerate() {
+            let mut ip = IndexPair::from(ni);
+            ip.set_local(unsafe { LocalNodeIndex::make(i as u32) });
+            graph[ni].set_finalized_addr(ip);
+            graph[ni].add_to(0.into());
+            remap.insert(ni, ip);
+        }
+        let mut nodes = DomainNodes::default();
+        for &ni in &[a, b, c, u] {
+            graph[ni].on_commit(&remap);
+            let n = graph[ni].take().finalize(&graph);
+            nodes.insert(n.local_addr(), cell::RefCell::new(n));
+        }
+        let (a, b, c, u) = (*remap[&a], *remap[&b], *remap[&c], *remap[&u]);
+
+        // neither end of the link can be missing, and the old end has to be a parent
+        let missing = unsafe { LocalNodeIndex::make(10) };
+        assert!(reparent_node(&nodes, u, a, missing).is_err());
+        assert!(reparent_node(&nodes, u, c, a).is_err());
+        assert!(reparent_node(&nodes, u, a, b).is_err());
+
+        assert_eq!(reparent_node(&nodes, u, a, c), Ok(()));
+        assert!(nodes[u].borrow().parents().contains(&c));
+        assert!(!nodes[u].borrow().parents().contains(&a));
+        assert!(nodes[a].borrow().children().is_empty());
+        assert_eq!(nodes[c].borrow().children(), &[u]);
+
+        // c's rows are now emitted the way a's were
+        let mut m = Some(Box::new(Packet::Message {
+            link: Link::new(c, u),
+            data: vec![vec![1.into(), 2.into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        }));
+        nodes[u].borrow_mut().process(
+            &mut m,
+            None,
+            &mut StateMap::new(),
+            &nodes,
+            None,
+            true,
+            &mut Ex,
+        );
+        match m.map(|p| *p) {
+            Some(Packet::Message { data, .. }) => {
+                assert_eq!(data, vec![vec![2.into(), 1.into()]].into())
+            }
+            p => panic!("expected union output, got {:?}", p),
+        }
+    }
+
+    #[test]
+    fn remove_leaf_node() {
+        use crate::node::special::Base;
+        use crate::node::NodeType;
+        use crate::ops::identity::Identity;
+
+        let mut graph = Graph::new();
+        let source = graph.add_node(Node::new("source", &["x"], NodeType::Source));
+        let a = graph.add_node(Node::new("a", &["a", "b"], Base::default()));
+        graph.add_edge(source, a, ());
+        let b = graph.add_node(Node::new("b", &["a", "b"], Identity::new(a)));
+        graph.add_edge(a, b, ());
+
+        let mut remap = HashMap::new();
+        for (i, &ni) in [a, b].iter().enumerate() {
+            let mut ip = IndexPair::from(ni);
+            ip.set_local(unsafe { LocalNodeIndex::make(i as u32) });
+            graph[ni].set_finalized_addr(ip);
+            graph[ni].add_to(0.into());
+            remap.insert(ni, ip);
+        }
+        let mut nodes = DomainNodes::default();
+        for &ni in &[a, b] {
+            graph[ni].on_commit(&remap);
+            let n = graph[ni].take().finalize(&graph);
+            nodes.insert(n.local_addr(), cell::RefCell::new(n));
+        }
+        let (a, b) = (*remap[&a], *remap[&b]);
+
+        let mut state = StateMap::new();
+        let mut s = MemoryState::default();
+        s.add_key(&[0], None);
+        let mut records: Records = vec![vec![1.into(), 2.into()]].into();
+        s.process_records(&mut records, None);
+        state.insert(b, Box::new(s));
+
+        // a node with children cannot go, and neither can one that does not exist
+        assert!(remove_node(&mut nodes, &mut state, a).is_err());
+        let missing = unsafe { LocalNodeIndex::make(10) };
+        assert!(remove_node(&mut nodes, &mut state, missing).is_err());
+
+        // the slot is emptied, so the index can be given to a new node
+        assert_eq!(remove_node(&mut nodes, &mut state, b), Ok(()));
+        assert!(!nodes.contains_key(b));
+        assert!(!state.contains_key(b));
+        assert!(nodes[a].borrow().children().is_empty());
+        assert!(remove_node(&mut nodes, &mut state, b).is_err());
+
+        // the parent is now a leaf, so it can go too
+        assert_eq!(remove_node(&mut nodes, &mut state, a), Ok(()));
+        assert!(nodes.is_empty());
+    }
+
+    #[test]
+    fn add_index_to_running_state() {
+        let rows: Vec<Vec<DataType>> = (0..10).map(|i| vec![i.into(), (i % 3).into()]).collect();
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut records: Records = rows.iter().cloned().map(Record::Positive).collect();
+        state.process_records(&mut records, None);
+
+        assert_eq!(add_full_index(&mut state, 2, &[1]), Ok(true));
+        for k in 0..3 {
+            let k = DataType::from(k);
+            match state.lookup(&[1], &KeyType::Single(&k)) {
+                LookupResult::Some(rs) => {
+                    let mut found: Vec<_> = rs.into_iter().map(|r| r.into_owned()).collect();
+                    found.sort();
+                    let expected: Vec<_> = rows.iter().filter(|r| r[1] == k).cloned().collect();
+                    assert_eq!(found, expected);
+                }
+                LookupResult::Missing => unreachable!(),
+            }
+        }
+
+        // adding it again is a no-op
+        assert_eq!(add_full_index(&mut state, 2, &[1]), Ok(false));
+        assert_eq!(state.keys().len(), 2);
+        assert!(add_full_index(&mut state, 2, &[2]).is_err());
+
+        let mut partial = MemoryState::default();
+        partial.add_key(&[0], Some(vec![Tag(0)]));
+        assert!(add_full_index(&mut partial, 2, &[1]).is_err());
+    }
+
+    #[test]
+    fn drop_index_from_running_state() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        let mut records: Records = (0..100)
+            .map(|i| Record::Positive(vec![i.into(), (i % 7).into()]))
+            .collect();
+        state.process_records(&mut records, None);
+        let (rows, bytes) = (state.rows(), state.deep_size_of());
+
+        assert_eq!(add_full_index(&mut state, 2, &[1]), Ok(true));
+        assert_eq!(state.rows(), 2 * rows);
+        let indexed_bytes = state.deep_size_of();
+        assert!(indexed_bytes > bytes);
+
+        // the first index cannot go, and neither can one that does not exist
+        assert!(drop_full_index(&mut state, &[0]).is_err());
+        assert!(drop_full_index(&mut state, &[0, 1]).is_err());
+
+        // the state size probe reports index entries and row bytes; the rows are shared between
+        // indices, so dropping the secondary index frees just its entries
+        assert_eq!(drop_full_index(&mut state, &[1]), Ok(()));
+        assert_eq!(state.keys(), vec![vec![0]]);
+        assert_eq!(state.rows(), rows);
+        assert!(state.deep_size_of() < indexed_bytes);
+        assert_eq!(state.deep_size_of(), bytes);
+        assert!(drop_full_index(&mut state, &[1]).is_err());
+    }
+
+    #[test]
+    fn chunk_replay_progress() {
+        let total = 2 * BATCH_SIZE + 10;
+        let state: Vec<Vec<DataType>> = (0..total).map(|i| vec![i.into()]).collect();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let progress = channel::ChannelSender::from_local(tx);
+
+        let mut batches = Vec::new();
+        chunk_replay(
+            state,
+            |r| r,
+            Some(&progress),
+            |i, chunk, last| {
+                batches.push((i, chunk.len(), last));
+                true
+            },
+        );
+
+        assert_eq!(
+            batches,
+            vec![
+                (0, BATCH_SIZE, false),
+                (1, BATCH_SIZE, false),
+                (2, 10, true)
+            ]
+        );
+
+        let reported: Vec<_> = rx.try_iter().collect();
+        assert_eq!(reported, vec![BATCH_SIZE, 2 * BATCH_SIZE, total]);
+    }
+
+    #[test]
+    fn dropped_packets_are_counted() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Box<Packet>>();
+        let mut tx: Box<dyn channel::Sender<Item = Box<Packet>> + Send> = Box::new(tx);
+        let mut dropped = DroppedPackets::default();
+
+        assert!(dropped.send(&mut *tx, Box::new(Packet::Spin)));
+        assert_eq!(dropped.data + dropped.control, 0);
+
+        // once the receiving domain has gone away, every send fails
+        drop(rx);
+        for _ in 0..3 {
+            assert!(!dropped.send(&mut *tx, Box::new(Packet::Spin)));
+        }
+        for _ in 0..2 {
+            assert!(!dropped.send(&mut *tx, Box::new(Packet::GetStatistics)));
+        }
+        assert_eq!(dropped.data, 3);
+        assert_eq!(dropped.control, 2);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "arrived after packet"))]
+    fn misordered_link() {
+        let link = |src: u32, seq: Option<u64>| {
+            let mut link = unsafe { Link::new(LocalNodeIndex::make(src), LocalNodeIndex::make(9)) };
+            link.seq = seq;
+            link
+        };
+
+        let mut order = LinkOrder::default();
+        order.saw(&link(0, Some(0)));
+        order.saw(&link(0, Some(1)));
+        // packets that weren't sent by another replica aren't checked
+        order.saw(&link(0, None));
+        // and each link has an order of its own
+        order.saw(&link(1, Some(0)));
+        order.saw(&link(0, Some(2)));
+        assert_eq!(order.misordered, 0);
+
+        // packet 1 is delivered again, after packet 2
+        order.saw(&link(0, Some(1)));
+        assert_eq!(order.misordered, 1);
+    }
+
+    #[test]
+    fn statistics_subscription() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let interval = time::Duration::from_millis(10);
+        let start = time::Instant::now();
+        let mut subscriptions = vec![StatisticsSubscription {
+            interval,
+            next: start + interval,
+            sink: channel::ChannelSender::from_local(tx),
+        }];
+
+        // nothing is sent before the first interval has passed
+        push_statistics(&mut subscriptions, start, || 0);
+        assert!(rx.try_recv().is_err());
+
+        let mut now = start;
+        for i in 1..=3 {
+            now += interval;
+            push_statistics(&mut subscriptions, now, || i);
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        // dropping the sink cancels the subscription on the next tick
+        drop(rx);
+        let mut snapshots = 0;
+        now += interval;
+        push_statistics(&mut subscriptions, now, || {
+            snapshots += 1;
+            4
+        });
+        assert!(subscriptions.is_empty());
+
+        now += interval;
+        push_statistics(&mut subscriptions, now, || {
+            snapshots += 1;
+            5
+        });
+        assert_eq!(snapshots, 1);
+    }
+
+    #[test]
+    fn schema_ops_applied_once() {
+        let mut applied = AppliedSchemaOps::default();
+        assert!(applied.insert(1));
+        assert!(!applied.insert(1));
+        assert!(applied.insert(2));
+        assert!(!applied.insert(1));
+
+        // only the most recent changes are remembered
+        for op_id in 3..(3 + SCHEMA_OP_HISTORY as u64) {
+            assert!(applied.insert(op_id));
+        }
+        assert!(applied.insert(1));
+    }
+
+    struct Ex;
+    impl Executor for Ex {
+        fn ack(&mut self, _: SourceChannelIdentifier) {}
+        fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+        fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
+    }
+
+    /// Build a domain holding a base node with columns `a` and `b`, and a reader keyed on `a`
+    /// below it.
+    ///
+    /// Also returns the addresses of the base and the reader, and the stream the domain sends its
+    /// control replies on.
+    fn test_domain(
+        coordinator: ChannelCoordinator,
+        max_concurrent_replays: usize,
+        max_pending_replays: Option<usize>,
+    ) -> (Domain, LocalNodeIndex, LocalNodeIndex, std::net::TcpStream) {
+        use crate::node::special::{Base, Reader};
+        use crate::node::NodeType;
+
+        let mut graph = Graph::new();
+        let source = graph.add_node(Node::new("source", &["x"], NodeType::Source));
+        let a = graph.add_node(Node::new("a", &["a", "b"], Base::default()));
+        graph.add_edge(source, a, ());
+        let r = graph.add_node(Node::new("r", &["a", "b"], Reader::new(a)));
+        graph.add_edge(a, r, ());
+        graph[r].with_reader_mut(|r| r.set_key(&[0])).unwrap();
+
+        let mut remap = HashMap::new();
+        for (i, &ni) in [a, r].iter().enumerate() {
+            let mut ip = IndexPair::from(ni);
+            ip.set_local(unsafe { LocalNodeIndex::make(i as u32) });
+            graph[ni].set_finalized_addr(ip);
+            graph[ni].add_to(0.into());
+            remap.insert(ni, ip);
+        }
+        let mut nodes = DomainNodes::default();
+        for &ni in &[a, r] {
+            graph[ni].on_commit(&remap);
+            let n = graph[ni].take().finalize(&graph);
+            nodes.insert(n.local_addr(), cell::RefCell::new(n));
+        }
+        let (a, r) = (*remap[&a], *remap[&r]);
+
+        let controller = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (_trigger, valve) = Valve::new();
+        let domain = DomainBuilder {
+            index: 0.into(),
+            shard: None,
+            nshards: 1,
+            nodes,
+            persistence_parameters: Default::default(),
+            config: Config {
+                concurrent_replays: max_concurrent_replays,
+                max_pending_replays,
+                replay_batch_timeout: time::Duration::from_millis(0),
+                check_link_order: false,
+            },
+        }
+        .build(
+            Logger::root(slog::Discard, o!()),
+            Default::default(),
+            Arc::new(coordinator),
+            controller.local_addr().unwrap(),
+            &valve,
+            Arc::new(AtomicUsize::new(0)),
+            None,
+        );
+        let (replies, _) = controller.accept().unwrap();
+        (domain, a, r, replies)
+    }
+
+    /// A path into `node`'s state keyed on column 0, triggered in domain 1.
+    fn replay_path_into(node: LocalNodeIndex, tag: Tag, priority: Option<u8>) -> Box<Packet> {
+        Box::new(Packet::SetupReplayPath {
+            tag,
+            source: None,
+            path: vec![ReplayPathSegment {
+                node,
+                partial_key: Some(vec![0]),
+            }],
+            notify_done: false,
+            trigger: crate::payload::TriggerEndpoint::End(SourceSelection::AllShards(1), 1.into()),
+            priority,
+            compress: false,
+        })
+    }
+
+    fn read_reply(replies: &mut std::net::TcpStream) -> ControlReplyPacket {
+        use std::io::Read;
+
+        let mut size = [0; 4];
+        replies.read_exact(&mut size).unwrap();
+        let mut reply = vec![0; u32::from_be_bytes(size) as usize];
+        replies.read_exact(&mut reply).unwrap();
+        bincode::deserialize(&reply).unwrap()
+    }
+
+    #[test]
+    fn retried_schema_op_acked_and_applied_once() {
+        let (mut domain, a, _, mut replies) = test_domain(ChannelCoordinator::new(), 1, None);
+
+        // the same change arrives twice, as it does when the controller retries it
+        for _ in 0..2 {
+            let m = Box::new(Packet::AddBaseColumns {
+                node: a,
+                fields: vec![("c".to_owned(), 3.into())],
+                op_id: 7,
+            });
+            domain.on_event(&mut Ex, PollEvent::Process(m));
+        }
+
+        // both deliveries are acked, so the retry doesn't leave the controller waiting
+        for _ in 0..2 {
+            match read_reply(&mut replies) {
+                ControlReplyPacket::Ack(_) => {}
+                reply => panic!("expected an ack, got {:?}", reply),
+            }
+        }
+
+        // but the column is only added once
+        assert_eq!(domain.nodes[a].borrow().fields(), &["a", "b", "c"]);
+    }
+
+    #[test]
+    fn buffered_replay_requests_sent_by_priority() {
+        let coordinator = ChannelCoordinator::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        coordinator.insert_local((1.into(), 0), tx);
+        let (mut domain, a, _, mut replies) = test_domain(coordinator, 1, None);
+
+        // three paths into the base's state, triggered in another domain
+        for &(tag, priority) in &[(Tag(0), None), (Tag(1), Some(1)), (Tag(2), Some(5))] {
+            let m = replay_path_into(a, tag, priority);
+            domain.on_event(&mut Ex, PollEvent::Process(m));
+            match read_reply(&mut replies) {
+                ControlReplyPacket::Ack(_) => {}
+                reply => panic!("expected an ack, got {:?}", reply),
+            }
+        }
+
+        // the first request takes the only replay slot, so the rest are held back
+        domain.request_partial_replay(Tag(0), vec![vec![1.into()]]);
+        domain.request_partial_replay(Tag(0), vec![vec![2.into()]]);
+        domain.request_partial_replay(Tag(1), vec![vec![3.into()]]);
+        domain.request_partial_replay(Tag(2), vec![vec![4.into()]]);
+        domain.finished_partial_replay(Tag(0), 1);
+
+        let mut sent = Vec::new();
+        while let Ok(m) = rx.try_recv() {
+            match *m {
+                Packet::RequestPartialReplay { tag, keys, .. } => sent.push((tag, keys)),
+                p => panic!("expected a replay request, got {:?}", p),
+            }
+        }
+        assert_eq!(
+            sent,
+            vec![
+                (Tag(0), vec![vec![1.into()]]),
+                (Tag(2), vec![vec![4.into()]]),
+                (Tag(1), vec![vec![3.into()]]),
+                (Tag(0), vec![vec![2.into()]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_replay_path_rejected() {
+        let (mut domain, a, r, mut replies) = test_domain(ChannelCoordinator::new(), 1, None);
+
+        // the reader is not the base's parent, so this path is not connected
+        let m = Box::new(Packet::SetupReplayPath {
+            tag: Tag(0),
+            source: None,
+            path: vec![
+                ReplayPathSegment {
+                    node: r,
+                    partial_key: None,
+                },
+                ReplayPathSegment {
+                    node: a,
+                    partial_key: Some(vec![0]),
+                },
+            ],
+            notify_done: false,
+            trigger: crate::payload::TriggerEndpoint::None,
+            priority: None,
+            compress: false,
+        });
+        domain.on_event(&mut Ex, PollEvent::Process(m));
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Nack(e) => assert!(e.contains("not connected"), "{}", e),
+            reply => panic!("expected a nack, got {:?}", reply),
+        }
+        assert!(!domain.replay_paths.contains_key(&Tag(0)));
+        assert!(domain.replay_paths_by_dst.is_empty());
+
+        // while a path that fits the domain is set up
+        domain.on_event(
+            &mut Ex,
+            PollEvent::Process(replay_path_into(r, Tag(1), None)),
+        );
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Ack(_) => {}
+            reply => panic!("expected an ack, got {:?}", reply),
+        }
+        assert!(domain.replay_paths.contains_key(&Tag(1)));
+    }
+
+    #[test]
+    fn reader_replays_rejected_while_queue_full() {
+        let coordinator = ChannelCoordinator::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        coordinator.insert_local((1.into(), 0), tx);
+        let (mut domain, _, r, mut replies) = test_domain(coordinator, 1, Some(1));
+
+        domain.on_event(
+            &mut Ex,
+            PollEvent::Process(replay_path_into(r, Tag(0), None)),
+        );
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Ack(_) => {}
+            reply => panic!("expected an ack, got {:?}", reply),
+        }
+        let (_, w) =
+            crate::backlog::new_partial(2, &[0], |_: &mut dyn Iterator<Item = &[DataType]>| true);
+        domain.nodes[r]
+            .borrow_mut()
+            .with_reader_mut(|r| r.set_write_handle(w))
+            .unwrap();
+
+        // the first miss takes the only replay slot, and the second fills the queue
+        for key in 0..3 {
+            let m = Box::new(Packet::RequestReaderReplay {
+                node: r,
+                cols: vec![0],
+                keys: vec![vec![key.into()]],
+            });
+            domain.on_event(&mut Ex, PollEvent::Process(m));
+        }
+        assert_eq!(domain.replay_request_queue.len(), 1);
+
+        // so the third is turned away, and isn't marked as triggered so that it is asked for again
+        assert_eq!(domain.dropped_packets.data, 1);
+        assert!(!domain.reader_triggered[r].contains(&vec![DataType::from(2)]));
+
+        let mut sent = Vec::new();
+        while let Ok(m) = rx.try_recv() {
+            match *m {
+                Packet::RequestPartialReplay { keys, .. } => sent.extend(keys),
+                p => panic!("expected a replay request, got {:?}", p),
+            }
+        }
+        assert_eq!(sent, vec![vec![0.into()]]);
+    }
+
+    #[test]
+    fn removed_node_forgets_its_replays() {
+        let coordinator = ChannelCoordinator::new();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        coordinator.insert_local((1.into(), 0), tx);
+        let (mut domain, _, r, mut replies) = test_domain(coordinator, 1, None);
+
+        domain.on_event(
+            &mut Ex,
+            PollEvent::Process(replay_path_into(r, Tag(0), None)),
+        );
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Ack(_) => {}
+            reply => panic!("expected an ack, got {:?}", reply),
+        }
+        let (_, w) =
+            crate::backlog::new_partial(2, &[0], |_: &mut dyn Iterator<Item = &[DataType]>| true);
+        domain.nodes[r]
+            .borrow_mut()
+            .with_reader_mut(|r| r.set_write_handle(w))
+            .unwrap();
+        let m = Box::new(Packet::RequestReaderReplay {
+            node: r,
+            cols: vec![0],
+            keys: vec![vec![0.into()]],
+        });
+        domain.on_event(&mut Ex, PollEvent::Process(m));
+
+        // the replay would come back along a path that is gone
+        let remove = || Box::new(Packet::RemoveNode { node: r });
+        domain.on_event(&mut Ex, PollEvent::Process(remove()));
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Nack(e) => assert!(e.contains("replay"), "{}", e),
+            reply => panic!("expected a nack, got {:?}", reply),
+        }
+        assert!(domain.nodes.contains_key(r));
+
+        // once it is done, the node goes along with its paths and triggered keys
+        domain.replay_started.clear();
+        domain.on_event(&mut Ex, PollEvent::Process(remove()));
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Ack(_) => {}
+            reply => panic!("expected an ack, got {:?}", reply),
+        }
+        assert!(!domain.nodes.contains_key(r));
+        assert!(!domain.replay_paths.contains_key(&Tag(0)));
+        assert!(!domain.replay_paths_by_dst.contains_key(r));
+        assert!(!domain.reader_triggered.contains_key(r));
+    }
+
+    #[test]
+    fn failed_sends_reported_as_dropped() {
+        let (mut domain, _, _, mut replies) = test_domain(ChannelCoordinator::new(), 1, None);
+
+        // the replica failed to send these on to another domain
+        domain.failed_to_send(false);
+        domain.failed_to_send(false);
+        domain.failed_to_send(true);
+
+        domain.on_event(&mut Ex, PollEvent::Process(Box::new(Packet::GetStatistics)));
+        match read_reply(&mut replies) {
+            ControlReplyPacket::Statistics(stats, _) => {
+                assert_eq!(stats.dropped_packets, 3);
+                assert_eq!(stats.dropped_control_packets, 1);
+            }
+            reply => panic!("expected statistics, got {:?}", reply),
+        }
+    }
 }
@@ -18,6 +18,13 @@ impl GroupCommitQueueSet {
         }
     }
 
+    /// Change how long packets may wait for others to be merged with.
+    ///
+    /// A timeout of zero flushes every packet as soon as it is appended.
+    pub fn set_flush_timeout(&mut self, timeout: time::Duration) {
+        self.params.flush_timeout = timeout;
+    }
+
     /// Returns whether the given packet should be persisted.
     pub fn should_append(&self, p: &Packet, nodes: &DomainNodes) -> bool {
         if let Packet::Input { .. } = *p {
@@ -45,6 +52,21 @@ impl GroupCommitQueueSet {
         }
     }
 
+    /// Flush the first queue that has pending packets, whether or not it has timed out yet.
+    pub fn flush_any(&mut self) -> Option<Box<Packet>> {
+        let node = self
+            .pending_packets
+            .iter()
+            .find(|(_, &(_, ref ps))| !ps.is_empty())
+            .map(|(n, _)| n);
+
+        if let Some(node) = node {
+            self.flush_internal(node)
+        } else {
+            None
+        }
+    }
+
     /// Merge any pending packets.
     fn flush_internal(&mut self, node: LocalNodeIndex) -> Option<Box<Packet>> {
         Self::merge_packets(&mut self.pending_packets[node].1)
@@ -93,6 +115,8 @@ impl GroupCommitQueueSet {
         let merged_dst = packets.peek().as_mut().unwrap().dst();
 
         let mut all_senders = vec![];
+        let mut merged_offset = None;
+        let mut merged_request_ids = Vec::new();
         let merged_data = packets.fold(Vec::new(), |mut acc, p| {
             match *p {
                 Packet::Input {
@@ -100,11 +124,18 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input {
+                        dst,
+                        data,
+                        source_offset,
+                        request_ids,
+                    } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
                     assert_eq!(merged_dst, dst);
                     acc.extend(data);
+                    merged_offset = merged_offset.max(source_offset);
+                    merged_request_ids.extend(request_ids);
 
                     if let Some(src) = src {
                         all_senders.push(src);
@@ -119,6 +150,8 @@ impl GroupCommitQueueSet {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                source_offset: merged_offset,
+                request_ids: merged_request_ids,
             }),
             src: None,
             senders: all_senders,
@@ -135,3 +168,74 @@ impl GroupCommitQueueSet {
         Self::merge_committed_packets(packets.drain(..))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::TableOperation;
+    use std::thread;
+
+    fn input(i: i32) -> Box<Packet> {
+        tagged_input(i, Vec::new())
+    }
+
+    fn tagged_input(i: i32, request_ids: Vec<u64>) -> Box<Packet> {
+        Box::new(Packet::Input {
+            inner: LocalOrNot::new(Input {
+                dst: unsafe { LocalNodeIndex::make(0) },
+                data: vec![TableOperation::Insert(vec![i.into()])],
+                source_offset: None,
+                request_ids,
+            }),
+            src: None,
+            senders: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn flushes_lone_packet_after_timeout() {
+        let timeout = time::Duration::from_millis(10);
+        let mut queues = GroupCommitQueueSet::new(&PersistenceParameters::default());
+        queues.set_flush_timeout(timeout);
+
+        assert!(queues.append(input(1)).is_none());
+        assert!(queues.duration_until_flush().unwrap() <= timeout);
+        assert!(queues.flush_if_necessary().is_none());
+
+        // no second packet arrives, but the first is let go once the timeout has passed
+        thread::sleep(timeout);
+        assert_eq!(
+            queues.duration_until_flush(),
+            Some(time::Duration::from_millis(0))
+        );
+        assert!(queues.flush_if_necessary().is_some());
+        assert!(queues.duration_until_flush().is_none());
+    }
+
+    #[test]
+    fn merge_keeps_all_request_ids() {
+        let mut queues = GroupCommitQueueSet::new(&PersistenceParameters::default());
+        queues.set_flush_timeout(time::Duration::from_secs(60));
+        assert!(queues.append(tagged_input(0, vec![10])).is_none());
+        assert!(queues.append(input(1)).is_none());
+        assert!(queues.append(tagged_input(2, vec![12])).is_none());
+
+        match *queues.flush_any().unwrap() {
+            Packet::Input { inner, .. } => {
+                let input = unsafe { inner.take() };
+                assert_eq!(input.data.len(), 3);
+                assert_eq!(input.request_ids, vec![10, 12]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn zero_timeout_disables_batching() {
+        let mut queues = GroupCommitQueueSet::new(&PersistenceParameters::default());
+        queues.set_flush_timeout(time::Duration::from_millis(0));
+        assert!(queues.append(input(1)).is_some());
+        assert!(queues.append(input(2)).is_some());
+        assert!(queues.duration_until_flush().is_none());
+    }
+}
@@ -23,6 +23,7 @@ pub(crate) mod state;
 mod domain;
 mod group_commit;
 mod processing;
+mod rate_limit;
 
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -34,7 +35,9 @@ pub type Readers =
     Arc<Mutex<HashMap<(petgraph::graph::NodeIndex, usize), backlog::SingleReadHandle>>>;
 pub type DomainConfig = domain::Config;
 
-pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
+pub use crate::domain::{
+    Domain, DomainBuilder, Index, PacketObserver, PacketSummary, PollEvent, ProcessResult,
+};
 pub use crate::payload::Packet;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
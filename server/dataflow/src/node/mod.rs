@@ -10,7 +10,7 @@ mod process;
 pub(crate) use self::process::materialize;
 
 pub mod special;
-pub use self::special::StreamUpdate;
+pub use self::special::{StreamBatch, StreamUpdate};
 
 mod ntype;
 pub use self::ntype::NodeType; // crate viz for tests
@@ -268,6 +268,15 @@ impl Node {
         }
     }
 
+    /// The domain shards this node sends data to, if it is an egress or a sharder.
+    pub(crate) fn downstream_replicas(&self) -> Vec<ReplicaAddr> {
+        match self.inner {
+            NodeType::Egress(Some(ref e)) => e.destinations().collect(),
+            NodeType::Sharder(ref s) => s.destinations().collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn get_base(&self) -> Option<&special::Base> {
         if let NodeType::Base(ref b) = self.inner {
             Some(b)
@@ -326,6 +335,17 @@ impl Node {
         self.children.push(child);
     }
 
+    /// Replace `old` with `new` in this node's list of domain-local parents.
+    pub(crate) fn replace_parent(&mut self, old: LocalNodeIndex, new: LocalNodeIndex) -> bool {
+        match self.parents.iter_mut().find(|p| **p == old) {
+            Some(p) => {
+                *p = new;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub(crate) fn try_remove_child(&mut self, child: LocalNodeIndex) -> bool {
         for i in 0..self.children.len() {
             if self.children[i] == child {
@@ -341,6 +361,19 @@ impl Node {
         self.fields.len() - 1
     }
 
+    /// Rename the field at index `column`.
+    ///
+    /// Returns `false` if there is no such column.
+    pub fn rename_column(&mut self, column: usize, field: &str) -> bool {
+        match self.fields.get_mut(column) {
+            Some(f) => {
+                *f = field.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn has_domain(&self) -> bool {
         self.domain.is_some()
     }
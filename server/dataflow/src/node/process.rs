@@ -31,7 +31,12 @@ impl Node {
                     Some(Packet::Input {
                         inner, mut senders, ..
                     }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
+                        let Input {
+                            dst,
+                            data,
+                            source_offset,
+                            request_ids,
+                        } = unsafe { inner.take() };
                         let mut rs = b.process(addr, data, &*state);
 
                         // When a replay originates at a base node, we replay the data *through* that
@@ -52,6 +57,8 @@ impl Node {
                         *m = Some(Box::new(Packet::Message {
                             link: Link::new(dst, dst),
                             data: rs,
+                            source_offset,
+                            request_ids,
                         }));
                     }
                     Some(ref p) => {
@@ -229,10 +236,7 @@ impl Node {
             NodeType::Egress(Some(ref mut e)) => {
                 e.process(
                     &mut Some(Box::new(Packet::EvictKeys {
-                        link: Link {
-                            src: addr,
-                            dst: addr,
-                        },
+                        link: Link::new(addr, addr),
                         tag,
                         keys: keys.to_vec(),
                     })),
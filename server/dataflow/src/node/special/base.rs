@@ -65,6 +65,35 @@ impl Base {
         self.dropped.push(column);
     }
 
+    /// Drop several columns from this base node.
+    ///
+    /// Columns are identified by their original index. Columns that do not exist, or that have
+    /// already been dropped, are ignored.
+    pub fn drop_columns(&mut self, columns: &[usize]) {
+        let mut columns = columns.to_vec();
+        columns.sort_unstable_by(|a, b| b.cmp(a));
+        columns.dedup();
+        for column in columns {
+            if column < self.defaults.len() && !self.dropped.contains(&column) {
+                self.drop_column(column);
+            }
+        }
+    }
+
+    /// Change the default value of a column.
+    ///
+    /// The new default is used for writes that arrive after this call without a value for the
+    /// column. Returns `false`, and changes nothing, if the column has no default to change.
+    pub fn set_default(&mut self, column: usize, default: DataType) -> bool {
+        match self.defaults.get_mut(column) {
+            Some(d) => {
+                *d = default;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn get_dropped(&self) -> VecMap<DataType> {
         self.dropped
             .iter()
@@ -307,6 +336,61 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
+    #[test]
+    fn added_columns_are_backfilled() {
+        let mut b = Base::new(vec![1.into(), 2.into()]);
+        assert_eq!(b.add_column("a".into()), 2);
+        assert_eq!(b.add_column(3.into()), 3);
+        assert_eq!(b.add_column(DataType::None), 4);
+
+        let mut row = vec![10.into(), 20.into()];
+        b.fix(&mut row);
+        assert_eq!(
+            row,
+            vec![10.into(), 20.into(), "a".into(), 3.into(), DataType::None]
+        );
+
+        // rows that already have all the columns are left alone
+        let mut row = vec![10.into(), 20.into(), "b".into(), 4.into(), 5.into()];
+        b.fix(&mut row);
+        assert_eq!(
+            row,
+            vec![10.into(), 20.into(), "b".into(), 4.into(), 5.into()]
+        );
+    }
+
+    #[test]
+    fn set_column_default() {
+        let mut b = Base::new(vec![1.into(), 2.into()]);
+        b.add_column(3.into());
+        assert!(b.set_default(2, 4.into()));
+        assert!(!b.set_default(3, 5.into()));
+
+        let mut row = vec![10.into(), 20.into()];
+        b.fix(&mut row);
+        assert_eq!(row, vec![10.into(), 20.into(), 4.into()]);
+
+        // bases created without defaults have none to change
+        assert!(!Base::default().set_default(0, 1.into()));
+    }
+
+    #[test]
+    fn drop_multiple_columns() {
+        let mut b = Base::new(vec![1.into(), 2.into(), 3.into(), 4.into()]);
+        b.drop_columns(&[0, 2, 2, 7]);
+
+        let dropped = b.get_dropped();
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped.get(0), Some(&1.into()));
+        assert_eq!(dropped.get(2), Some(&3.into()));
+
+        // dropping columns does not shift the remaining ones
+        let mut row = vec![10.into(), 20.into(), 30.into(), 40.into()];
+        b.fix(&mut row);
+        assert_eq!(row[1], 20.into());
+        assert_eq!(row[3], 40.into());
+    }
+
     fn test_lots_of_changes_in_same_batch(mut state: Box<dyn State>) {
         use crate::node;
         use crate::prelude::*;
@@ -1,5 +1,5 @@
 use crate::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Serialize, Deserialize)]
 struct EgressTx {
@@ -12,6 +12,8 @@ struct EgressTx {
 pub struct Egress {
     txs: Vec<EgressTx>,
     tags: HashMap<Tag, NodeIndex>,
+    /// replay paths whose pieces are compressed before they are sent
+    compressed: HashSet<Tag>,
 }
 
 impl Clone for Egress {
@@ -21,6 +23,7 @@ impl Clone for Egress {
         Self {
             txs: Vec::new(),
             tags: self.tags.clone(),
+            compressed: self.compressed.clone(),
         }
     }
 }
@@ -30,6 +33,7 @@ impl Default for Egress {
         Self {
             tags: Default::default(),
             txs: Default::default(),
+            compressed: Default::default(),
         }
     }
 }
@@ -43,10 +47,49 @@ impl Egress {
         });
     }
 
+    /// The domain shards this egress sends to.
+    pub(crate) fn destinations(&self) -> impl Iterator<Item = ReplicaAddr> + '_ {
+        self.txs.iter().map(|tx| tx.dest)
+    }
+
+    /// Send to `new` instead of to the existing downstream node `old_dst`.
+    ///
+    /// Replay paths that went to `old_dst` now go to the new node instead. Returns `false` if
+    /// there is no downstream node `old_dst`.
+    pub fn replace_tx(
+        &mut self,
+        old_dst: NodeIndex,
+        new: (NodeIndex, LocalNodeIndex, ReplicaAddr),
+    ) -> bool {
+        let (dst_g, dst_l, addr) = new;
+        match self.txs.iter_mut().find(|tx| tx.node == old_dst) {
+            Some(tx) => {
+                *tx = EgressTx {
+                    node: dst_g,
+                    local: dst_l,
+                    dest: addr,
+                };
+            }
+            None => return false,
+        }
+
+        for dst in self.tags.values_mut() {
+            if *dst == old_dst {
+                *dst = dst_g;
+            }
+        }
+        true
+    }
+
     pub fn add_tag(&mut self, tag: Tag, dst: NodeIndex) {
         self.tags.insert(tag, dst);
     }
 
+    /// Compress the replay pieces sent along the replay path `tag`.
+    pub fn compress_tag(&mut self, tag: Tag) {
+        self.compressed.insert(tag);
+    }
+
     pub fn process(
         &mut self,
         m: &mut Option<Box<Packet>>,
@@ -56,6 +99,7 @@ impl Egress {
         let &mut Self {
             ref mut txs,
             ref tags,
+            ref compressed,
         } = self;
 
         // send any queued updates to all external children
@@ -65,7 +109,8 @@ impl Egress {
         // we need to find the ingress node following this egress according to the path
         // with replay.tag, and then forward this message only on the channel corresponding
         // to that ingress node.
-        let replay_to = m.as_ref().unwrap().tag().map(|tag| {
+        let tag = m.as_ref().unwrap().tag();
+        let replay_to = tag.map(|tag| {
             tags.get(&tag)
                 .cloned()
                 .expect("egress node told about replay message, but not on replay path")
@@ -93,8 +138,10 @@ impl Egress {
             // src is usually ignored and overwritten by ingress
             // *except* if the ingress is marked as a shard merger
             // in which case it wants to know about the shard
-            m.link_mut().src = unsafe { LocalNodeIndex::make(shard as u32) };
-            m.link_mut().dst = tx.local;
+            m.reroute(unsafe { LocalNodeIndex::make(shard as u32) }, tx.local);
+            if tag.map(|tag| compressed.contains(&tag)).unwrap_or(false) {
+                m = Box::new(m.compress());
+            }
 
             output.send(tx.dest, m);
             if take {
@@ -103,3 +150,105 @@ impl Egress {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Ex(Vec<ReplicaAddr>);
+
+    impl Executor for Ex {
+        fn ack(&mut self, _: SourceChannelIdentifier) {}
+        fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+        fn send(&mut self, dest: ReplicaAddr, _: Box<Packet>) {
+            self.0.push(dest);
+        }
+    }
+
+    fn message() -> Option<Box<Packet>> {
+        Some(Box::new(Packet::Message {
+            link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(0)) },
+            data: vec![vec![1.into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn replace_tx_mid_stream() {
+        let old: ReplicaAddr = (0.into(), 0);
+        let new: ReplicaAddr = (1.into(), 0);
+        let dst_l = unsafe { LocalNodeIndex::make(0) };
+
+        let mut e = Egress::default();
+        e.add_tx(NodeIndex::new(1), dst_l, old);
+        e.add_tag(Tag(0), NodeIndex::new(1));
+
+        let mut ex = Ex::default();
+        e.process(&mut message(), 0, &mut ex);
+        assert_eq!(ex.0, vec![old]);
+
+        assert!(!e.replace_tx(NodeIndex::new(3), (NodeIndex::new(2), dst_l, new)));
+        assert!(e.replace_tx(NodeIndex::new(1), (NodeIndex::new(2), dst_l, new)));
+        assert_eq!(e.tags[&Tag(0)], NodeIndex::new(2));
+
+        ex.0.clear();
+        for _ in 0..3 {
+            e.process(&mut message(), 0, &mut ex);
+        }
+        assert_eq!(ex.0, vec![new; 3]);
+    }
+
+    #[test]
+    fn compresses_tagged_replays_on_send() {
+        let a: ReplicaAddr = (0.into(), 0);
+        let b: ReplicaAddr = (1.into(), 0);
+        let dst_l = unsafe { LocalNodeIndex::make(0) };
+
+        let mut e = Egress::default();
+        e.add_tx(NodeIndex::new(1), dst_l, a);
+        e.add_tx(NodeIndex::new(2), dst_l, b);
+        e.add_tag(Tag(0), NodeIndex::new(2));
+        e.compress_tag(Tag(0));
+
+        let mut sent = Vec::new();
+        struct Collect<'a>(&'a mut Vec<(ReplicaAddr, Box<Packet>)>);
+        impl<'a> Executor for Collect<'a> {
+            fn ack(&mut self, _: SourceChannelIdentifier) {}
+            fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+            fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
+                self.0.push((dest, m));
+            }
+        }
+
+        let mut m = Some(Box::new(Packet::ReplayPiece {
+            link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(0)) },
+            tag: Tag(0),
+            data: vec![vec![1.into()]].into(),
+            context: crate::payload::ReplayPieceContext::Regular { last: true },
+        }));
+        e.process(&mut m, 0, &mut Collect(&mut sent));
+        // regular updates still fan out to every destination
+        e.process(&mut message(), 0, &mut Collect(&mut sent));
+
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].0, b);
+        match *sent[0].1 {
+            Packet::CompressedReplayPiece { .. } => {}
+            ref p => panic!("replay sent uncompressed: {:?}", p),
+        }
+        let p = sent.remove(0).1.decompress().unwrap();
+        assert_eq!(p.tag(), Some(Tag(0)));
+        match p {
+            Packet::ReplayPiece { ref data, .. } => assert_eq!(data.len(), 1),
+            ref p => panic!("not a replay: {:?}", p),
+        }
+        for (_, m) in sent {
+            match *m {
+                Packet::Message { .. } => {}
+                ref p => panic!("not a message: {:?}", p),
+            }
+        }
+    }
+}
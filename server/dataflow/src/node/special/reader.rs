@@ -1,6 +1,8 @@
 use crate::backlog;
 use crate::prelude::*;
 use noria::channel;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
 
 /// A StreamUpdate reflects the addition or deletion of a row from a reader node.
 #[derive(Clone, Debug, PartialEq)]
@@ -9,6 +11,8 @@ pub enum StreamUpdate {
     AddRow(Vec<DataType>),
     /// Indicates the removal of an existing row
     DeleteRow(Vec<DataType>),
+    /// Indicates that an existing row (the first) was replaced by a row with the same key
+    Update(Vec<DataType>, Vec<DataType>),
 }
 
 impl From<Record> for StreamUpdate {
@@ -26,13 +30,85 @@ impl From<Vec<DataType>> for StreamUpdate {
     }
 }
 
+/// The updates a reader sends to a streamer for one batch of records it processed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamBatch {
+    /// The client requests that caused these updates, for the writes that were tagged with one
+    pub request_ids: Vec<u64>,
+    /// The updates, in the order the reader saw them
+    pub updates: Vec<StreamUpdate>,
+}
+
+impl From<Vec<StreamUpdate>> for StreamBatch {
+    fn from(updates: Vec<StreamUpdate>) -> Self {
+        StreamBatch {
+            request_ids: Vec::new(),
+            updates,
+        }
+    }
+}
+
+/// Turn a batch of records into stream updates.
+///
+/// If the reader is keyed, each delete that is followed by an insert with the same key in the
+/// batch becomes a single `StreamUpdate::Update` at the position of the delete. All other records
+/// are passed through in order.
+fn coalesce<I>(records: I, key: Option<&[usize]>) -> Vec<StreamUpdate>
+where
+    I: IntoIterator<Item = Record>,
+{
+    let key = match key {
+        Some(key) => key,
+        None => return records.into_iter().map(Into::into).collect(),
+    };
+
+    let mut updates = Vec::new();
+    let mut deletes: HashMap<Vec<DataType>, VecDeque<usize>> = HashMap::new();
+    for r in records {
+        let k: Vec<DataType> = key.iter().map(|&c| r[c].clone()).collect();
+        match r {
+            Record::Negative(old) => {
+                deletes.entry(k).or_default().push_back(updates.len());
+                updates.push(StreamUpdate::DeleteRow(old));
+            }
+            Record::Positive(new) => match deletes.get_mut(&k).and_then(VecDeque::pop_front) {
+                Some(i) => {
+                    if let StreamUpdate::DeleteRow(ref mut old) = updates[i] {
+                        let old = mem::take(old);
+                        updates[i] = StreamUpdate::Update(old, new);
+                    } else {
+                        unreachable!("paired insert with something other than a delete");
+                    }
+                }
+                None => updates.push(StreamUpdate::AddRow(new)),
+            },
+        }
+    }
+    updates
+}
+
+/// A channel that receives a reader's updates, the column values an update must have to be sent
+/// on it, and whether deletes and inserts of the same key are paired up before sending.
+#[derive(Clone)]
+struct Streamer {
+    tx: channel::StreamSender<StreamBatch>,
+    filter: Vec<(usize, DataType)>,
+    coalesce: bool,
+}
+
+impl Streamer {
+    fn matches(&self, row: &[DataType]) -> bool {
+        self.filter.iter().all(|(c, v)| row.get(*c) == Some(v))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
     #[serde(skip)]
     writer: Option<backlog::WriteHandle>,
 
     #[serde(skip)]
-    streamers: Vec<channel::StreamSender<Vec<StreamUpdate>>>,
+    streamers: Vec<Streamer>,
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
@@ -66,8 +142,7 @@ impl Reader {
         self.for_node
     }
 
-    #[allow(dead_code)]
-    fn writer(&self) -> Option<&backlog::WriteHandle> {
+    pub(crate) fn writer(&self) -> Option<&backlog::WriteHandle> {
         self.writer.as_ref()
     }
 
@@ -85,11 +160,52 @@ impl Reader {
         }
     }
 
+    /// Start sending the updates this reader sees to `new_streamer`.
+    ///
+    /// With `send_initial`, the reader's current contents are sent first as a single batch of
+    /// `StreamUpdate::AddRow`s, followed by every later update. Since both happen on the domain
+    /// thread, no update is missed or sent twice in between. For partially materialized readers,
+    /// only the keys that have been filled are included.
+    ///
+    /// If `filter` lists any `(column, value)` pairs, only rows with those values in those
+    /// columns are sent, and batches with no such rows are skipped.
+    ///
+    /// With `coalesce`, a keyed reader sends each delete that is followed by an insert of the same
+    /// key in a batch as a single `StreamUpdate::Update`. Otherwise every record is sent as is.
     pub(crate) fn add_streamer(
         &mut self,
-        new_streamer: channel::StreamSender<Vec<StreamUpdate>>,
-    ) -> Result<(), channel::StreamSender<Vec<StreamUpdate>>> {
-        self.streamers.push(new_streamer);
+        new_streamer: channel::StreamSender<StreamBatch>,
+        send_initial: bool,
+        filter: Option<Vec<(usize, DataType)>>,
+        coalesce: bool,
+    ) -> Result<(), channel::StreamSender<StreamBatch>> {
+        let streamer = Streamer {
+            tx: new_streamer,
+            filter: filter.unwrap_or_default(),
+            coalesce,
+        };
+
+        if send_initial {
+            let mut initial = Vec::new();
+            if let Some(ref mut w) = self.writer {
+                // make everything we have processed so far visible
+                w.swap();
+                if let Ok(rows) = w.try_find_all_and(|rs| {
+                    rs.iter()
+                        .filter(|r| streamer.matches(r))
+                        .map(|r| StreamUpdate::AddRow(r.clone()))
+                        .collect::<Vec<_>>()
+                }) {
+                    initial = rows.into_iter().flatten().collect();
+                }
+            }
+
+            if streamer.tx.send(StreamBatch::from(initial)).is_err() {
+                return Err(streamer.tx);
+            }
+        }
+
+        self.streamers.push(streamer);
         Ok(())
     }
 
@@ -156,10 +272,13 @@ impl Reader {
             if m.is_regular() && state.is_partial() {
                 m.map_data(|data| {
                     data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+                        let hit = state.entry_from_record(&row[..]).try_find_and(|_| ());
+                        match hit {
                             Ok((None, _)) => {
                                 // row would miss in partial state.
                                 // leave it blank so later lookup triggers replay.
+                                // lookups on a prefix of its key must replay it too, though.
+                                state.saw_hole(&row[..]);
                                 false
                             }
                             Err(_) => unreachable!(),
@@ -206,6 +325,14 @@ impl Reader {
                 state.add(m.data().iter().cloned());
             }
 
+            if let Packet::Message {
+                source_offset: Some(offset),
+                ..
+            } = **m
+            {
+                state.applied(offset);
+            }
+
             if swap {
                 // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
                 state.swap();
@@ -215,19 +342,261 @@ impl Reader {
         // TODO: don't send replays to streams?
 
         if !self.streamers.is_empty() {
-            let mut data = Some(m.take().unwrap().take_data()); // so we can .take() for last tx
-            let mut left = self.streamers.len();
+            let mut m = m.take().unwrap();
+            let request_ids = match *m {
+                Packet::Message {
+                    ref mut request_ids,
+                    ..
+                } => std::mem::replace(request_ids, Vec::new()),
+                _ => Vec::new(),
+            };
+            let data = m.take_data();
+            let batch = |updates| StreamBatch {
+                request_ids: request_ids.clone(),
+                updates,
+            };
+            let key = self.state.as_deref();
+
+            // unfiltered streamers all get the same updates, up to coalescing, so only build those
+            // once for each way of sending them
+            let mut left = [0, 0];
+            for s in self.streamers.iter().filter(|s| s.filter.is_empty()) {
+                left[s.coalesce as usize] += 1;
+            }
+            let mut all = [None, None];
+            for (i, &n) in left.iter().enumerate() {
+                if n != 0 {
+                    let key = if i == 1 { key } else { None };
+                    all[i] = Some(coalesce(data.iter().cloned(), key));
+                }
+            }
 
             // remove any channels where the receiver has hung up
-            self.streamers.retain(|tx| {
-                left -= 1;
-                if left == 0 {
-                    tx.send(data.take().unwrap().into_iter().map(Into::into).collect())
+            self.streamers.retain(|s| {
+                if !s.filter.is_empty() {
+                    let matching = data.iter().filter(|r| s.matches(r)).cloned();
+                    let updates = coalesce(matching, if s.coalesce { key } else { None });
+                    return updates.is_empty() || s.tx.send(batch(updates)).is_ok();
+                }
+
+                let i = s.coalesce as usize;
+                left[i] -= 1;
+                if left[i] == 0 {
+                    s.tx.send(batch(all[i].take().unwrap()))
                 } else {
-                    tx.send(data.clone().unwrap().into_iter().map(Into::into).collect())
+                    s.tx.send(batch(all[i].clone().unwrap()))
                 }
                 .is_ok()
             });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(data: Records) -> Option<Box<Packet>> {
+        Some(Box::new(Packet::Message {
+            link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(0)) },
+            data,
+            source_offset: None,
+            request_ids: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn streamer_gets_initial_state() {
+        let (_, w) = backlog::new(2, &[0]);
+        let mut r = Reader::new(NodeIndex::new(0));
+        r.set_key(&[0]);
+        r.set_write_handle(w);
+
+        let rows: Vec<Vec<DataType>> = (0..5).map(|i| vec![i.into(), "a".into()]).collect();
+        // the last of these has not been swapped in yet, but must still be in the snapshot
+        r.process(&mut message(rows[..4].to_vec().into()), true);
+        r.process(&mut message(rows[4..].to_vec().into()), false);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        r.add_streamer(channel::ChannelSender::from_local(tx), true, None, false)
+            .unwrap();
+
+        let mut initial = rx.try_recv().unwrap().updates;
+        initial.sort_by(|a, b| match (a, b) {
+            (StreamUpdate::AddRow(a), StreamUpdate::AddRow(b)) => a.cmp(b),
+            _ => unreachable!(),
+        });
+        assert_eq!(
+            initial,
+            rows.iter()
+                .cloned()
+                .map(StreamUpdate::AddRow)
+                .collect::<Vec<_>>()
+        );
+
+        let live = vec![5.into(), "b".into()];
+        r.process(&mut message(vec![live.clone()].into()), true);
+        assert_eq!(
+            rx.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![vec![StreamUpdate::AddRow(live)]]
+        );
+    }
+
+    #[test]
+    fn streamer_without_initial_state() {
+        let (_, w) = backlog::new(2, &[0]);
+        let mut r = Reader::new(NodeIndex::new(0));
+        r.set_key(&[0]);
+        r.set_write_handle(w);
+        r.process(&mut message(vec![vec![1.into(), "a".into()]].into()), true);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        r.add_streamer(channel::ChannelSender::from_local(tx), false, None, false)
+            .unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn filtered_streamers() {
+        let (_, w) = backlog::new(2, &[0]);
+        let mut r = Reader::new(NodeIndex::new(0));
+        r.set_key(&[0]);
+        r.set_write_handle(w);
+
+        let (tx_a, rx_a) = std::sync::mpsc::channel();
+        let (tx_b, rx_b) = std::sync::mpsc::channel();
+        let (tx_all, rx_all) = std::sync::mpsc::channel();
+        let a = Some(vec![(1, "a".into())]);
+        let b = Some(vec![(0, 2.into()), (1, "b".into())]);
+        r.add_streamer(channel::ChannelSender::from_local(tx_a), false, a, false)
+            .unwrap();
+        r.add_streamer(channel::ChannelSender::from_local(tx_b), false, b, false)
+            .unwrap();
+        r.add_streamer(
+            channel::ChannelSender::from_local(tx_all),
+            false,
+            Some(vec![]),
+            false,
+        )
+        .unwrap();
+
+        let rows: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "a".into()],
+            vec![2.into(), "b".into()],
+            vec![3.into(), "b".into()],
+        ];
+        r.process(&mut message(rows.clone().into()), true);
+        // a batch none of whose rows match is not sent to filtered streamers at all
+        r.process(&mut message(vec![vec![4.into(), "c".into()]].into()), true);
+
+        let updates = |rows: &[Vec<DataType>]| -> Vec<StreamUpdate> {
+            rows.iter().cloned().map(StreamUpdate::AddRow).collect()
+        };
+        assert_eq!(
+            rx_a.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![updates(&rows[0..1])]
+        );
+        assert_eq!(
+            rx_b.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![updates(&rows[1..2])]
+        );
+        assert_eq!(
+            rx_all.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![updates(&rows), updates(&[vec![4.into(), "c".into()]])]
+        );
+    }
+
+    #[test]
+    fn streamer_coalesces_updates() {
+        let (_, w) = backlog::new(2, &[0]);
+        let mut r = Reader::new(NodeIndex::new(0));
+        r.set_key(&[0]);
+        r.set_write_handle(w);
+        r.process(
+            &mut message(vec![vec![1.into(), "a".into()], vec![2.into(), "b".into()]].into()),
+            true,
+        );
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        r.add_streamer(channel::ChannelSender::from_local(tx), false, None, true)
+            .unwrap();
+        let (tx_raw, rx_raw) = std::sync::mpsc::channel();
+        r.add_streamer(
+            channel::ChannelSender::from_local(tx_raw),
+            false,
+            None,
+            false,
+        )
+        .unwrap();
+
+        let batch: Records = vec![
+            (vec![1.into(), "a".into()], false),
+            (vec![3.into(), "c".into()], true),
+            (vec![1.into(), "A".into()], true),
+            (vec![2.into(), "b".into()], false),
+        ]
+        .into();
+        r.process(&mut message(batch), true);
+
+        assert_eq!(
+            rx.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![vec![
+                StreamUpdate::Update(vec![1.into(), "a".into()], vec![1.into(), "A".into()]),
+                StreamUpdate::AddRow(vec![3.into(), "c".into()]),
+                StreamUpdate::DeleteRow(vec![2.into(), "b".into()]),
+            ]]
+        );
+        // streamers that didn't ask for coalescing see the records as they came
+        assert_eq!(
+            rx_raw.try_iter().map(|b| b.updates).collect::<Vec<_>>(),
+            vec![vec![
+                StreamUpdate::DeleteRow(vec![1.into(), "a".into()]),
+                StreamUpdate::AddRow(vec![3.into(), "c".into()]),
+                StreamUpdate::AddRow(vec![1.into(), "A".into()]),
+                StreamUpdate::DeleteRow(vec![2.into(), "b".into()]),
+            ]]
+        );
+    }
+
+    #[test]
+    fn streamer_sees_request_id() {
+        let (_, w) = backlog::new(2, &[0]);
+        let mut r = Reader::new(NodeIndex::new(0));
+        r.set_key(&[0]);
+        r.set_write_handle(w);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        r.add_streamer(channel::ChannelSender::from_local(tx), false, None, false)
+            .unwrap();
+
+        let row = vec![1.into(), "a".into()];
+        let mut m = message(vec![row.clone()].into());
+        if let Some(Packet::Message { request_ids, .. }) = m.as_deref_mut() {
+            request_ids.push(42);
+        }
+        r.process(&mut m, true);
+        r.process(&mut message(vec![vec![2.into(), "b".into()]].into()), true);
+
+        let batches = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].request_ids, vec![42]);
+        assert_eq!(batches[0].updates, vec![StreamUpdate::AddRow(row)]);
+        assert!(batches[1].request_ids.is_empty());
+    }
+
+    #[test]
+    fn coalesce_only_pairs_delete_then_insert() {
+        let records = vec![
+            Record::Positive(vec![1.into(), "new".into()]),
+            Record::Negative(vec![1.into(), "old".into()]),
+        ];
+        assert_eq!(
+            coalesce(records.clone(), Some(&[0])),
+            vec![
+                StreamUpdate::AddRow(vec![1.into(), "new".into()]),
+                StreamUpdate::DeleteRow(vec![1.into(), "old".into()]),
+            ]
+        );
+        assert_eq!(coalesce(records.clone(), None).len(), 2);
+    }
+}
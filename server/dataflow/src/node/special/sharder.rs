@@ -30,6 +30,11 @@ impl Sharder {
         }
     }
 
+    /// The domain shards this sharder sends to.
+    pub(crate) fn destinations(&self) -> impl Iterator<Item = ReplicaAddr> + '_ {
+        self.txs.iter().map(|&(_, dest)| dest)
+    }
+
     pub fn take(&mut self) -> Self {
         use std::mem;
         let txs = mem::replace(&mut self.txs, Vec::new());
@@ -120,8 +125,7 @@ impl Sharder {
 
         for (i, &mut (dst, addr)) in self.txs.iter_mut().enumerate() {
             if let Some(mut shard) = self.sharded.remove(i) {
-                shard.link_mut().src = index;
-                shard.link_mut().dst = dst;
+                shard.reroute(index, dst);
                 output.send(addr, shard);
             }
         }
@@ -145,7 +149,7 @@ impl Sharder {
                 let dst = self.txs[shard].0;
                 let p = self.sharded.entry(shard).or_insert_with(|| {
                     Box::new(Packet::EvictKeys {
-                        link: Link { src, dst },
+                        link: Link::new(src, dst),
                         keys: Vec::new(),
                         tag,
                     })
@@ -170,7 +174,7 @@ impl Sharder {
                 output.send(
                     addr,
                     Box::new(Packet::EvictKeys {
-                        link: Link { src, dst },
+                        link: Link::new(src, dst),
                         keys: keys.to_vec(),
                         tag,
                     }),
@@ -11,6 +11,7 @@ use noria::internal::LocalOrNot;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::SocketAddr;
+use std::time;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReplayPathSegment {
@@ -45,6 +46,10 @@ pub enum TriggerEndpoint {
 pub enum InitialState {
     PartialLocal(Vec<(Vec<usize>, Vec<Tag>)>),
     IndexedLocal(HashSet<Vec<usize>>),
+    /// A partially materialized reader.
+    ///
+    /// Only the reader's shape is sent; the receiving domain constructs the `backlog` handle pair
+    /// (and the replay trigger) itself, so this can safely cross process boundaries.
     PartialGlobal {
         gid: petgraph::graph::NodeIndex,
         cols: usize,
@@ -77,6 +82,27 @@ pub struct SourceChannelIdentifier {
     pub tag: u32,
 }
 
+/// Version of the serialized form of `Packet`, announced when a domain connects to another.
+///
+/// Bump this whenever a change to `Packet` alters its serialized form.
+pub const PACKET_VERSION: u16 = 4;
+
+/// Versions whose encoded packets can still be decoded by this version.
+const COMPATIBLE_PACKET_VERSIONS: &[u16] = &[PACKET_VERSION];
+
+/// Check whether a peer running version `ours` that accepts the given `compatible` versions can
+/// decode packets encoded with version `theirs`.
+fn check_version_as(theirs: u16, ours: u16, compatible: &[u16]) -> Result<(), String> {
+    if compatible.contains(&theirs) {
+        Ok(())
+    } else {
+        Err(format!(
+            "packets are encoded with version {}, which version {} cannot decode",
+            theirs, ours
+        ))
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum Packet {
@@ -92,6 +118,11 @@ pub enum Packet {
     Message {
         link: Link,
         data: Records,
+        /// Offset in an upstream source of the write that produced this update, if the client
+        /// supplied one. Carried along unchanged so that readers can report how far they've got.
+        source_offset: Option<u64>,
+        /// Client requests that caused this update, for the writes the client tagged with one.
+        request_ids: Vec<u64>,
     },
 
     /// Update that is part of a tagged data-flow replay path.
@@ -102,12 +133,48 @@ pub enum Packet {
         context: ReplayPieceContext,
     },
 
+    /// A `ReplayPiece` whose records have been serialized and compressed.
+    ///
+    /// Sent in its place across domain boundaries on replay paths set up with `compress`. The
+    /// receiving domain decompresses it before doing anything else with it.
+    CompressedReplayPiece {
+        link: Link,
+        tag: Tag,
+        data: Vec<u8>,
+        context: ReplayPieceContext,
+    },
+
+    /// A packet that carries records, serialized for a remote connection along with a CRC32 of
+    /// the serialized bytes.
+    ///
+    /// The receiving end checks the bytes that arrived against the checksum before it turns them
+    /// back into the packet. The link is kept alongside, so that a packet that fails the check
+    /// can still be accounted for on its link.
+    Checksummed {
+        link: Link,
+        checksum: u32,
+        packet: Vec<u8>,
+    },
+
     /// Trigger an eviction from the target node.
     Evict {
         node: Option<LocalNodeIndex>,
         num_bytes: usize,
     },
 
+    /// Throw away the state of a materialized node, so that it is rebuilt from its parents.
+    ///
+    /// Every filled key of partial state is evicted, along with the state downstream that was
+    /// derived from it, and later lookups replay those keys anew. Full state is set aside for the
+    /// replays the controller starts next, and keeps serving lookups until they complete unless
+    /// `block` is set. Acked once the old state is set aside. Replies with
+    /// `ControlReplyPacket::Nack` if the node does not exist, is a base table, is not
+    /// materialized, or is partial and `block` is not set.
+    Recompute {
+        node: LocalNodeIndex,
+        block: bool,
+    },
+
     /// Evict the indicated keys from the materialization targed by the replay path `tag` (along
     /// with any other materializations below it).
     EvictKeys {
@@ -134,17 +201,68 @@ pub enum Packet {
         nodes: Vec<LocalNodeIndex>,
     },
 
+    /// Detach a node from the domain and free its state, for instance to undo an `AddNode` whose
+    /// wiring failed.
+    ///
+    /// Unlike `RemoveNodes`, the node is taken out of the domain entirely, so its local index can
+    /// be used for a new node. Acked once the node is gone. Replies with `ControlReplyPacket::Nack`
+    /// if the node does not exist or still has children.
+    RemoveNode {
+        node: LocalNodeIndex,
+    },
+
     /// Add a new column to an existing `Base` node.
+    ///
+    /// Like the other column changes, this carries an `op_id`. A change whose `op_id` the node has
+    /// recently applied is acked without being applied again.
     AddBaseColumn {
         node: LocalNodeIndex,
         field: String,
         default: DataType,
+        op_id: u64,
+    },
+
+    /// Add several new columns to an existing `Base` node, in order.
+    AddBaseColumns {
+        node: LocalNodeIndex,
+        fields: Vec<(String, DataType)>,
+        op_id: u64,
     },
 
     /// Drops an existing column from a `Base` node.
     DropBaseColumn {
         node: LocalNodeIndex,
         column: usize,
+        op_id: u64,
+    },
+
+    /// Drops several existing columns from a `Base` node.
+    ///
+    /// Columns are identified by their index before any of them were dropped.
+    DropBaseColumns {
+        node: LocalNodeIndex,
+        columns: Vec<usize>,
+        op_id: u64,
+    },
+
+    /// Renames an existing column of a `Base` node.
+    ///
+    /// Only the node's metadata changes; existing rows are left untouched. Replies with
+    /// `ControlReplyPacket::Nack` if the column does not exist.
+    RenameBaseColumn {
+        node: LocalNodeIndex,
+        column: usize,
+        new_name: String,
+    },
+
+    /// Changes the default value that a `Base` node fills in for writes that omit a column.
+    ///
+    /// Rows that are already stored keep their values. Replies with `ControlReplyPacket::Nack` if
+    /// the base has no default for that column.
+    SetColumnDefault {
+        node: LocalNodeIndex,
+        column: usize,
+        default: DataType,
     },
 
     /// Update Egress node.
@@ -154,6 +272,17 @@ pub enum Packet {
         new_tag: Option<(Tag, NodeIndex)>,
     },
 
+    /// Make an Egress node send to `new` instead of to its existing downstream node `old_dst`.
+    ///
+    /// Everything the egress sent before this packet went to the old downstream node, and
+    /// everything after goes to the new one. Acked once the swap has happened, or replies with
+    /// `ControlReplyPacket::Nack` if the egress does not send to `old_dst`.
+    ReplaceEgressTx {
+        node: LocalNodeIndex,
+        old_dst: NodeIndex,
+        new: (NodeIndex, LocalNodeIndex, ReplicaAddr),
+    },
+
     /// Add a shard to a Sharder node.
     ///
     /// Note that this *must* be done *before* the sharder starts being used!
@@ -163,9 +292,17 @@ pub enum Packet {
     },
 
     /// Add a streamer to an existing reader node.
+    ///
+    /// If `send_initial` is set, the streamer first receives the reader's current contents. If
+    /// `filter` is set and not empty, only rows whose given columns hold the given values are
+    /// sent. If `coalesce` is set, a delete and a later insert of the same key in one batch are
+    /// sent as a single update.
     AddStreamer {
         node: LocalNodeIndex,
-        new_streamer: channel::StreamSender<Vec<node::StreamUpdate>>,
+        new_streamer: channel::StreamSender<node::StreamBatch>,
+        send_initial: bool,
+        filter: Option<Vec<(usize, DataType)>>,
+        coalesce: bool,
     },
 
     /// Set up a fresh, empty state for a node, indexed by a particular column.
@@ -176,7 +313,50 @@ pub enum Packet {
         state: InitialState,
     },
 
-    /// Probe for the number of records in the given node's state
+    /// Add an index on `index` to the fully materialized state of a running node.
+    ///
+    /// The index is built over the rows already in the state. Acked once it can be used, or if
+    /// the node was already indexed on those columns. Replies with `ControlReplyPacket::Nack` if
+    /// the node has no state, its state is partial, or a column is out of range.
+    AddIndex {
+        node: LocalNodeIndex,
+        index: Vec<usize>,
+    },
+
+    /// Remove the full index on `index` from a running node's state.
+    ///
+    /// Acked once the index is gone. Replies with `ControlReplyPacket::Nack` if the node is not
+    /// indexed on those columns, if it is the node's first index (which holds its rows), or if a
+    /// replay path or an operator still looks up through it.
+    DropIndex {
+        node: LocalNodeIndex,
+        index: Vec<usize>,
+    },
+
+    /// Ask for the column sets `node` is currently indexed on.
+    ///
+    /// Replies with `ControlReplyPacket::NodeIndices`. A reader reports its lookup key, and a
+    /// node without materialized state reports no indices.
+    GetNodeIndices {
+        node: LocalNodeIndex,
+    },
+
+    /// Make `new_parent` the parent of `node` in place of `old_parent`.
+    ///
+    /// Updates flowing out of `new_parent` are handled by `node` just like those from
+    /// `old_parent` were. Acked once the link is moved. Replies with `ControlReplyPacket::Nack` if
+    /// either parent does not exist, `old_parent` is not a parent of `node`, the parents differ
+    /// in width, or a replay path goes through `node`.
+    Reparent {
+        node: LocalNodeIndex,
+        old_parent: LocalNodeIndex,
+        new_parent: LocalNodeIndex,
+    },
+
+    /// Probe for the number of records in the given node's state, and their size in bytes.
+    ///
+    /// Both figures are taken in the same step, and are sent back as a
+    /// `ControlReplyPacket::StateSize`.
     StateSizeProbe {
         node: LocalNodeIndex,
     },
@@ -188,6 +368,11 @@ pub enum Packet {
         path: Vec<ReplayPathSegment>,
         notify_done: bool,
         trigger: TriggerEndpoint,
+        /// Buffered replay requests for paths with a higher priority are sent first. Paths
+        /// without a priority have the lowest one.
+        priority: Option<u8>,
+        /// Whether replay pieces should be compressed before they leave this domain.
+        compress: bool,
     },
 
     /// Ask domain (nicely) to replay a particular set of keys.
@@ -197,6 +382,14 @@ pub enum Packet {
         unishard: bool,
     },
 
+    /// Withdraw an earlier `RequestPartialReplay` for `key` along `tag`.
+    ///
+    /// The request is dropped if its replay has not started yet; otherwise this does nothing.
+    CancelPartialReplay {
+        tag: Tag,
+        key: Vec<DataType>,
+    },
+
     /// Ask domain (nicely) to replay a particular set of keys into a Reader.
     RequestReaderReplay {
         node: LocalNodeIndex,
@@ -204,10 +397,31 @@ pub enum Packet {
         keys: Vec<Vec<DataType>>,
     },
 
+    /// Ask the domain where replay path `tag` starts to list the keys of its state that start
+    /// with `prefix`, and to send them back to `requester` in a `PrefixKeys`.
+    ///
+    /// This is how a partial reader finds the keys to replay for a lookup on a prefix of its key.
+    RequestPrefixKeys {
+        tag: Tag,
+        node: LocalNodeIndex,
+        prefix: Vec<DataType>,
+        requester: (domain::Index, usize),
+    },
+
+    /// The keys that start with `prefix` in one source of the reader `node`, or `None` if that
+    /// source cannot list them.
+    PrefixKeys {
+        node: LocalNodeIndex,
+        prefix: Vec<DataType>,
+        keys: Option<Vec<Vec<DataType>>>,
+    },
+
     /// Instruct domain to replay the state of a particular node along an existing replay path.
     StartReplay {
         tag: Tag,
         from: LocalNodeIndex,
+        /// If given, the cumulative number of records replayed is sent here after each batch.
+        progress: Option<channel::ChannelSender<usize>>,
     },
 
     /// Sent to instruct a domain that a particular node should be considered ready to process
@@ -221,15 +435,121 @@ pub enum Packet {
     /// Notification from Blender for domain to terminate
     Quit,
 
+    /// Ask domain to terminate once it has processed every data packet it received before this
+    /// one, and an `UpstreamQuit` from each of the `upstreams` domain shards that send it data.
+    /// Data packets received after that are dropped, and control packets are answered with a
+    /// `ControlReplyPacket::Nack`.
+    ///
+    /// On quitting, the domain sends an `UpstreamQuit` to every domain shard it sends data to,
+    /// and then replies with an ack. This travels with regular data packets, so it does not
+    /// overtake them.
+    QuitDrain {
+        upstreams: usize,
+    },
+
+    /// Sent by a domain that is quitting after a `QuitDrain` to each of its downstream domain
+    /// shards, after all of its data. Lets the receiver know that nothing more will come from
+    /// that upstream.
+    UpstreamQuit,
+
     /// A packet used solely to drive the event loop forward.
     Spin,
 
+    /// Ask domain to acknowledge on the given channel once it has processed every data packet it
+    /// received before this one.
+    ///
+    /// This travels with regular data packets, so it does not overtake them.
+    FlushAndAck(channel::ChannelSender<()>),
+
     /// Request that a domain send usage statistics on the control reply channel.
     /// Argument specifies if we wish to get the full state size or just the partial nodes.
     GetStatistics,
 
+    /// Ask domain to push a statistics snapshot to `sink` every `interval_ms` milliseconds.
+    ///
+    /// The subscription is cancelled once `sink` is dropped.
+    SubscribeStatistics {
+        interval_ms: u64,
+        sink: channel::ChannelSender<(
+            noria::debug::stats::DomainStats,
+            HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
+        )>,
+    },
+
+    /// Start counting the processing statistics of `node`, or of every node in the domain if
+    /// `node` is `None`, from zero again.
+    ///
+    /// Only the processing times and counts are reset; a node's memory size is always its current
+    /// size. Acked once the counters are reset. Replies with `ControlReplyPacket::Nack` if `node`
+    /// is not in the domain.
+    ResetStatistics {
+        node: Option<LocalNodeIndex>,
+    },
+
     /// Ask domain to log its state size
     UpdateStateSize,
+
+    /// Ask domain to reply with a `ControlReplyPacket::Pong` as soon as possible.
+    Ping {
+        /// When the ping was sent, echoed back in the pong.
+        sent: time::SystemTime,
+    },
+
+    /// Request that a domain describe the replay paths it knows about on the control reply
+    /// channel.
+    GetReplayPaths,
+
+    /// Have the domain evict partial state whenever it grows beyond `bytes` bytes.
+    SetMemoryLimit {
+        bytes: usize,
+    },
+
+    /// Set how long, in microseconds, the domain may hold writes to a base table while waiting
+    /// for more to merge them with. The held writes are sent along once the time is up, even if
+    /// no more arrive. A value of 0 disables merging.
+    SetBatchTimeout {
+        micros: u64,
+    },
+
+    /// Accept at most `per_sec` rows a second written to the base table `node`.
+    ///
+    /// Writes that arrive faster than that are held, in order, until the rate allows them, which
+    /// also holds back the acknowledgements the writing clients wait for. A rate of 0 removes the
+    /// limit. Replies with `ControlReplyPacket::Nack` if `node` is not a base table in the domain.
+    ///
+    /// If `reject` is set, writes that would be held are rejected instead, and otherwise writes
+    /// that arrive while `max_held` writes are already held are. Rejected writes are dropped
+    /// without being processed, but are still acknowledged, and are counted in
+    /// `DomainStats::rejected_writes`.
+    SetIngressRateLimit {
+        node: LocalNodeIndex,
+        per_sec: u64,
+        max_held: Option<usize>,
+        reject: bool,
+    },
+
+    /// Stop processing data packets until a `Packet::Resume` arrives.
+    ///
+    /// Data packets received while paused are held, in order, and control packets are still
+    /// handled so that the paused domain can be inspected. Acked once the domain is paused.
+    ///
+    /// A `Packet::QuitDrain` resumes the domain, as it must process everything it received
+    /// before it quits.
+    Pause,
+
+    /// Continue processing data packets after a `Packet::Pause`, starting with any held packets.
+    ///
+    /// Acked once the held packets have been processed.
+    Resume,
+
+    /// Process the oldest data packet held by a paused domain, and then stay paused.
+    ///
+    /// Acked once that packet has been processed, or right away if no packets are held. Replies
+    /// with `ControlReplyPacket::Nack` if the domain is not paused.
+    ///
+    /// A stepped write to a base table with a `Packet::SetIngressRateLimit` is processed right
+    /// away, along with any writes to that table held before it.
+    Step,
 }
 
 impl Packet {
@@ -263,6 +583,32 @@ impl Packet {
         }
     }
 
+    /// Point this packet at a new destination node.
+    ///
+    /// This is a no-op for packets that do not travel along a link.
+    pub(crate) fn retarget(&mut self, new_dst: LocalNodeIndex) {
+        match *self {
+            Packet::Message { ref mut link, .. }
+            | Packet::ReplayPiece { ref mut link, .. }
+            | Packet::CompressedReplayPiece { ref mut link, .. }
+            | Packet::EvictKeys { ref mut link, .. } => link.dst = new_dst,
+            _ => {}
+        }
+    }
+
+    /// Replace both ends of the link this packet travels along.
+    ///
+    /// This is a no-op for packets that do not travel along a link.
+    pub(crate) fn reroute(&mut self, new_src: LocalNodeIndex, new_dst: LocalNodeIndex) {
+        match *self {
+            Packet::Message { ref mut link, .. }
+            | Packet::ReplayPiece { ref mut link, .. }
+            | Packet::CompressedReplayPiece { ref mut link, .. }
+            | Packet::EvictKeys { ref mut link, .. } => *link = Link::new(new_src, new_dst),
+            _ => {}
+        }
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         match *self {
             Packet::Message { ref data, .. } => data.is_empty(),
@@ -292,37 +638,350 @@ impl Packet {
         }
     }
 
+    /// Is this a control packet (as opposed to a data, replay, or eviction packet)?
+    ///
+    /// Control packets are those sent by the controller to direct the domain. Most of them change
+    /// the domain, so they are still handled in the order they were received in relative to data;
+    /// see `Packet::may_overtake` for those that are not.
+    pub fn is_control(&self) -> bool {
+        match *self {
+            Packet::Input { .. }
+            | Packet::Message { .. }
+            | Packet::ReplayPiece { .. }
+            | Packet::CompressedReplayPiece { .. }
+            | Packet::Checksummed { .. }
+            | Packet::Evict { .. }
+            | Packet::EvictKeys { .. }
+            | Packet::Finish(..)
+            | Packet::RequestPartialReplay { .. }
+            | Packet::CancelPartialReplay { .. }
+            | Packet::RequestReaderReplay { .. }
+            | Packet::RequestPrefixKeys { .. }
+            | Packet::PrefixKeys { .. }
+            | Packet::Spin
+            | Packet::FlushAndAck(..)
+            | Packet::QuitDrain { .. }
+            | Packet::UpstreamQuit => false,
+            _ => true,
+        }
+    }
+
+    /// Can this packet be handled ahead of packets that were received before it?
+    ///
+    /// True only for control packets that inspect the domain without changing it, or that stop it
+    /// outright, so that what they do cannot depend on what was received before them.
+    pub fn may_overtake(&self) -> bool {
+        match *self {
+            Packet::GetStatistics
+            | Packet::SubscribeStatistics { .. }
+            | Packet::GetReplayPaths
+            | Packet::GetNodeIndices { .. }
+            | Packet::Ping { .. }
+            | Packet::Quit => true,
+            _ => false,
+        }
+    }
+
     pub(crate) fn tag(&self) -> Option<Tag> {
         match *self {
             Packet::ReplayPiece { tag, .. } => Some(tag),
+            Packet::CompressedReplayPiece { tag, .. } => Some(tag),
             Packet::EvictKeys { tag, .. } => Some(tag),
             _ => None,
         }
     }
 
-    pub(crate) fn data(&self) -> &Records {
+    /// The link this packet travels along, or `None` if it is not a data packet.
+    pub fn try_link(&self) -> Option<&Link> {
         match *self {
-            Packet::Message { ref data, .. } => data,
-            Packet::ReplayPiece { ref data, .. } => data,
-            _ => unreachable!(),
+            Packet::Message { ref link, .. } => Some(link),
+            Packet::ReplayPiece { ref link, .. } => Some(link),
+            Packet::CompressedReplayPiece { ref link, .. } => Some(link),
+            Packet::Checksummed { ref link, .. } => Some(link),
+            Packet::EvictKeys { ref link, .. } => Some(link),
+            _ => None,
         }
     }
 
-    pub(crate) fn take_data(&mut self) -> Records {
+    /// Stamp `seq` onto the link this packet travels along.
+    ///
+    /// Returns `false`, and leaves the packet alone, if the packet does not travel along a link.
+    pub fn sequence(&mut self, seq: u64) -> bool {
+        match *self {
+            Packet::Message { ref mut link, .. }
+            | Packet::ReplayPiece { ref mut link, .. }
+            | Packet::CompressedReplayPiece { ref mut link, .. }
+            | Packet::EvictKeys { ref mut link, .. } => {
+                link.seq = Some(seq);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Serialize a packet that carries records into a `Checksummed` one, so that the receiving
+    /// end of a remote connection can tell if its bytes were corrupted on the way.
+    ///
+    /// Any other packet is returned unchanged.
+    pub fn checksummed(self) -> Packet {
+        let link = match self {
+            Packet::Message { link, .. }
+            | Packet::ReplayPiece { link, .. }
+            | Packet::CompressedReplayPiece { link, .. } => link,
+            p => return p,
+        };
+        let packet = bincode::serialize(&self).expect("failed to serialize packet");
+        Packet::Checksummed {
+            link,
+            checksum: crc32fast::hash(&packet),
+            packet,
+        }
+    }
+
+    /// Check that packets encoded with version `theirs` can be decoded by this version.
+    ///
+    /// Fails with an error naming both versions if they cannot.
+    pub fn check_version(theirs: u16) -> Result<(), String> {
+        check_version_as(theirs, PACKET_VERSION, COMPATIBLE_PACKET_VERSIONS)
+    }
+
+    /// Check the bytes of a `Checksummed` packet against its checksum, and turn them back into
+    /// the packet they were made from.
+    ///
+    /// Any other packet is returned unchanged. Fails with an error describing the mismatch if the
+    /// bytes were corrupted.
+    pub fn verify_checksum(self) -> Result<Packet, String> {
+        match self {
+            Packet::Checksummed {
+                checksum, packet, ..
+            } => {
+                let actual = crc32fast::hash(&packet);
+                if actual != checksum {
+                    return Err(format!(
+                        "checksum mismatch (expected {:08x}, got {:08x})",
+                        checksum, actual
+                    ));
+                }
+                bincode::deserialize(&packet).map_err(|e| e.to_string())
+            }
+            p => Ok(p),
+        }
+    }
+
+    /// The records carried by this packet, or `None` if it carries no records.
+    pub(crate) fn try_data(&self) -> Option<&Records> {
+        match *self {
+            Packet::Message { ref data, .. } => Some(data),
+            Packet::ReplayPiece { ref data, .. } => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Take the records carried by this packet, or `None` if it carries no records.
+    pub(crate) fn try_take_data(&mut self) -> Option<Records> {
         use std::mem;
         let inner = match *self {
             Packet::Message { ref mut data, .. } => data,
             Packet::ReplayPiece { ref mut data, .. } => data,
-            _ => unreachable!(),
+            _ => return None,
+        };
+        Some(mem::replace(inner, Records::default()))
+    }
+
+    pub(crate) fn data(&self) -> &Records {
+        self.try_data().unwrap()
+    }
+
+    pub(crate) fn take_data(&mut self) -> Records {
+        self.try_take_data().unwrap()
+    }
+
+    /// Compress the records of a `ReplayPiece`, turning it into a `CompressedReplayPiece`.
+    ///
+    /// Any other packet is returned unchanged.
+    pub(crate) fn compress(self) -> Packet {
+        match self {
+            Packet::ReplayPiece {
+                link,
+                tag,
+                data,
+                context,
+            } => {
+                let data = bincode::serialize(&data).expect("failed to serialize replay piece");
+                let data = zstd::encode_all(&data[..], 0).expect("failed to compress replay piece");
+                Packet::CompressedReplayPiece {
+                    link,
+                    tag,
+                    data,
+                    context,
+                }
+            }
+            p => p,
+        }
+    }
+
+    /// Turn a `CompressedReplayPiece` back into the `ReplayPiece` it was made from.
+    ///
+    /// Any other packet is returned unchanged. Fails with an error describing the problem if the
+    /// compressed records can't be read back.
+    pub fn decompress(self) -> Result<Packet, String> {
+        match self {
+            Packet::CompressedReplayPiece {
+                link,
+                tag,
+                data,
+                context,
+            } => {
+                let data = zstd::decode_all(&data[..])
+                    .map_err(|e| format!("failed to decompress replay piece: {}", e))?;
+                let data = bincode::deserialize(&data[..])
+                    .map_err(|e| format!("corrupt replay piece: {}", e))?;
+                Ok(Packet::ReplayPiece {
+                    link,
+                    tag,
+                    data,
+                    context,
+                })
+            }
+            p => Ok(p),
+        }
+    }
+
+    /// Try to fold `other` into this packet so that it can be processed as a single update.
+    ///
+    /// Only two regular `Message`s that travel along the same link can be merged; in that case
+    /// `other`'s records are appended to ours, and we keep the later of the two source offsets
+    /// and the request ids of both. Any other packet is handed back untouched.
+    pub(crate) fn try_merge(&mut self, other: Packet) -> Result<(), Packet> {
+        match (self, other) {
+            (
+                &mut Packet::Message {
+                    ref link,
+                    ref mut data,
+                    ref mut source_offset,
+                    ref mut request_ids,
+                },
+                Packet::Message {
+                    link: olink,
+                    data: odata,
+                    source_offset: ooffset,
+                    request_ids: orequest_ids,
+                },
+            ) if *link == olink => {
+                data.extend(odata);
+                *source_offset = (*source_offset).max(ooffset);
+                request_ids.extend(orequest_ids);
+                Ok(())
+            }
+            (_, other) => Err(other),
+        }
+    }
+
+    /// Estimate the number of bytes this packet occupies, including the heap allocations of any
+    /// records or keys it carries.
+    pub(crate) fn estimate_heap_size(&self) -> usize {
+        use std::mem::size_of;
+
+        let heap = match *self {
+            Packet::Message { ref data, .. } | Packet::ReplayPiece { ref data, .. } => {
+                data.capacity() * size_of::<Record>()
+                    + data
+                        .iter()
+                        .flat_map(|r| r.iter())
+                        .map(|v| v.deep_size_of() as usize)
+                        .sum::<usize>()
+            }
+            Packet::EvictKeys { ref keys, .. } => keys
+                .iter()
+                .map(|k| k.deep_size_of() as usize)
+                .sum::<usize>(),
+            _ => 0,
         };
-        mem::replace(inner, Records::default())
+
+        size_of::<Packet>() + heap
+    }
+
+    /// Split a data-carrying packet into packets whose `estimate_heap_size` is at most
+    /// `max_bytes`, preserving the order of its records.
+    ///
+    /// A record that is too large on its own gets a packet to itself. Only `Message`s and regular
+    /// `ReplayPiece`s are split: every chunk gets the same link and tag, but the end-of-replay
+    /// marker and the source offset go only on the last chunk, since they describe the packet as
+    /// a whole. Any other packet, including a partial replay (whose keys must be filled in one
+    /// go), is returned unchanged.
+    #[allow(dead_code)]
+    pub(crate) fn split_by_size(mut self, max_bytes: usize) -> Vec<Packet> {
+        use std::mem::{self, size_of};
+
+        let splittable = match self {
+            Packet::Message { .. }
+            | Packet::ReplayPiece {
+                context: ReplayPieceContext::Regular { .. },
+                ..
+            } => true,
+            _ => false,
+        };
+        if !splittable || self.estimate_heap_size() <= max_bytes {
+            return vec![self];
+        }
+
+        let record_size = |r: &Record| {
+            size_of::<Record>() + r.iter().map(|v| v.deep_size_of() as usize).sum::<usize>()
+        };
+
+        let mut chunks: Vec<Vec<Record>> = Vec::new();
+        let mut chunk = Vec::new();
+        let mut chunk_size = size_of::<Packet>();
+        for r in self.take_data().drain(..) {
+            let rsize = record_size(&r);
+            if !chunk.is_empty() && chunk_size + rsize > max_bytes {
+                chunks.push(mem::take(&mut chunk));
+                chunk_size = size_of::<Packet>();
+            }
+            chunk_size += rsize;
+            chunk.push(r);
+        }
+        chunks.push(chunk);
+
+        let nchunks = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut rs)| {
+                // don't let spare capacity push the chunk over the limit
+                rs.shrink_to_fit();
+                let mut p = self.clone_data();
+                p.map_data(|data| *data = rs.into());
+                if i != nchunks - 1 {
+                    match p {
+                        Packet::Message {
+                            ref mut source_offset,
+                            ..
+                        } => *source_offset = None,
+                        Packet::ReplayPiece {
+                            context: ReplayPieceContext::Regular { ref mut last },
+                            ..
+                        } => *last = false,
+                        _ => unreachable!(),
+                    }
+                }
+                p
+            })
+            .collect()
     }
 
     pub(crate) fn clone_data(&self) -> Self {
         match *self {
-            Packet::Message { link, ref data } => Packet::Message {
+            Packet::Message {
+                link,
+                ref data,
+                source_offset,
+                ref request_ids,
+            } => Packet::Message {
                 link,
                 data: data.clone(),
+                source_offset,
+                request_ids: request_ids.clone(),
             },
             Packet::ReplayPiece {
                 link,
@@ -335,6 +994,17 @@ impl Packet {
                 data: data.clone(),
                 context: context.clone(),
             },
+            Packet::CompressedReplayPiece {
+                link,
+                tag,
+                ref data,
+                ref context,
+            } => Packet::CompressedReplayPiece {
+                link,
+                tag,
+                data: data.clone(),
+                context: context.clone(),
+            },
             _ => unreachable!(),
         }
     }
@@ -344,13 +1014,25 @@ impl fmt::Debug for Packet {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Packet::Input { .. } => write!(f, "Packet::Input"),
-            Packet::Message { ref link, .. } => write!(f, "Packet::Message({:?})", link),
+            Packet::Message {
+                ref link, ref data, ..
+            } => {
+                let (pos, neg) = data.polarity_counts();
+                write!(f, "Packet::Message({:?}, +{}/-{})", link, pos, neg)
+            }
             Packet::RequestReaderReplay { ref keys, .. } => {
                 write!(f, "Packet::RequestReaderReplay({:?})", keys)
             }
             Packet::RequestPartialReplay { ref tag, .. } => {
                 write!(f, "Packet::RequestPartialReplay({:?})", tag)
             }
+            Packet::RequestPrefixKeys { ref prefix, .. } => {
+                write!(f, "Packet::RequestPrefixKeys({:?})", prefix)
+            }
+            Packet::PrefixKeys { ref prefix, .. } => write!(f, "Packet::PrefixKeys({:?})", prefix),
+            Packet::CancelPartialReplay { ref tag, .. } => {
+                write!(f, "Packet::CancelPartialReplay({:?})", tag)
+            }
             Packet::ReplayPiece {
                 ref link,
                 ref tag,
@@ -363,6 +1045,23 @@ impl fmt::Debug for Packet {
                 tag.id(),
                 data.len()
             ),
+            Packet::CompressedReplayPiece {
+                ref link,
+                ref tag,
+                ref data,
+                ..
+            } => write!(
+                f,
+                "Packet::CompressedReplayPiece({:?}, tag {}, {} bytes)",
+                link,
+                tag.id(),
+                data.len()
+            ),
+            Packet::Checksummed {
+                ref link,
+                ref packet,
+                ..
+            } => write!(f, "Packet::Checksummed({:?}, {} bytes)", link, packet.len()),
             ref p => {
                 use std::mem;
                 write!(f, "Packet::Control({:?})", mem::discriminant(p))
@@ -374,6 +1073,8 @@ impl fmt::Debug for Packet {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ControlReplyPacket {
     Ack(()),
+    /// A control packet could not be applied, and was ignored.
+    Nack(String),
     /// (number of rows, size in bytes)
     StateSize(usize, u64),
     Statistics(
@@ -381,6 +1082,13 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    /// The time the `Packet::Ping` being answered was sent.
+    Pong(time::SystemTime),
+    /// The local index a domain installed a new node at.
+    NodeAdded(LocalNodeIndex),
+    ReplayPaths(Vec<noria::debug::replay::ReplayPathInfo>),
+    /// The column sets a node is indexed on.
+    NodeIndices(Vec<Vec<usize>>),
 }
 
 impl ControlReplyPacket {
@@ -388,3 +1096,394 @@ impl ControlReplyPacket {
         ControlReplyPacket::Ack(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepare_partial_global_roundtrip() {
+        let node = unsafe { LocalNodeIndex::make(3) };
+        let p = Packet::PrepareState {
+            node,
+            state: InitialState::PartialGlobal {
+                gid: petgraph::graph::NodeIndex::new(7),
+                cols: 4,
+                key: vec![1, 2],
+                trigger_domain: (domain::Index::from(2), 3),
+            },
+        };
+
+        let bytes = bincode::serialize(&p).unwrap();
+        match bincode::deserialize(&bytes[..]).unwrap() {
+            Packet::PrepareState {
+                node: n,
+                state:
+                    InitialState::PartialGlobal {
+                        gid,
+                        cols,
+                        key,
+                        trigger_domain,
+                    },
+            } => {
+                assert_eq!(n, node);
+                assert_eq!(gid, petgraph::graph::NodeIndex::new(7));
+                assert_eq!(cols, 4);
+                assert_eq!(key, vec![1, 2]);
+                assert_eq!(trigger_domain, (domain::Index::from(2), 3));
+            }
+            p => panic!("got unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn estimate_heap_size() {
+        use std::mem::size_of;
+
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let long = "this string is too long to be stored inline";
+        let data: Records = vec![
+            Record::Positive(vec![1.into(), long.into(), 2.5f64.into()]),
+            Record::Negative(vec![DataType::None, "short".into(), 42u64.into()]),
+        ]
+        .into();
+        let nvals = 6;
+        let ncap = data.capacity();
+
+        let p = Packet::Message {
+            link,
+            data,
+            source_offset: None,
+            request_ids: Vec::new(),
+        };
+        let est = p.estimate_heap_size();
+
+        // every value takes up at least a DataType slot, and only the long string spills
+        let lower =
+            size_of::<Packet>() + ncap * size_of::<Record>() + nvals * size_of::<DataType>();
+        assert!(est >= lower + long.len());
+        assert!(est <= lower + long.len() + 64);
+
+        // control packets only count their fixed size
+        assert_eq!(Packet::Quit.estimate_heap_size(), size_of::<Packet>());
+    }
+
+    #[test]
+    fn split_by_size() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let data: Records = (0..100)
+            .map(|i| {
+                vec![
+                    i.into(),
+                    format!("a value that does not fit inline {}", i).into(),
+                ]
+            })
+            .collect::<Vec<Vec<DataType>>>()
+            .into();
+        let p = Packet::Message {
+            link,
+            data: data.clone(),
+            source_offset: Some(7),
+            request_ids: Vec::new(),
+        };
+        let max = p.estimate_heap_size() / 4;
+
+        let chunks = p.split_by_size(max);
+        assert!(chunks.len() >= 4);
+        let mut all = Vec::new();
+        for (i, c) in chunks.iter().enumerate() {
+            assert!(c.estimate_heap_size() <= max);
+            match *c {
+                Packet::Message {
+                    link: clink,
+                    ref data,
+                    source_offset,
+                    ..
+                } => {
+                    assert_eq!(clink, link);
+                    assert!(!data.is_empty());
+                    let last = i == chunks.len() - 1;
+                    assert_eq!(source_offset, if last { Some(7) } else { None });
+                    all.extend(data.iter().cloned());
+                }
+                _ => unreachable!(),
+            }
+        }
+        let all: Records = all.into();
+        assert_eq!(all, data);
+
+        // small packets and control packets come back as they are
+        let p = Packet::Message {
+            link,
+            data: vec![vec![1.into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        };
+        assert_eq!(p.split_by_size(max).len(), 1);
+        match &Packet::Quit.split_by_size(0)[..] {
+            [Packet::Quit] => {}
+            r => panic!("unexpected split {:?}", r),
+        }
+    }
+
+    #[test]
+    fn checksum_detects_corruption() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let p = Packet::Message {
+            link,
+            data: vec![vec![1.into(), "some text to be checksummed".into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        };
+        // packets that were never checksummed have nothing to check
+        assert!(p.clone().verify_checksum().is_ok());
+
+        let p = p.checksummed();
+        assert_eq!(p.try_link(), Some(&link));
+        let mut bytes = bincode::serialize(&p).unwrap();
+        let received: Packet = bincode::deserialize(&bytes).unwrap();
+        match received.verify_checksum() {
+            Ok(Packet::Message { data, .. }) => assert_eq!(data.len(), 1),
+            p => panic!("unexpected packet: {:?}", p),
+        }
+
+        let at = bytes.windows(4).position(|w| w == b"text").unwrap();
+        bytes[at] = b'T';
+        let received: Packet = bincode::deserialize(&bytes).unwrap();
+        let e = received.verify_checksum().unwrap_err();
+        assert!(e.contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn versioned_encoding() {
+        assert!(Packet::check_version(PACKET_VERSION).is_ok());
+
+        // a peer one version behind does not know how to read this
+        let older = PACKET_VERSION - 1;
+        let e = check_version_as(PACKET_VERSION, older, &[older]).unwrap_err();
+        assert!(e.contains(&PACKET_VERSION.to_string()));
+        assert!(e.contains(&older.to_string()));
+    }
+
+    #[test]
+    fn try_merge() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let mut p = Packet::Message {
+            link,
+            data: vec![vec![1.into()]].into(),
+            source_offset: Some(2),
+            request_ids: vec![7],
+        };
+
+        let other = Packet::Message {
+            link,
+            data: vec![(vec![2.into()], false)].into(),
+            source_offset: Some(1),
+            request_ids: vec![8],
+        };
+        assert!(p.try_merge(other).is_ok());
+        match p {
+            Packet::Message {
+                source_offset,
+                ref request_ids,
+                ..
+            } => {
+                assert_eq!(source_offset, Some(2));
+                assert_eq!(request_ids, &vec![7, 8]);
+            }
+            _ => unreachable!(),
+        }
+        let expected: Records = vec![
+            Record::Positive(vec![1.into()]),
+            Record::Negative(vec![2.into()]),
+        ]
+        .into();
+        assert_eq!(p.data(), &expected);
+
+        // messages along a different link must be kept apart
+        let other_link = unsafe { Link::new(LocalNodeIndex::make(2), LocalNodeIndex::make(1)) };
+        let other = Packet::Message {
+            link: other_link,
+            data: vec![vec![3.into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        };
+        match p.try_merge(other) {
+            Err(Packet::Message { link, data, .. }) => {
+                assert_eq!(link, other_link);
+                assert_eq!(data.len(), 1);
+            }
+            r => panic!("unexpected merge result {:?}", r),
+        }
+
+        // replays have their own ordering guarantees, and are never merged into messages
+        let other = Packet::ReplayPiece {
+            link,
+            tag: Tag(0),
+            data: vec![vec![4.into()]].into(),
+            context: ReplayPieceContext::Regular { last: true },
+        };
+        assert!(p.try_merge(other).is_err());
+        assert_eq!(p.data().len(), 2);
+    }
+
+    #[test]
+    fn try_accessors() {
+        let mut p = Packet::Quit;
+        assert!(p.try_link().is_none());
+        assert!(p.try_data().is_none());
+        assert!(p.try_take_data().is_none());
+
+        let mut p = Packet::Ready {
+            node: unsafe { LocalNodeIndex::make(0) },
+            purge: false,
+            index: HashSet::new(),
+        };
+        assert!(p.try_link().is_none());
+        assert!(p.try_data().is_none());
+        assert!(p.try_take_data().is_none());
+
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let mut p = Packet::Message {
+            link,
+            data: vec![vec![1.into()]].into(),
+            source_offset: None,
+            request_ids: Vec::new(),
+        };
+        assert_eq!(p.try_link(), Some(&link));
+        assert_eq!(p.try_data().map(|d| d.len()), Some(1));
+        assert_eq!(p.try_take_data().map(|d| d.len()), Some(1));
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn retarget_replay_piece() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let mut p = Packet::ReplayPiece {
+            link,
+            tag: Tag(7),
+            data: vec![vec![1.into()]].into(),
+            context: ReplayPieceContext::Regular { last: false },
+        };
+
+        let (src, dst) = unsafe { (LocalNodeIndex::make(2), LocalNodeIndex::make(3)) };
+        p.retarget(dst);
+        assert_eq!(p.try_link(), Some(&Link::new(link.src, dst)));
+        p.reroute(src, link.dst);
+        assert_eq!(p.try_link(), Some(&Link::new(src, link.dst)));
+        assert_eq!(p.tag(), Some(Tag(7)));
+        let expected: Records = vec![vec![1.into()]].into();
+        assert_eq!(p.data(), &expected);
+
+        // control packets are left alone
+        let mut p = Packet::Quit;
+        p.reroute(src, dst);
+        assert!(p.try_link().is_none());
+    }
+
+    #[test]
+    fn clone_replay_piece() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let mut for_keys = HashSet::new();
+        for_keys.insert(vec![DataType::from(1)]);
+        let p = Packet::ReplayPiece {
+            link,
+            tag: Tag(1),
+            data: vec![vec![1.into(), "a".into()]].into(),
+            context: ReplayPieceContext::Partial {
+                for_keys,
+                unishard: true,
+                ignore: false,
+            },
+        };
+
+        let mut c = p.clone_data();
+        c.map_data(|rs| rs.push(Record::Negative(vec![1.into(), "a".into()])));
+        if let Packet::ReplayPiece {
+            context:
+                ReplayPieceContext::Partial {
+                    ref mut for_keys, ..
+                },
+            ..
+        } = c
+        {
+            for_keys.insert(vec![DataType::from(2)]);
+        }
+
+        assert_eq!(p.data().len(), 1);
+        assert_eq!(c.data().len(), 2);
+        match (p, c) {
+            (
+                Packet::ReplayPiece {
+                    context: ReplayPieceContext::Partial { for_keys: pk, .. },
+                    ..
+                },
+                Packet::ReplayPiece {
+                    link: clink,
+                    tag,
+                    context: ReplayPieceContext::Partial { for_keys: ck, .. },
+                    ..
+                },
+            ) => {
+                assert_eq!(clink, link);
+                assert_eq!(tag, Tag(1));
+                assert_eq!(pk.len(), 1);
+                assert_eq!(ck.len(), 2);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn compressed_replay_piece_roundtrip() {
+        let link = unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) };
+        let data: Records = (0..10_000)
+            .map(|i| vec![i.into(), format!("row number {}", i).into(), (i % 7).into()])
+            .collect::<Vec<Vec<DataType>>>()
+            .into();
+        let raw = bincode::serialize(&data).unwrap();
+
+        let p = Packet::ReplayPiece {
+            link,
+            tag: Tag(3),
+            data,
+            context: ReplayPieceContext::Regular { last: true },
+        };
+
+        let c = p.compress();
+        match c {
+            Packet::CompressedReplayPiece { ref data, .. } => assert!(data.len() < raw.len()),
+            _ => unreachable!(),
+        }
+        // compressed pieces must still be routed like the replay piece they stand in for
+        assert_eq!(c.tag(), Some(Tag(3)));
+        assert_eq!(c.try_link(), Some(&link));
+        assert!(!c.is_control());
+
+        match bincode::deserialize(&bincode::serialize(&c).unwrap()[..]).unwrap() {
+            p @ Packet::CompressedReplayPiece { .. } => match p.decompress().unwrap() {
+                Packet::ReplayPiece {
+                    link: dlink,
+                    tag,
+                    data,
+                    context: ReplayPieceContext::Regular { last: true },
+                } => {
+                    assert_eq!(dlink, link);
+                    assert_eq!(tag, Tag(3));
+                    assert_eq!(bincode::serialize(&data).unwrap(), raw);
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+
+        // corrupted bytes are reported rather than panicking the receiving domain
+        let corrupt = Packet::CompressedReplayPiece {
+            link,
+            tag: Tag(3),
+            data: vec![1, 2, 3],
+            context: ReplayPieceContext::Regular { last: true },
+        };
+        assert!(corrupt.decompress().is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::prelude::*;
+use std::collections::VecDeque;
+use std::time;
+
+/// Holds back writes to a base table so that they are accepted at no more than a set rate.
+///
+/// Every row written uses up a fixed slice of a second, and a write is only accepted once the
+/// slices used by the writes before it have passed. Writes that arrive too early are held, in
+/// order, until their turn comes, unless the limit rejects them instead.
+pub struct IngressRateLimit {
+    /// how much time each row uses up, or `None` if there is no limit
+    per_row: Option<time::Duration>,
+    /// when the next write may be accepted
+    next: time::Instant,
+    held: VecDeque<Box<Packet>>,
+    /// how many writes may be held at once, if there is a cap
+    max_held: Option<usize>,
+    /// reject writes that can't be accepted right away, rather than hold them
+    reject: bool,
+}
+
+impl IngressRateLimit {
+    /// Accept at most `per_sec` rows per second, or any number of rows if `per_sec` is 0.
+    pub fn new(per_sec: u64) -> Self {
+        let mut limit = IngressRateLimit {
+            per_row: None,
+            next: time::Instant::now(),
+            held: VecDeque::new(),
+            max_held: None,
+            reject: false,
+        };
+        limit.set_rate(per_sec);
+        limit
+    }
+
+    /// Change the rate writes are accepted at. If `per_sec` is 0, held writes are let go at once.
+    pub fn set_rate(&mut self, per_sec: u64) {
+        if per_sec == 0 {
+            self.per_row = None;
+            self.next = time::Instant::now();
+        } else {
+            self.per_row = Some(time::Duration::from_nanos(1_000_000_000 / per_sec));
+        }
+    }
+
+    /// Reject writes that arrive while `max_held` writes are already held, or any write that
+    /// can't be accepted right away if `reject` is set.
+    pub fn set_overflow(&mut self, max_held: Option<usize>, reject: bool) {
+        self.max_held = max_held;
+        self.reject = reject;
+    }
+
+    /// Returns true if there is no limit, and no writes are held.
+    pub fn is_idle(&self) -> bool {
+        self.per_row.is_none() && self.held.is_empty()
+    }
+
+    /// Hold a write that arrived at `now` until it may be accepted.
+    ///
+    /// The write is handed back if it is rejected instead.
+    pub fn hold(&mut self, p: Box<Packet>, now: time::Instant) -> Result<(), Box<Packet>> {
+        if self.held.is_empty() && self.next < now {
+            // time spent idle isn't saved up for a burst of writes later
+            self.next = now;
+        }
+
+        let waits = self.per_row.is_some() && (!self.held.is_empty() || now < self.next);
+        let full = self
+            .max_held
+            .map(|max| self.held.len() >= max)
+            .unwrap_or(false);
+        if waits && (self.reject || full) {
+            return Err(p);
+        }
+        self.held.push_back(p);
+        Ok(())
+    }
+
+    /// Take the oldest held write, if it may be accepted at `now`.
+    pub fn release(&mut self, now: time::Instant) -> Option<Box<Packet>> {
+        let per_row = match self.per_row {
+            Some(per_row) => per_row,
+            None => return self.held.pop_front(),
+        };
+        if now < self.next {
+            return None;
+        }
+
+        let p = self.held.pop_front()?;
+        let rows = match *p {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.data.len(),
+            _ => unreachable!(),
+        };
+        self.next += per_row * rows as u32;
+        Some(p)
+    }
+
+    /// Let go of all held writes, whether or not they may be accepted yet.
+    pub fn release_all(&mut self) -> impl Iterator<Item = Box<Packet>> + '_ {
+        self.held.drain(..)
+    }
+
+    /// When the oldest held write may be accepted, if any writes are held.
+    pub fn next_release(&self) -> Option<time::Instant> {
+        if self.held.is_empty() {
+            None
+        } else {
+            Some(self.next)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::internal::LocalOrNot;
+    use noria::TableOperation;
+
+    fn input(rows: i32) -> Box<Packet> {
+        Box::new(Packet::Input {
+            inner: LocalOrNot::new(Input {
+                dst: unsafe { LocalNodeIndex::make(0) },
+                data: (0..rows)
+                    .map(|i| TableOperation::Insert(vec![i.into()]))
+                    .collect(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            }),
+            src: None,
+            senders: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn sustains_configured_rate() {
+        let mut limit = IngressRateLimit::new(100);
+        let start = time::Instant::now();
+
+        // a burst of far more writes than the limit allows arrives at once
+        for _ in 0..1000 {
+            limit.hold(input(1), start).unwrap();
+        }
+
+        // let writes go as the clock ticks along for two seconds
+        let tick = time::Duration::from_millis(3);
+        let mut now = start;
+        let mut accepted = 0;
+        while now < start + time::Duration::from_secs(2) {
+            while limit.release(now).is_some() {
+                accepted += 1;
+            }
+            now += tick;
+        }
+        assert!(accepted >= 199 && accepted <= 201, "accepted {}", accepted);
+        assert!(limit.next_release().unwrap() >= now - tick);
+    }
+
+    #[test]
+    fn counts_rows_not_packets() {
+        let mut limit = IngressRateLimit::new(10);
+        let start = time::Instant::now();
+        limit.hold(input(5), start).unwrap();
+        limit.hold(input(1), start).unwrap();
+
+        assert!(limit.release(start).is_some());
+        // the first write used up half a second
+        assert!(limit
+            .release(start + time::Duration::from_millis(400))
+            .is_none());
+        assert!(limit
+            .release(start + time::Duration::from_millis(500))
+            .is_some());
+        assert!(limit.next_release().is_none());
+    }
+
+    #[test]
+    fn caps_held_writes() {
+        let mut limit = IngressRateLimit::new(1);
+        limit.set_overflow(Some(2), false);
+        let start = time::Instant::now();
+
+        // the first write is accepted right away, and two more are held
+        limit.hold(input(1), start).unwrap();
+        assert!(limit.release(start).is_some());
+        limit.hold(input(1), start).unwrap();
+        limit.hold(input(1), start).unwrap();
+        assert!(limit.hold(input(1), start).is_err());
+
+        // once a held write is accepted, there's room for another
+        let later = start + time::Duration::from_secs(1);
+        assert!(limit.release(later).is_some());
+        assert!(limit.hold(input(1), later).is_ok());
+        assert!(limit.hold(input(1), later).is_err());
+    }
+
+    #[test]
+    fn rejects_writes_that_would_wait() {
+        let mut limit = IngressRateLimit::new(1);
+        limit.set_overflow(None, true);
+        let start = time::Instant::now();
+
+        limit.hold(input(1), start).unwrap();
+        assert!(limit.release(start).is_some());
+        assert!(limit.hold(input(1), start).is_err());
+        assert!(limit.release(start).is_none());
+
+        let later = start + time::Duration::from_secs(1);
+        assert!(limit.hold(input(1), later).is_ok());
+        assert!(limit.release(later).is_some());
+    }
+
+    #[test]
+    fn zero_removes_limit() {
+        let mut limit = IngressRateLimit::new(1);
+        let start = time::Instant::now();
+        for _ in 0..3 {
+            limit.hold(input(1), start).unwrap();
+        }
+        assert!(limit.release(start).is_some());
+        assert!(limit.release(start).is_none());
+
+        limit.set_rate(0);
+        assert!(limit.next_release().unwrap() <= time::Instant::now());
+        assert!(limit.release(start).is_some());
+        assert!(limit.release(start).is_some());
+        assert!(limit.is_idle());
+    }
+}
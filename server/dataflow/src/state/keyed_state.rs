@@ -31,6 +31,47 @@ impl KeyedState {
         }
     }
 
+    /// Returns a copy of every key in the map.
+    pub(super) fn keys(&self) -> Vec<Vec<DataType>> {
+        match *self {
+            KeyedState::Single(ref m) => m.keys().map(|k| vec![k.clone()]).collect(),
+            KeyedState::Double(ref m) => m.keys().map(|k| vec![k.0.clone(), k.1.clone()]).collect(),
+            KeyedState::Tri(ref m) => m
+                .keys()
+                .map(|k| vec![k.0.clone(), k.1.clone(), k.2.clone()])
+                .collect(),
+            KeyedState::Quad(ref m) => m
+                .keys()
+                .map(|k| vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()])
+                .collect(),
+            KeyedState::Quin(ref m) => m
+                .keys()
+                .map(|k| {
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                    ]
+                })
+                .collect(),
+            KeyedState::Sex(ref m) => m
+                .keys()
+                .map(|k| {
+                    vec![
+                        k.0.clone(),
+                        k.1.clone(),
+                        k.2.clone(),
+                        k.3.clone(),
+                        k.4.clone(),
+                        k.5.clone(),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
     /// Remove all rows for a randomly chosen key seeded by `seed`, returning that key along with
     /// the number of bytes freed. Returns `None` if map is empty.
     pub(super) fn evict_with_seed(&mut self, seed: usize) -> Option<(u64, Vec<DataType>)> {
@@ -22,7 +22,10 @@ impl SizeOf for MemoryState {
     }
 
     fn deep_size_of(&self) -> u64 {
-        self.mem_size
+        use std::mem::size_of;
+
+        // the rows are shared between indices, but every index holds its own entry for each row
+        self.mem_size + (self.rows() * size_of::<Row>()) as u64
     }
 }
 
@@ -64,6 +67,18 @@ impl State for MemoryState {
         }
     }
 
+    fn drop_key(&mut self, columns: &[usize]) -> bool {
+        match self.state_for(columns) {
+            Some(i) if i > 0 => {
+                // rows are shared with the first index, so only the index's entries are freed
+                assert!(!self.state[i].partial());
+                self.state.remove(i);
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn is_useful(&self) -> bool {
         !self.state.is_empty()
     }
@@ -127,12 +142,20 @@ impl State for MemoryState {
         self.mem_size = self.mem_size.checked_sub(freed_bytes).unwrap();
     }
 
-    fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
+    fn try_lookup<'a>(
+        &'a self,
+        columns: &[usize],
+        key: &KeyType,
+    ) -> Result<LookupResult<'a>, String> {
         debug_assert!(!self.state.is_empty(), "lookup on uninitialized index");
-        let index = self
-            .state_for(columns)
-            .expect("lookup on non-indexed column set");
-        self.state[index].lookup(key)
+        let index = self.state_for(columns).ok_or_else(|| {
+            format!(
+                "lookup on non-indexed column set {:?} (indexed on {:?})",
+                columns,
+                self.keys()
+            )
+        })?;
+        Ok(self.state[index].lookup(key))
     }
 
     fn keys(&self) -> Vec<Vec<usize>> {
@@ -149,6 +172,25 @@ impl State for MemoryState {
         self.state[0].values().flat_map(fix).collect()
     }
 
+    fn filled_keys(&self, tag: Tag) -> Vec<Vec<DataType>> {
+        self.by_tag
+            .get(&tag)
+            .map(|&index| self.state[index].filled_keys())
+            .unwrap_or_default()
+    }
+
+    fn keys_with_prefix(
+        &self,
+        columns: &[usize],
+        prefix: &[DataType],
+    ) -> Option<Vec<Vec<DataType>>> {
+        let index = &self.state[self.state_for(columns)?];
+        if index.partial() {
+            return None;
+        }
+        Some(index.keys_with_prefix(prefix))
+    }
+
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
         let mut rng = rand::thread_rng();
         let index = rng.gen_range(0, self.state.len());
@@ -280,4 +322,111 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_drop_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        for i in 0..10 {
+            insert(&mut state, vec![i.into(), (i % 2).into()]);
+        }
+        let bytes = state.deep_size_of();
+        assert_eq!(state.rows(), 20);
+
+        // the first index holds the rows
+        assert!(!state.drop_key(&[0]));
+        assert!(!state.drop_key(&[2]));
+
+        assert!(state.drop_key(&[1]));
+        assert_eq!(state.keys(), vec![vec![0]]);
+        assert_eq!(state.rows(), 10);
+        assert!(state.deep_size_of() < bytes);
+
+        match state.lookup(&[0], &KeyType::Single(&3.into())) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => assert_eq!(rows.len(), 1),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn memory_state_filled_keys() {
+        let mut state = MemoryState::default();
+        let tag = Tag(1);
+        state.add_key(&[0], Some(vec![tag]));
+        state.mark_filled(vec![1.into()], tag);
+        state.mark_filled(vec![2.into()], tag);
+        state.mark_hole(&[1.into()], tag);
+
+        assert_eq!(state.filled_keys(tag), vec![vec![2.into()]]);
+        assert!(state.filled_keys(Tag(2)).is_empty());
+    }
+
+    #[test]
+    fn memory_state_keys_with_prefix() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0, 1], None);
+        insert(&mut state, vec![1.into(), 1.into()]);
+        insert(&mut state, vec![1.into(), 2.into()]);
+        insert(&mut state, vec![2.into(), 1.into()]);
+
+        let mut keys = state.keys_with_prefix(&[0, 1], &[1.into()]).unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![vec![1.into(), 1.into()], vec![1.into(), 2.into()]]
+        );
+        assert!(state
+            .keys_with_prefix(&[0, 1], &[3.into()])
+            .unwrap()
+            .is_empty());
+        assert_eq!(state.keys_with_prefix(&[1], &[1.into()]), None);
+
+        let tag = Tag(1);
+        state.add_key(&[1, 0], Some(vec![tag]));
+        assert_eq!(state.keys_with_prefix(&[1, 0], &[1.into()]), None);
+    }
+
+    #[test]
+    fn memory_state_lookup_dropped_key() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        insert(&mut state, vec![1.into(), 2.into()]);
+        state.drop_key(&[1]);
+        match state.try_lookup(&[1], &KeyType::Single(&2.into())) {
+            Err(e) => assert_eq!(e, "lookup on non-indexed column set [1] (indexed on [[0]])"),
+            Ok(_) => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn memory_state_size() {
+        let mut state = MemoryState::default();
+        let rows: Vec<Vec<DataType>> = vec![
+            vec![1.into(), "A".into()],
+            vec![2.into(), "a string that is long enough to spill".into()],
+            vec![3.into(), "C".into()],
+        ];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        for row in &rows {
+            insert(&mut state, row.clone());
+        }
+
+        // every index holds an entry for every row, but the rows themselves are shared between
+        // indices, so their bytes are only counted once
+        assert_eq!(state.rows(), 6);
+        let entry = std::mem::size_of::<Row>() as u64;
+        let bytes: u64 = rows.iter().map(|r| r.deep_size_of()).sum();
+        assert_eq!(state.deep_size_of(), bytes + 6 * entry);
+
+        let delete: Record = (rows[1].clone(), false).into();
+        state.process_records(&mut delete.into(), None);
+        assert_eq!(state.rows(), 4);
+        assert_eq!(
+            state.deep_size_of(),
+            bytes - rows[1].deep_size_of() + 4 * entry
+        );
+    }
 }
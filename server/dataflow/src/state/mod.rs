@@ -20,6 +20,12 @@ pub(crate) trait State: SizeOf + Send {
     /// Add an index keyed by the given columns and replayed to by the given partial tags.
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>);
 
+    /// Remove the full index keyed by the given columns.
+    ///
+    /// The first index is where the state keeps its rows, so it is never removed. Returns whether
+    /// an index was removed.
+    fn drop_key(&mut self, columns: &[usize]) -> bool;
+
     /// Returns whether this state is currently keyed on anything. If not, then it cannot store any
     /// infromation and is thus "not useful".
     fn is_useful(&self) -> bool;
@@ -34,7 +40,21 @@ pub(crate) trait State: SizeOf + Send {
 
     fn mark_filled(&mut self, key: Vec<DataType>, tag: Tag);
 
-    fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a>;
+    /// Look up `key` in the index on `columns`.
+    ///
+    /// Fails with an error naming the indices the state does have if there is no index on
+    /// `columns`, for example because it was dropped.
+    fn try_lookup<'a>(
+        &'a self,
+        columns: &[usize],
+        key: &KeyType,
+    ) -> Result<LookupResult<'a>, String>;
+
+    /// Look up `key` in the index on `columns`, which must exist.
+    fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a> {
+        self.try_lookup(columns, key)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
     fn rows(&self) -> usize;
 
@@ -43,6 +63,18 @@ pub(crate) trait State: SizeOf + Send {
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
+    /// Return a copy of every key held by the partial materialization targeted by `tag`.
+    fn filled_keys(&self, tag: Tag) -> Vec<Vec<DataType>>;
+
+    /// Return a copy of every key of the index on `columns` that starts with `prefix`, or `None`
+    /// if that index is partial, and so may not know of every such key, or if the keys can't be
+    /// listed without a full scan.
+    fn keys_with_prefix(
+        &self,
+        columns: &[usize],
+        prefix: &[DataType],
+    ) -> Option<Vec<Vec<DataType>>>;
+
     /// Evict `count` randomly selected keys, returning key colunms of the index chosen to evict
     /// from along with the keys evicted and the number of bytes evicted.
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64);
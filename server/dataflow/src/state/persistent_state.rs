@@ -83,13 +83,19 @@ impl State for PersistentState {
         self.db.as_ref().unwrap().write_opt(batch, &opts).unwrap();
     }
 
-    fn lookup(&self, columns: &[usize], key: &KeyType) -> LookupResult {
+    fn try_lookup(&self, columns: &[usize], key: &KeyType) -> Result<LookupResult, String> {
         let db = self.db.as_ref().unwrap();
         let index_id = self
             .indices
             .iter()
             .position(|index| &index.columns[..] == columns)
-            .expect("lookup on non-indexed column set");
+            .ok_or_else(|| {
+                format!(
+                    "lookup on non-indexed column set {:?} (indexed on {:?})",
+                    columns,
+                    self.keys()
+                )
+            })?;
         let cf = db.cf_handle(&self.indices[index_id].column_family).unwrap();
         let prefix = Self::serialize_prefix(&key);
         let data = if index_id == 0 && self.has_unique_index {
@@ -110,7 +116,7 @@ impl State for PersistentState {
                 .collect()
         };
 
-        LookupResult::Some(RecordResult::Owned(data))
+        Ok(LookupResult::Some(RecordResult::Owned(data)))
     }
 
     fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
@@ -159,6 +165,23 @@ impl State for PersistentState {
         self.persist_meta();
     }
 
+    // Column families are named by index position, so only the last index can be dropped without
+    // renaming the ones after it.
+    fn drop_key(&mut self, columns: &[usize]) -> bool {
+        if self.indices.len() < 2 || self.indices.last().unwrap().columns != columns {
+            return false;
+        }
+
+        let index = self.indices.pop().unwrap();
+        self.db
+            .as_mut()
+            .unwrap()
+            .drop_cf(&index.column_family)
+            .unwrap();
+        self.persist_meta();
+        true
+    }
+
     fn keys(&self) -> Vec<Vec<usize>> {
         self.indices
             .iter()
@@ -200,6 +223,38 @@ impl State for PersistentState {
         unreachable!("PersistentState can't be partial")
     }
 
+    fn filled_keys(&self, _: Tag) -> Vec<Vec<DataType>> {
+        unreachable!("PersistentState is never partial")
+    }
+
+    fn keys_with_prefix(
+        &self,
+        columns: &[usize],
+        prefix: &[DataType],
+    ) -> Option<Vec<Vec<DataType>>> {
+        // Keys are serialized along with their size, so the keys that start with `prefix` only
+        // share a prefix_iterator prefix in an index on exactly the prefix columns. Without one,
+        // listing them would mean reading every row.
+        let prefix_columns = columns.get(..prefix.len())?;
+        let index = self
+            .indices
+            .iter()
+            .find(|index| &index.columns[..] == prefix_columns)?;
+        let db = self.db.as_ref().unwrap();
+        let cf = db.cf_handle(&index.column_family).unwrap();
+        let key = Self::serialize_prefix(&KeyType::from(prefix));
+        Some(
+            db.prefix_iterator_cf(cf, &key)
+                .unwrap()
+                .map(|(_key, value)| {
+                    let row: Vec<DataType> = bincode::deserialize(&*value).unwrap();
+                    columns.iter().map(|&c| row[c].clone()).collect::<Vec<_>>()
+                })
+                .unique()
+                .collect(),
+        )
+    }
+
     fn evict_random_keys(&mut self, _: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
         unreachable!("can't evict keys from PersistentState")
     }
@@ -865,6 +920,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_keys_with_prefix() {
+        let mut state = setup_persistent("persistent_state_keys_with_prefix");
+        state.add_key(&[0, 1], None);
+        state.process_records(
+            &mut (0..6i32)
+                .map(|i| vec![(i % 2).into(), (i / 2).into(), i.into()])
+                .collect::<Vec<Vec<DataType>>>()
+                .into(),
+            None,
+        );
+
+        // the keys can only be listed from an index on the prefix columns
+        assert_eq!(state.keys_with_prefix(&[0, 1], &[1.into()]), None);
+
+        state.add_key(&[0], None);
+        let mut keys = state.keys_with_prefix(&[0, 1], &[1.into()]).unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                vec![1.into(), 0.into()],
+                vec![1.into(), 1.into()],
+                vec![1.into(), 2.into()],
+            ] as Vec<Vec<DataType>>
+        );
+    }
+
     #[test]
     fn persistent_state_recover_unique_key() {
         let (_dir, name) = get_tmp_path();
@@ -1096,6 +1179,30 @@ mod tests {
         };
     }
 
+    #[test]
+    fn persistent_state_drop_key() {
+        let mut state = setup_persistent("persistent_state_drop_key");
+        let row: Vec<DataType> = vec![10.into(), "Cat".into(), 3.into()];
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        state.add_key(&[2], None);
+        insert(&mut state, row.clone());
+
+        // only the last secondary index can be dropped
+        assert!(!state.drop_key(&[0]));
+        assert!(!state.drop_key(&[1]));
+        assert!(state.drop_key(&[2]));
+        assert_eq!(state.keys(), vec![vec![0], vec![1]]);
+        assert!(state.try_lookup(&[2], &KeyType::Single(&row[2])).is_err());
+
+        // and its column family can be reused by a new index
+        state.add_key(&[2], None);
+        match state.lookup(&[2], &KeyType::Single(&row[2])) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(&rows[0], &row),
+            _ => unreachable!(),
+        };
+    }
+
     #[test]
     fn persistent_state_process_records() {
         let mut state = setup_persistent("persistent_state_process_records");
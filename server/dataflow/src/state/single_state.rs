@@ -245,6 +245,17 @@ impl SingleState {
         keys.iter().map(|k| self.state.evict(k)).sum()
     }
 
+    /// Returns a copy of every key in this state.
+    pub(super) fn filled_keys(&self) -> Vec<Vec<DataType>> {
+        self.state.keys()
+    }
+
+    pub(super) fn keys_with_prefix(&self, prefix: &[DataType]) -> Vec<Vec<DataType>> {
+        let mut keys = self.state.keys();
+        keys.retain(|key| key.starts_with(prefix));
+        keys
+    }
+
     pub(super) fn values<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Rows> + 'a> {
         match self.state {
             KeyedState::Single(ref map) => Box::new(map.values()),
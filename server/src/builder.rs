@@ -2,7 +2,7 @@ use crate::handle::Handle;
 use crate::Config;
 use crate::FrontierStrategy;
 use crate::ReuseConfigType;
-use dataflow::PersistenceParameters;
+use dataflow::{PacketObserver, PersistenceParameters};
 use noria::consensus::{Authority, LocalAuthority};
 use std::future::Future;
 use std::net::IpAddr;
@@ -16,6 +16,7 @@ pub struct Builder {
     memory_check_frequency: Option<time::Duration>,
     listen_addr: IpAddr,
     log: slog::Logger,
+    packet_observer: Option<Arc<dyn PacketObserver>>,
 }
 impl Default for Builder {
     fn default() -> Self {
@@ -25,6 +26,7 @@ impl Default for Builder {
             log: slog::Logger::root(slog::Discard, o!()),
             memory_limit: None,
             memory_check_frequency: None,
+            packet_observer: None,
         }
     }
 }
@@ -38,6 +40,15 @@ impl Builder {
         self.config.domain_config.concurrent_replays = n;
     }
 
+    /// Set the maximum number of partial replay requests a domain will queue while waiting for
+    /// one of its concurrent replays to finish.
+    ///
+    /// Once this many are queued, replays triggered by reads are rejected until the queue drains;
+    /// blocking reads will retry them. By default, the queue is unbounded.
+    pub fn set_max_pending_replays(&mut self, n: usize) {
+        self.config.domain_config.max_pending_replays = Some(n);
+    }
+
     /// Set the longest time a partial replay response can be delayed.
     pub fn set_partial_replay_batch_timeout(&mut self, t: time::Duration) {
         self.config.domain_config.replay_batch_timeout = t;
@@ -58,6 +69,22 @@ impl Builder {
         self.config.frontier_strategy = f;
     }
 
+    /// Compress replays that cross domain boundaries for all subsequent migrations.
+    ///
+    /// This trades CPU time for less data sent between domains, which pays off for large
+    /// backfills when domains run on different machines.
+    pub fn enable_replay_compression(&mut self) {
+        self.config.compress_replays = true;
+    }
+
+    /// Check that every domain processes the packets along each link in the order they were
+    /// sent.
+    ///
+    /// A violation panics in debug builds, and is counted in the domain's statistics otherwise.
+    pub fn check_link_order(&mut self) {
+        self.config.domain_config.check_link_order = true;
+    }
+
     /// Set sharding policy for all subsequent migrations; `None` disables
     pub fn set_sharding(&mut self, shards: Option<usize>) {
         self.config.sharding = shards;
@@ -88,6 +115,11 @@ impl Builder {
         self.log = log;
     }
 
+    /// Watch every packet processed by the domains this worker runs.
+    pub fn set_packet_observer(&mut self, observer: Arc<dyn PacketObserver>) {
+        self.packet_observer = Some(observer);
+    }
+
     /// Set the reuse policy for all subsequent migrations
     pub fn set_reuse(&mut self, reuse_type: ReuseConfigType) {
         self.config.reuse = reuse_type;
@@ -114,10 +146,12 @@ impl Builder {
             memory_limit,
             memory_check_frequency,
             ref log,
+            ref packet_observer,
         } = *self;
 
         let config = config.clone();
         let log = log.clone();
+        let packet_observer = packet_observer.clone();
 
         crate::startup::start_instance(
             authority,
@@ -126,6 +160,7 @@ impl Builder {
             memory_limit,
             memory_check_frequency,
             log,
+            packet_observer,
         )
     }
 
@@ -5,14 +5,16 @@ use crate::controller::schema;
 use crate::controller::{ControllerState, Migration, Recipe};
 use crate::controller::{Worker, WorkerIdentifier};
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use dataflow::payload::{ControlReplyPacket, PACKET_VERSION};
 use dataflow::prelude::*;
-use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use dataflow::{node, prelude::Packet, DomainBuilder, DomainConfig};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
 use nom_sql::ColumnSpecification;
 use noria::builders::*;
 use noria::channel::tcp::{SendError, TcpSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
+use noria::debug::replay::{ReplayPathInfo, ReplayPaths};
 use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
 use noria::ActivationResult;
 use petgraph::visit::Bfs;
@@ -57,6 +59,8 @@ pub(super) struct ControllerInner {
     pub(super) remap: HashMap<DomainIndex, HashMap<NodeIndex, IndexPair>>,
 
     pub(super) epoch: Epoch,
+    /// The `op_id` of the last column change sent to a domain.
+    pub(super) schema_ops: u64,
 
     pending_recovery: Option<(Vec<String>, usize)>,
 
@@ -92,11 +96,84 @@ impl DomainReplies {
         for r in self.read_n_domain_replies(d.shards()).await {
             match r {
                 ControlReplyPacket::Ack(_) => {}
+                ControlReplyPacket::Nack(e) => panic!("domain refused control packet: {}", e),
                 r => unreachable!("got unexpected non-ack control reply: {:?}", r),
             }
         }
     }
 
+    /// Like `wait_for_acks`, but tolerates domains rejecting the request.
+    ///
+    /// Returns the first rejection, if any.
+    pub(in crate::controller) async fn wait_for_acks_or_nack(
+        &mut self,
+        d: &DomainHandle,
+    ) -> Result<(), String> {
+        let mut res = Ok(());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Ack(_) => {}
+                ControlReplyPacket::Nack(e) => {
+                    if res.is_ok() {
+                        res = Err(e);
+                    }
+                }
+                r => unreachable!("got unexpected non-ack control reply: {:?}", r),
+            }
+        }
+        res
+    }
+
+    pub(in crate::controller) async fn wait_for_node_added(
+        &mut self,
+        d: &DomainHandle,
+    ) -> Vec<LocalNodeIndex> {
+        let mut added = Vec::with_capacity(d.shards());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::NodeAdded(addr) => added.push(addr),
+                r => unreachable!("got unexpected non-node-added control reply: {:?}", r),
+            }
+        }
+        added
+    }
+
+    /// Wait for every shard of `d` to answer a ping, and return the longest round trip.
+    async fn wait_for_pongs(&mut self, d: &DomainHandle) -> Duration {
+        let mut rtt = Duration::from_secs(0);
+        for _ in 0..d.shards() {
+            match self.read_n_domain_replies(1).await.swap_remove(0) {
+                ControlReplyPacket::Pong(sent) => {
+                    rtt = rtt.max(sent.elapsed().unwrap_or_default());
+                }
+                r => unreachable!("got unexpected non-pong control reply: {:?}", r),
+            }
+        }
+        rtt
+    }
+
+    async fn wait_for_replay_paths(&mut self, d: &DomainHandle) -> Vec<Vec<ReplayPathInfo>> {
+        let mut paths = Vec::with_capacity(d.shards());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::ReplayPaths(p) => paths.push(p),
+                r => unreachable!("got unexpected non-replay-paths control reply: {:?}", r),
+            }
+        }
+        paths
+    }
+
+    async fn wait_for_node_indices(&mut self, d: &DomainHandle) -> Vec<Vec<Vec<usize>>> {
+        let mut indices = Vec::with_capacity(d.shards());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::NodeIndices(i) => indices.push(i),
+                r => unreachable!("got unexpected non-node-indices control reply: {:?}", r),
+            }
+        }
+        indices
+    }
+
     async fn wait_for_statistics(
         &mut self,
         d: &DomainHandle,
@@ -222,6 +299,18 @@ impl ControllerInner {
             (Method::GET, "/flush_partial") => {
                 Ok(Ok(json::to_string(&self.flush_partial()).unwrap()))
             }
+            (Method::POST, "/set_memory_limit") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.set_memory_limit(args)).unwrap())),
+            (Method::POST, "/ping_domains") => {
+                Ok(Ok(json::to_string(&self.ping_domains()).unwrap()))
+            }
+            (Method::POST, "/quit_domains") => {
+                Ok(self.quit_domains().map(|r| json::to_string(&r).unwrap()))
+            }
+            (Method::POST, "/replay_paths") => {
+                Ok(Ok(json::to_string(&self.replay_paths()).unwrap()))
+            }
             (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
             (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
             (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
@@ -289,6 +378,48 @@ impl ControllerInner {
                     self.create_universe(args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/node_indices") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.node_indices(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/pause_domain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_domain_paused(args, true)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/resume_domain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_domain_paused(args, false)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/step_domain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.step_domain(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/reset_statistics") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.reset_statistics(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/cancel_partial_replay") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.cancel_partial_replay(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/add_index") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.add_index(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/recompute_node") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.recompute_node(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
             (Method::POST, "/remove_node") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -434,8 +565,11 @@ impl ControllerInner {
             materializations.disable_partial()
         }
         materializations.set_frontier_strategy(state.config.frontier_strategy);
+        if state.config.compress_replays {
+            materializations.enable_replay_compression();
+        }
 
-        let cc = Arc::new(ChannelCoordinator::new());
+        let cc = Arc::new(ChannelCoordinator::with_version(PACKET_VERSION));
         assert_ne!(state.config.quorum, 0);
 
         let pending_recovery = if !state.recipes.is_empty() {
@@ -466,6 +600,7 @@ impl ControllerInner {
             domain_nodes: Default::default(),
             channel_coordinator: cc,
             epoch: state.epoch,
+            schema_ops: state.schema_ops,
 
             remap: HashMap::default(),
 
@@ -775,10 +910,16 @@ impl ControllerInner {
                 .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
                 .collect();
 
+            let key = self.ingredients[r]
+                .with_reader(|r| r.key().map(Vec::from))
+                .ok()
+                .flatten();
+
             ViewBuilder {
                 node: r,
                 columns,
                 schema,
+                key,
                 shards,
             }
         })
@@ -885,6 +1026,135 @@ impl ControllerInner {
         GraphStats { domains }
     }
 
+    /// Start counting processing statistics from zero again, for node `ni` or for every node.
+    fn reset_statistics(&mut self, ni: Option<NodeIndex>) -> Result<(), String> {
+        let targets: Vec<_> = match ni {
+            Some(ni) => match self.ingredients.node_weight(ni) {
+                Some(n) if n.has_domain() && !n.is_dropped() => {
+                    vec![(n.domain(), Some(n.local_addr()))]
+                }
+                _ => return Err(format!("no live domain node {}", ni.index())),
+            },
+            None => self.domains.keys().map(|&di| (di, None)).collect(),
+        };
+
+        for (domain, node) in targets {
+            let d = self.domains.get_mut(&domain).unwrap();
+            d.send_to_healthy(Box::new(Packet::ResetStatistics { node }), &self.workers)
+                .expect("failed to send statistics reset to domain");
+            futures_executor::block_on(self.replies.wait_for_acks_or_nack(&d))?;
+        }
+        Ok(())
+    }
+
+    fn replay_paths(&mut self) -> ReplayPaths {
+        let workers = &self.workers;
+        let replies = &mut self.replies;
+        self.domains
+            .iter_mut()
+            .flat_map(|(&di, s)| {
+                s.send_to_healthy(Box::new(Packet::GetReplayPaths), workers)
+                    .unwrap();
+                futures_executor::block_on(replies.wait_for_replay_paths(&s))
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, p)| ((di, i), p))
+            })
+            .collect()
+    }
+
+    /// Get the column sets `ni` is indexed on.
+    ///
+    /// All shards of a node share the same indices, so only the first shard's answer is used.
+    fn node_indices(&mut self, ni: NodeIndex) -> Result<Vec<Vec<usize>>, String> {
+        let node = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => n,
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let local = node.local_addr();
+        let domain = self.domains.get_mut(&node.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::GetNodeIndices { node: local }),
+                &self.workers,
+            )
+            .unwrap();
+        let mut indices = futures_executor::block_on(self.replies.wait_for_node_indices(&domain));
+        Ok(indices.swap_remove(0))
+    }
+
+    /// Measure how long it takes each domain to respond to a ping from all of its shards.
+    fn ping_domains(&mut self) -> Vec<(DomainIndex, Duration)> {
+        let workers = &self.workers;
+        let replies = &mut self.replies;
+        self.domains
+            .iter_mut()
+            .map(|(&di, s)| {
+                let ping = Packet::Ping {
+                    sent: time::SystemTime::now(),
+                };
+                s.send_to_healthy(Box::new(ping), workers).unwrap();
+                (di, futures_executor::block_on(replies.wait_for_pongs(&s)))
+            })
+            .collect()
+    }
+
+    /// Shut down every domain, upstream domains before the domains they feed.
+    ///
+    /// Each domain shard finishes the data it has received, and waits for every domain shard
+    /// that sends it data to do the same, before it quits. That way no domain is left sending
+    /// into a domain that has already gone away.
+    fn quit_domains(&mut self) -> Result<(), String> {
+        // which domain shards send data to each domain shard
+        let mut upstreams: HashMap<(DomainIndex, usize), HashSet<(DomainIndex, usize)>> =
+            HashMap::new();
+        for ni in self.ingredients.node_indices() {
+            let n = &self.ingredients[ni];
+            if !n.is_ingress() || n.is_dropped() {
+                continue;
+            }
+            let d = n.domain();
+            let d_shards = self.domains[&d].shards();
+            for p in self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            {
+                let p = &self.ingredients[p];
+                if !p.has_domain() {
+                    continue;
+                }
+                let u = p.domain();
+                let u_shards = self.domains[&u].shards();
+                for s in 0..d_shards {
+                    let senders = upstreams.entry((d, s)).or_default();
+                    if p.is_sharder() || u_shards != d_shards {
+                        senders.extend((0..u_shards).map(|t| (u, t)));
+                    } else {
+                        senders.insert((u, s));
+                    }
+                }
+            }
+        }
+
+        let workers = &self.workers;
+        for (&di, d) in self.domains.iter_mut() {
+            for s in 0..d.shards() {
+                let n = upstreams.get(&(di, s)).map(HashSet::len).unwrap_or(0);
+                d.send_to_healthy_shard(s, Box::new(Packet::QuitDrain { upstreams: n }), workers)
+                    .map_err(|e| format!("failed to tell domain {} to quit: {}", di.index(), e))?;
+            }
+        }
+
+        let mut res = Ok(());
+        for d in self.domains.values() {
+            let r = futures_executor::block_on(self.replies.wait_for_acks_or_nack(&d));
+            if res.is_ok() {
+                res = r;
+            }
+        }
+        res
+    }
+
     fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
         self.workers
             .iter()
@@ -948,6 +1218,113 @@ impl ControllerInner {
         total_evicted
     }
 
+    fn set_memory_limit(&mut self, bytes: usize) {
+        let workers = &self.workers;
+        let replies = &mut self.replies;
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::SetMemoryLimit { bytes }), workers)
+                .expect("failed to send memory limit to domain");
+            futures_executor::block_on(replies.wait_for_acks(&d));
+        }
+    }
+
+    /// Pause or resume processing of data packets in the domain that runs `ni`.
+    fn set_domain_paused(&mut self, ni: NodeIndex, paused: bool) -> Result<(), String> {
+        let domain = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => n.domain(),
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let d = self.domains.get_mut(&domain).unwrap();
+        let m = if paused {
+            Packet::Pause
+        } else {
+            Packet::Resume
+        };
+        d.send_to_healthy(Box::new(m), &self.workers)
+            .expect("failed to send pause or resume to domain");
+        futures_executor::block_on(self.replies.wait_for_acks(&d));
+        Ok(())
+    }
+
+    /// Process a single packet held by the paused domain that runs `ni`.
+    fn step_domain(&mut self, ni: NodeIndex) -> Result<(), String> {
+        let domain = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => n.domain(),
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let d = self.domains.get_mut(&domain).unwrap();
+        d.send_to_healthy(Box::new(Packet::Step), &self.workers)
+            .expect("failed to send step to domain");
+        futures_executor::block_on(self.replies.wait_for_acks_or_nack(&d))
+    }
+
+    /// Have the domain that runs `ni` withdraw its request to replay `key` along the path `tag`.
+    fn cancel_partial_replay(
+        &mut self,
+        (ni, tag, key): (NodeIndex, u32, Vec<DataType>),
+    ) -> Result<(), String> {
+        let domain = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => n.domain(),
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let d = self.domains.get_mut(&domain).unwrap();
+        d.send_to_healthy(
+            Box::new(Packet::CancelPartialReplay { tag: Tag(tag), key }),
+            &self.workers,
+        )
+        .map_err(|e| format!("failed to send cancel to domain: {}", e))
+    }
+
+    /// Index the fully materialized state of `ni` on the columns in `index`.
+    ///
+    /// The index is built from the rows the node already holds, without a replay.
+    fn add_index(&mut self, (ni, index): (NodeIndex, Vec<usize>)) -> Result<(), String> {
+        let (domain, node) = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => (n.domain(), n.local_addr()),
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let d = self.domains.get_mut(&domain).unwrap();
+        d.send_to_healthy(
+            Box::new(Packet::AddIndex {
+                node,
+                index: index.clone(),
+            }),
+            &self.workers,
+        )
+        .map_err(|e| format!("failed to send index to domain: {}", e))?;
+        futures_executor::block_on(self.replies.wait_for_acks_or_nack(&d))?;
+
+        self.materializations.add_index(ni, index);
+        Ok(())
+    }
+
+    /// Throw away the state of the materialized node `ni`, so that it is replayed anew.
+    ///
+    /// Partial state is replayed as it is read, while full state is rebuilt before this returns.
+    fn recompute_node(&mut self, (ni, block): (NodeIndex, bool)) -> Result<(), String> {
+        let (domain, node) = match self.ingredients.node_weight(ni) {
+            Some(n) if n.has_domain() && !n.is_dropped() => (n.domain(), n.local_addr()),
+            _ => return Err(format!("no live domain node {}", ni.index())),
+        };
+        let d = self.domains.get_mut(&domain).unwrap();
+        d.send_to_healthy(Box::new(Packet::Recompute { node, block }), &self.workers)
+            .expect("failed to send recompute to domain");
+        futures_executor::block_on(self.replies.wait_for_acks_or_nack(&d))?;
+
+        if let MaterializationStatus::Full =
+            self.materializations.get_status(ni, &self.ingredients[ni])
+        {
+            self.materializations.rebuild(
+                ni,
+                &self.ingredients,
+                &mut self.domains,
+                &self.workers,
+                &mut self.replies,
+            )?;
+        }
+        Ok(())
+    }
+
     pub(super) fn create_universe(
         &mut self,
         context: HashMap<String, DataType>,
@@ -1084,6 +1461,7 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes.push(add_txt.clone());
+                            state.schema_ops = self.schema_ops;
                             Ok(state)
                         }
                     })
@@ -1120,6 +1498,7 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes = vec![r_txt.clone()];
+                            state.schema_ops = self.schema_ops;
                             Ok(state)
                         }
                     })
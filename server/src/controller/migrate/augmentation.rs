@@ -16,11 +16,14 @@ use petgraph::graph::NodeIndex;
 
 use slog::Logger;
 
+/// Send the new nodes of each existing domain to that domain.
+///
+/// Fails if a domain installs a node at a different local index than the one it was assigned.
 pub(super) fn inform(
     log: &Logger,
     controller: &mut controller::ControllerInner,
     nodes: HashMap<DomainIndex, Vec<(NodeIndex, bool)>>,
-) {
+) -> Result<(), String> {
     let source = controller.source;
     for (domain, nodes) in nodes {
         let log = log.new(o!("domain" => domain.index()));
@@ -54,6 +57,7 @@ pub(super) fn inform(
                 .collect();
 
             trace!(log, "request addition of node"; "node" => ni.index());
+            let local = node.local_addr();
             ctx.send_to_healthy(
                 Box::new(Packet::AddNode {
                     node,
@@ -62,6 +66,20 @@ pub(super) fn inform(
                 &controller.workers,
             )
             .unwrap();
+
+            // make sure every shard put the node where we expect it to be before we wire it up
+            for addr in futures_executor::block_on(controller.replies.wait_for_node_added(ctx)) {
+                if addr != local {
+                    return Err(format!(
+                        "domain {} installed node {} at local index {}, expected {}",
+                        domain.index(),
+                        ni.index(),
+                        addr.id(),
+                        local.id()
+                    ));
+                }
+            }
         }
     }
+    Ok(())
 }
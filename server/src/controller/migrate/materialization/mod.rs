@@ -55,6 +55,9 @@ pub(in crate::controller) struct Materializations {
     partial: HashSet<NodeIndex>,
     partial_enabled: bool,
     frontier_strategy: FrontierStrategy,
+    compress_replays: bool,
+    /// priorities of the replay paths that fill each node's state
+    priorities: HashMap<NodeIndex, u8>,
 
     tag_generator: AtomicUsize,
 }
@@ -71,6 +74,8 @@ impl Materializations {
             partial: HashSet::default(),
             partial_enabled: true,
             frontier_strategy: FrontierStrategy::None,
+            compress_replays: false,
+            priorities: HashMap::default(),
 
             tag_generator: AtomicUsize::default(),
         }
@@ -90,6 +95,21 @@ impl Materializations {
     pub(in crate::controller) fn set_frontier_strategy(&mut self, f: FrontierStrategy) {
         self.frontier_strategy = f;
     }
+
+    /// Compress replays that cross domain boundaries for all new materializations.
+    pub(in crate::controller) fn enable_replay_compression(&mut self) {
+        self.compress_replays = true;
+    }
+
+    /// Note that the materialized node `ni` has been indexed on `index` while running.
+    pub(in crate::controller) fn add_index(&mut self, ni: NodeIndex, index: Vec<usize>) {
+        self.have.entry(ni).or_default().insert(index);
+    }
+
+    /// Give the replay paths that are later set up to fill `ni`'s state the given priority.
+    pub(in crate::controller) fn set_priority(&mut self, ni: NodeIndex, priority: u8) {
+        self.priorities.insert(ni, priority);
+    }
 }
 
 impl Materializations {
@@ -477,6 +497,8 @@ impl Materializations {
     ///
     /// This includes setting up replay paths, adding new indices to existing materializations, and
     /// populating new materializations.
+    ///
+    /// Returns an error if a domain rejects a replay path it is told about.
     #[allow(clippy::cognitive_complexity)]
     pub(super) fn commit(
         &mut self,
@@ -485,7 +507,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
+    ) -> Result<(), String> {
         self.extend(graph, new);
 
         // check that we don't have fully materialized nodes downstream of partially materialized
@@ -806,8 +828,9 @@ impl Materializations {
                       "cols" => ?index_on);
                 let log = self.log.new(o!("node" => node.index()));
                 let log = mem::replace(&mut self.log, log);
-                self.setup(node, &mut index_on, graph, domains, workers, replies);
+                let r = self.setup(node, &mut index_on, graph, domains, workers, replies);
                 mem::replace(&mut self.log, log);
+                r?;
                 index_on.clear();
             } else {
                 use dataflow::payload::InitialState;
@@ -838,7 +861,7 @@ impl Materializations {
                 .unwrap_or_else(HashSet::new);
 
             let start = ::std::time::Instant::now();
-            self.ready_one(ni, &mut index_on, graph, domains, workers, replies);
+            self.ready_one(ni, &mut index_on, graph, domains, workers, replies)?;
             let reconstructed = index_on.is_empty();
 
             // communicate to the domain in charge of a particular node that it should start
@@ -870,6 +893,7 @@ impl Materializations {
         }
 
         self.added.clear();
+        Ok(())
     }
 
     /// Perform all operations necessary to bring any materializations for the given node up, and
@@ -882,7 +906,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
+    ) -> Result<(), String> {
         let n = &graph[ni];
         let mut has_state = !index_on.is_empty();
 
@@ -900,7 +924,7 @@ impl Materializations {
             // a new base must be empty, so we can materialize it immediately
             info!(self.log, "no need to replay empty new base"; "node" => ni.index());
             assert!(!self.partial.contains(&ni));
-            return;
+            return Ok(());
         }
 
         // if this node doesn't need to be materialized, then we're done.
@@ -914,21 +938,66 @@ impl Materializations {
 
         if !has_state {
             debug!(self.log, "no need to replay non-materialized view"; "node" => ni.index());
-            return;
+            return Ok(());
         }
 
         // we have a parent that has data, so we need to replay and reconstruct
         info!(self.log, "beginning reconstruction of {:?}", n);
         let log = self.log.new(o!("node" => ni.index()));
         let log = mem::replace(&mut self.log, log);
-        self.setup(ni, index_on, graph, domains, workers, replies);
+        let r = self.setup(ni, index_on, graph, domains, workers, replies);
         mem::replace(&mut self.log, log);
+        r?;
 
         // NOTE: the state has already been marked ready by the replay completing, but we want to
         // wait for the domain to finish replay, which the ready executed by the outer commit()
         // loop does.
         index_on.clear();
-        return;
+        Ok(())
+    }
+
+    /// Rebuild the full materialization of the given existing node through replay.
+    ///
+    /// The node's domain must already have set the old state aside, which keeps serving lookups
+    /// until the replays complete.
+    pub(in crate::controller) fn rebuild(
+        &mut self,
+        ni: NodeIndex,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) -> Result<(), String> {
+        assert!(!self.partial.contains(&ni));
+        info!(self.log, "rebuilding full materialization"; "node" => ni.index());
+
+        let mut index_on = self.have.get(&ni).cloned().unwrap_or_default();
+        reader_index(ni, &mut index_on, graph);
+        let pending = {
+            let mut plan = plan::Plan::new(self, graph, ni, domains, workers);
+            for index in index_on.drain() {
+                plan.add(index, replies)?;
+            }
+            plan.replays()
+        };
+        self.replay(ni, pending, graph, domains, workers, replies);
+
+        // expose the rebuilt state of readers
+        let target = graph[ni].domain();
+        domains
+            .get_mut(&target)
+            .unwrap()
+            .send_to_healthy(
+                Box::new(Packet::Ready {
+                    node: graph[ni].local_addr(),
+                    purge: graph[ni].purge,
+                    index: HashSet::new(),
+                }),
+                workers,
+            )
+            .unwrap();
+        futures_executor::block_on(replies.wait_for_acks(&domains[&target]));
+        Ok(())
     }
 
     /// Reconstruct the materialized state required by the given (new) node through replay.
@@ -940,29 +1009,31 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
-        if index_on.is_empty() {
-            // we must be reconstructing a Reader.
-            // figure out what key that Reader is using
-            graph[ni]
-                .with_reader(|r| {
-                    assert!(r.is_materialized());
-                    if let Some(rh) = r.key() {
-                        index_on.insert(Vec::from(rh));
-                    }
-                })
-                .unwrap();
-        }
+    ) -> Result<(), String> {
+        reader_index(ni, index_on, graph);
 
         // construct and disseminate a plan for each index
         let pending = {
             let mut plan = plan::Plan::new(self, graph, ni, domains, workers);
             for index in index_on.drain() {
-                plan.add(index, replies);
+                plan.add(index, replies)?;
             }
             plan.finalize()
         };
+        self.replay(ni, pending, graph, domains, workers, replies);
+        Ok(())
+    }
 
+    /// Start the given backfill replays, and wait for them to reach `ni`.
+    fn replay(
+        &mut self,
+        ni: NodeIndex,
+        pending: Vec<plan::PendingReplay>,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) {
         if !pending.is_empty() {
             trace!(self.log, "all domains ready for replay");
 
@@ -979,6 +1050,7 @@ impl Materializations {
                         Box::new(Packet::StartReplay {
                             tag: pending.tag,
                             from: pending.source,
+                            progress: None,
                         }),
                         workers,
                     )
@@ -996,3 +1068,17 @@ impl Materializations {
         }
     }
 }
+
+/// If `index_on` is empty, we must be reconstructing a Reader, so index it on the Reader's key.
+fn reader_index(ni: NodeIndex, index_on: &mut HashSet<Vec<usize>>, graph: &Graph) {
+    if index_on.is_empty() {
+        graph[ni]
+            .with_reader(|r| {
+                assert!(r.is_materialized());
+                if let Some(rh) = r.key() {
+                    index_on.insert(Vec::from(rh));
+                }
+            })
+            .unwrap();
+    }
+}
@@ -105,14 +105,20 @@ impl<'a> Plan<'a> {
     /// Finds the appropriate replay paths for the given index, and inform all domains on those
     /// paths about them. It also notes if any data backfills will need to be run, which is
     /// eventually reported back by `finalize`.
+    ///
+    /// Returns an error if a domain rejects one of the replay paths.
     #[allow(clippy::cognitive_complexity)]
-    pub(super) fn add(&mut self, index_on: Vec<usize>, replies: &mut DomainReplies) {
+    pub(super) fn add(
+        &mut self,
+        index_on: Vec<usize>,
+        replies: &mut DomainReplies,
+    ) -> Result<(), String> {
         if !self.partial && !self.paths.is_empty() {
             // non-partial views should not have one replay path per index. that would cause us to
             // replay several times, even though one full replay should always be sufficient.
             // we do need to keep track of the fact that there should be an index here though.
             self.tags.entry(index_on).or_default();
-            return;
+            return Ok(());
         }
 
         // inform domains about replay paths
@@ -196,6 +202,8 @@ impl<'a> Plan<'a> {
                     path: locals,
                     notify_done: false,
                     trigger: TriggerEndpoint::None,
+                    priority: self.m.priorities.get(&self.node).cloned(),
+                    compress: self.m.compress_replays,
                 });
 
                 // the first domain also gets to know source node
@@ -358,7 +366,14 @@ impl<'a> Plan<'a> {
                 trace!(self.m.log, "telling domain about replay path"; "domain" => domain.index());
                 let ctx = self.domains.get_mut(&domain).unwrap();
                 ctx.send_to_healthy(setup, self.workers).unwrap();
-                futures_executor::block_on(replies.wait_for_acks(&ctx));
+                if let Err(e) = futures_executor::block_on(replies.wait_for_acks_or_nack(&ctx)) {
+                    return Err(format!(
+                        "domain {} rejected replay path {}: {}",
+                        domain.index(),
+                        tag.id(),
+                        e
+                    ));
+                }
             }
 
             if !self.partial {
@@ -370,6 +385,7 @@ impl<'a> Plan<'a> {
         }
 
         self.tags.entry(index_on).or_default().extend(tags);
+        Ok(())
     }
 
     /// Instructs the target node to set up appropriate state for any new indices that have been
@@ -435,15 +451,25 @@ impl<'a> Plan<'a> {
             )
             .unwrap();
 
+        self.replays()
+    }
+
+    /// Returns the backfill replays that fill the target's state, without preparing new state
+    /// for it.
+    ///
+    /// This is what rebuilds an existing full materialization once its domain has set the old
+    /// state aside.
+    pub(super) fn replays(mut self) -> Vec<PendingReplay> {
         if !self.partial {
-            // we know that this must be a *new* fully materialized node:
+            // we know that this must be a new or rebuilt fully materialized node:
             //
+            //  - replays() is only called by finalize() and rebuild()
             //  - finalize() is only called by setup()
             //  - setup() is only called for existing nodes if they are partial
             //  - this branch has !self.partial
             //
-            // if we're constructing a new view, there is no reason to replay any given path more
-            // than once. we do need to be careful here though: the fact that the source and
+            // if we're constructing a view from scratch, there is no reason to replay any given
+            // path more than once. we do need to be careful here though: the fact that the source and
             // destination of a path are the same does *not* mean that the path is the same (b/c of
             // unions), and we do not want to eliminate different paths!
             let mut distinct_paths = HashSet::new();
@@ -37,8 +37,10 @@ mod sharding;
 
 #[derive(Clone)]
 pub(super) enum ColumnChange {
-    Add(String, DataType),
-    Drop(usize),
+    Add(Vec<(String, DataType)>),
+    Drop(Vec<usize>),
+    Rename(usize, String),
+    SetDefault(usize, DataType),
 }
 
 /// A `Migration` encapsulates a number of changes to the Soup data flow graph.
@@ -201,8 +203,14 @@ impl<'a> Migration<'a> {
             assert_eq!(col_i1, col_i2);
         }
 
-        // also eventually propagate to domain clone
-        self.columns.push((node, ColumnChange::Add(field, default)));
+        // also eventually propagate to domain clone.
+        // consecutive additions to the same base are sent to the domain in one go.
+        match self.columns.last_mut() {
+            Some((ni, ColumnChange::Add(fields))) if *ni == node => fields.push((field, default)),
+            _ => self
+                .columns
+                .push((node, ColumnChange::Add(vec![(field, default)]))),
+        }
 
         col_i1
     }
@@ -222,7 +230,68 @@ impl<'a> Migration<'a> {
         base.get_base_mut().unwrap().drop_column(column);
 
         // also eventually propagate to domain clone
-        self.columns.push((node, ColumnChange::Drop(column)));
+        match self.columns.last_mut() {
+            Some((ni, ColumnChange::Drop(columns))) if *ni == node => columns.push(column),
+            _ => self.columns.push((node, ColumnChange::Drop(vec![column]))),
+        }
+    }
+
+    /// Rename a column of a base node.
+    ///
+    /// Only the column's name changes; the values stored in it are unaffected.
+    // crate viz for tests
+    pub fn rename_column<S: ToString>(&mut self, node: NodeIndex, column: usize, field: S) {
+        // not allowed to rename columns of new nodes
+        assert!(!self.added.contains(&node));
+
+        let field = field.to_string();
+        let base = &mut self.mainline.ingredients[node];
+        assert!(base.is_base());
+        assert!(
+            base.rename_column(column, &field),
+            "base has no column {} to rename",
+            column
+        );
+
+        // also eventually propagate to domain clone
+        self.columns
+            .push((node, ColumnChange::Rename(column, field)));
+    }
+
+    /// Change the default value of a column of a base node.
+    ///
+    /// Writes that arrive without a value for the column, such as those from `Table` handles
+    /// obtained before the column was added, get the new default. Rows already in the base keep
+    /// the values they have.
+    ///
+    /// Returns an error, and changes nothing, if `node` is not a base that existed before this
+    /// migration, or if the base has no default for the column.
+    pub fn set_column_default(
+        &mut self,
+        node: NodeIndex,
+        column: usize,
+        default: DataType,
+    ) -> Result<(), String> {
+        // not allowed to change columns of new nodes
+        if self.added.contains(&node) {
+            return Err(format!(
+                "node {} was added in this migration, and has no defaults to change yet",
+                node.index()
+            ));
+        }
+
+        let changed = match self.mainline.ingredients[node].get_base_mut() {
+            Some(base) => base.set_default(column, default.clone()),
+            None => return Err(format!("node {} is not a base", node.index())),
+        };
+        if !changed {
+            return Err(format!("base has no default for column {}", column));
+        }
+
+        // also eventually propagate to domain clone
+        self.columns
+            .push((node, ColumnChange::SetDefault(column, default)));
+        Ok(())
     }
 
     #[cfg(test)]
@@ -277,6 +346,16 @@ impl<'a> Migration<'a> {
             .unwrap();
     }
 
+    /// Give the replays that fill in missing state for `n` the given priority.
+    ///
+    /// A domain that has to hold back requests for missing state sends the requests for higher
+    /// priority replays first. Replays have priority 0 unless given another one. If `n` is
+    /// maintained, the priority applies to the replays into its reader.
+    pub fn set_replay_priority(&mut self, n: NodeIndex, priority: u8) {
+        let n = self.readers.get(&n).cloned().unwrap_or(n);
+        self.mainline.materializations.set_priority(n, priority);
+    }
+
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
     ///
     /// This will spin up an execution thread for each new thread domain, and hook those new
@@ -514,7 +593,11 @@ impl<'a> Migration<'a> {
 
         // Add any new nodes to existing domains (they'll also ignore all updates for now)
         debug!(log, "mutating existing domains");
-        augmentation::inform(&log, &mut mainline, uninformed_domain_nodes);
+        if let Err(e) = augmentation::inform(&log, &mut mainline, uninformed_domain_nodes) {
+            // the new nodes can't be wired up if we don't know where they are
+            crit!(log, "failed to add nodes to existing domains"; "err" => e);
+            return;
+        }
 
         // Tell all base nodes and base ingress children about newly added columns
         for (ni, change) in self.columns {
@@ -533,30 +616,59 @@ impl<'a> Migration<'a> {
                     })
                     .collect()
             } else {
-                // ingress nodes don't need to know about deleted columns, because those are only
-                // relevant when new writes enter the graph.
+                // ingress nodes don't need to know about deleted or renamed columns, or changed
+                // defaults, because those are only relevant when new writes enter the graph.
                 Vec::new()
             };
             inform.push(ni);
 
+            mainline.schema_ops += 1;
+            let op_id = mainline.schema_ops;
             for ni in inform {
                 let n = &mainline.ingredients[ni];
                 let m = match change.clone() {
-                    ColumnChange::Add(field, default) => Box::new(Packet::AddBaseColumn {
+                    ColumnChange::Add(fields) => Box::new(Packet::AddBaseColumns {
                         node: n.local_addr(),
-                        field,
-                        default,
+                        fields,
+                        op_id,
                     }),
-                    ColumnChange::Drop(column) => Box::new(Packet::DropBaseColumn {
+                    ColumnChange::Drop(columns) => Box::new(Packet::DropBaseColumns {
+                        node: n.local_addr(),
+                        columns,
+                        op_id,
+                    }),
+                    ColumnChange::Rename(column, new_name) => Box::new(Packet::RenameBaseColumn {
                         node: n.local_addr(),
                         column,
+                        new_name,
                     }),
+                    ColumnChange::SetDefault(column, default) => {
+                        Box::new(Packet::SetColumnDefault {
+                            node: n.local_addr(),
+                            column,
+                            default,
+                        })
+                    }
                 };
 
                 let domain = mainline.domains.get_mut(&n.domain()).unwrap();
 
                 domain.send_to_healthy(m, &mainline.workers).unwrap();
-                futures_executor::block_on(mainline.replies.wait_for_acks(&domain));
+                if let ColumnChange::Rename(..) = change {
+                    if let Err(e) =
+                        futures_executor::block_on(mainline.replies.wait_for_acks_or_nack(&domain))
+                    {
+                        error!(log, "failed to rename base column"; "node" => ni.index(), "err" => e);
+                    }
+                } else if let ColumnChange::SetDefault(..) = change {
+                    if let Err(e) =
+                        futures_executor::block_on(mainline.replies.wait_for_acks_or_nack(&domain))
+                    {
+                        error!(log, "failed to set base column default"; "node" => ni.index(), "err" => e);
+                    }
+                } else {
+                    futures_executor::block_on(mainline.replies.wait_for_acks(&domain));
+                }
             }
         }
 
@@ -573,13 +685,15 @@ impl<'a> Migration<'a> {
 
         // And now, the last piece of the puzzle -- set up materializations
         info!(log, "initializing new materializations");
-        mainline.materializations.commit(
+        if let Err(e) = mainline.materializations.commit(
             &mut mainline.ingredients,
             &new,
             &mut mainline.domains,
             &mainline.workers,
             &mut mainline.replies,
-        );
+        ) {
+            crit!(log, "failed to set up materializations"; "err" => e);
+        }
 
         warn!(log, "migration completed"; "ms" => start.elapsed().as_millis());
     }
@@ -41,6 +41,9 @@ pub(crate) struct ControllerState {
 
     recipe_version: usize,
     recipes: Vec<String>,
+    /// The `op_id` of the last column change sent to a domain.
+    #[serde(default)]
+    schema_ops: u64,
 }
 
 struct Worker {
@@ -262,6 +265,7 @@ fn instance_campaign<A: Authority + 'static>(
                         epoch,
                         recipe_version: 0,
                         recipes: vec![],
+                        schema_ops: 0,
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
@@ -10,7 +10,7 @@ use dataflow::ops::project::Project;
 use dataflow::ops::union::Union;
 use dataflow::{DurabilityMode, PersistenceParameters};
 use noria::consensus::LocalAuthority;
-use noria::DataType;
+use noria::{DataType, DataTypeKind};
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -457,1479 +457,1572 @@ async fn it_works_w_partial_mat() {
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_w_partial_mat_below_empty() {
-    // set up graph with all nodes added in a single migration. The base tables are therefore empty
-    // for now.
-    let mut g = start_simple("it_works_w_partial_mat_below_empty").await;
-    let _ = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::default());
-            let b = mig.add_base("b", &["a", "b"], Base::default());
-            let mut emits = HashMap::new();
-            emits.insert(a, vec![0, 1]);
-            emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
-            let c = mig.add_ingredient("c", &["a", "b"], u);
-            mig.maintain_anonymous(c, &[0]);
-            (a, b, c)
-        })
+async fn it_works_w_partial_mat_multi_key() {
+    // set up graph
+    let mut g = start_simple_unsharded("it_works_w_partial_mat_multi_key").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
         .await;
 
     let mut muta = g.table("a").await.unwrap();
-    let id: DataType = 1.into();
+    for i in 1..=3i32 {
+        muta.insert(vec![i.into(), (i * 10).into()]).await.unwrap();
+    }
 
-    // send a few values on a
-    muta.insert(vec![id.clone(), 1.into()]).await.unwrap();
-    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
-    muta.insert(vec![id.clone(), 3.into()]).await.unwrap();
+    // give it some time to propagate
+    sleep().await;
+
+    let _ = g
+        .migrate(move |mig| {
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
 
     // give it some time to propagate
     sleep().await;
 
-    let mut cq = g.view("c").await.unwrap();
+    let mut bq = g.view("b").await.unwrap();
 
-    // despite the empty base tables, we'll make the reader partial and therefore we should have no
-    // key until we read
-    assert_eq!(cq.len().await.unwrap(), 0);
+    // because the reader is partial, we should have no key until we read
+    assert_eq!(bq.len().await.unwrap(), 0);
 
-    // now do some reads
-    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    // a single lookup for several keys should fill all of them
+    let keys = (1..=3i32).map(|i| vec![i.into()]).collect();
+    let res = bq.multi_lookup(keys, true).await.unwrap();
     assert_eq!(res.len(), 3);
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 1.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 3.into()]));
+    for (i, rs) in (1..=3i32).zip(res) {
+        assert_eq!(rs, vec![vec![i.into(), (i * 10).into()]]);
+    }
 
-    // should have one key in the reader now
-    assert_eq!(cq.len().await.unwrap(), 1);
+    // all three keys should now be in the reader
+    assert_eq!(bq.len().await.unwrap(), 3);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_deletion() {
-    // set up graph
-    let mut g = start_simple("it_works_deletion").await;
-    let _ = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["x", "y"], Base::new(vec![]).with_key(vec![1]));
-            let b = mig.add_base("b", &["_", "x", "y"], Base::new(vec![]).with_key(vec![2]));
-
-            let mut emits = HashMap::new();
-            emits.insert(a, vec![0, 1]);
-            emits.insert(b, vec![1, 2]);
-            let u = Union::new(emits);
-            let c = mig.add_ingredient("c", &["x", "y"], u);
-            mig.maintain_anonymous(c, &[0]);
-            (a, b, c)
-        })
+async fn it_works_w_insert_many() {
+    let mut g = start_simple("it_works_w_insert_many").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0])))
         .await;
+    g.migrate(move |mig| {
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
 
-    let mut cq = g.view("c").await.unwrap();
     let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
 
-    // send a value on a
-    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
-    sleep().await;
-    assert_eq!(
-        cq.lookup(&[1.into()], true).await.unwrap(),
-        vec![vec![1.into(), 2.into()]]
-    );
+    // a single malformed row should keep the whole batch from being inserted
+    let res = muta
+        .insert_many(vec![vec![1.into(), 2.into()], vec![3.into()]])
+        .await;
+    match res {
+        Err(noria::error::TableError::WrongColumnCount(2, 1)) => {}
+        r => panic!("expected column count error, got {:?}", r),
+    }
 
-    // send a value on b
-    mutb.insert(vec![0.into(), 1.into(), 4.into()])
+    let n = 10_000i32;
+    muta.insert_many((0..n).map(|i| vec![i.into(), (i * 2).into()]).collect())
         .await
         .unwrap();
     sleep().await;
 
-    let res = cq.lookup(&[1.into()], true).await.unwrap();
-    assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![1.into(), 2.into()]));
-    assert!(res.contains(&vec![1.into(), 4.into()]));
+    let mut bq = g.view("b").await.unwrap();
+    let keys: Vec<_> = (0..n).map(|i| vec![i.into()]).collect();
+    let res = bq.multi_lookup(keys, true).await.unwrap();
+    for (i, rs) in (0..n).zip(res) {
+        assert_eq!(rs, vec![vec![i.into(), (i * 2).into()]]);
+    }
 
-    // delete first value
-    muta.delete(vec![2.into()]).await.unwrap();
+    // and the failed batch left nothing behind
+    assert_eq!(bq.len().await.unwrap(), n as usize);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_w_upsert() {
+    let mut g = start_simple("it_works_w_upsert").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut aq = g.view("a").await.unwrap();
+
+    muta.upsert(&[0], vec![1.into(), 2.into()]).await.unwrap();
     sleep().await;
     assert_eq!(
-        cq.lookup(&[1.into()], true).await.unwrap(),
-        vec![vec![1.into(), 4.into()]]
+        aq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    // upserting the same key again should replace the row rather than add another
+    muta.upsert(&[0], vec![1.into(), 3.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        aq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 3.into()]]
     );
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_sql_recipe() {
-    let mut g = start_simple("it_works_with_sql_recipe").await;
-    let sql = "
-        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
-        QUERY CountCars: SELECT COUNT(*) FROM Car WHERE brand = ?;
-    ";
-    g.install_recipe(sql).await.unwrap();
-
-    let mut mutator = g.table("Car").await.unwrap();
-    let mut getter = g.view("CountCars").await.unwrap();
-
-    assert_eq!(mutator.table_name(), "Car");
-    assert_eq!(mutator.columns(), &["id", "brand"]);
+async fn multi_lookup_preserves_key_order() {
+    let mut g = start_simple("multi_lookup_preserves_key_order").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
 
-    let brands = vec!["Volvo", "Volvo", "Volkswagen"];
-    for (i, &brand) in brands.iter().enumerate() {
-        mutator.insert(vec![i.into(), brand.into()]).await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    for i in (0..20i32).filter(|i| i % 3 != 0) {
+        muta.insert(vec![i.into(), (i * 10).into()]).await.unwrap();
     }
-
-    // Let writes propagate:
     sleep().await;
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][0], 2.into());
+    // keys spread over all shards, with every third one missing
+    let mut aq = g.view("a").await.unwrap();
+    let keys: Vec<i32> = (0..20).rev().collect();
+    let res = aq
+        .multi_lookup(keys.iter().map(|&i| vec![i.into()]).collect(), true)
+        .await
+        .unwrap();
+    assert_eq!(res.len(), keys.len());
+    for (i, rs) in keys.into_iter().zip(res) {
+        if i % 3 == 0 {
+            assert!(rs.is_empty(), "key {} should have missed", i);
+        } else {
+            assert_eq!(rs, vec![vec![i.into(), (i * 10).into()]]);
+        }
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_vote() {
-    let mut g = start_simple("it_works_with_vote").await;
-    let sql = "
-        # base tables
-        CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
-        CREATE TABLE Vote (article_id int, user int);
+async fn it_works_w_range_lookup() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_persistence(get_persistence_params("it_works_w_range_lookup"));
+    let mut g = b.start_local().await.unwrap().0;
 
-        # read queries
-        QUERY ArticleWithVoteCount: SELECT Article.id, title, VoteCount.votes AS votes \
-                    FROM Article \
-                    LEFT JOIN (SELECT Vote.article_id, COUNT(user) AS votes \
-                               FROM Vote GROUP BY Vote.article_id) AS VoteCount \
-                    ON (Article.id = VoteCount.article_id) WHERE Article.id = ?;
-    ";
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
 
-    g.install_recipe(sql).await.unwrap();
-    let mut article = g.table("Article").await.unwrap();
-    let mut vote = g.table("Vote").await.unwrap();
-    let mut awvc = g.view("ArticleWithVoteCount").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    // insert out of order, so that the results have to be sorted
+    for &i in &[5, 9, 0, 3, 7, 1, 6, 4, 8, 2] {
+        muta.insert(vec![i.into(), (i * 10).into()]).await.unwrap();
+    }
+    sleep().await;
 
-    article
-        .insert(vec![0i64.into(), "Article".into()])
-        .await
-        .unwrap();
-    article
-        .insert(vec![1i64.into(), "Article".into()])
+    let mut aq = g.view("a").await.unwrap();
+    let res = aq
+        .lookup_range(&[3.into()], &[7.into()], true)
         .await
         .unwrap();
-    vote.insert(vec![0i64.into(), 0.into()]).await.unwrap();
+    let res: Vec<Vec<DataType>> = res.into_iter().map(Into::into).collect();
+    assert_eq!(
+        res,
+        (3..7)
+            .map(|i| vec![i.into(), (i * 10).into()])
+            .collect::<Vec<Vec<DataType>>>()
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_w_prefix_lookup() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_persistence(get_persistence_params("it_works_w_prefix_lookup"));
+    let mut g = b.start_local().await.unwrap().0;
+
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b", "c"], Base::default());
+        mig.maintain_anonymous(a, &[0, 1]);
+    })
+    .await;
 
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..6i32 {
+        muta.insert(vec![(i % 2).into(), i.into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
     sleep().await;
 
-    let rs = awvc.lookup(&[0i64.into()], true).await.unwrap();
-    assert_eq!(rs.len(), 1);
-    assert_eq!(rs[0], vec![0i64.into(), "Article".into(), 1.into()]);
+    let mut aq = g.view("a").await.unwrap();
+    let mut res: Vec<Vec<DataType>> = aq
+        .lookup(&[1.into()], true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    res.sort();
+    assert_eq!(
+        res,
+        [1, 3, 5]
+            .iter()
+            .map(|&i: &i32| vec![1.into(), i.into(), (i * 10).into()])
+            .collect::<Vec<Vec<DataType>>>()
+    );
 
-    let empty = awvc.lookup(&[1i64.into()], true).await.unwrap();
-    assert_eq!(empty.len(), 1);
+    // full keys still match exactly
     assert_eq!(
-        empty[0],
-        vec![1i64.into(), "Article".into(), DataType::None]
+        aq.lookup(&[0.into(), 2.into()], true).await.unwrap(),
+        vec![vec![0.into(), 2.into(), 20.into()]]
     );
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_identical_queries() {
-    let mut g = start_simple("it_works_with_identical_queries").await;
-    let sql = "
-        CREATE TABLE Article (aid int, PRIMARY KEY(aid));
-        QUERY aq1: SELECT Article.* FROM Article WHERE Article.aid = ?;
-        QUERY aq2: SELECT Article.* FROM Article WHERE Article.aid = ?;
-    ";
+async fn it_works_w_partial_prefix_lookup() {
+    // a base kept in RocksDB can only list the keys with a prefix from an index on the prefix
+    // columns, and this one is only indexed on the reader's key
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    let mut params = get_persistence_params("it_works_w_partial_prefix_lookup");
+    params.mode = DurabilityMode::MemoryOnly;
+    b.set_persistence(params);
+    let mut g = b.start_local().await.unwrap().0;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b", "c"], Base::default());
+        mig.maintain_anonymous(a, &[0, 1]);
+    })
+    .await;
 
-    g.install_recipe(sql).await.unwrap();
-    let mut article = g.table("Article").await.unwrap();
-    let mut aq1 = g.view("aq1").await.unwrap();
-    let mut aq2 = g.view("aq2").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..6i32 {
+        muta.insert(vec![(i % 2).into(), i.into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
 
-    let aid = 1u64;
+    // the reader is partial, so the keys with the prefix are listed upstream and replayed
+    let mut aq = g.view("a").await.unwrap();
+    let rows = |rs: noria::results::Results| {
+        let mut rs: Vec<Vec<DataType>> = rs.into_iter().map(Into::into).collect();
+        rs.sort();
+        rs
+    };
+    assert_eq!(
+        rows(aq.lookup(&[1.into()], true).await.unwrap()),
+        [1, 3, 5]
+            .iter()
+            .map(|&i: &i32| vec![1.into(), i.into(), (i * 10).into()])
+            .collect::<Vec<Vec<DataType>>>()
+    );
 
-    assert!(aq1.lookup(&[aid.into()], true).await.unwrap().is_empty());
-    assert!(aq2.lookup(&[aid.into()], true).await.unwrap().is_empty());
-    article.insert(vec![aid.into()]).await.unwrap();
+    // a key with the prefix that shows up later is replayed too
+    muta.insert(vec![1.into(), 7.into(), 70.into()])
+        .await
+        .unwrap();
     sleep().await;
+    assert_eq!(rows(aq.lookup(&[1.into()], true).await.unwrap()).len(), 4);
 
-    let result = aq2.lookup(&[aid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0], vec![aid.into()]);
+    match aq.lookup(&[], true).await {
+        Err(noria::error::ViewError::EmptyKey) => {}
+        r => panic!("expected an empty key error, got {:?}", r),
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_double_query_through() {
-    let mut g = start_simple_unsharded("it_works_with_double_query_through").await;
-    let sql = "
-        # base tables
-        CREATE TABLE A (aid int, other int, PRIMARY KEY(aid));
-        CREATE TABLE B (bid int, PRIMARY KEY(bid));
+async fn it_works_w_count() {
+    let mut g = start_simple("it_works_w_count").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
 
-        # read queries
-        QUERY ReadJoin: SELECT J.aid, J.other \
-            FROM B \
-            LEFT JOIN (SELECT A.aid, A.other FROM A \
-                WHERE A.other = 5) AS J \
-            ON (J.aid = B.bid) \
-            WHERE J.aid = ?;
-    ";
+    let mut muta = g.table("a").await.unwrap();
+    for k in 0..5i32 {
+        for i in 0..k {
+            muta.insert(vec![k.into(), i.into()]).await.unwrap();
+        }
+    }
+    sleep().await;
 
-    g.install_recipe(sql).await.unwrap();
-    let mut a = g.table("A").await.unwrap();
-    let mut b = g.table("B").await.unwrap();
-    let mut getter = g.view("ReadJoin").await.unwrap();
+    // the reader is partial, so counting has to trigger replays
+    let _ = g
+        .migrate(move |mig| {
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
+    sleep().await;
 
-    a.insert(vec![1i64.into(), 5.into()]).await.unwrap();
-    a.insert(vec![2i64.into(), 10.into()]).await.unwrap();
-    b.insert(vec![1i64.into()]).await.unwrap();
+    let mut bq = g.view("b").await.unwrap();
+    for k in 0..6i32 {
+        let n = bq.count(&[k.into()], true).await.unwrap();
+        assert_eq!(n, bq.lookup(&[k.into()], true).await.unwrap().len());
+        assert_eq!(n, if k < 5 { k as usize } else { 0 });
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_w_top_n() {
+    let mut g = start_simple("it_works_w_top_n").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["story", "id", "score"], Base::default()))
+        .await;
 
+    let mut muta = g.table("a").await.unwrap();
+    let scores = [(1, 5), (2, 9), (3, 1), (4, 9), (5, 7), (6, 3)];
+    for &(id, score) in &scores {
+        muta.insert(vec![1.into(), id.into(), score.into()])
+            .await
+            .unwrap();
+    }
+    muta.insert(vec![2.into(), 7.into(), 100.into()])
+        .await
+        .unwrap();
     sleep().await;
 
-    let rs = getter.lookup(&[1i64.into()], true).await.unwrap();
-    assert_eq!(rs.len(), 1);
-    assert_eq!(rs[0], vec![1i64.into(), 5.into()]);
+    // the reader is partial, so the first query has to trigger a replay
+    g.migrate(move |mig| {
+        let b = mig.add_ingredient("b", &["story", "id", "score"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
+    sleep().await;
 
-    let empty = getter.lookup(&[2i64.into()], true).await.unwrap();
-    assert_eq!(empty.len(), 0);
+    let mut bq = g.view("b").await.unwrap();
+    let ids = |rows: Vec<noria::results::Row>| -> Vec<DataType> {
+        rows.into_iter()
+            .map(|r| {
+                let r: Vec<DataType> = r.into();
+                r[1].clone()
+            })
+            .collect()
+    };
+
+    // ids 2 and 4 tie on score, and come back in the same order every time
+    for _ in 0..3 {
+        let top = bq.top_n(&[1.into()], 2, 3, true).await.unwrap();
+        assert_eq!(
+            ids(top),
+            vec![2.into(), 4.into(), 5.into()] as Vec<DataType>
+        );
+    }
+    let bottom = bq.top_n(&[1.into()], 2, 2, false).await.unwrap();
+    assert_eq!(ids(bottom), vec![3.into(), 6.into()] as Vec<DataType>);
+
+    // asking for more rows than there are returns them all
+    assert_eq!(bq.top_n(&[1.into()], 2, 10, true).await.unwrap().len(), 6);
+    assert_eq!(bq.top_n(&[3.into()], 2, 10, true).await.unwrap().len(), 0);
+
+    // ordering by a column the view doesn't have is refused rather than reaching the reader
+    match bq.top_n(&[1.into()], 3, 1, true).await {
+        Err(noria::error::ViewError::NoSuchColumn(3)) => {}
+        Err(e) => panic!("ordered by a missing column: {}", e),
+        Ok(_) => panic!("ordered by a missing column"),
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_reads_before_writes() {
-    let mut g = start_simple("it_works_with_reads_before_writes").await;
-    let sql = "
-        CREATE TABLE Article (aid int, PRIMARY KEY(aid));
-        CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(aid, uid));
-        QUERY ArticleVote: SELECT Article.aid, Vote.uid \
-            FROM Article, Vote \
-            WHERE Article.aid = Vote.aid AND Article.aid = ?;
-    ";
+async fn it_works_w_lookup_stream() {
+    let mut g = start_simple("it_works_w_lookup_stream").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
 
-    g.install_recipe(sql).await.unwrap();
-    let mut article = g.table("Article").await.unwrap();
-    let mut vote = g.table("Vote").await.unwrap();
-    let mut awvc = g.view("ArticleVote").await.unwrap();
+    // three copies of each row, so that some runs of duplicates span two pages
+    let n = 3000;
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert_many((0..n).map(|i| vec![1.into(), (i / 3).into()]).collect())
+        .await
+        .unwrap();
+    sleep().await;
 
-    let aid = 1;
-    let uid = 10;
+    // the reader is partial, so the first page has to trigger a replay
+    g.migrate(move |mig| {
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
+    sleep().await;
 
-    assert!(awvc.lookup(&[aid.into()], true).await.unwrap().is_empty());
-    article.insert(vec![aid.into()]).await.unwrap();
+    let mut bq = g.view("b").await.unwrap();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let stream = bq.lookup_stream(&[1.into()], tx);
+    let count = async move {
+        let (mut count, mut sum, mut prev) = (0, 0, -1);
+        while let Some(row) = rx.recv().await {
+            let b: i32 = (&row["b"]).into();
+            assert!(b >= prev);
+            prev = b;
+            count += 1;
+            sum += b as i64;
+        }
+        (count, sum)
+    };
+    let (streamed, (count, sum)) = futures_util::future::join(stream, count).await;
+    streamed.unwrap();
+    assert_eq!(count, n);
+    assert_eq!(sum, 3 * (0..n as i64 / 3).sum::<i64>());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn lookup_if_present_does_not_replay() {
+    let mut g = start_simple_unsharded("lookup_if_present_does_not_replay").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
     sleep().await;
 
-    vote.insert(vec![aid.into(), uid.into()]).await.unwrap();
+    // the reader is partial, and starts out empty
+    g.migrate(move |mig| {
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
     sleep().await;
 
-    let result = awvc.lookup(&[aid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0], vec![aid.into(), uid.into()]);
+    let mut bq = g.view("b").await.unwrap();
+    assert!(bq.lookup_if_present(&[1.into()]).await.unwrap().is_none());
+    sleep().await;
+    // had the miss been replayed, the key would be there by now
+    assert!(bq.lookup_if_present(&[1.into()]).await.unwrap().is_none());
+    let stats = g.statistics().await.unwrap();
+    assert!(stats
+        .values()
+        .flat_map(|(ds, _)| ds.replay_latency.values())
+        .all(|l| l.completed == 0 && l.in_flight == 0));
+
+    // once the key has been read, it is present
+    assert_eq!(bq.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    let rows = bq.lookup_if_present(&[1.into()]).await.unwrap().unwrap();
+    assert_eq!(rows, vec![vec![1.into(), 2.into()]]);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn forced_shuffle_despite_same_shard() {
-    // XXX: this test doesn't currently *fail* despite
-    // multiple trailing replay responses that are simply ignored...
+async fn cancel_pending_partial_replay() {
+    use noria::debug::replay::ReplayTrigger;
 
-    let mut g = start_simple("forced_shuffle_despite_same_shard").await;
-    let sql = "
-        CREATE TABLE Car (cid int, pid int, PRIMARY KEY(pid));
-        CREATE TABLE Price (pid int, price int, PRIMARY KEY(pid));
-        QUERY CarPrice: SELECT cid, price FROM Car \
-            JOIN Price ON Car.pid = Price.pid WHERE cid = ?;
-    ";
-    g.install_recipe(sql).await.unwrap();
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params("cancel_pending_partial_replay"));
+    // keep requests buffered long enough for the cancel to find them
+    builder.set_partial_replay_batch_timeout(Duration::from_millis(500));
+    let mut g = builder.start_local().await.unwrap().0;
+    let r = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0])
+        })
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    muta.insert(vec![2.into(), 3.into()]).await.unwrap();
+    sleep().await;
 
-    let mut car_mutator = g.table("Car").await.unwrap();
-    let mut price_mutator = g.table("Price").await.unwrap();
-    let mut getter = g.view("CarPrice").await.unwrap();
-    let cid = 1;
-    let pid = 1;
-    let price = 100;
+    let paths = g.replay_paths().await.unwrap();
+    let tag = paths
+        .iter()
+        .flat_map(|(_, ps)| ps)
+        .find(|p| {
+            (p.trigger == ReplayTrigger::End || p.trigger == ReplayTrigger::Local)
+                && p.path.iter().any(|&(ni, _)| ni == r)
+        })
+        .unwrap()
+        .tag;
 
-    price_mutator
-        .insert(vec![pid.into(), price.into()])
-        .await
-        .unwrap();
-    car_mutator
-        .insert(vec![cid.into(), pid.into()])
+    // hold the domain back so that the cancel is seen before either replay has started
+    let mut bq = g.view("b").await.unwrap();
+    g.pause_domain(r).await.unwrap();
+    assert!(bq.lookup(&[1.into()], false).await.unwrap().is_empty());
+    assert!(bq.lookup(&[2.into()], false).await.unwrap().is_empty());
+    sleep().await;
+    g.cancel_partial_replay(r, tag, vec![2.into()])
         .await
         .unwrap();
-
-    // Let writes propagate:
     sleep().await;
+    g.resume_domain(r).await.unwrap();
+    // give the batch of requests time to be answered
+    tokio::time::delay_for(Duration::from_secs(1)).await;
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[cid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], price.into());
+    // only the key that wasn't cancelled was replayed
+    assert_eq!(
+        bq.lookup_if_present(&[1.into()])
+            .await
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(bq.lookup_if_present(&[2.into()]).await.unwrap().is_none());
+
+    // and the cancelled key is requested again when it is next read
+    let rows = tokio::time::timeout(Duration::from_secs(10), bq.lookup(&[2.into()], true))
+        .await
+        .expect("cancelled key was never replayed")
+        .unwrap();
+    assert_eq!(rows.len(), 1);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn double_shuffle() {
-    let mut g = start_simple("double_shuffle").await;
-    let sql = "
-        CREATE TABLE Car (cid int, pid int, PRIMARY KEY(cid));
-        CREATE TABLE Price (pid int, price int, PRIMARY KEY(pid));
-        QUERY CarPrice: SELECT cid, price FROM Car \
-            JOIN Price ON Car.pid = Price.pid WHERE cid = ?;
-    ";
-    g.install_recipe(sql).await.unwrap();
+async fn lookup_null_key_matches_nothing() {
+    let mut g = start_simple("lookup_null_key_matches_nothing").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
 
-    let mut car_mutator = g.table("Car").await.unwrap();
-    let mut price_mutator = g.table("Price").await.unwrap();
-    let mut getter = g.view("CarPrice").await.unwrap();
-    let cid = 1;
-    let pid = 1;
-    let price = 100;
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    muta.insert(vec![DataType::None, 3.into()]).await.unwrap();
+    sleep().await;
 
-    price_mutator
-        .insert(vec![pid.into(), price.into()])
-        .await
-        .unwrap();
-    car_mutator
-        .insert(vec![cid.into(), pid.into()])
+    let mut aq = g.view("a").await.unwrap();
+    assert!(aq.lookup(&[DataType::None], true).await.unwrap().is_empty());
+    assert_eq!(aq.count(&[DataType::None], true).await.unwrap(), 0);
+
+    let rs = aq
+        .multi_lookup(
+            vec![vec![DataType::None], vec![1.into()], vec![DataType::None]],
+            true,
+        )
         .await
         .unwrap();
-
-    // Let writes propagate:
-    sleep().await;
-
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[cid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], price.into());
+    assert_eq!(rs.len(), 3);
+    assert!(rs[0].is_empty());
+    assert_eq!(rs[1], vec![vec![1.into(), 2.into()]]);
+    assert!(rs[2].is_empty());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_arithmetic_aliases() {
-    let mut g = start_simple("it_works_with_arithmetic_aliases").await;
-    let sql = "
-        CREATE TABLE Price (pid int, cent_price int, PRIMARY KEY(pid));
-        ModPrice: SELECT pid, cent_price / 100 AS price FROM Price;
-        QUERY AltPrice: SELECT pid, price FROM ModPrice WHERE pid = ?;
-    ";
-    g.install_recipe(sql).await.unwrap();
+async fn it_works_w_dump() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_persistence(get_persistence_params("it_works_w_dump"));
+    let mut g = b.start_local().await.unwrap().0;
 
-    let mut price_mutator = g.table("Price").await.unwrap();
-    let mut getter = g.view("AltPrice").await.unwrap();
-    let pid = 1;
-    let price = 10000;
-    price_mutator
-        .insert(vec![pid.into(), price.into()])
-        .await
-        .unwrap();
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
 
-    // Let writes propagate:
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..20i32 {
+        muta.insert(vec![(i % 7).into(), i.into()]).await.unwrap();
+    }
     sleep().await;
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[pid.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], (price / 100).into());
+    let mut aq = g.view("a").await.unwrap();
+    let mut res: Vec<Vec<DataType>> = aq
+        .dump()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    res.sort();
+    let mut expected: Vec<Vec<DataType>> =
+        (0..20i32).map(|i| vec![(i % 7).into(), i.into()]).collect();
+    expected.sort();
+    assert_eq!(res, expected);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_recovers_persisted_bases() {
-    let authority = Arc::new(LocalAuthority::new());
-    let dir = tempfile::tempdir().unwrap();
-    let path = dir.path().join("it_recovers_persisted_bases");
-    let persistence_params = PersistenceParameters::new(
-        DurabilityMode::Permanent,
-        Duration::from_millis(1),
-        Some(path.to_string_lossy().into()),
-        1,
-    );
+async fn partial_eviction_retriggers_replay() {
+    let mut g = start_simple_unsharded("partial_eviction_retriggers_replay").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
 
-    {
-        let mut g = Builder::default();
-        g.set_persistence(persistence_params.clone());
-        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    let id: DataType = 1.into();
+    muta.insert(vec![id.clone(), 1.into()]).await.unwrap();
+    sleep().await;
 
-        {
-            let sql = "
-            CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
-            QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
-        ";
-            g.install_recipe(sql).await.unwrap();
+    let _ = g
+        .migrate(move |mig| {
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
+    sleep().await;
 
-            let mut mutator = g.table("Car").await.unwrap();
+    // fill the key
+    let mut bq = g.view("b").await.unwrap();
+    assert_eq!(
+        bq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![id.clone(), 1.into()]]
+    );
+    assert_eq!(bq.len().await.unwrap(), 1);
 
-            for i in 1..10 {
-                let price = i * 10;
-                mutator.insert(vec![i.into(), price.into()]).await.unwrap();
-            }
-        }
+    // evict it again; the limit is only enforced as the domain processes more packets
+    g.set_memory_limit(0).await.unwrap();
+    muta.insert(vec![2.into(), 1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(bq.len().await.unwrap(), 0);
 
-        // Let writes propagate:
-        sleep().await;
-        drop(g);
-        done.await;
-    }
+    // evicting when there's nothing left to evict is harmless
+    muta.insert(vec![3.into(), 1.into()]).await.unwrap();
+    sleep().await;
+    g.set_memory_limit(usize::max_value()).await.unwrap();
 
-    let mut g = Builder::default();
-    g.set_persistence(persistence_params);
-    let (mut g, done) = g.start(authority.clone()).await.unwrap();
-    {
-        let mut getter = g.view("CarPrice").await.unwrap();
+    // this write is dropped by the reader, since it no longer has the key
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    sleep().await;
 
-        // Make sure that the new graph contains the old writes
-        for i in 1..10 {
-            let price = i * 10;
-            let result = getter.lookup(&[i.into()], true).await.unwrap();
-            assert_eq!(result.len(), 1);
-            assert_eq!(result[0][0], price.into());
-        }
-    }
-    drop(g);
-    done.await;
+    // so the next read has to replay the key, and must see both rows
+    let res = bq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 1.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn mutator_churn() {
-    let mut g = start_simple("mutator_churn").await;
+async fn it_works_w_partial_mat_below_empty() {
+    // set up graph with all nodes added in a single migration. The base tables are therefore empty
+    // for now.
+    let mut g = start_simple("it_works_w_partial_mat_below_empty").await;
     let _ = g
         .migrate(|mig| {
-            // migrate
-
-            // add vote base table
-            let vote = mig.add_base("vote", &["user", "id"], Base::default());
-
-            // add vote count
-            let vc = mig.add_ingredient(
-                "votecount",
-                &["id", "votes"],
-                Aggregation::COUNT.over(vote, 0, &[1]),
-            );
-
-            mig.maintain_anonymous(vc, &[0]);
-            (vote, vc)
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["a", "b"], u);
+            mig.maintain_anonymous(c, &[0]);
+            (a, b, c)
         })
         .await;
 
-    let mut vc_state = g.view("votecount").await.unwrap();
-
-    let ids = 10;
-    let votes = 7;
+    let mut muta = g.table("a").await.unwrap();
+    let id: DataType = 1.into();
 
-    // continuously write to vote with new mutators
-    let user: DataType = 0.into();
-    for _ in 0..votes {
-        for i in 0..ids {
-            g.table("vote")
-                .await
-                .unwrap()
-                .insert(vec![user.clone(), i.into()])
-                .await
-                .unwrap();
-        }
-    }
+    // send a few values on a
+    muta.insert(vec![id.clone(), 1.into()]).await.unwrap();
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    muta.insert(vec![id.clone(), 3.into()]).await.unwrap();
 
-    // allow the system to catch up with the last writes
+    // give it some time to propagate
     sleep().await;
 
-    // check that all writes happened the right number of times
-    for i in 0..ids {
-        assert_eq!(
-            vc_state.lookup(&[i.into()], true).await.unwrap(),
-            vec![vec![i.into(), votes.into()]]
-        );
-    }
+    let mut cq = g.view("c").await.unwrap();
+
+    // despite the empty base tables, we'll make the reader partial and therefore we should have no
+    // key until we read
+    assert_eq!(cq.len().await.unwrap(), 0);
+
+    // now do some reads
+    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 3);
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 1.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 3.into()]));
+
+    // should have one key in the reader now
+    assert_eq!(cq.len().await.unwrap(), 1);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn connection_churn() {
-    let authority = Arc::new(LocalAuthority::new());
+async fn it_works_deletion() {
+    // set up graph
+    let mut g = start_simple("it_works_deletion").await;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["x", "y"], Base::new(vec![]).with_key(vec![1]));
+            let b = mig.add_base("b", &["_", "x", "y"], Base::new(vec![]).with_key(vec![2]));
 
-    let mut builder = Builder::default();
-    builder.set_sharding(DEFAULT_SHARDING);
-    builder.set_persistence(get_persistence_params("connection_churn"));
-    let (mut g, done) = builder.start(authority.clone()).await.unwrap();
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![1, 2]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["x", "y"], u);
+            mig.maintain_anonymous(c, &[0]);
+            (a, b, c)
+        })
+        .await;
 
-    g.install_recipe("CREATE TABLE A (id int, PRIMARY KEY(id));")
+    let mut cq = g.view("c").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    // send a value on a
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        cq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    // send a value on b
+    mutb.insert(vec![0.into(), 1.into(), 4.into()])
         .await
         .unwrap();
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
-
-    // continuously write to vote with entirely new connections
-    for i in 0..20 {
-        let authority = authority.clone();
-        let tx = tx.clone();
-        tokio::spawn(async move {
-            let mut builder = Builder::default();
-            builder.set_sharding(DEFAULT_SHARDING);
-            builder.set_persistence(get_persistence_params("connection_churn"));
-            let (mut g, done) = builder.start(authority.clone()).await.unwrap();
+    sleep().await;
 
-            g.table("A")
-                .await
-                .unwrap()
-                .insert(vec![DataType::from(i)])
-                .await
-                .unwrap();
+    let res = cq.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![1.into(), 2.into()]));
+    assert!(res.contains(&vec![1.into(), 4.into()]));
 
-            drop(tx);
-            drop(g);
-            done.await;
-        });
-    }
-    drop(tx);
-    let _ = rx.recv().await;
-    drop(g);
-    done.await;
+    // delete first value
+    muta.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        cq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 4.into()]]
+    );
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_recovers_persisted_bases_w_multiple_nodes() {
-    let authority = Arc::new(LocalAuthority::new());
-    let dir = tempfile::tempdir().unwrap();
-    let path = dir
-        .path()
-        .join("it_recovers_persisted_bases_w_multiple_nodes");
-    let tables = vec!["A", "B", "C"];
-    let persistence_parameters = PersistenceParameters::new(
-        DurabilityMode::Permanent,
-        Duration::from_millis(1),
-        Some(path.to_string_lossy().into()),
-        1,
+async fn table_and_view_columns() {
+    let mut g = start_simple("table_and_view_columns").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CarById: SELECT id, brand FROM Car WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    assert_eq!(
+        mutator.column_types(),
+        vec![
+            ("id".to_owned(), Some(DataTypeKind::Int)),
+            ("brand".to_owned(), Some(DataTypeKind::Text)),
+        ]
     );
+    let getter = g.view("CarById").await.unwrap();
+    assert_eq!(getter.columns(), &["id", "brand"]);
 
-    {
-        let mut g = Builder::default();
-        g.set_persistence(persistence_parameters.clone());
-        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    // a row that does not match the schema is rejected before it is sent
+    match mutator.insert(vec![1.into()]).await {
+        Err(noria::error::TableError::WrongColumnCount(2, 1)) => {}
+        r => panic!("expected column count error, got {:?}", r),
+    }
+}
 
-        {
-            let sql = "
-            CREATE TABLE A (id int, PRIMARY KEY(id));
-            CREATE TABLE B (id int, PRIMARY KEY(id));
-            CREATE TABLE C (id int, PRIMARY KEY(id));
+#[tokio::test(threaded_scheduler)]
+async fn lists_tables_and_views() {
+    let mut g = start_simple("lists_tables_and_views").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        CREATE TABLE Owner (id int, car int, PRIMARY KEY(id));
+        QUERY CountCars: SELECT COUNT(*) FROM Car WHERE brand = ?;
+        QUERY OwnerById: SELECT * FROM Owner WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-            QUERY AID: SELECT id FROM A WHERE id = ?;
-            QUERY BID: SELECT id FROM B WHERE id = ?;
-            QUERY CID: SELECT id FROM C WHERE id = ?;
-        ";
-            g.install_recipe(sql).await.unwrap();
-            for (i, table) in tables.iter().enumerate() {
-                let mut mutator = g.table(table).await.unwrap();
-                mutator.insert(vec![i.into()]).await.unwrap();
-            }
-        }
-        sleep().await;
-        drop(g);
-        done.await;
-    }
+    let tables = g.list_tables().await.unwrap();
+    assert_eq!(tables, vec!["Car", "Owner"]);
+    let views = g.list_views().await.unwrap();
+    assert_eq!(views, vec!["CountCars", "OwnerById"]);
 
-    // Create a new controller with the same authority, and make sure that it recovers to the same
-    // state that the other one had.
-    let mut g = Builder::default();
-    g.set_persistence(persistence_parameters);
-    let (mut g, done) = g.start(authority.clone()).await.unwrap();
-    for (i, table) in tables.iter().enumerate() {
-        let mut getter = g.view(&format!("{}ID", table)).await.unwrap();
-        let result = getter.lookup(&[i.into()], true).await.unwrap();
-        assert_eq!(result.len(), 1);
-        assert_eq!(result[0][0], i.into());
+    // every listed name can be opened
+    for t in tables {
+        g.table(&t).await.unwrap();
+    }
+    for v in views {
+        g.view(&v).await.unwrap();
     }
-    drop(g);
-    done.await;
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_simple_arithmetic() {
-    let mut g = start_simple("it_works_with_simple_arithmetic").await;
-
-    g.migrate(|mig| {
-        let sql = "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
-                   QUERY CarPrice: SELECT 2 * price FROM Car WHERE id = ?;";
-        let mut recipe = Recipe::from_str(&sql, None).unwrap();
-        recipe.activate(mig).unwrap();
-    })
-    .await;
+async fn it_works_with_sql_recipe() {
+    let mut g = start_simple("it_works_with_sql_recipe").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CountCars: SELECT COUNT(*) FROM Car WHERE brand = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
     let mut mutator = g.table("Car").await.unwrap();
-    let mut getter = g.view("CarPrice").await.unwrap();
-    let id: DataType = 1.into();
-    let price: DataType = 123.into();
-    mutator.insert(vec![id.clone(), price]).await.unwrap();
+    let mut getter = g.view("CountCars").await.unwrap();
+
+    assert_eq!(mutator.table_name(), "Car");
+    assert_eq!(mutator.columns(), &["id", "brand"]);
+
+    let brands = vec!["Volvo", "Volvo", "Volkswagen"];
+    for (i, &brand) in brands.iter().enumerate() {
+        mutator.insert(vec![i.into(), brand.into()]).await.unwrap();
+    }
 
     // Let writes propagate:
     sleep().await;
 
     // Retrieve the result of the count query:
-    let result = getter.lookup(&[id.clone()], true).await.unwrap();
+    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
     assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], 246.into());
+    assert_eq!(result[0][0], 2.into());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_multiple_arithmetic_expressions() {
-    let mut g = start_simple("it_works_with_multiple_arithmetic_expressions").await;
-    let sql = "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
-               QUERY CarPrice: SELECT 10 * 10, 2 * price, 10 * price, FROM Car WHERE id = ?;
-               ";
+async fn it_works_with_coerced_keys() {
+    let mut g = start_simple("it_works_with_coerced_keys").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CarById: SELECT brand FROM Car WHERE id = ?;
+    ";
     g.install_recipe(sql).await.unwrap();
 
     let mut mutator = g.table("Car").await.unwrap();
-    let mut getter = g.view("CarPrice").await.unwrap();
-    let id: DataType = 1.into();
-    let price: DataType = 123.into();
-    mutator.insert(vec![id.clone(), price]).await.unwrap();
-
-    // Let writes propagate:
+    let mut getter = g.view("CarById").await.unwrap();
+    mutator
+        .insert(vec![1i32.into(), "Volvo".into()])
+        .await
+        .unwrap();
     sleep().await;
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[id.clone()], true).await.unwrap();
+    // without coercion, a string key never matches the int column
+    let result = getter.lookup(&["1".into()], true).await.unwrap();
+    assert!(result.is_empty());
+
+    getter.coerce_keys(true);
+    let result = getter.lookup(&["1".into()], true).await.unwrap();
     assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], 100.into());
-    assert_eq!(result[0][2], 246.into());
-    assert_eq!(result[0][3], 1230.into());
+    assert_eq!(result[0][0], "Volvo".into());
+    assert_eq!(getter.count(&["1".into()], true).await.unwrap(), 1);
+
+    match getter.lookup(&["one".into()], true).await {
+        Err(noria::error::ViewError::Coerce(_)) => {}
+        r => panic!("expected a coercion error, got {:?}", r),
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_join_arithmetic() {
-    let mut g = start_simple("it_works_with_join_arithmetic").await;
+async fn it_works_with_vote() {
+    let mut g = start_simple("it_works_with_vote").await;
     let sql = "
-        CREATE TABLE Car (car_id int, price_id int, PRIMARY KEY(car_id));
-        CREATE TABLE Price (price_id int, price int, PRIMARY KEY(price_id));
-        CREATE TABLE Sales (sales_id int, price_id int, fraction float, PRIMARY KEY(sales_id));
-        QUERY CarPrice: SELECT price * fraction FROM Car \
-                  JOIN Price ON Car.price_id = Price.price_id \
-                  JOIN Sales ON Price.price_id = Sales.price_id \
-                  WHERE car_id = ?;
+        # base tables
+        CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+        CREATE TABLE Vote (article_id int, user int);
+
+        # read queries
+        QUERY ArticleWithVoteCount: SELECT Article.id, title, VoteCount.votes AS votes \
+                    FROM Article \
+                    LEFT JOIN (SELECT Vote.article_id, COUNT(user) AS votes \
+                               FROM Vote GROUP BY Vote.article_id) AS VoteCount \
+                    ON (Article.id = VoteCount.article_id) WHERE Article.id = ?;
     ";
+
     g.install_recipe(sql).await.unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    let mut vote = g.table("Vote").await.unwrap();
+    let mut awvc = g.view("ArticleWithVoteCount").await.unwrap();
 
-    let mut car_mutator = g.table("Car").await.unwrap();
-    let mut price_mutator = g.table("Price").await.unwrap();
-    let mut sales_mutator = g.table("Sales").await.unwrap();
-    let mut getter = g.view("CarPrice").await.unwrap();
-    let id = 1;
-    let price = 123;
-    let fraction = 0.7;
-    car_mutator
-        .insert(vec![id.into(), id.into()])
-        .await
-        .unwrap();
-    price_mutator
-        .insert(vec![id.into(), price.into()])
+    article
+        .insert(vec![0i64.into(), "Article".into()])
         .await
         .unwrap();
-    sales_mutator
-        .insert(vec![id.into(), id.into(), fraction.into()])
+    article
+        .insert(vec![1i64.into(), "Article".into()])
         .await
         .unwrap();
+    vote.insert(vec![0i64.into(), 0.into()]).await.unwrap();
 
-    // Let writes propagate:
     sleep().await;
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&[id.into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][1], (f64::from(price) * fraction).into());
+    let rs = awvc.lookup(&[0i64.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![0i64.into(), "Article".into(), 1.into()]);
+
+    let empty = awvc.lookup(&[1i64.into()], true).await.unwrap();
+    assert_eq!(empty.len(), 1);
+    assert_eq!(
+        empty[0],
+        vec![1i64.into(), "Article".into(), DataType::None]
+    );
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_function_arithmetic() {
-    let mut g = start_simple("it_works_with_function_arithmetic").await;
+async fn it_works_with_identical_queries() {
+    let mut g = start_simple("it_works_with_identical_queries").await;
     let sql = "
-        CREATE TABLE Bread (id int, price int, PRIMARY KEY(id));
-        QUERY Price: SELECT 2 * MAX(price) FROM Bread;
+        CREATE TABLE Article (aid int, PRIMARY KEY(aid));
+        QUERY aq1: SELECT Article.* FROM Article WHERE Article.aid = ?;
+        QUERY aq2: SELECT Article.* FROM Article WHERE Article.aid = ?;
     ";
+
     g.install_recipe(sql).await.unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    let mut aq1 = g.view("aq1").await.unwrap();
+    let mut aq2 = g.view("aq2").await.unwrap();
 
-    let mut mutator = g.table("Bread").await.unwrap();
-    let mut getter = g.view("Price").await.unwrap();
-    let max_price = 20;
-    for (i, price) in (10..=max_price).enumerate() {
-        let id = i + 1;
-        mutator.insert(vec![id.into(), price.into()]).await.unwrap();
-    }
+    let aid = 1u64;
 
-    // Let writes propagate:
+    assert!(aq1.lookup(&[aid.into()], true).await.unwrap().is_empty());
+    assert!(aq2.lookup(&[aid.into()], true).await.unwrap().is_empty());
+    article.insert(vec![aid.into()]).await.unwrap();
     sleep().await;
 
-    let result = getter.lookup(&[0.into()], true).await.unwrap();
+    let result = aq2.lookup(&[aid.into()], true).await.unwrap();
     assert_eq!(result.len(), 1);
-    assert_eq!(result[0][0], DataType::from(max_price * 2));
+    assert_eq!(result[0], vec![aid.into()]);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn votes() {
-    // set up graph
-    let mut g = start_simple("votes").await;
-    let _ = g
-        .migrate(|mig| {
-            // add article base nodes (we use two so we can exercise unions too)
-            let article1 = mig.add_base("article1", &["id", "title"], Base::default());
-            let article2 = mig.add_base("article2", &["id", "title"], Base::default());
+async fn it_works_with_double_query_through() {
+    let mut g = start_simple_unsharded("it_works_with_double_query_through").await;
+    let sql = "
+        # base tables
+        CREATE TABLE A (aid int, other int, PRIMARY KEY(aid));
+        CREATE TABLE B (bid int, PRIMARY KEY(bid));
 
-            // add a (stupid) union of article1 + article2
-            let mut emits = HashMap::new();
-            emits.insert(article1, vec![0, 1]);
-            emits.insert(article2, vec![0, 1]);
-            let u = Union::new(emits);
-            let article = mig.add_ingredient("article", &["id", "title"], u);
-            mig.maintain_anonymous(article, &[0]);
+        # read queries
+        QUERY ReadJoin: SELECT J.aid, J.other \
+            FROM B \
+            LEFT JOIN (SELECT A.aid, A.other FROM A \
+                WHERE A.other = 5) AS J \
+            ON (J.aid = B.bid) \
+            WHERE J.aid = ?;
+    ";
 
-            // add vote base table
-            let vote = mig.add_base("vote", &["user", "id"], Base::default());
+    g.install_recipe(sql).await.unwrap();
+    let mut a = g.table("A").await.unwrap();
+    let mut b = g.table("B").await.unwrap();
+    let mut getter = g.view("ReadJoin").await.unwrap();
 
-            // add vote count
-            let vc = mig.add_ingredient(
-                "vc",
-                &["id", "votes"],
-                Aggregation::COUNT.over(vote, 0, &[1]),
-            );
-            mig.maintain_anonymous(vc, &[0]);
+    a.insert(vec![1i64.into(), 5.into()]).await.unwrap();
+    a.insert(vec![2i64.into(), 10.into()]).await.unwrap();
+    b.insert(vec![1i64.into()]).await.unwrap();
 
-            // add final join using first field from article and first from vc
-            let j = Join::new(article, vc, JoinType::Inner, vec![B(0, 0), L(1), R(1)]);
-            let end = mig.add_ingredient("end", &["id", "title", "votes"], j);
-            mig.maintain_anonymous(end, &[0]);
+    sleep().await;
 
-            (article1, article2, vote, article, vc, end)
-        })
-        .await;
+    let rs = getter.lookup(&[1i64.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0], vec![1i64.into(), 5.into()]);
 
-    let mut articleq = g.view("article").await.unwrap();
-    let mut vcq = g.view("vc").await.unwrap();
-    let mut endq = g.view("end").await.unwrap();
+    let empty = getter.lookup(&[2i64.into()], true).await.unwrap();
+    assert_eq!(empty.len(), 0);
+}
 
-    let mut mut1 = g.table("article1").await.unwrap();
-    let mut mut2 = g.table("article2").await.unwrap();
-    let mut mutv = g.table("vote").await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_reads_before_writes() {
+    let mut g = start_simple("it_works_with_reads_before_writes").await;
+    let sql = "
+        CREATE TABLE Article (aid int, PRIMARY KEY(aid));
+        CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(aid, uid));
+        QUERY ArticleVote: SELECT Article.aid, Vote.uid \
+            FROM Article, Vote \
+            WHERE Article.aid = Vote.aid AND Article.aid = ?;
+    ";
 
-    let a1: DataType = 1.into();
-    let a2: DataType = 2.into();
+    g.install_recipe(sql).await.unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    let mut vote = g.table("Vote").await.unwrap();
+    let mut awvc = g.view("ArticleVote").await.unwrap();
 
-    // make one article
-    mut1.insert(vec![a1.clone(), 2.into()]).await.unwrap();
+    let aid = 1;
+    let uid = 10;
 
-    // give it some time to propagate
+    assert!(awvc.lookup(&[aid.into()], true).await.unwrap().is_empty());
+    article.insert(vec![aid.into()]).await.unwrap();
     sleep().await;
 
-    // query articles to see that it was updated
-    assert_eq!(
-        articleq.lookup(&[a1.clone()], true).await.unwrap(),
-        vec![vec![a1.clone(), 2.into()]]
-    );
-
-    // make another article
-    mut2.insert(vec![a2.clone(), 4.into()]).await.unwrap();
-
-    // give it some time to propagate
+    vote.insert(vec![aid.into(), uid.into()]).await.unwrap();
     sleep().await;
 
-    // query articles again to see that the new article was absorbed
-    // and that the old one is still present
-    assert_eq!(
-        articleq.lookup(&[a1.clone()], true).await.unwrap(),
-        vec![vec![a1.clone(), 2.into()]]
-    );
-    assert_eq!(
-        articleq.lookup(&[a2.clone()], true).await.unwrap(),
-        vec![vec![a2.clone(), 4.into()]]
-    );
-
-    // create a vote (user 1 votes for article 1)
-    mutv.insert(vec![1.into(), a1.clone()]).await.unwrap();
+    let result = awvc.lookup(&[aid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0], vec![aid.into(), uid.into()]);
+}
 
-    // give it some time to propagate
-    sleep().await;
+#[tokio::test(threaded_scheduler)]
+async fn forced_shuffle_despite_same_shard() {
+    // XXX: this test doesn't currently *fail* despite
+    // multiple trailing replay responses that are simply ignored...
 
-    // query vote count to see that the count was updated
-    let res = vcq.lookup(&[a1.clone()], true).await.unwrap();
-    assert!(res.iter().all(|r| r[0] == a1.clone() && r[1] == 1.into()));
-    assert_eq!(res.len(), 1);
+    let mut g = start_simple("forced_shuffle_despite_same_shard").await;
+    let sql = "
+        CREATE TABLE Car (cid int, pid int, PRIMARY KEY(pid));
+        CREATE TABLE Price (pid int, price int, PRIMARY KEY(pid));
+        QUERY CarPrice: SELECT cid, price FROM Car \
+            JOIN Price ON Car.pid = Price.pid WHERE cid = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-    // check that article 1 appears in the join view with a vote count of one
-    let res = endq.lookup(&[a1.clone()], true).await.unwrap();
-    assert!(
-        res.iter()
-            .any(|r| r[0] == a1.clone() && r[1] == 2.into() && r[2] == 1.into()),
-        "no entry for [1,2,1|2] in {:?}",
-        res
-    );
-    assert_eq!(res.len(), 1);
+    let mut car_mutator = g.table("Car").await.unwrap();
+    let mut price_mutator = g.table("Price").await.unwrap();
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let cid = 1;
+    let pid = 1;
+    let price = 100;
 
-    // check that article 2 doesn't have any votes
-    let res = endq.lookup(&[a2.clone()], true).await.unwrap();
-    assert!(res.len() <= 1) // could be 1 if we had zero-rows
-}
+    price_mutator
+        .insert(vec![pid.into(), price.into()])
+        .await
+        .unwrap();
+    car_mutator
+        .insert(vec![cid.into(), pid.into()])
+        .await
+        .unwrap();
 
-#[tokio::test(threaded_scheduler)]
-async fn empty_migration() {
-    // set up graph
-    let mut g = start_simple("empty_migration").await;
-    g.migrate(|_| {}).await;
+    // Let writes propagate:
+    sleep().await;
 
-    let _ = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::default());
-            let b = mig.add_base("b", &["a", "b"], Base::default());
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[cid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], price.into());
+}
 
-            let mut emits = HashMap::new();
-            emits.insert(a, vec![0, 1]);
-            emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
-            let c = mig.add_ingredient("c", &["a", "b"], u);
-            mig.maintain_anonymous(c, &[0]);
-            (a, b, c)
-        })
-        .await;
+#[tokio::test(threaded_scheduler)]
+async fn double_shuffle() {
+    let mut g = start_simple("double_shuffle").await;
+    let sql = "
+        CREATE TABLE Car (cid int, pid int, PRIMARY KEY(cid));
+        CREATE TABLE Price (pid int, price int, PRIMARY KEY(pid));
+        QUERY CarPrice: SELECT cid, price FROM Car \
+            JOIN Price ON Car.pid = Price.pid WHERE cid = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-    let mut cq = g.view("c").await.unwrap();
-    let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
-    let id: DataType = 1.into();
+    let mut car_mutator = g.table("Car").await.unwrap();
+    let mut price_mutator = g.table("Price").await.unwrap();
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let cid = 1;
+    let pid = 1;
+    let price = 100;
 
-    // send a value on a
-    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    price_mutator
+        .insert(vec![pid.into(), price.into()])
+        .await
+        .unwrap();
+    car_mutator
+        .insert(vec![cid.into(), pid.into()])
+        .await
+        .unwrap();
 
-    // give it some time to propagate
+    // Let writes propagate:
     sleep().await;
 
-    // send a query to c
-    assert_eq!(
-        cq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![1.into(), 2.into()]]
-    );
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[cid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], price.into());
+}
 
-    // update value again
-    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_arithmetic_aliases() {
+    let mut g = start_simple("it_works_with_arithmetic_aliases").await;
+    let sql = "
+        CREATE TABLE Price (pid int, cent_price int, PRIMARY KEY(pid));
+        ModPrice: SELECT pid, cent_price / 100 AS price FROM Price;
+        QUERY AltPrice: SELECT pid, price FROM ModPrice WHERE pid = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-    // give it some time to propagate
+    let mut price_mutator = g.table("Price").await.unwrap();
+    let mut getter = g.view("AltPrice").await.unwrap();
+    let pid = 1;
+    let price = 10000;
+    price_mutator
+        .insert(vec![pid.into(), price.into()])
+        .await
+        .unwrap();
+
+    // Let writes propagate:
     sleep().await;
 
-    // check that value was updated again
-    let res = cq.lookup(&[id.clone()], true).await.unwrap();
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[pid.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], (price / 100).into());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn simple_migration() {
-    let id: DataType = 1.into();
+async fn it_recovers_persisted_bases() {
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("it_recovers_persisted_bases");
+    let persistence_params = PersistenceParameters::new(
+        DurabilityMode::Permanent,
+        Duration::from_millis(1),
+        Some(path.to_string_lossy().into()),
+        1,
+    );
 
-    // set up graph
-    let mut g = start_simple("simple_migration").await;
-    let _ = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::default());
-            mig.maintain_anonymous(a, &[0]);
-            a
-        })
-        .await;
+    {
+        let mut g = Builder::default();
+        g.set_persistence(persistence_params.clone());
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
 
-    let mut aq = g.view("a").await.unwrap();
-    let mut muta = g.table("a").await.unwrap();
+        {
+            let sql = "
+            CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+            QUERY CarPrice: SELECT price FROM Car WHERE id = ?;
+        ";
+            g.install_recipe(sql).await.unwrap();
 
-    // send a value on a
-    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+            let mut mutator = g.table("Car").await.unwrap();
 
-    // give it some time to propagate
-    sleep().await;
+            for i in 1..10 {
+                let price = i * 10;
+                mutator.insert(vec![i.into(), price.into()]).await.unwrap();
+            }
+        }
 
-    // check that a got it
-    assert_eq!(
-        aq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![1.into(), 2.into()]]
-    );
+        // Let writes propagate:
+        sleep().await;
+        drop(g);
+        done.await;
+    }
 
-    // add unrelated node b in a migration
+    let mut g = Builder::default();
+    g.set_persistence(persistence_params);
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    {
+        let mut getter = g.view("CarPrice").await.unwrap();
+
+        // Make sure that the new graph contains the old writes
+        for i in 1..10 {
+            let price = i * 10;
+            let result = getter.lookup(&[i.into()], true).await.unwrap();
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0][0], price.into());
+        }
+    }
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn mutator_churn() {
+    let mut g = start_simple("mutator_churn").await;
     let _ = g
         .migrate(|mig| {
-            let b = mig.add_base("b", &["a", "b"], Base::default());
-            mig.maintain_anonymous(b, &[0]);
-            b
+            // migrate
+
+            // add vote base table
+            let vote = mig.add_base("vote", &["user", "id"], Base::default());
+
+            // add vote count
+            let vc = mig.add_ingredient(
+                "votecount",
+                &["id", "votes"],
+                Aggregation::COUNT.over(vote, 0, &[1]),
+            );
+
+            mig.maintain_anonymous(vc, &[0]);
+            (vote, vc)
         })
         .await;
 
-    let mut bq = g.view("b").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
+    let mut vc_state = g.view("votecount").await.unwrap();
 
-    // send a value on b
-    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+    let ids = 10;
+    let votes = 7;
 
-    // give it some time to propagate
-    sleep().await;
+    // continuously write to vote with new mutators
+    let user: DataType = 0.into();
+    for _ in 0..votes {
+        for i in 0..ids {
+            g.table("vote")
+                .await
+                .unwrap()
+                .insert(vec![user.clone(), i.into()])
+                .await
+                .unwrap();
+        }
+    }
 
-    // check that b got it
-    assert_eq!(
-        bq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![1.into(), 4.into()]]
-    );
-}
+    // allow the system to catch up with the last writes
+    sleep().await;
+
+    // check that all writes happened the right number of times
+    for i in 0..ids {
+        assert_eq!(
+            vc_state.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![i.into(), votes.into()]]
+        );
+    }
+}
 
 #[tokio::test(threaded_scheduler)]
-async fn add_columns() {
-    let id: DataType = "x".into();
+async fn connection_churn() {
+    let authority = Arc::new(LocalAuthority::new());
 
-    // set up graph
-    let mut g = start_simple("add_columns").await;
-    let a = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()]));
-            mig.maintain_anonymous(a, &[0]);
-            a
-        })
-        .await;
-    let mut aq = g.view("a").await.unwrap();
-    let mut muta = g.table("a").await.unwrap();
+    let mut builder = Builder::default();
+    builder.set_sharding(DEFAULT_SHARDING);
+    builder.set_persistence(get_persistence_params("connection_churn"));
+    let (mut g, done) = builder.start(authority.clone()).await.unwrap();
 
-    // send a value on a
-    muta.insert(vec![id.clone(), "y".into()]).await.unwrap();
-    sleep().await;
+    g.install_recipe("CREATE TABLE A (id int, PRIMARY KEY(id));")
+        .await
+        .unwrap();
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
 
-    // check that a got it
-    assert_eq!(
-        aq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![id.clone(), "y".into()]]
+    // continuously write to vote with entirely new connections
+    for i in 0..20 {
+        let authority = authority.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut builder = Builder::default();
+            builder.set_sharding(DEFAULT_SHARDING);
+            builder.set_persistence(get_persistence_params("connection_churn"));
+            let (mut g, done) = builder.start(authority.clone()).await.unwrap();
+
+            g.table("A")
+                .await
+                .unwrap()
+                .insert(vec![DataType::from(i)])
+                .await
+                .unwrap();
+
+            drop(tx);
+            drop(g);
+            done.await;
+        });
+    }
+    drop(tx);
+    let _ = rx.recv().await;
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_recovers_persisted_bases_w_multiple_nodes() {
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir
+        .path()
+        .join("it_recovers_persisted_bases_w_multiple_nodes");
+    let tables = vec!["A", "B", "C"];
+    let persistence_parameters = PersistenceParameters::new(
+        DurabilityMode::Permanent,
+        Duration::from_millis(1),
+        Some(path.to_string_lossy().into()),
+        1,
     );
 
-    // add a third column to a
-    g.migrate(move |mig| {
-        mig.add_column(a, "c", 3.into());
+    {
+        let mut g = Builder::default();
+        g.set_persistence(persistence_parameters.clone());
+        let (mut g, done) = g.start(authority.clone()).await.unwrap();
+
+        {
+            let sql = "
+            CREATE TABLE A (id int, PRIMARY KEY(id));
+            CREATE TABLE B (id int, PRIMARY KEY(id));
+            CREATE TABLE C (id int, PRIMARY KEY(id));
+
+            QUERY AID: SELECT id FROM A WHERE id = ?;
+            QUERY BID: SELECT id FROM B WHERE id = ?;
+            QUERY CID: SELECT id FROM C WHERE id = ?;
+        ";
+            g.install_recipe(sql).await.unwrap();
+            for (i, table) in tables.iter().enumerate() {
+                let mut mutator = g.table(table).await.unwrap();
+                mutator.insert(vec![i.into()]).await.unwrap();
+            }
+        }
+        sleep().await;
+        drop(g);
+        done.await;
+    }
+
+    // Create a new controller with the same authority, and make sure that it recovers to the same
+    // state that the other one had.
+    let mut g = Builder::default();
+    g.set_persistence(persistence_parameters);
+    let (mut g, done) = g.start(authority.clone()).await.unwrap();
+    for (i, table) in tables.iter().enumerate() {
+        let mut getter = g.view(&format!("{}ID", table)).await.unwrap();
+        let result = getter.lookup(&[i.into()], true).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0][0], i.into());
+    }
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_simple_arithmetic() {
+    let mut g = start_simple("it_works_with_simple_arithmetic").await;
+
+    g.migrate(|mig| {
+        let sql = "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+                   QUERY CarPrice: SELECT 2 * price FROM Car WHERE id = ?;";
+        let mut recipe = Recipe::from_str(&sql, None).unwrap();
+        recipe.activate(mig).unwrap();
     })
     .await;
-    sleep().await;
-
-    // send another (old) value on a
-    muta.insert(vec![id.clone(), "z".into()]).await.unwrap();
-    sleep().await;
 
-    // check that a got it, and added the new, third column's default
-    let res = aq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![id.clone(), "y".into()]));
-    assert!(res.contains(&vec![id.clone(), "z".into(), 3.into()]));
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let id: DataType = 1.into();
+    let price: DataType = 123.into();
+    mutator.insert(vec![id.clone(), price]).await.unwrap();
 
-    // get a new muta and send a new value on it
-    let mut muta = g.table("a").await.unwrap();
-    muta.insert(vec![id.clone(), "a".into(), 10.into()])
-        .await
-        .unwrap();
+    // Let writes propagate:
     sleep().await;
 
-    // check that a got it, and included the third column
-    let res = aq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 3);
-    assert!(res.contains(&vec![id.clone(), "y".into()]));
-    assert!(res.contains(&vec![id.clone(), "z".into(), 3.into()]));
-    assert!(res.contains(&vec![id.clone(), "a".into(), 10.into()]));
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], 246.into());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn migrate_added_columns() {
-    let id: DataType = "x".into();
+async fn it_works_with_multiple_arithmetic_expressions() {
+    let mut g = start_simple("it_works_with_multiple_arithmetic_expressions").await;
+    let sql = "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+               QUERY CarPrice: SELECT 10 * 10, 2 * price, 10 * price, FROM Car WHERE id = ?;
+               ";
+    g.install_recipe(sql).await.unwrap();
 
-    // set up graph
-    let mut g = start_simple("migrate_added_columns").await;
-    let a = g
-        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()])))
-        .await;
-    let mut muta = g.table("a").await.unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let id: DataType = 1.into();
+    let price: DataType = 123.into();
+    mutator.insert(vec![id.clone(), price]).await.unwrap();
 
-    // send a value on a
-    muta.insert(vec![id.clone(), "y".into()]).await.unwrap();
+    // Let writes propagate:
     sleep().await;
 
-    // add a third column to a, and a view that uses it
-    let _ = g
-        .migrate(move |mig| {
-            mig.add_column(a, "c", 3.into());
-            let b = mig.add_ingredient("x", &["c", "b"], Project::new(a, &[2, 0], None, None));
-            mig.maintain_anonymous(b, &[1]);
-            b
-        })
-        .await;
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], 100.into());
+    assert_eq!(result[0][2], 246.into());
+    assert_eq!(result[0][3], 1230.into());
+}
 
-    let mut bq = g.view("x").await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_join_arithmetic() {
+    let mut g = start_simple("it_works_with_join_arithmetic").await;
+    let sql = "
+        CREATE TABLE Car (car_id int, price_id int, PRIMARY KEY(car_id));
+        CREATE TABLE Price (price_id int, price int, PRIMARY KEY(price_id));
+        CREATE TABLE Sales (sales_id int, price_id int, fraction float, PRIMARY KEY(sales_id));
+        QUERY CarPrice: SELECT price * fraction FROM Car \
+                  JOIN Price ON Car.price_id = Price.price_id \
+                  JOIN Sales ON Price.price_id = Sales.price_id \
+                  WHERE car_id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-    // send another (old) value on a
-    muta.insert(vec![id.clone(), "z".into()]).await.unwrap();
-    // and an entirely new value
-    let mut muta = g.table("a").await.unwrap();
-    muta.insert(vec![id.clone(), "a".into(), 10.into()])
+    let mut car_mutator = g.table("Car").await.unwrap();
+    let mut price_mutator = g.table("Price").await.unwrap();
+    let mut sales_mutator = g.table("Sales").await.unwrap();
+    let mut getter = g.view("CarPrice").await.unwrap();
+    let id = 1;
+    let price = 123;
+    let fraction = 0.7;
+    car_mutator
+        .insert(vec![id.into(), id.into()])
+        .await
+        .unwrap();
+    price_mutator
+        .insert(vec![id.into(), price.into()])
+        .await
+        .unwrap();
+    sales_mutator
+        .insert(vec![id.into(), id.into(), fraction.into()])
         .await
         .unwrap();
 
-    // give it some time to propagate
+    // Let writes propagate:
     sleep().await;
 
-    // we should now see the pre-migration write and the old post-migration write with the default
-    // value, and the new post-migration write with the value it contained.
-    let res = bq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 3);
-    assert_eq!(
-        res.iter()
-            .filter(|r| r == &vec![3.into(), id.clone()])
-            .count(),
-        2
-    );
-    assert!(res.iter().any(|r| r == &vec![10.into(), id.clone()]));
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&[id.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][1], (f64::from(price) * fraction).into());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn migrate_drop_columns() {
-    let id: DataType = "x".into();
-
-    // set up graph
-    let mut g = start_simple("migrate_drop_columns").await;
-    let a = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::new(vec!["a".into(), "b".into()]));
-            mig.maintain_anonymous(a, &[0]);
-            a
-        })
-        .await;
-    let mut aq = g.view("a").await.unwrap();
-    let mut muta1 = g.table("a").await.unwrap();
-
-    // send a value on a
-    muta1.insert(vec![id.clone(), "bx".into()]).await.unwrap();
-
-    // check that it's there
-    sleep().await;
-    let res = aq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 1);
-    assert!(res.contains(&vec![id.clone(), "bx".into()]));
-
-    // drop a column
-    g.migrate(move |mig| {
-        mig.drop_column(a, 1);
-        mig.maintain_anonymous(a, &[0]);
-    })
-    .await;
-
-    // new mutator should only require one column
-    // and should inject default for a.b
-    let mut muta2 = g.table("a").await.unwrap();
-    muta2.insert(vec![id.clone()]).await.unwrap();
-
-    // so two rows now!
-    sleep().await;
-    let res = aq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![id.clone(), "bx".into()]));
-    assert!(res.contains(&vec![id.clone(), "b".into()]));
-
-    // add a new column
-    g.migrate(move |mig| {
-        mig.add_column(a, "c", "c".into());
-    })
-    .await;
-
-    // new mutator allows putting two values, and injects default for a.b
-    let mut muta3 = g.table("a").await.unwrap();
-    muta3.insert(vec![id.clone(), "cy".into()]).await.unwrap();
+async fn it_works_with_function_arithmetic() {
+    let mut g = start_simple("it_works_with_function_arithmetic").await;
+    let sql = "
+        CREATE TABLE Bread (id int, price int, PRIMARY KEY(id));
+        QUERY Price: SELECT 2 * MAX(price) FROM Bread;
+    ";
+    g.install_recipe(sql).await.unwrap();
 
-    // using an old putter now should add default for c
-    muta1.insert(vec![id.clone(), "bz".into()]).await.unwrap();
+    let mut mutator = g.table("Bread").await.unwrap();
+    let mut getter = g.view("Price").await.unwrap();
+    let max_price = 20;
+    for (i, price) in (10..=max_price).enumerate() {
+        let id = i + 1;
+        mutator.insert(vec![id.into(), price.into()]).await.unwrap();
+    }
 
-    // using putter that knows of neither b nor c should result in defaults for both
-    muta2.insert(vec![id.clone()]).await.unwrap();
+    // Let writes propagate:
     sleep().await;
 
-    let res = aq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 5);
-    // NOTE: if we *hadn't* read bx and b above, they would have also have c because it would have
-    // been added when the lookups caused partial backfills.
-    assert!(res.contains(&vec![id.clone(), "bx".into()]));
-    assert!(res.contains(&vec![id.clone(), "b".into()]));
-    assert!(res.contains(&vec![id.clone(), "b".into(), "cy".into()]));
-    assert!(res.contains(&vec![id.clone(), "bz".into(), "c".into()]));
-    assert!(res.contains(&vec![id.clone(), "b".into(), "c".into()]));
+    let result = getter.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], DataType::from(max_price * 2));
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn key_on_added() {
+async fn votes() {
     // set up graph
-    let mut g = start_simple("key_on_added").await;
-    let a = g
+    let mut g = start_simple("votes").await;
+    let _ = g
         .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()]));
-            a
-        })
-        .await;
+            // add article base nodes (we use two so we can exercise unions too)
+            let article1 = mig.add_base("article1", &["id", "title"], Base::default());
+            let article2 = mig.add_base("article2", &["id", "title"], Base::default());
 
-    // add a maintained view keyed on newly added column
-    let _ = g
-        .migrate(move |mig| {
-            mig.add_column(a, "c", 3.into());
-            let b = mig.add_ingredient("x", &["c", "b"], Project::new(a, &[2, 1], None, None));
-            mig.maintain_anonymous(b, &[0]);
-            b
-        })
-        .await;
+            // add a (stupid) union of article1 + article2
+            let mut emits = HashMap::new();
+            emits.insert(article1, vec![0, 1]);
+            emits.insert(article2, vec![0, 1]);
+            let u = Union::new(emits);
+            let article = mig.add_ingredient("article", &["id", "title"], u);
+            mig.maintain_anonymous(article, &[0]);
 
-    // make sure we can read (may trigger a replay)
-    let mut bq = g.view("x").await.unwrap();
-    assert!(bq.lookup(&[3.into()], true).await.unwrap().is_empty());
-}
+            // add vote base table
+            let vote = mig.add_base("vote", &["user", "id"], Base::default());
 
-#[tokio::test(threaded_scheduler)]
-async fn replay_during_replay() {
-    // what we're trying to set up here is a case where a join receives a record with a value for
-    // the join key that does not exist in the view the record was sent from. since joins only do
-    // lookups into the origin view during forward processing when it receives things from the
-    // right in a left join, that's what we have to construct.
-    let mut g = Builder::default();
-    g.disable_partial();
-    g.set_persistence(get_persistence_params("replay_during_replay"));
-    let mut g = g.start_local().await.unwrap().0;
-    let (a, u1, u2) = g
-        .migrate(|mig| {
-            // we need three bases:
-            //
-            //  - a will be the left side of the left join
-            //  - u1 and u2 will be joined together with a regular one-to-one join to produce a partial
-            //    view (remember, we need to miss in the source of the replay, so it must be partial).
-            let a = mig.add_base("a", &["a"], Base::new(vec![1.into()]));
-            let u1 = mig.add_base("u1", &["u"], Base::new(vec![1.into()]));
-            let u2 = mig.add_base("u2", &["u", "a"], Base::new(vec![1.into(), 2.into()]));
-            (a, u1, u2)
-        })
-        .await;
+            // add vote count
+            let vc = mig.add_ingredient(
+                "vc",
+                &["id", "votes"],
+                Aggregation::COUNT.over(vote, 0, &[1]),
+            );
+            mig.maintain_anonymous(vc, &[0]);
 
-    // add our joins
-    let (u, _) = g
-        .migrate(move |mig| {
-            // u = u1 * u2
-            let j = Join::new(u1, u2, JoinType::Inner, vec![B(0, 0), R(1)]);
-            let u = mig.add_ingredient("u", &["u", "a"], j);
-            let j = Join::new(a, u, JoinType::Left, vec![B(0, 1), R(0)]);
-            let end = mig.add_ingredient("end", &["a", "u"], j);
+            // add final join using first field from article and first from vc
+            let j = Join::new(article, vc, JoinType::Inner, vec![B(0, 0), L(1), R(1)]);
+            let end = mig.add_ingredient("end", &["id", "title", "votes"], j);
             mig.maintain_anonymous(end, &[0]);
-            (u, end)
+
+            (article1, article2, vote, article, vc, end)
         })
         .await;
 
-    // at this point, there's no secondary index on `u`, so any records that are forwarded from `u`
-    // must already be present in the one index that `u` has. let's do some writes and check that
-    // nothing crashes.
-
-    let mut muta = g.table("a").await.unwrap();
-    let mut mutu1 = g.table("u1").await.unwrap();
-    let mut mutu2 = g.table("u2").await.unwrap();
+    let mut articleq = g.view("article").await.unwrap();
+    let mut vcq = g.view("vc").await.unwrap();
+    let mut endq = g.view("end").await.unwrap();
 
-    // as are numbers
-    muta.insert(vec![1.into()]).await.unwrap();
-    muta.insert(vec![2.into()]).await.unwrap();
-    muta.insert(vec![3.into()]).await.unwrap();
+    let mut mut1 = g.table("article1").await.unwrap();
+    let mut mut2 = g.table("article2").await.unwrap();
+    let mut mutv = g.table("vote").await.unwrap();
 
-    // us are strings
-    mutu1.insert(vec!["a".into()]).await.unwrap();
-    mutu1.insert(vec!["b".into()]).await.unwrap();
-    mutu1.insert(vec!["c".into()]).await.unwrap();
+    let a1: DataType = 1.into();
+    let a2: DataType = 2.into();
 
-    // we want there to be data for all keys
-    mutu2.insert(vec!["a".into(), 1.into()]).await.unwrap();
-    mutu2.insert(vec!["b".into(), 2.into()]).await.unwrap();
-    mutu2.insert(vec!["c".into(), 3.into()]).await.unwrap();
+    // make one article
+    mut1.insert(vec![a1.clone(), 2.into()]).await.unwrap();
 
+    // give it some time to propagate
     sleep().await;
 
-    // since u and target are both partial, the writes should not actually have propagated through
-    // yet. do a read to see that one makes it through correctly:
-    let mut r = g.view("end").await.unwrap();
-
+    // query articles to see that it was updated
     assert_eq!(
-        r.lookup(&[1.into()], true).await.unwrap(),
-        vec![vec![1.into(), "a".into()]]
+        articleq.lookup(&[a1.clone()], true).await.unwrap(),
+        vec![vec![a1.clone(), 2.into()]]
     );
 
-    // we now know that u has key a=1 in its index
-    // now we add a secondary index on u.u
-    g.migrate(move |mig| {
-        mig.maintain_anonymous(u, &[0]);
-    })
-    .await;
+    // make another article
+    mut2.insert(vec![a2.clone(), 4.into()]).await.unwrap();
 
-    let mut second = g.view("u").await.unwrap();
+    // give it some time to propagate
+    sleep().await;
 
-    // second is partial and empty, so any read should trigger a replay.
-    // though that shouldn't interact with target in any way.
+    // query articles again to see that the new article was absorbed
+    // and that the old one is still present
     assert_eq!(
-        second.lookup(&["a".into()], true).await.unwrap(),
-        vec![vec!["a".into(), 1.into()]]
+        articleq.lookup(&[a1.clone()], true).await.unwrap(),
+        vec![vec![a1.clone(), 2.into()]]
     );
-
-    // now we get to the funky part.
-    // we're going to request a second key from the secondary index on `u`, which causes that hole
-    // to disappear. then we're going to do a write to `u2` that has that second key, but has an
-    // "a" value for which u has a hole. that record is then going to be forwarded to *both*
-    // children, and it'll be interesting to see what the join then does.
     assert_eq!(
-        second.lookup(&["b".into()], true).await.unwrap(),
-        vec![vec!["b".into(), 2.into()]]
+        articleq.lookup(&[a2.clone()], true).await.unwrap(),
+        vec![vec![a2.clone(), 4.into()]]
     );
 
-    // u has a hole for a=2, but not for u=b, and so should forward this to both children
-    mutu2.insert(vec!["b".into(), 2.into()]).await.unwrap();
+    // create a vote (user 1 votes for article 1)
+    mutv.insert(vec![1.into(), a1.clone()]).await.unwrap();
 
+    // give it some time to propagate
     sleep().await;
 
-    // what happens if we now query for 2?
-    assert_eq!(
-        r.lookup(&[2.into()], true).await.unwrap(),
-        vec![vec![2.into(), "b".into()], vec![2.into(), "b".into()]]
+    // query vote count to see that the count was updated
+    let res = vcq.lookup(&[a1.clone()], true).await.unwrap();
+    assert!(res.iter().all(|r| r[0] == a1.clone() && r[1] == 1.into()));
+    assert_eq!(res.len(), 1);
+
+    // check that article 1 appears in the join view with a vote count of one
+    let res = endq.lookup(&[a1.clone()], true).await.unwrap();
+    assert!(
+        res.iter()
+            .any(|r| r[0] == a1.clone() && r[1] == 2.into() && r[2] == 1.into()),
+        "no entry for [1,2,1|2] in {:?}",
+        res
     );
+    assert_eq!(res.len(), 1);
+
+    // check that article 2 doesn't have any votes
+    let res = endq.lookup(&[a2.clone()], true).await.unwrap();
+    assert!(res.len() <= 1) // could be 1 if we had zero-rows
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn cascading_replays_with_sharding() {
-    let mut g = start_simple("cascading_replays_with_sharding").await;
-
-    // add each two bases. these are initially unsharded, but f will end up being sharded by u1,
-    // while v will be sharded by u
-
-    // force v to be in a different domain by adding it in a separate migration
-    let v = g
-        .migrate(|mig| mig.add_base("v", &["u", "s"], Base::new(vec!["".into(), 1.into()])))
-        .await;
-    // now add the rest
-    let _ = g
-        .migrate(move |mig| {
-            let f = mig.add_base("f", &["f1", "f2"], Base::new(vec!["".into(), "".into()]));
-            // add a join
-            let jb = Join::new(f, v, JoinType::Inner, vec![B(0, 0), R(1), L(1)]);
-            let j = mig.add_ingredient("j", &["u", "s", "f2"], jb);
-            // aggregate over the join. this will force a shard merger to be inserted because the
-            // group-by column ("f2") isn't the same as the join's output sharding column ("f1"/"u")
-            let a = Aggregation::COUNT.over(j, 0, &[2]);
-            let end = mig.add_ingredient("end", &["u", "c"], a);
-            mig.maintain_anonymous(end, &[0]);
-            (j, end)
-        })
-        .await;
-
-    let mut mutf = g.table("f").await.unwrap();
-    let mut mutv = g.table("v").await.unwrap();
-
-    //                f1           f2
-    mutf.insert(vec!["u1".into(), "u3".into()]).await.unwrap();
-    mutf.insert(vec!["u2".into(), "u3".into()]).await.unwrap();
-    mutf.insert(vec!["u3".into(), "u1".into()]).await.unwrap();
-
-    //                u
-    mutv.insert(vec!["u1".into(), 1.into()]).await.unwrap();
-    mutv.insert(vec!["u2".into(), 1.into()]).await.unwrap();
-    mutv.insert(vec!["u3".into(), 1.into()]).await.unwrap();
-
-    sleep().await;
-
-    let mut e = g.view("end").await.unwrap();
-
-    assert_eq!(
-        e.lookup(&["u1".into()], true).await.unwrap(),
-        vec![vec!["u1".into(), 1.into()]]
-    );
-    assert_eq!(
-        e.lookup(&["u2".into()], true).await.unwrap(),
-        Vec::<Vec<DataType>>::new()
-    );
-    assert_eq!(
-        e.lookup(&["u3".into()], true).await.unwrap(),
-        vec![vec!["u3".into(), 2.into()]]
-    );
-
-    sleep().await;
-}
-
-#[tokio::test(threaded_scheduler)]
-async fn full_aggregation_with_bogokey() {
+async fn empty_migration() {
     // set up graph
-    let mut g = start_simple("full_aggregation_with_bogokey").await;
-    let base = g
-        .migrate(|mig| mig.add_base("base", &["x"], Base::new(vec![1.into()])))
-        .await;
+    let mut g = start_simple("empty_migration").await;
+    g.migrate(|_| {}).await;
 
-    // add an aggregation over the base with a bogo key.
-    // in other words, the aggregation is across all rows.
     let _ = g
-        .migrate(move |mig| {
-            let bogo = mig.add_ingredient(
-                "bogo",
-                &["x", "bogo"],
-                Project::new(base, &[0], Some(vec![0.into()]), None),
-            );
-            let agg = mig.add_ingredient(
-                "agg",
-                &["bogo", "count"],
-                Aggregation::COUNT.over(bogo, 0, &[1]),
-            );
-            mig.maintain_anonymous(agg, &[0]);
-            agg
-        })
-        .await;
-
-    let mut aggq = g.view("agg").await.unwrap();
-    let mut base = g.table("base").await.unwrap();
-
-    // insert some values
-    base.insert(vec![1.into()]).await.unwrap();
-    base.insert(vec![2.into()]).await.unwrap();
-    base.insert(vec![3.into()]).await.unwrap();
-
-    // give it some time to propagate
-    sleep().await;
-
-    // send a query to aggregation materialization
-    assert_eq!(
-        aggq.lookup(&[0.into()], true).await.unwrap(),
-        vec![vec![0.into(), 3.into()]]
-    );
-
-    // update value again
-    base.insert(vec![4.into()]).await.unwrap();
-
-    // give it some time to propagate
-    sleep().await;
-
-    // check that value was updated again
-    assert_eq!(
-        aggq.lookup(&[0.into()], true).await.unwrap(),
-        vec![vec![0.into(), 4.into()]]
-    );
-}
-
-#[tokio::test(threaded_scheduler)]
-async fn materialization_frontier() {
-    // set up graph
-    let mut g = start_simple_unsharded("materialization_frontier").await;
-    g.migrate(|mig| {
-        // migrate
-
-        // add article base node
-        let article = mig.add_base("article", &["id", "title"], Base::default());
-
-        // add vote base table
-        let vote = mig.add_base(
-            "vote",
-            &["user", "id"],
-            Base::default().with_key(vec![0, 1]),
-        );
-
-        // add vote count
-        let vc = mig.add_ingredient(
-            "votecount",
-            &["id", "votes"],
-            Aggregation::COUNT.over(vote, 0, &[1]),
-        );
-        mig.mark_shallow(vc);
-
-        // add final join using first field from article and first from vc
-        let j = Join::new(article, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
-        let end = mig.add_ingredient("awvc", &["id", "title", "votes"], j);
-
-        let ri = mig.maintain_anonymous(end, &[0]);
-        mig.mark_shallow(ri);
-        (article, vote, vc, end)
-    })
-    .await;
-
-    let mut a = g.table("article").await.unwrap();
-    let mut v = g.table("vote").await.unwrap();
-    let mut r = g.view("awvc").await.unwrap();
-
-    // seed votes
-    v.insert(vec!["a".into(), 1.into()]).await.unwrap();
-    v.insert(vec!["a".into(), 2.into()]).await.unwrap();
-    v.insert(vec!["b".into(), 1.into()]).await.unwrap();
-    v.insert(vec!["c".into(), 2.into()]).await.unwrap();
-    v.insert(vec!["d".into(), 2.into()]).await.unwrap();
-
-    // seed articles
-    a.insert(vec![1.into(), "Hello world #1".into()])
-        .await
-        .unwrap();
-    a.insert(vec![2.into(), "Hello world #2".into()])
-        .await
-        .unwrap();
-    sleep().await;
-
-    // we want to alternately read article 1 and 2, knowing that reading one will purge the other.
-    // we first "warm up" by reading both to ensure all other necessary state is present.
-    let one = 1.into();
-    let two = 2.into();
-    assert_eq!(
-        r.lookup(&[one], true).await.unwrap(),
-        vec![vec![1.into(), "Hello world #1".into(), 2.into()]]
-    );
-    assert_eq!(
-        r.lookup(&[two], true).await.unwrap(),
-        vec![vec![2.into(), "Hello world #2".into(), 3.into()]]
-    );
-
-    for _ in 0..1_000 {
-        for &id in &[1, 2] {
-            let r = r.lookup(&[id.into()], true).await.unwrap();
-            match id {
-                1 => {
-                    assert_eq!(r, vec![vec![1.into(), "Hello world #1".into(), 2.into()]]);
-                }
-                2 => {
-                    assert_eq!(r, vec![vec![2.into(), "Hello world #2".into(), 3.into()]]);
-                }
-                _ => unreachable!(),
-            }
-        }
-    }
-}
-
-#[tokio::test(threaded_scheduler)]
-async fn crossing_migration() {
-    // set up graph
-    let mut g = start_simple("crossing_migration").await;
-    let (a, b) = g
         .migrate(|mig| {
             let a = mig.add_base("a", &["a", "b"], Base::default());
             let b = mig.add_base("b", &["a", "b"], Base::default());
-            (a, b)
-        })
-        .await;
-    let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
 
-    let _ = g
-        .migrate(move |mig| {
             let mut emits = HashMap::new();
             emits.insert(a, vec![0, 1]);
             emits.insert(b, vec![0, 1]);
             let u = Union::new(emits);
             let c = mig.add_ingredient("c", &["a", "b"], u);
             mig.maintain_anonymous(c, &[0]);
-            c
+            (a, b, c)
         })
         .await;
 
     let mut cq = g.view("c").await.unwrap();
-
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
     let id: DataType = 1.into();
 
     // send a value on a
     muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+
+    // give it some time to propagate
     sleep().await;
 
+    // send a query to c
     assert_eq!(
         cq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![id.clone(), 2.into()]]
+        vec![vec![1.into(), 2.into()]]
     );
 
     // update value again
     mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+
+    // give it some time to propagate
     sleep().await;
 
+    // check that value was updated again
     let res = cq.lookup(&[id.clone()], true).await.unwrap();
-    assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![id.clone(), 2.into()]));
-    assert!(res.contains(&vec![id.clone(), 4.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn independent_domain_migration() {
+async fn simple_migration() {
     let id: DataType = 1.into();
 
     // set up graph
-    let mut g = start_simple("independent_domain_migration").await;
+    let mut g = start_simple("simple_migration").await;
     let _ = g
         .migrate(|mig| {
             let a = mig.add_base("a", &["a", "b"], Base::default());
@@ -1971,7 +2064,7 @@ async fn independent_domain_migration() {
     // give it some time to propagate
     sleep().await;
 
-    // check that a got it
+    // check that b got it
     assert_eq!(
         bq.lookup(&[id.clone()], true).await.unwrap(),
         vec![vec![1.into(), 4.into()]]
@@ -1979,806 +2072,2338 @@ async fn independent_domain_migration() {
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn domain_amend_migration() {
+async fn add_columns() {
+    let id: DataType = "x".into();
+
     // set up graph
-    let mut g = start_simple("domain_amend_migration").await;
-    let (a, b) = g
+    let mut g = start_simple("add_columns").await;
+    let a = g
         .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::default());
-            let b = mig.add_base("b", &["a", "b"], Base::default());
-            (a, b)
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()]));
+            mig.maintain_anonymous(a, &[0]);
+            a
         })
         .await;
+    let mut aq = g.view("a").await.unwrap();
     let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
 
-    let _ = g
-        .migrate(move |mig| {
-            let mut emits = HashMap::new();
-            emits.insert(a, vec![0, 1]);
-            emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
-            let c = mig.add_ingredient("c", &["a", "b"], u);
-            mig.maintain_anonymous(c, &[0]);
-            c
+    // send a value on a
+    muta.insert(vec![id.clone(), "y".into()]).await.unwrap();
+    sleep().await;
+
+    // check that a got it
+    assert_eq!(
+        aq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![id.clone(), "y".into()]]
+    );
+
+    // add a third column to a
+    g.migrate(move |mig| {
+        mig.add_column(a, "c", 3.into());
+    })
+    .await;
+    sleep().await;
+
+    // send another (old) value on a
+    muta.insert(vec![id.clone(), "z".into()]).await.unwrap();
+    sleep().await;
+
+    // check that a got it, and added the new, third column's default
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![id.clone(), "y".into()]));
+    assert!(res.contains(&vec![id.clone(), "z".into(), 3.into()]));
+
+    // get a new muta and send a new value on it
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![id.clone(), "a".into(), 10.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // check that a got it, and included the third column
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 3);
+    assert!(res.contains(&vec![id.clone(), "y".into()]));
+    assert!(res.contains(&vec![id.clone(), "z".into(), 3.into()]));
+    assert!(res.contains(&vec![id.clone(), "a".into(), 10.into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn migrate_added_columns() {
+    let id: DataType = "x".into();
+
+    // set up graph
+    let mut g = start_simple("migrate_added_columns").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()])))
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+
+    // send a value on a
+    muta.insert(vec![id.clone(), "y".into()]).await.unwrap();
+    sleep().await;
+
+    // add a third column to a, and a view that uses it
+    let _ = g
+        .migrate(move |mig| {
+            mig.add_column(a, "c", 3.into());
+            let b = mig.add_ingredient("x", &["c", "b"], Project::new(a, &[2, 0], None, None));
+            mig.maintain_anonymous(b, &[1]);
+            b
         })
         .await;
-    let mut cq = g.view("c").await.unwrap();
 
-    let id: DataType = 1.into();
+    let mut bq = g.view("x").await.unwrap();
 
-    // send a value on a
-    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    // send another (old) value on a
+    muta.insert(vec![id.clone(), "z".into()]).await.unwrap();
+    // and an entirely new value
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![id.clone(), "a".into(), 10.into()])
+        .await
+        .unwrap();
+
+    // give it some time to propagate
     sleep().await;
 
+    // we should now see the pre-migration write and the old post-migration write with the default
+    // value, and the new post-migration write with the value it contained.
+    let res = bq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 3);
     assert_eq!(
-        cq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![id.clone(), 2.into()]]
+        res.iter()
+            .filter(|r| r == &vec![3.into(), id.clone()])
+            .count(),
+        2
     );
+    assert!(res.iter().any(|r| r == &vec![10.into(), id.clone()]));
+}
 
-    // update value again
-    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn migrate_set_column_default() {
+    let id: DataType = "x".into();
+
+    // set up graph
+    let mut g = start_simple("migrate_set_column_default").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()])))
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+
+    // add a third column to a, and a view that uses it
+    let _ = g
+        .migrate(move |mig| {
+            mig.add_column(a, "c", 3.into());
+            let b = mig.add_ingredient("x", &["c", "b"], Project::new(a, &[2, 0], None, None));
+            mig.maintain_anonymous(b, &[1]);
+            b
+        })
+        .await;
+    let mut bq = g.view("x").await.unwrap();
+
+    // writes from the old handle don't have the new column, and so get its default
+    muta.insert(vec![id.clone(), "y".into()]).await.unwrap();
     sleep().await;
 
-    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    g.migrate(move |mig| {
+        // only columns that have a default can have it changed
+        assert!(mig.set_column_default(a, 3, 5.into()).is_err());
+        mig.set_column_default(a, 2, 4.into()).unwrap();
+    })
+    .await;
+
+    // later writes get the new default, while the earlier row keeps the old one
+    muta.insert(vec![id.clone(), "z".into()]).await.unwrap();
+    sleep().await;
+
+    let res = bq.lookup(&[id.clone()], true).await.unwrap();
     assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![id.clone(), 2.into()]));
-    assert!(res.contains(&vec![id.clone(), 4.into()]));
+    assert!(res.contains(&vec![3.into(), id.clone()]));
+    assert!(res.contains(&vec![4.into(), id.clone()]));
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn migration_depends_on_unchanged_domain() {
-    // here's the case we want to test: before the migration, we have some domain that contains
-    // some materialized node n, as well as an egress node. after the migration, we add a domain
-    // that depends on n being materialized. the tricky part here is that n's domain hasn't changed
-    // as far as the system is aware (in particular, because it didn't need to add an egress node).
-    // this is tricky, because the system must realize that n is materialized, even though it
-    // normally wouldn't even look at that part of the data flow graph!
+async fn migrate_drop_columns() {
+    let id: DataType = "x".into();
 
-    let mut g = start_simple("migration_depends_on_unchanged_domain").await;
-    let left = g
+    // set up graph
+    let mut g = start_simple("migrate_drop_columns").await;
+    let a = g
         .migrate(|mig| {
-            // base node, so will be materialized
-            let left = mig.add_base("foo", &["a", "b"], Base::default());
-
-            // node in different domain that depends on foo causes egress to be added
-            mig.add_ingredient("bar", &["a", "b"], Identity::new(left));
-            left
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec!["a".into(), "b".into()]));
+            mig.maintain_anonymous(a, &[0]);
+            a
         })
         .await;
+    let mut aq = g.view("a").await.unwrap();
+    let mut muta1 = g.table("a").await.unwrap();
+
+    // send a value on a
+    muta1.insert(vec![id.clone(), "bx".into()]).await.unwrap();
+
+    // check that it's there
+    sleep().await;
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 1);
+    assert!(res.contains(&vec![id.clone(), "bx".into()]));
 
+    // drop a column
     g.migrate(move |mig| {
-        // joins require their inputs to be materialized
-        // we need a new base as well so we can actually make a join
-        let tmp = mig.add_base("tmp", &["a", "b"], Base::default());
-        let j = Join::new(
-            left,
-            tmp,
-            JoinType::Inner,
-            vec![JoinSource::B(0, 0), JoinSource::R(1)],
-        );
-        mig.add_ingredient("join", &["a", "b"], j);
+        mig.drop_column(a, 1);
+        mig.maintain_anonymous(a, &[0]);
     })
     .await;
-}
 
-async fn do_full_vote_migration(sharded: bool, old_puts_after: bool) {
-    let name = format!("do_full_vote_migration_{}", old_puts_after);
-    let mut g = if sharded {
-        start_simple(&name).await
-    } else {
-        start_simple_unsharded(&name).await
-    };
-    let (article, _vote, vc, _end) = g
-        .migrate(|mig| {
-            // migrate
+    // new mutator should only require one column
+    // and should inject default for a.b
+    let mut muta2 = g.table("a").await.unwrap();
+    muta2.insert(vec![id.clone()]).await.unwrap();
 
-            // add article base node
-            let article = mig.add_base("article", &["id", "title"], Base::default());
+    // so two rows now!
+    sleep().await;
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![id.clone(), "bx".into()]));
+    assert!(res.contains(&vec![id.clone(), "b".into()]));
 
-            // add vote base table
-            // NOTE: the double-column key here means that we can't shard vote
-            let vote = mig.add_base(
-                "vote",
-                &["user", "id"],
-                Base::default().with_key(vec![0, 1]),
-            );
+    // add a new column
+    g.migrate(move |mig| {
+        mig.add_column(a, "c", "c".into());
+    })
+    .await;
 
-            // add vote count
-            let vc = mig.add_ingredient(
-                "votecount",
-                &["id", "votes"],
-                Aggregation::COUNT.over(vote, 0, &[1]),
-            );
+    // new mutator allows putting two values, and injects default for a.b
+    let mut muta3 = g.table("a").await.unwrap();
+    muta3.insert(vec![id.clone(), "cy".into()]).await.unwrap();
 
-            // add final join using first field from article and first from vc
-            let j = Join::new(article, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
-            let end = mig.add_ingredient("awvc", &["id", "title", "votes"], j);
+    // using an old putter now should add default for c
+    muta1.insert(vec![id.clone(), "bz".into()]).await.unwrap();
 
-            mig.maintain_anonymous(end, &[0]);
-            (article, vote, vc, end)
+    // using putter that knows of neither b nor c should result in defaults for both
+    muta2.insert(vec![id.clone()]).await.unwrap();
+    sleep().await;
+
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 5);
+    // NOTE: if we *hadn't* read bx and b above, they would have also have c because it would have
+    // been added when the lookups caused partial backfills.
+    assert!(res.contains(&vec![id.clone(), "bx".into()]));
+    assert!(res.contains(&vec![id.clone(), "b".into()]));
+    assert!(res.contains(&vec![id.clone(), "b".into(), "cy".into()]));
+    assert!(res.contains(&vec![id.clone(), "bz".into(), "c".into()]));
+    assert!(res.contains(&vec![id.clone(), "b".into(), "c".into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn migrate_rename_column() {
+    let id: DataType = "x".into();
+
+    // set up graph
+    let mut g = start_simple("migrate_rename_column").await;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec!["a".into(), "b".into()]));
+            mig.maintain_anonymous(a, &[0]);
+            a
         })
         .await;
-    let mut muta = g.table("article").await.unwrap();
-    let mut mutv = g.table("vote").await.unwrap();
+    let mut aq = g.view("a").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    muta.insert(vec![id.clone(), "bx".into()]).await.unwrap();
+    sleep().await;
 
-    let n = 250i64;
-    let title: DataType = "foo".into();
-    let raten: DataType = 5.into();
+    // rename the second column
+    g.migrate(move |mig| {
+        mig.rename_column(a, 1, "c");
+    })
+    .await;
+
+    // the table schema should reflect the new name
+    let mut muta = g.table("a").await.unwrap();
+    assert_eq!(muta.columns(), &["a", "c"]);
+
+    // but existing values should be unaffected
+    muta.insert(vec![id.clone(), "cx".into()]).await.unwrap();
+    sleep().await;
+    let res = aq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![id.clone(), "bx".into()]));
+    assert!(res.contains(&vec![id.clone(), "cx".into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn compressed_full_replay() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(None);
+    b.enable_replay_compression();
+    b.set_persistence(get_persistence_params("compressed_full_replay"));
+    let mut g = b.start_local().await.unwrap().0;
 
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0])))
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    let n = 1000i32;
     for i in 0..n {
-        muta.insert(vec![i.into(), title.clone()]).await.unwrap();
+        muta.insert(vec![i.into(), format!("value {}", i).into()])
+            .await
+            .unwrap();
     }
-    for i in 0..n {
-        mutv.insert(vec![1.into(), i.into()]).await.unwrap();
+    sleep().await;
+
+    // a new, fully materialized view in another domain is backfilled with a compressed replay
+    g.migrate(move |mig| {
+        let b = mig.add_ingredient("b", &["b", "a"], Project::new(a, &[1, 0], None, None));
+        mig.maintain_anonymous(b, &[1]);
+    })
+    .await;
+    sleep().await;
+
+    let mut bq = g.view("b").await.unwrap();
+    for &i in &[0, n / 2, n - 1] {
+        assert_eq!(
+            bq.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![format!("value {}", i).into(), i.into()]]
+        );
     }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn key_on_added() {
+    // set up graph
+    let mut g = start_simple("key_on_added").await;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec![1.into(), 2.into()]));
+            a
+        })
+        .await;
+
+    // add a maintained view keyed on newly added column
+    let _ = g
+        .migrate(move |mig| {
+            mig.add_column(a, "c", 3.into());
+            let b = mig.add_ingredient("x", &["c", "b"], Project::new(a, &[2, 1], None, None));
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
+
+    // make sure we can read (may trigger a replay)
+    let mut bq = g.view("x").await.unwrap();
+    assert!(bq.lookup(&[3.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn replay_during_replay() {
+    // what we're trying to set up here is a case where a join receives a record with a value for
+    // the join key that does not exist in the view the record was sent from. since joins only do
+    // lookups into the origin view during forward processing when it receives things from the
+    // right in a left join, that's what we have to construct.
+    let mut g = Builder::default();
+    g.disable_partial();
+    g.set_persistence(get_persistence_params("replay_during_replay"));
+    let mut g = g.start_local().await.unwrap().0;
+    let (a, u1, u2) = g
+        .migrate(|mig| {
+            // we need three bases:
+            //
+            //  - a will be the left side of the left join
+            //  - u1 and u2 will be joined together with a regular one-to-one join to produce a partial
+            //    view (remember, we need to miss in the source of the replay, so it must be partial).
+            let a = mig.add_base("a", &["a"], Base::new(vec![1.into()]));
+            let u1 = mig.add_base("u1", &["u"], Base::new(vec![1.into()]));
+            let u2 = mig.add_base("u2", &["u", "a"], Base::new(vec![1.into(), 2.into()]));
+            (a, u1, u2)
+        })
+        .await;
+
+    // add our joins
+    let (u, _) = g
+        .migrate(move |mig| {
+            // u = u1 * u2
+            let j = Join::new(u1, u2, JoinType::Inner, vec![B(0, 0), R(1)]);
+            let u = mig.add_ingredient("u", &["u", "a"], j);
+            let j = Join::new(a, u, JoinType::Left, vec![B(0, 1), R(0)]);
+            let end = mig.add_ingredient("end", &["a", "u"], j);
+            mig.maintain_anonymous(end, &[0]);
+            (u, end)
+        })
+        .await;
+
+    // at this point, there's no secondary index on `u`, so any records that are forwarded from `u`
+    // must already be present in the one index that `u` has. let's do some writes and check that
+    // nothing crashes.
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutu1 = g.table("u1").await.unwrap();
+    let mut mutu2 = g.table("u2").await.unwrap();
+
+    // as are numbers
+    muta.insert(vec![1.into()]).await.unwrap();
+    muta.insert(vec![2.into()]).await.unwrap();
+    muta.insert(vec![3.into()]).await.unwrap();
+
+    // us are strings
+    mutu1.insert(vec!["a".into()]).await.unwrap();
+    mutu1.insert(vec!["b".into()]).await.unwrap();
+    mutu1.insert(vec!["c".into()]).await.unwrap();
+
+    // we want there to be data for all keys
+    mutu2.insert(vec!["a".into(), 1.into()]).await.unwrap();
+    mutu2.insert(vec!["b".into(), 2.into()]).await.unwrap();
+    mutu2.insert(vec!["c".into(), 3.into()]).await.unwrap();
+
+    sleep().await;
+
+    // since u and target are both partial, the writes should not actually have propagated through
+    // yet. do a read to see that one makes it through correctly:
+    let mut r = g.view("end").await.unwrap();
+
+    assert_eq!(
+        r.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a".into()]]
+    );
+
+    // we now know that u has key a=1 in its index
+    // now we add a secondary index on u.u
+    g.migrate(move |mig| {
+        mig.maintain_anonymous(u, &[0]);
+    })
+    .await;
+
+    let mut second = g.view("u").await.unwrap();
+
+    // second is partial and empty, so any read should trigger a replay.
+    // though that shouldn't interact with target in any way.
+    assert_eq!(
+        second.lookup(&["a".into()], true).await.unwrap(),
+        vec![vec!["a".into(), 1.into()]]
+    );
+
+    // now we get to the funky part.
+    // we're going to request a second key from the secondary index on `u`, which causes that hole
+    // to disappear. then we're going to do a write to `u2` that has that second key, but has an
+    // "a" value for which u has a hole. that record is then going to be forwarded to *both*
+    // children, and it'll be interesting to see what the join then does.
+    assert_eq!(
+        second.lookup(&["b".into()], true).await.unwrap(),
+        vec![vec!["b".into(), 2.into()]]
+    );
+
+    // u has a hole for a=2, but not for u=b, and so should forward this to both children
+    mutu2.insert(vec!["b".into(), 2.into()]).await.unwrap();
+
+    sleep().await;
+
+    // what happens if we now query for 2?
+    assert_eq!(
+        r.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), "b".into()], vec![2.into(), "b".into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn cascading_replays_with_sharding() {
+    let mut g = start_simple("cascading_replays_with_sharding").await;
+
+    // add each two bases. these are initially unsharded, but f will end up being sharded by u1,
+    // while v will be sharded by u
+
+    // force v to be in a different domain by adding it in a separate migration
+    let v = g
+        .migrate(|mig| mig.add_base("v", &["u", "s"], Base::new(vec!["".into(), 1.into()])))
+        .await;
+    // now add the rest
+    let _ = g
+        .migrate(move |mig| {
+            let f = mig.add_base("f", &["f1", "f2"], Base::new(vec!["".into(), "".into()]));
+            // add a join
+            let jb = Join::new(f, v, JoinType::Inner, vec![B(0, 0), R(1), L(1)]);
+            let j = mig.add_ingredient("j", &["u", "s", "f2"], jb);
+            // aggregate over the join. this will force a shard merger to be inserted because the
+            // group-by column ("f2") isn't the same as the join's output sharding column ("f1"/"u")
+            let a = Aggregation::COUNT.over(j, 0, &[2]);
+            let end = mig.add_ingredient("end", &["u", "c"], a);
+            mig.maintain_anonymous(end, &[0]);
+            (j, end)
+        })
+        .await;
+
+    let mut mutf = g.table("f").await.unwrap();
+    let mut mutv = g.table("v").await.unwrap();
+
+    //                f1           f2
+    mutf.insert(vec!["u1".into(), "u3".into()]).await.unwrap();
+    mutf.insert(vec!["u2".into(), "u3".into()]).await.unwrap();
+    mutf.insert(vec!["u3".into(), "u1".into()]).await.unwrap();
+
+    //                u
+    mutv.insert(vec!["u1".into(), 1.into()]).await.unwrap();
+    mutv.insert(vec!["u2".into(), 1.into()]).await.unwrap();
+    mutv.insert(vec!["u3".into(), 1.into()]).await.unwrap();
+
+    sleep().await;
+
+    let mut e = g.view("end").await.unwrap();
+
+    assert_eq!(
+        e.lookup(&["u1".into()], true).await.unwrap(),
+        vec![vec!["u1".into(), 1.into()]]
+    );
+    assert_eq!(
+        e.lookup(&["u2".into()], true).await.unwrap(),
+        Vec::<Vec<DataType>>::new()
+    );
+    assert_eq!(
+        e.lookup(&["u3".into()], true).await.unwrap(),
+        vec![vec!["u3".into(), 2.into()]]
+    );
+
+    sleep().await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn full_aggregation_with_bogokey() {
+    // set up graph
+    let mut g = start_simple("full_aggregation_with_bogokey").await;
+    let base = g
+        .migrate(|mig| mig.add_base("base", &["x"], Base::new(vec![1.into()])))
+        .await;
+
+    // add an aggregation over the base with a bogo key.
+    // in other words, the aggregation is across all rows.
+    let _ = g
+        .migrate(move |mig| {
+            let bogo = mig.add_ingredient(
+                "bogo",
+                &["x", "bogo"],
+                Project::new(base, &[0], Some(vec![0.into()]), None),
+            );
+            let agg = mig.add_ingredient(
+                "agg",
+                &["bogo", "count"],
+                Aggregation::COUNT.over(bogo, 0, &[1]),
+            );
+            mig.maintain_anonymous(agg, &[0]);
+            agg
+        })
+        .await;
+
+    let mut aggq = g.view("agg").await.unwrap();
+    let mut base = g.table("base").await.unwrap();
+
+    // insert some values
+    base.insert(vec![1.into()]).await.unwrap();
+    base.insert(vec![2.into()]).await.unwrap();
+    base.insert(vec![3.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // send a query to aggregation materialization
+    assert_eq!(
+        aggq.lookup(&[0.into()], true).await.unwrap(),
+        vec![vec![0.into(), 3.into()]]
+    );
+
+    // update value again
+    base.insert(vec![4.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // check that value was updated again
+    assert_eq!(
+        aggq.lookup(&[0.into()], true).await.unwrap(),
+        vec![vec![0.into(), 4.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn materialization_frontier() {
+    // set up graph
+    let mut g = start_simple_unsharded("materialization_frontier").await;
+    g.migrate(|mig| {
+        // migrate
+
+        // add article base node
+        let article = mig.add_base("article", &["id", "title"], Base::default());
+
+        // add vote base table
+        let vote = mig.add_base(
+            "vote",
+            &["user", "id"],
+            Base::default().with_key(vec![0, 1]),
+        );
+
+        // add vote count
+        let vc = mig.add_ingredient(
+            "votecount",
+            &["id", "votes"],
+            Aggregation::COUNT.over(vote, 0, &[1]),
+        );
+        mig.mark_shallow(vc);
+
+        // add final join using first field from article and first from vc
+        let j = Join::new(article, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
+        let end = mig.add_ingredient("awvc", &["id", "title", "votes"], j);
+
+        let ri = mig.maintain_anonymous(end, &[0]);
+        mig.mark_shallow(ri);
+        (article, vote, vc, end)
+    })
+    .await;
+
+    let mut a = g.table("article").await.unwrap();
+    let mut v = g.table("vote").await.unwrap();
+    let mut r = g.view("awvc").await.unwrap();
+
+    // seed votes
+    v.insert(vec!["a".into(), 1.into()]).await.unwrap();
+    v.insert(vec!["a".into(), 2.into()]).await.unwrap();
+    v.insert(vec!["b".into(), 1.into()]).await.unwrap();
+    v.insert(vec!["c".into(), 2.into()]).await.unwrap();
+    v.insert(vec!["d".into(), 2.into()]).await.unwrap();
+
+    // seed articles
+    a.insert(vec![1.into(), "Hello world #1".into()])
+        .await
+        .unwrap();
+    a.insert(vec![2.into(), "Hello world #2".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // we want to alternately read article 1 and 2, knowing that reading one will purge the other.
+    // we first "warm up" by reading both to ensure all other necessary state is present.
+    let one = 1.into();
+    let two = 2.into();
+    assert_eq!(
+        r.lookup(&[one], true).await.unwrap(),
+        vec![vec![1.into(), "Hello world #1".into(), 2.into()]]
+    );
+    assert_eq!(
+        r.lookup(&[two], true).await.unwrap(),
+        vec![vec![2.into(), "Hello world #2".into(), 3.into()]]
+    );
+
+    for _ in 0..1_000 {
+        for &id in &[1, 2] {
+            let r = r.lookup(&[id.into()], true).await.unwrap();
+            match id {
+                1 => {
+                    assert_eq!(r, vec![vec![1.into(), "Hello world #1".into(), 2.into()]]);
+                }
+                2 => {
+                    assert_eq!(r, vec![vec![2.into(), "Hello world #2".into(), 3.into()]]);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn crossing_migration() {
+    // set up graph
+    let mut g = start_simple("crossing_migration").await;
+    let (a, b) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            (a, b)
+        })
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    let _ = g
+        .migrate(move |mig| {
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["a", "b"], u);
+            mig.maintain_anonymous(c, &[0]);
+            c
+        })
+        .await;
+
+    let mut cq = g.view("c").await.unwrap();
+
+    let id: DataType = 1.into();
+
+    // send a value on a
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    sleep().await;
+
+    assert_eq!(
+        cq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![id.clone(), 2.into()]]
+    );
+
+    // update value again
+    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+    sleep().await;
+
+    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![id.clone(), 2.into()]));
+    assert!(res.contains(&vec![id.clone(), 4.into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn independent_domain_migration() {
+    let id: DataType = 1.into();
+
+    // set up graph
+    let mut g = start_simple("independent_domain_migration").await;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
+
+    let mut aq = g.view("a").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+
+    // send a value on a
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // check that a got it
+    assert_eq!(
+        aq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    // add unrelated node b in a migration
+    let _ = g
+        .migrate(|mig| {
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
+
+    let mut bq = g.view("b").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    // send a value on b
+    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // check that a got it
+    assert_eq!(
+        bq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![1.into(), 4.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn domain_amend_migration() {
+    // set up graph
+    let mut g = start_simple("domain_amend_migration").await;
+    let (a, b) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            (a, b)
+        })
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    let _ = g
+        .migrate(move |mig| {
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["a", "b"], u);
+            mig.maintain_anonymous(c, &[0]);
+            c
+        })
+        .await;
+    let mut cq = g.view("c").await.unwrap();
+
+    let id: DataType = 1.into();
+
+    // send a value on a
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    sleep().await;
+
+    assert_eq!(
+        cq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![id.clone(), 2.into()]]
+    );
+
+    // update value again
+    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+    sleep().await;
+
+    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![id.clone(), 2.into()]));
+    assert!(res.contains(&vec![id.clone(), 4.into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn migration_depends_on_unchanged_domain() {
+    // here's the case we want to test: before the migration, we have some domain that contains
+    // some materialized node n, as well as an egress node. after the migration, we add a domain
+    // that depends on n being materialized. the tricky part here is that n's domain hasn't changed
+    // as far as the system is aware (in particular, because it didn't need to add an egress node).
+    // this is tricky, because the system must realize that n is materialized, even though it
+    // normally wouldn't even look at that part of the data flow graph!
+
+    let mut g = start_simple("migration_depends_on_unchanged_domain").await;
+    let left = g
+        .migrate(|mig| {
+            // base node, so will be materialized
+            let left = mig.add_base("foo", &["a", "b"], Base::default());
+
+            // node in different domain that depends on foo causes egress to be added
+            mig.add_ingredient("bar", &["a", "b"], Identity::new(left));
+            left
+        })
+        .await;
+
+    g.migrate(move |mig| {
+        // joins require their inputs to be materialized
+        // we need a new base as well so we can actually make a join
+        let tmp = mig.add_base("tmp", &["a", "b"], Base::default());
+        let j = Join::new(
+            left,
+            tmp,
+            JoinType::Inner,
+            vec![JoinSource::B(0, 0), JoinSource::R(1)],
+        );
+        mig.add_ingredient("join", &["a", "b"], j);
+    })
+    .await;
+}
+
+async fn do_full_vote_migration(sharded: bool, old_puts_after: bool) {
+    let name = format!("do_full_vote_migration_{}", old_puts_after);
+    let mut g = if sharded {
+        start_simple(&name).await
+    } else {
+        start_simple_unsharded(&name).await
+    };
+    let (article, _vote, vc, _end) = g
+        .migrate(|mig| {
+            // migrate
+
+            // add article base node
+            let article = mig.add_base("article", &["id", "title"], Base::default());
+
+            // add vote base table
+            // NOTE: the double-column key here means that we can't shard vote
+            let vote = mig.add_base(
+                "vote",
+                &["user", "id"],
+                Base::default().with_key(vec![0, 1]),
+            );
+
+            // add vote count
+            let vc = mig.add_ingredient(
+                "votecount",
+                &["id", "votes"],
+                Aggregation::COUNT.over(vote, 0, &[1]),
+            );
+
+            // add final join using first field from article and first from vc
+            let j = Join::new(article, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
+            let end = mig.add_ingredient("awvc", &["id", "title", "votes"], j);
+
+            mig.maintain_anonymous(end, &[0]);
+            (article, vote, vc, end)
+        })
+        .await;
+    let mut muta = g.table("article").await.unwrap();
+    let mut mutv = g.table("vote").await.unwrap();
+
+    let n = 250i64;
+    let title: DataType = "foo".into();
+    let raten: DataType = 5.into();
+
+    for i in 0..n {
+        muta.insert(vec![i.into(), title.clone()]).await.unwrap();
+    }
+    for i in 0..n {
+        mutv.insert(vec![1.into(), i.into()]).await.unwrap();
+    }
+
+    let mut last = g.view("awvc").await.unwrap();
+    thread::sleep(get_settle_time().checked_mul(3).unwrap());
+    for i in 0..n {
+        let rows = last.lookup(&[i.into()], true).await.unwrap();
+        assert!(!rows.is_empty(), "every article should be voted for");
+        assert_eq!(rows.len(), 1, "every article should have only one entry");
+        let row = rows.into_iter().next().unwrap();
+        assert_eq!(
+            row[0],
+            i.into(),
+            "each article result should have the right id"
+        );
+        assert_eq!(row[1], title, "all articles should have title 'foo'");
+        assert_eq!(row[2], 1.into(), "all articles should have one vote");
+    }
+
+    // migrate
+    let _ = g
+        .migrate(move |mig| {
+            // add new "ratings" base table
+            let rating = mig.add_base("rating", &["user", "id", "stars"], Base::default());
+
+            // add sum of ratings
+            let rs = mig.add_ingredient(
+                "rsum",
+                &["id", "total"],
+                Aggregation::SUM.over(rating, 2, &[1]),
+            );
+
+            // join vote count and rsum (and in theory, sum them)
+            let j = Join::new(rs, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
+            let total = mig.add_ingredient("total", &["id", "ratings", "votes"], j);
+
+            // finally, produce end result
+            let j = Join::new(
+                article,
+                total,
+                JoinType::Inner,
+                vec![B(0, 0), L(1), R(1), R(2)],
+            );
+            let newend = mig.add_ingredient("awr", &["id", "title", "ratings", "votes"], j);
+            mig.maintain_anonymous(newend, &[0]);
+            (rating, newend)
+        })
+        .await;
+
+    let mut last = g.view("awr").await.unwrap();
+    let mut mutr = g.table("rating").await.unwrap();
+    for i in 0..n {
+        if old_puts_after {
+            mutv.insert(vec![2.into(), i.into()]).await.unwrap();
+        }
+        mutr.insert(vec![2.into(), i.into(), raten.clone()])
+            .await
+            .unwrap();
+    }
+
+    thread::sleep(get_settle_time().checked_mul(3).unwrap());
+    for i in 0..n {
+        let rows = last.lookup(&[i.into()], true).await.unwrap();
+        assert!(!rows.is_empty(), "every article should be voted for");
+        assert_eq!(rows.len(), 1, "every article should have only one entry");
+        let row = rows.into_iter().next().unwrap();
+        assert_eq!(
+            row[0],
+            i.into(),
+            "each article result should have the right id"
+        );
+        assert_eq!(row[1], title, "all articles should have title 'foo'");
+        assert_eq!(row[2], raten, "all articles should have one 5-star rating");
+        if old_puts_after {
+            assert_eq!(row[3], 2.into(), "all articles should have two votes");
+        } else {
+            assert_eq!(row[3], 1.into(), "all articles should have one vote");
+        }
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn full_vote_migration_only_new() {
+    do_full_vote_migration(true, false).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn full_vote_migration_new_and_old() {
+    do_full_vote_migration(true, true).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn full_vote_migration_new_and_old_unsharded() {
+    do_full_vote_migration(false, true).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn live_writes() {
+    let mut g = start_simple("live_writes").await;
+    let (_vote, vc) = g
+        .migrate(|mig| {
+            // migrate
+
+            // add vote base table
+            let vote = mig.add_base("vote", &["user", "id"], Base::default());
+
+            // add vote count
+            let vc = mig.add_ingredient(
+                "votecount",
+                &["id", "votes"],
+                Aggregation::COUNT.over(vote, 0, &[1]),
+            );
+
+            mig.maintain_anonymous(vc, &[0]);
+            (vote, vc)
+        })
+        .await;
+
+    let mut vc_state = g.view("votecount").await.unwrap();
+    let mut add = g.table("vote").await.unwrap();
+
+    let ids = 1000;
+    let votes = 7;
+
+    // continuously write to vote
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        // we need to use a batch putter because otherwise we'd wait for 7000 batch intervals
+        let fut =
+            add.perform_all((0..votes).flat_map(|_| (0..ids).map(|i| vec![0.into(), i.into()])));
+        tx.send(fut.await.unwrap()).unwrap();
+    });
+
+    // let a few writes through to make migration take a while
+    sleep().await;
+
+    // now do a migration that's going to have to copy state
+    let _ = g
+        .migrate(move |mig| {
+            let vc2 = mig.add_ingredient(
+                "votecount2",
+                &["id", "votes"],
+                Aggregation::SUM.over(vc, 1, &[0]),
+            );
+            mig.maintain_anonymous(vc2, &[0]);
+            vc2
+        })
+        .await;
+
+    let mut vc2_state = g.view("votecount2").await.unwrap();
+
+    // TODO: check that the writer did indeed complete writes during the migration
+
+    // wait for writer to finish
+    rx.await.unwrap();
+
+    // allow the system to catch up with the last writes
+    sleep().await;
+
+    // check that all writes happened the right number of times
+    for i in 0..ids {
+        assert_eq!(
+            vc_state.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![i.into(), votes.into()]]
+        );
+        assert_eq!(
+            vc2_state.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![i.into(), votes.into()]]
+        );
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn state_replay_migration_query() {
+    // similar to test above, except we will have a materialized Reader node that we're going to
+    // read from rather than relying on forwarding. to further stress the graph, *both* base nodes
+    // are created and populated before the migration, meaning we have to replay through a join.
+
+    let mut g = start_simple("state_replay_migration_query").await;
+    let (a, b) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["x", "y"], Base::default());
+            let b = mig.add_base("b", &["x", "z"], Base::default());
+
+            (a, b)
+        })
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    // make a couple of records
+    muta.insert(vec![1.into(), "a".into()]).await.unwrap();
+    muta.insert(vec![1.into(), "b".into()]).await.unwrap();
+    muta.insert(vec![2.into(), "c".into()]).await.unwrap();
+    mutb.insert(vec![1.into(), "n".into()]).await.unwrap();
+    mutb.insert(vec![2.into(), "o".into()]).await.unwrap();
+
+    let _ = g
+        .migrate(move |mig| {
+            // add join and a reader node
+            let j = Join::new(a, b, JoinType::Inner, vec![B(0, 0), L(1), R(1)]);
+            let j = mig.add_ingredient("j", &["x", "y", "z"], j);
+
+            // we want to observe what comes out of the join
+            mig.maintain_anonymous(j, &[0]);
+            j
+        })
+        .await;
+    let mut out = g.view("j").await.unwrap();
+    sleep().await;
+
+    // if all went according to plan, the join should now be fully populated!
+    // there are (/should be) two records in a with x == 1
+    // they may appear in any order
+    let res = out.lookup(&[1.into()], true).await.unwrap();
+    assert!(res
+        .iter()
+        .any(|r| r == &vec![1.into(), "a".into(), "n".into()]));
+    assert!(res
+        .iter()
+        .any(|r| r == &vec![1.into(), "b".into(), "n".into()]));
+
+    // there are (/should be) one record in a with x == 2
+    assert_eq!(
+        out.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), "c".into(), "o".into()]]
+    );
+
+    // there are (/should be) no records with x == 3
+    assert!(out.lookup(&[3.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn recipe_activates() {
+    let mut g = start_simple("recipe_activates").await;
+    g.migrate(|mig| {
+        let r_txt = "CREATE TABLE b (a text, c text, x text);\n";
+        let mut r = Recipe::from_str(r_txt, None).unwrap();
+        assert_eq!(r.version(), 0);
+        assert_eq!(r.expressions().len(), 1);
+        assert_eq!(r.prior(), None);
+        assert!(r.activate(mig).is_ok());
+    })
+    .await;
+    // one base node
+    assert_eq!(g.inputs().await.unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn recipe_activates_and_migrates() {
+    let r_txt = "CREATE TABLE b (a text, c text, x text);\n";
+    let r1_txt = "QUERY qa: SELECT a FROM b;\n
+                  QUERY qb: SELECT a, c FROM b WHERE a = 42;";
+
+    let mut g = start_simple("recipe_activates_and_migrates").await;
+    g.install_recipe(r_txt).await.unwrap();
+    // one base node
+    assert_eq!(g.inputs().await.unwrap().len(), 1);
+
+    g.extend_recipe(r1_txt).await.unwrap();
+    // still one base node
+    assert_eq!(g.inputs().await.unwrap().len(), 1);
+    // two leaf nodes
+    assert_eq!(g.outputs().await.unwrap().len(), 2);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn recipe_activates_and_migrates_with_join() {
+    let r_txt = "CREATE TABLE a (x int, y int, z int);\n
+                 CREATE TABLE b (r int, s int);\n";
+    let r1_txt = "QUERY q: SELECT y, s FROM a, b WHERE a.x = b.r;";
+
+    let mut g = start_simple("recipe_activates_and_migrates_with_join").await;
+    g.install_recipe(r_txt).await.unwrap();
+
+    // two base nodes
+    assert_eq!(g.inputs().await.unwrap().len(), 2);
+
+    g.extend_recipe(r1_txt).await.unwrap();
+
+    // still two base nodes
+    assert_eq!(g.inputs().await.unwrap().len(), 2);
+    // one leaf node
+    assert_eq!(g.outputs().await.unwrap().len(), 1);
+}
+
+async fn test_queries(test: &str, file: &'static str, shard: bool, reuse: bool, log: bool) {
+    use crate::logger_pls;
+    use std::fs::File;
+    use std::io::Read;
+
+    let logger = if log { Some(logger_pls()) } else { None };
+
+    // set up graph
+    let mut g = if shard {
+        start_simple(test).await
+    } else {
+        start_simple_unsharded(test).await
+    };
+
+    // move needed for some funny lifetime reason
+    g.migrate(move |mig| {
+        let mut r = Recipe::blank(logger);
+        if !reuse {
+            r.disable_reuse();
+        }
+        let mut f = File::open(&file).unwrap();
+        let mut s = String::new();
+
+        // Load queries
+        f.read_to_string(&mut s).unwrap();
+        let lines: Vec<String> = s
+            .lines()
+            .filter(|l| {
+                !l.is_empty()
+                    && !l.starts_with("--")
+                    && !l.starts_with('#')
+                    && !l.starts_with("DROP TABLE")
+            })
+            .map(|l| {
+                if !(l.ends_with('\n') || l.ends_with(';')) {
+                    String::from(l) + "\n"
+                } else {
+                    String::from(l)
+                }
+            })
+            .collect();
+
+        // Add them one by one
+        for (_i, q) in lines.iter().enumerate() {
+            //println!("{}: {}", i, q);
+            r = match r.extend(q) {
+                Ok(mut nr) => {
+                    assert!(nr.activate(mig).is_ok());
+                    nr
+                }
+                Err(e) => {
+                    panic!("{:?}", e);
+                }
+            }
+        }
+    })
+    .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn finkelstein1982_queries() {
+    use std::fs::File;
+    use std::io::Read;
+
+    // set up graph
+    let mut g = start_simple("finkelstein1982_queries").await;
+    g.migrate(|mig| {
+        let mut inc = SqlIncorporator::default();
+        let mut f = File::open("tests/finkelstein82.txt").unwrap();
+        let mut s = String::new();
+
+        // Load queries
+        f.read_to_string(&mut s).unwrap();
+        let lines: Vec<String> = s
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| {
+                if !(l.ends_with('\n') || l.ends_with(';')) {
+                    String::from(l) + "\n"
+                } else {
+                    String::from(l)
+                }
+            })
+            .collect();
+
+        // Add them one by one
+        for q in lines.iter() {
+            assert!(inc.add_query(q, None, mig).is_ok());
+        }
+    })
+    .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn tpc_w() {
+    test_queries("tpc-w", "tests/tpc-w-queries.txt", true, true, false).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn lobsters() {
+    test_queries("lobsters", "tests/lobsters-schema.txt", false, false, false).await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn soupy_lobsters() {
+    test_queries(
+        "soupy_lobsters",
+        "tests/soupy-lobsters-schema.txt",
+        false,
+        false,
+        false,
+    )
+    .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn mergeable_lobsters() {
+    test_queries(
+        "mergeable_lobsters",
+        "tests/mergeable-lobsters-schema.sql",
+        false,
+        false,
+        false,
+    )
+    .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn filter_aggregate_lobsters() {
+    test_queries(
+        "filter_aggregate_lobsters",
+        "tests/filter-aggregate-lobsters-schema.sql",
+        false,
+        false,
+        false,
+    )
+    .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn node_removal() {
+    let mut g = start_simple("domain_removal").await;
+    let cid = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+            let b = mig.add_base("b", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["a", "b"], u);
+            mig.maintain_anonymous(c, &[0])
+        })
+        .await;
+
+    let mut cq = g.view("c").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+    let id: DataType = 1.into();
+
+    assert_eq!(muta.table_name(), "a");
+    assert_eq!(muta.columns(), &["a", "b"]);
+
+    // send a value on a
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // send a query to c
+    assert_eq!(
+        cq.lookup(&[id.clone()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    let _ = g.remove_node(cid).await.unwrap();
+
+    // update value again
+    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // // check that value was updated again
+    // let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    // assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    // assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+
+    // Delete first record
+    muta.delete(vec![id.clone()]).await.unwrap();
+
+    // give it some time to propagate
+    sleep().await;
+
+    // // send a query to c
+    // assert_eq!(
+    //     cq.lookup(&[id.clone()], true).await,
+    //     Ok(vec![vec![1.into(), 4.into()]])
+    // );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn remove_query() {
+    let r_txt = "CREATE TABLE b (a int, c text, x text);\n
+                 QUERY qa: SELECT a FROM b;\n
+                 QUERY qb: SELECT a, c FROM b WHERE a = 42;";
+
+    let r2_txt = "CREATE TABLE b (a int, c text, x text);\n
+                  QUERY qa: SELECT a FROM b;";
+
+    let mut g = start_simple("remove_query").await;
+    g.install_recipe(r_txt).await.unwrap();
+    assert_eq!(g.inputs().await.unwrap().len(), 1);
+    assert_eq!(g.outputs().await.unwrap().len(), 2);
+
+    let mut mutb = g.table("b").await.unwrap();
+    let mut qa = g.view("qa").await.unwrap();
+    let mut qb = g.view("qb").await.unwrap();
+
+    mutb.insert(vec![42.into(), "2".into(), "3".into()])
+        .await
+        .unwrap();
+    mutb.insert(vec![1.into(), "4".into(), "5".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    assert_eq!(qa.lookup(&[0.into()], true).await.unwrap().len(), 2);
+    assert_eq!(qb.lookup(&[0.into()], true).await.unwrap().len(), 1);
+
+    // Remove qb and check that the graph still functions as expected.
+    g.install_recipe(r2_txt).await.unwrap();
+    assert_eq!(g.inputs().await.unwrap().len(), 1);
+    assert_eq!(g.outputs().await.unwrap().len(), 1);
+    assert!(g.view("qb").await.is_err());
+
+    mutb.insert(vec![42.into(), "6".into(), "7".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    match qb.lookup(&[0.into()], true).await.unwrap_err() {
+        noria::error::ViewError::NotYetAvailable => {}
+        e => unreachable!("{:?}", e),
+    }
+}
+
+macro_rules! get {
+    ($private:ident, $public:ident, $uid:expr, $aid:expr) => {{
+        // combine private and public results
+        // also, there's currently a bug where MIR doesn't guarantee the order of parameters, so we try both O:)
+        let v: Vec<_> = $private
+            .lookup(&[$uid.into(), $aid.into()], true)
+            .await
+            .unwrap()
+            .into_iter()
+            .chain(
+                $private
+                    .lookup(&[$aid.into(), $uid.into()], true)
+                    .await
+                    .unwrap(),
+            )
+            .chain($public.lookup(&[$aid.into()], true).await.unwrap())
+            .collect();
+        eprintln!("check {} as {}: {:?}", $aid, $uid, v);
+        v
+    }}
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn albums() {
+    let mut g = start_simple_unsharded("albums").await;
+    g.install_recipe(
+        "CREATE TABLE friend (usera int, userb int);
+                 CREATE TABLE album (a_id text, u_id int, public tinyint(1));
+                 CREATE TABLE photo (p_id text, album text);",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe("VIEW album_friends: \
+                   (SELECT album.a_id AS aid, friend.userb AS uid FROM album JOIN friend ON (album.u_id = friend.usera) WHERE album.public = 0) \
+                   UNION \
+                   (SELECT album.a_id AS aid, friend.usera AS uid FROM album JOIN friend ON (album.u_id = friend.userb) WHERE album.public = 0) \
+                   UNION \
+                   (SELECT album.a_id AS aid, album.u_id AS uid FROM album WHERE album.public = 0);
+QUERY private_photos: \
+SELECT photo.p_id FROM photo JOIN album_friends ON (photo.album = album_friends.aid) WHERE album_friends.uid = ? AND photo.album = ?;
+QUERY public_photos: \
+SELECT photo.p_id FROM photo JOIN album ON (photo.album = album.a_id) WHERE album.public = 1 AND album.a_id = ?;").await.unwrap();
+
+    let mut friends = g.table("friend").await.unwrap();
+    let mut albums = g.table("album").await.unwrap();
+    let mut photos = g.table("photo").await.unwrap();
+
+    // four users: 1, 2, 3, and 4
+    // 1 and 2 are friends, 3 is a friend of 1 but not 2
+    // 4 isn't friends with anyone
+    //
+    // four albums: x, y, z, and q; one authored by each user
+    // z is public.
+    //
+    // there's one photo in each album
+    //
+    // what should each user be able to see?
+    //
+    //  - 1 should be able to see albums x, y, and z
+    //  - 2 should be able to see albums x, y, and z
+    //  - 3 should be able to see albums x and z
+    //  - 4 should be able to see albums z and q
+    friends
+        .perform_all(vec![vec![1.into(), 2.into()], vec![3.into(), 1.into()]])
+        .await
+        .unwrap();
+    albums
+        .perform_all(vec![
+            vec!["x".into(), 1.into(), 0.into()],
+            vec!["y".into(), 2.into(), 0.into()],
+            vec!["z".into(), 3.into(), 1.into()],
+            vec!["q".into(), 4.into(), 0.into()],
+        ])
+        .await
+        .unwrap();
+    photos
+        .perform_all(vec![
+            vec!["a".into(), "x".into()],
+            vec!["b".into(), "y".into()],
+            vec!["c".into(), "z".into()],
+            vec!["d".into(), "q".into()],
+        ])
+        .await
+        .unwrap();
+
+    let mut private = g.view("private_photos").await.unwrap();
+    let mut public = g.view("public_photos").await.unwrap();
+
+    sleep().await;
+
+    assert_eq!(get!(private, public, 1, "x").len(), 1);
+    assert_eq!(get!(private, public, 1, "y").len(), 1);
+    assert_eq!(get!(private, public, 1, "z").len(), 1);
+    assert_eq!(get!(private, public, 1, "q").len(), 0);
+
+    assert_eq!(get!(private, public, 2, "x").len(), 1);
+    assert_eq!(get!(private, public, 2, "y").len(), 1);
+    assert_eq!(get!(private, public, 2, "z").len(), 1);
+    assert_eq!(get!(private, public, 2, "q").len(), 0);
+
+    assert_eq!(get!(private, public, 3, "x").len(), 1);
+    assert_eq!(get!(private, public, 3, "y").len(), 0);
+    assert_eq!(get!(private, public, 3, "z").len(), 1);
+    assert_eq!(get!(private, public, 3, "q").len(), 0);
+
+    assert_eq!(get!(private, public, 4, "x").len(), 0);
+    assert_eq!(get!(private, public, 4, "y").len(), 0);
+    assert_eq!(get!(private, public, 4, "z").len(), 1);
+    assert_eq!(get!(private, public, 4, "q").len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn correct_nested_view_schema() {
+    use nom_sql::{ColumnSpecification, SqlType};
+
+    let r_txt = "CREATE TABLE votes (story int, user int);
+                 CREATE TABLE stories (id int, content text);
+                 VIEW swvc: SELECT stories.id, stories.content, COUNT(votes.user) AS vc \
+                     FROM stories \
+                     JOIN votes ON (stories.id = votes.story) \
+                     WHERE stories.id = ? GROUP BY votes.story;";
+
+    let mut b = Builder::default();
+    // need to disable partial due to lack of support for key subsumption (#99)
+    b.disable_partial();
+    b.set_sharding(None);
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(r_txt).await.unwrap();
+
+    let q = g.view("swvc").await.unwrap();
+
+    let expected_schema = vec![
+        ColumnSpecification::new("swvc.id".into(), SqlType::Int(32)),
+        ColumnSpecification::new("swvc.content".into(), SqlType::Text),
+        ColumnSpecification::new("swvc.vc".into(), SqlType::Bigint(64)),
+    ];
+    assert_eq!(q.schema(), Some(&expected_schema[..]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn statistics_report_memory() {
+    let mut g = start_simple_unsharded("statistics_report_memory").await;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
 
-    let mut last = g.view("awvc").await.unwrap();
-    thread::sleep(get_settle_time().checked_mul(3).unwrap());
-    for i in 0..n {
-        let rows = last.lookup(&[i.into()], true).await.unwrap();
-        assert!(!rows.is_empty(), "every article should be voted for");
-        assert_eq!(rows.len(), 1, "every article should have only one entry");
-        let row = rows.into_iter().next().unwrap();
-        assert_eq!(
-            row[0],
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..10i32 {
+        muta.insert(vec![
             i.into(),
-            "each article result should have the right id"
-        );
-        assert_eq!(row[1], title, "all articles should have title 'foo'");
-        assert_eq!(row[2], 1.into(), "all articles should have one vote");
+            "a string that is long enough to spill".into(),
+        ])
+        .await
+        .unwrap();
     }
+    sleep().await;
 
-    // migrate
-    let _ = g
-        .migrate(move |mig| {
-            // add new "ratings" base table
-            let rating = mig.add_base("rating", &["user", "id", "stars"], Base::default());
-
-            // add sum of ratings
-            let rs = mig.add_ingredient(
-                "rsum",
-                &["id", "total"],
-                Aggregation::SUM.over(rating, 2, &[1]),
-            );
+    let stats = g.statistics().await.unwrap();
+    let mut total = 0;
+    for (domain_stats, node_stats) in stats.values() {
+        let sum: u64 = node_stats.values().map(|ns| ns.mem_size).sum();
+        assert_eq!(domain_stats.total_mem_size, sum);
+        total += domain_stats.total_mem_size;
+    }
 
-            // join vote count and rsum (and in theory, sum them)
-            let j = Join::new(rs, vc, JoinType::Left, vec![B(0, 0), L(1), R(1)]);
-            let total = mig.add_ingredient("total", &["id", "ratings", "votes"], j);
+    // both the base table and the reader hold on to the rows
+    assert!(total > 0);
+}
 
-            // finally, produce end result
-            let j = Join::new(
-                article,
-                total,
-                JoinType::Inner,
-                vec![B(0, 0), L(1), R(1), R(2)],
-            );
-            let newend = mig.add_ingredient("awr", &["id", "title", "ratings", "votes"], j);
-            mig.maintain_anonymous(newend, &[0]);
-            (rating, newend)
+#[tokio::test(threaded_scheduler)]
+async fn statistics_report_node_processing() {
+    let mut g = start_simple_unsharded("statistics_report_node_processing").await;
+    let b = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
         })
         .await;
 
-    let mut last = g.view("awr").await.unwrap();
-    let mut mutr = g.table("rating").await.unwrap();
+    // every insert waits for its ack, so each ends up in a packet of its own
+    let n = 10;
+    let mut muta = g.table("a").await.unwrap();
     for i in 0..n {
-        if old_puts_after {
-            mutv.insert(vec![2.into(), i.into()]).await.unwrap();
-        }
-        mutr.insert(vec![2.into(), i.into(), raten.clone()])
-            .await
-            .unwrap();
+        muta.insert(vec![i.into(), i.into()]).await.unwrap();
     }
+    sleep().await;
 
-    thread::sleep(get_settle_time().checked_mul(3).unwrap());
-    for i in 0..n {
-        let rows = last.lookup(&[i.into()], true).await.unwrap();
-        assert!(!rows.is_empty(), "every article should be voted for");
-        assert_eq!(rows.len(), 1, "every article should have only one entry");
-        let row = rows.into_iter().next().unwrap();
-        assert_eq!(
-            row[0],
-            i.into(),
-            "each article result should have the right id"
-        );
-        assert_eq!(row[1], title, "all articles should have title 'foo'");
-        assert_eq!(row[2], raten, "all articles should have one 5-star rating");
-        if old_puts_after {
-            assert_eq!(row[3], 2.into(), "all articles should have two votes");
-        } else {
-            assert_eq!(row[3], 1.into(), "all articles should have one vote");
-        }
-    }
+    let stats = g.statistics().await.unwrap();
+    let (ds, ns) = stats
+        .values()
+        .find(|(_, ns)| ns.contains_key(&b))
+        .expect("no statistics for identity node");
+    let ns = &ns[&b];
+    assert_eq!(ns.process_count, n as u64);
+    assert!(ns.process_time > 0);
+    assert!(ds.total_process_time >= ns.process_time);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn full_vote_migration_only_new() {
-    do_full_vote_migration(true, false).await;
-}
+async fn messages_arrive_in_link_order() {
+    let mut builder = Builder::default();
+    builder.set_sharding(DEFAULT_SHARDING);
+    builder.set_persistence(get_persistence_params("messages_arrive_in_link_order"));
+    builder.check_link_order();
+    let mut g = builder.start_local().await.unwrap().0;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[1]);
+    })
+    .await;
 
-#[tokio::test(threaded_scheduler)]
-async fn full_vote_migration_new_and_old() {
-    do_full_vote_migration(true, true).await;
-}
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..20 {
+        muta.insert(vec![i.into(), (i % 3).into()]).await.unwrap();
+    }
+    sleep().await;
 
-#[tokio::test(threaded_scheduler)]
-async fn full_vote_migration_new_and_old_unsharded() {
-    do_full_vote_migration(false, true).await;
+    let mut bq = g.view("b").await.unwrap();
+    assert_eq!(bq.lookup(&[0.into()], true).await.unwrap().len(), 7);
+    let stats = g.statistics().await.unwrap();
+    assert!(stats.values().all(|(ds, _)| ds.misordered_packets == 0));
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn live_writes() {
-    let mut g = start_simple("live_writes").await;
-    let (_vote, vc) = g
+async fn reset_statistics() {
+    let mut g = start_simple_unsharded("reset_statistics").await;
+    let b = g
         .migrate(|mig| {
-            // migrate
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
+        })
+        .await;
 
-            // add vote base table
-            let vote = mig.add_base("vote", &["user", "id"], Base::default());
+    let mut muta = g.table("a").await.unwrap();
+    let process_count = |stats: noria::debug::stats::GraphStats| {
+        stats
+            .values()
+            .find_map(|(_, ns)| ns.get(&b).map(|ns| ns.process_count))
+            .expect("no statistics for identity node")
+    };
 
-            // add vote count
-            let vc = mig.add_ingredient(
-                "votecount",
-                &["id", "votes"],
-                Aggregation::COUNT.over(vote, 0, &[1]),
-            );
+    for i in 0..10 {
+        muta.insert(vec![i.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+    assert_eq!(process_count(g.statistics().await.unwrap()), 10);
 
-            mig.maintain_anonymous(vc, &[0]);
-            (vote, vc)
-        })
-        .await;
+    g.reset_statistics(None).await.unwrap();
+    assert_eq!(process_count(g.statistics().await.unwrap()), 0);
 
-    let mut vc_state = g.view("votecount").await.unwrap();
-    let mut add = g.table("vote").await.unwrap();
+    // only these show up once the counters have been reset
+    for i in 10..13 {
+        muta.insert(vec![i.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+    assert_eq!(process_count(g.statistics().await.unwrap()), 3);
 
-    let ids = 1000;
-    let votes = 7;
+    // and for just the one node
+    g.reset_statistics(Some(b)).await.unwrap();
+    assert_eq!(process_count(g.statistics().await.unwrap()), 0);
+}
 
-    // continuously write to vote
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    tokio::spawn(async move {
-        // we need to use a batch putter because otherwise we'd wait for 7000 batch intervals
-        let fut =
-            add.perform_all((0..votes).flat_map(|_| (0..ids).map(|i| vec![0.into(), i.into()])));
-        tx.send(fut.await.unwrap()).unwrap();
-    });
+#[tokio::test(threaded_scheduler)]
+async fn statistics_report_replay_latency() {
+    let mut g = start_simple_unsharded("statistics_report_replay_latency").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
 
-    // let a few writes through to make migration take a while
+    // keys with very different numbers of rows, so that their replays take different amounts
+    // of time
+    let mut muta = g.table("a").await.unwrap();
+    let sizes = [1, 10, 100, 1000];
+    for (k, &n) in sizes.iter().enumerate() {
+        let k = k as i32;
+        muta.perform_all((0..n).map(|i: i32| vec![k.into(), i.into()]))
+            .await
+            .unwrap();
+    }
     sleep().await;
 
-    // now do a migration that's going to have to copy state
     let _ = g
         .migrate(move |mig| {
-            let vc2 = mig.add_ingredient(
-                "votecount2",
-                &["id", "votes"],
-                Aggregation::SUM.over(vc, 1, &[0]),
-            );
-            mig.maintain_anonymous(vc2, &[0]);
-            vc2
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
         })
         .await;
-
-    let mut vc2_state = g.view("votecount2").await.unwrap();
-
-    // TODO: check that the writer did indeed complete writes during the migration
-
-    // wait for writer to finish
-    rx.await.unwrap();
-
-    // allow the system to catch up with the last writes
     sleep().await;
 
-    // check that all writes happened the right number of times
-    for i in 0..ids {
-        assert_eq!(
-            vc_state.lookup(&[i.into()], true).await.unwrap(),
-            vec![vec![i.into(), votes.into()]]
-        );
-        assert_eq!(
-            vc2_state.lookup(&[i.into()], true).await.unwrap(),
-            vec![vec![i.into(), votes.into()]]
-        );
+    let mut bq = g.view("b").await.unwrap();
+    for (k, &n) in sizes.iter().enumerate() {
+        let k = k as i32;
+        let res = bq.lookup(&[k.into()], true).await.unwrap();
+        assert_eq!(res.len(), n as usize);
     }
+
+    let stats = g.statistics().await.unwrap();
+    let latencies: Vec<_> = stats
+        .values()
+        .flat_map(|(ds, _)| ds.replay_latency.values())
+        .filter(|l| l.completed > 0)
+        .collect();
+    assert_eq!(latencies.len(), 1);
+    let l = latencies[0];
+    assert_eq!(l.completed, sizes.len() as u64);
+    assert_eq!(l.in_flight, 0);
+    assert!(l.min <= l.avg() && l.avg() <= l.max);
+    assert!(l.max > l.min);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn state_replay_migration_query() {
-    // similar to test above, except we will have a materialized Reader node that we're going to
-    // read from rather than relying on forwarding. to further stress the graph, *both* base nodes
-    // are created and populated before the migration, meaning we have to replay through a join.
-
-    let mut g = start_simple("state_replay_migration_query").await;
-    let (a, b) = g
-        .migrate(|mig| {
-            let a = mig.add_base("a", &["x", "y"], Base::default());
-            let b = mig.add_base("b", &["x", "z"], Base::default());
-
-            (a, b)
-        })
+async fn domain_memory_limit() {
+    let mut g = start_simple_unsharded("domain_memory_limit").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
         .await;
-    let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
 
-    // make a couple of records
-    muta.insert(vec![1.into(), "a".into()]).await.unwrap();
-    muta.insert(vec![1.into(), "b".into()]).await.unwrap();
-    muta.insert(vec![2.into(), "c".into()]).await.unwrap();
-    mutb.insert(vec![1.into(), "n".into()]).await.unwrap();
-    mutb.insert(vec![2.into(), "o".into()]).await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..100i32 {
+        muta.insert(vec![
+            i.into(),
+            "a string that is long enough to spill".into(),
+        ])
+        .await
+        .unwrap();
+    }
+    sleep().await;
 
     let _ = g
         .migrate(move |mig| {
-            // add join and a reader node
-            let j = Join::new(a, b, JoinType::Inner, vec![B(0, 0), L(1), R(1)]);
-            let j = mig.add_ingredient("j", &["x", "y", "z"], j);
-
-            // we want to observe what comes out of the join
-            mig.maintain_anonymous(j, &[0]);
-            j
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            b
         })
         .await;
-    let mut out = g.view("j").await.unwrap();
-    sleep().await;
-
-    // if all went according to plan, the join should now be fully populated!
-    // there are (/should be) two records in a with x == 1
-    // they may appear in any order
-    let res = out.lookup(&[1.into()], true).await.unwrap();
-    assert!(res
-        .iter()
-        .any(|r| r == &vec![1.into(), "a".into(), "n".into()]));
-    assert!(res
-        .iter()
-        .any(|r| r == &vec![1.into(), "b".into(), "n".into()]));
-
-    // there are (/should be) one record in a with x == 2
-    assert_eq!(
-        out.lookup(&[2.into()], true).await.unwrap(),
-        vec![vec![2.into(), "c".into(), "o".into()]]
-    );
-
-    // there are (/should be) no records with x == 3
-    assert!(out.lookup(&[3.into()], true).await.unwrap().is_empty());
-}
-
-#[tokio::test(threaded_scheduler)]
-async fn recipe_activates() {
-    let mut g = start_simple("recipe_activates").await;
-    g.migrate(|mig| {
-        let r_txt = "CREATE TABLE b (a text, c text, x text);\n";
-        let mut r = Recipe::from_str(r_txt, None).unwrap();
-        assert_eq!(r.version(), 0);
-        assert_eq!(r.expressions().len(), 1);
-        assert_eq!(r.prior(), None);
-        assert!(r.activate(mig).is_ok());
-    })
-    .await;
-    // one base node
-    assert_eq!(g.inputs().await.unwrap().len(), 1);
+    sleep().await;
+
+    let mut bq = g.view("b").await.unwrap();
+    let keys = (0..100i32).map(|i| vec![i.into()]).collect();
+    bq.multi_lookup(keys, true).await.unwrap();
+
+    let reader_size = |stats: &noria::debug::stats::GraphStats| -> u64 {
+        stats
+            .values()
+            .flat_map(|(_, ns)| ns.values())
+            .filter(|ns| match ns.materialized {
+                noria::internal::MaterializationStatus::Partial { .. } => true,
+                _ => false,
+            })
+            .map(|ns| ns.mem_size)
+            .sum()
+    };
+    let before = reader_size(&g.statistics().await.unwrap());
+    assert!(before > 0);
+
+    // the limit is only enforced as the domain processes more packets
+    let limit = before as usize / 2;
+    g.set_memory_limit(limit).await.unwrap();
+    muta.insert(vec![100.into(), "trigger".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let low_water = (limit - limit / 10) as u64;
+    assert!(reader_size(&g.statistics().await.unwrap()) <= low_water);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn recipe_activates_and_migrates() {
-    let r_txt = "CREATE TABLE b (a text, c text, x text);\n";
-    let r1_txt = "QUERY qa: SELECT a FROM b;\n
-                  QUERY qb: SELECT a, c FROM b WHERE a = 42;";
+async fn replay_paths_introspection() {
+    use noria::debug::replay::ReplayTrigger;
 
-    let mut g = start_simple("recipe_activates_and_migrates").await;
-    g.install_recipe(r_txt).await.unwrap();
-    // one base node
-    assert_eq!(g.inputs().await.unwrap().len(), 1);
+    let mut g = start_simple_unsharded("replay_paths_introspection").await;
+    let (b, c) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            let c = mig.add_ingredient("c", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            mig.maintain_anonymous(c, &[1]);
+            (b, c)
+        })
+        .await;
 
-    g.extend_recipe(r1_txt).await.unwrap();
-    // still one base node
-    assert_eq!(g.inputs().await.unwrap().len(), 1);
-    // two leaf nodes
-    assert_eq!(g.outputs().await.unwrap().len(), 2);
+    let paths = g.replay_paths().await.unwrap();
+    let all: Vec<_> = paths.iter().flat_map(|(_, ps)| ps).collect();
+
+    // each reader should be fed by a partial replay path keyed on the reader's column
+    for &(view, col) in &[(b, 0), (c, 1)] {
+        assert!(
+            all.iter().any(|p| {
+                p.trigger != ReplayTrigger::None
+                    && p.path
+                        .iter()
+                        .any(|&(ni, ref key)| ni == view && key == &Some(vec![col]))
+            }),
+            "no replay path through {:?} keyed on column {}",
+            view,
+            col
+        );
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn recipe_activates_and_migrates_with_join() {
-    let r_txt = "CREATE TABLE a (x int, y int, z int);\n
-                 CREATE TABLE b (r int, s int);\n";
-    let r1_txt = "QUERY q: SELECT y, s FROM a, b WHERE a.x = b.r;";
+async fn node_indices_introspection() {
+    let mut g = start_simple_unsharded("node_indices_introspection").await;
+    let (a, b, vc, r) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            let vc = mig.add_ingredient("vc", &["b", "n"], Aggregation::COUNT.over(b, 0, &[1]));
+            let r = mig.maintain_anonymous(vc, &[0]);
+            (a, b, vc, r)
+        })
+        .await;
 
-    let mut g = start_simple("recipe_activates_and_migrates_with_join").await;
-    g.install_recipe(r_txt).await.unwrap();
+    assert_eq!(g.node_indices(r).await.unwrap(), vec![vec![0]]);
+    assert_eq!(g.node_indices(vc).await.unwrap(), vec![vec![0]]);
+    // b is an unmaterialized identity, while base tables are always materialized
+    assert!(g.node_indices(b).await.unwrap().is_empty());
+    assert!(!g.node_indices(a).await.unwrap().is_empty());
+
+    // indices added while running are reported too
+    assert!(!g.node_indices(a).await.unwrap().contains(&vec![0]));
+    g.add_index(a, vec![0]).await.unwrap();
+    assert!(g.node_indices(a).await.unwrap().contains(&vec![0]));
+    // adding it again does nothing
+    g.add_index(a, vec![0]).await.unwrap();
+    assert_eq!(
+        g.node_indices(a)
+            .await
+            .unwrap()
+            .iter()
+            .filter(|&i| i == &vec![0])
+            .count(),
+        1
+    );
 
-    // two base nodes
-    assert_eq!(g.inputs().await.unwrap().len(), 2);
+    // but nodes without state, and columns that don't exist, can't be indexed
+    assert!(g.add_index(b, vec![0]).await.is_err());
+    assert!(g.add_index(a, vec![2]).await.is_err());
+}
 
-    g.extend_recipe(r1_txt).await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn pause_and_resume_domain() {
+    let mut g = start_simple_unsharded("pause_and_resume_domain").await;
+    let r = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0])
+        })
+        .await;
 
-    // still two base nodes
-    assert_eq!(g.inputs().await.unwrap().len(), 2);
-    // one leaf node
-    assert_eq!(g.outputs().await.unwrap().len(), 1);
-}
+    let mut muta = g.table("a").await.unwrap();
+    let mut getter = g.view("b").await.unwrap();
 
-async fn test_queries(test: &str, file: &'static str, shard: bool, reuse: bool, log: bool) {
-    use crate::logger_pls;
-    use std::fs::File;
-    use std::io::Read;
+    g.pause_domain(r).await.unwrap();
 
-    let logger = if log { Some(logger_pls()) } else { None };
+    // the write is not acked until a paused base domain gets to it, so don't wait for it here
+    let write = tokio::spawn(async move {
+        muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    });
+    sleep().await;
 
-    // set up graph
-    let mut g = if shard {
-        start_simple(test).await
-    } else {
-        start_simple_unsharded(test).await
-    };
+    // the paused domain still answers control requests
+    assert_eq!(g.node_indices(r).await.unwrap(), vec![vec![0]]);
+    assert!(getter.lookup(&[1.into()], false).await.unwrap().is_empty());
 
-    // move needed for some funny lifetime reason
-    g.migrate(move |mig| {
-        let mut r = Recipe::blank(logger);
-        if !reuse {
-            r.disable_reuse();
-        }
-        let mut f = File::open(&file).unwrap();
-        let mut s = String::new();
+    g.resume_domain(r).await.unwrap();
+    write.await.unwrap();
+    sleep().await;
 
-        // Load queries
-        f.read_to_string(&mut s).unwrap();
-        let lines: Vec<String> = s
-            .lines()
-            .filter(|l| {
-                !l.is_empty()
-                    && !l.starts_with("--")
-                    && !l.starts_with('#')
-                    && !l.starts_with("DROP TABLE")
-            })
-            .map(|l| {
-                if !(l.ends_with('\n') || l.ends_with(';')) {
-                    String::from(l) + "\n"
-                } else {
-                    String::from(l)
-                }
-            })
-            .collect();
+    assert_eq!(
+        getter.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+}
 
-        // Add them one by one
-        for (_i, q) in lines.iter().enumerate() {
-            //println!("{}: {}", i, q);
-            r = match r.extend(q) {
-                Ok(mut nr) => {
-                    assert!(nr.activate(mig).is_ok());
-                    nr
-                }
-                Err(e) => {
-                    panic!("{:?}", e);
-                }
+#[tokio::test(threaded_scheduler)]
+async fn quit_domains_in_order() {
+    use dataflow::{Packet, PacketObserver, PacketSummary};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // counts the rows that reach the reader's domain from the base table's
+    #[derive(Default)]
+    struct Delivered(AtomicUsize);
+    impl PacketObserver for Delivered {
+        fn on_receive(&self, packet: &Packet) {
+            if let Packet::Message { ref data, .. } = *packet {
+                self.0.fetch_add(data.len(), Ordering::SeqCst);
             }
         }
+        fn on_complete(&self, _: &PacketSummary) {}
+    }
+
+    let delivered = Arc::new(Delivered::default());
+    let mut builder = Builder::default();
+    // sharding the reader puts it in a domain of its own
+    builder.set_sharding(DEFAULT_SHARDING);
+    builder.set_persistence(get_persistence_params("quit_domains_in_order"));
+    builder.set_packet_observer(delivered.clone());
+    let mut g = builder.start_local().await.unwrap().0;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
     })
     .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..10 {
+        muta.insert(vec![i.into(), i.into()]).await.unwrap();
+    }
+
+    // the writes may still be on their way to the reader domain, but the base domain only quits
+    // once it has sent them along, and the reader domain only quits once the base domain has.
+    // every domain acks its quit, so none of them saw its downstream disappear underneath it.
+    g.quit_domains().await.unwrap();
+
+    // and every write made it downstream before the base domain quit
+    assert_eq!(delivered.0.load(Ordering::SeqCst), 10);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn finkelstein1982_queries() {
-    use std::fs::File;
-    use std::io::Read;
-
-    // set up graph
-    let mut g = start_simple("finkelstein1982_queries").await;
+async fn quit_domains_drains_writes() {
+    let mut g = start_simple_unsharded("quit_domains_drains_writes").await;
     g.migrate(|mig| {
-        let mut inc = SqlIncorporator::default();
-        let mut f = File::open("tests/finkelstein82.txt").unwrap();
-        let mut s = String::new();
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
 
-        // Load queries
-        f.read_to_string(&mut s).unwrap();
-        let lines: Vec<String> = s
-            .lines()
-            .filter(|l| !l.is_empty() && !l.starts_with('#'))
-            .map(|l| {
-                if !(l.ends_with('\n') || l.ends_with(';')) {
-                    String::from(l) + "\n"
-                } else {
-                    String::from(l)
-                }
-            })
-            .collect();
+    let muta = g.table("a").await.unwrap();
+    let writes: Vec<_> = (0..20)
+        .map(|i| {
+            let mut muta = muta.clone();
+            tokio::spawn(async move { muta.insert(vec![i.into(), i.into()]).await.unwrap() })
+        })
+        .collect();
+    // the writes must have been sent before the quit, but may still be queued behind each other
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+
+    // every write that reached the domain before the quit is processed, and so acked
+    g.quit_domains().await.unwrap();
+    for w in writes {
+        tokio::time::timeout(Duration::from_secs(10), w)
+            .await
+            .expect("write was dropped by a quitting domain")
+            .unwrap();
+    }
 
-        // Add them one by one
-        for q in lines.iter() {
-            assert!(inc.add_query(q, None, mig).is_ok());
+    // but writes that arrive after it are not
+    let mut muta = muta.clone();
+    let late = tokio::spawn(async move { muta.insert(vec![100.into(), 100.into()]).await });
+    match tokio::time::timeout(get_settle_time(), late).await {
+        Ok(r) => assert!(r.unwrap().is_err(), "write was accepted after the quit"),
+        Err(_) => {
+            // never acked
         }
-    })
-    .await;
+    }
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn tpc_w() {
-    test_queries("tpc-w", "tests/tpc-w-queries.txt", true, true, false).await;
-}
+async fn quit_paused_domain() {
+    let mut g = start_simple_unsharded("quit_paused_domain").await;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            a
+        })
+        .await;
 
-#[tokio::test(threaded_scheduler)]
-async fn lobsters() {
-    test_queries("lobsters", "tests/lobsters-schema.txt", false, false, false).await;
+    let mut muta = g.table("a").await.unwrap();
+    g.pause_domain(a).await.unwrap();
+    // the write is not acked until the paused domain gets to it, so don't wait for it here
+    let write = tokio::spawn(async move {
+        muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    });
+    sleep().await;
+
+    // the held write is processed before the domain quits, so it is still acked
+    g.quit_domains().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), write)
+        .await
+        .expect("held write was dropped")
+        .unwrap();
 }
 
-#[tokio::test(threaded_scheduler)]
-async fn soupy_lobsters() {
-    test_queries(
-        "soupy_lobsters",
-        "tests/soupy-lobsters-schema.txt",
-        false,
-        false,
-        false,
-    )
-    .await;
+/// Drops every update carrying `MISSED` that crosses into a domain, so that the state it should
+/// have reached misses it.
+struct MissUpdates;
+const MISSED: i32 = -1;
+impl dataflow::PacketObserver for MissUpdates {
+    fn on_receive(&self, _: &dataflow::Packet) {}
+    fn on_complete(&self, _: &dataflow::PacketSummary) {}
+    fn drop_packet(&self, packet: &dataflow::Packet) -> bool {
+        if let dataflow::Packet::Message { ref data, .. } = *packet {
+            data.iter().any(|r| r[1] == DataType::from(MISSED))
+        } else {
+            false
+        }
+    }
 }
 
-#[tokio::test(threaded_scheduler)]
-async fn mergeable_lobsters() {
-    test_queries(
-        "mergeable_lobsters",
-        "tests/mergeable-lobsters-schema.sql",
-        false,
-        false,
-        false,
-    )
-    .await;
+/// Start a sharded instance whose reader domains miss updates carrying `MISSED`.
+async fn start_missing_updates(prefix: &str, partial: bool) -> Handle<LocalAuthority> {
+    let mut builder = Builder::default();
+    if !partial {
+        builder.disable_partial();
+    }
+    // sharding the reader puts it in a domain of its own
+    builder.set_sharding(DEFAULT_SHARDING);
+    builder.set_persistence(get_persistence_params(prefix));
+    builder.set_packet_observer(Arc::new(MissUpdates));
+    builder.start_local().await.unwrap().0
 }
 
-#[tokio::test(threaded_scheduler)]
-async fn filter_aggregate_lobsters() {
-    test_queries(
-        "filter_aggregate_lobsters",
-        "tests/filter-aggregate-lobsters-schema.sql",
-        false,
-        false,
-        false,
-    )
-    .await;
+async fn sorted_lookup(getter: &mut noria::View, k: i32) -> Vec<Vec<DataType>> {
+    let mut rows: Vec<Vec<DataType>> = getter.lookup(&[k.into()], true).await.unwrap().into();
+    rows.sort();
+    rows
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn node_removal() {
-    let mut g = start_simple("domain_removal").await;
-    let cid = g
+async fn recompute_partial_node() {
+    let mut g = start_missing_updates("recompute_partial_node", true).await;
+    let (a, r) = g
         .migrate(|mig| {
-            let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
-            let b = mig.add_base("b", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
-
-            let mut emits = HashMap::new();
-            emits.insert(a, vec![0, 1]);
-            emits.insert(b, vec![0, 1]);
-            let u = Union::new(emits);
-            let c = mig.add_ingredient("c", &["a", "b"], u);
-            mig.maintain_anonymous(c, &[0])
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            (a, mig.maintain_anonymous(b, &[0]))
         })
         .await;
 
-    let mut cq = g.view("c").await.unwrap();
     let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
-    let id: DataType = 1.into();
-
-    assert_eq!(muta.table_name(), "a");
-    assert_eq!(muta.columns(), &["a", "b"]);
+    for &(k, v) in &[(1, 2), (1, 3), (2, 4)] {
+        muta.insert(vec![k.into(), v.into()]).await.unwrap();
+    }
+    sleep().await;
 
-    // send a value on a
-    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    let mut getter = g.view("b").await.unwrap();
+    assert_eq!(sorted_lookup(&mut getter, 1).await.len(), 2);
+    assert_eq!(sorted_lookup(&mut getter, 2).await.len(), 1);
 
-    // give it some time to propagate
+    // the reader misses these, but the base table has them
+    muta.insert(vec![1.into(), MISSED.into()]).await.unwrap();
+    muta.insert(vec![2.into(), MISSED.into()]).await.unwrap();
     sleep().await;
+    assert_eq!(sorted_lookup(&mut getter, 1).await.len(), 2);
 
-    // send a query to c
+    // partial state has no old rows to serve while it is replayed
+    assert!(g.recompute_node(r, false).await.is_err());
+    g.recompute_node(r, true).await.unwrap();
+
+    // every key the reader held is replayed from the base table again
     assert_eq!(
-        cq.lookup(&[id.clone()], true).await.unwrap(),
-        vec![vec![1.into(), 2.into()]]
+        sorted_lookup(&mut getter, 1).await,
+        vec![
+            vec![1.into(), MISSED.into()],
+            vec![1.into(), 2.into()],
+            vec![1.into(), 3.into()],
+        ]
+    );
+    assert_eq!(
+        sorted_lookup(&mut getter, 2).await,
+        vec![vec![2.into(), MISSED.into()], vec![2.into(), 4.into()]]
     );
 
-    let _ = g.remove_node(cid).await.unwrap();
+    // a base table has no ancestors to rebuild it from
+    assert!(g.recompute_node(a, true).await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn recompute_full_node() {
+    let mut g = start_missing_updates("recompute_full_node", false).await;
+    let (b, r) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            (b, mig.maintain_anonymous(b, &[0]))
+        })
+        .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut getter = g.view("b").await.unwrap();
+    let mut expected: Vec<Vec<DataType>> = Vec::new();
+    for (i, &block) in [false, true].iter().enumerate() {
+        let k = i as i32;
+        let row = vec![k.into(), 2.into()];
+        muta.insert(row.clone()).await.unwrap();
+        expected.push(row);
+        muta.insert(vec![k.into(), MISSED.into()]).await.unwrap();
+        sleep().await;
 
-    // update value again
-    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
+        // the reader missed an update
+        let rows: Vec<Vec<DataType>> = getter.lookup(&[k.into()], true).await.unwrap().into();
+        assert_eq!(rows, vec![vec![k.into(), 2.into()]]);
+
+        g.recompute_node(r, block).await.unwrap();
+        expected.push(vec![k.into(), MISSED.into()]);
+
+        // the rebuilt reader has everything a fresh one does
+        for k in 0..=k {
+            let rows = sorted_lookup(&mut getter, k).await;
+            let mut want: Vec<_> = expected
+                .iter()
+                .filter(|r| r[0] == DataType::from(k))
+                .cloned()
+                .collect();
+            want.sort();
+            assert_eq!(rows, want);
+        }
+    }
 
-    // give it some time to propagate
+    // updates keep reaching the rebuilt reader
+    muta.insert(vec![0.into(), 3.into()]).await.unwrap();
     sleep().await;
+    let rows: Vec<Vec<DataType>> = getter.lookup(&[0.into()], true).await.unwrap().into();
+    assert_eq!(rows.len(), 3);
 
-    // // check that value was updated again
-    // let res = cq.lookup(&[id.clone()], true).await.unwrap();
-    // assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
-    // assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+    // the identity is not materialized, so there is nothing to rebuild
+    assert!(g.recompute_node(b, true).await.is_err());
+}
 
-    // Delete first record
-    muta.delete(vec![id.clone()]).await.unwrap();
+#[tokio::test(threaded_scheduler)]
+async fn observes_every_packet() {
+    use dataflow::{Packet, PacketObserver, PacketSummary};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        received: AtomicUsize,
+        completed: AtomicUsize,
+        inputs: AtomicUsize,
+    }
+    impl PacketObserver for Counter {
+        fn on_receive(&self, packet: &Packet) {
+            self.received.fetch_add(1, Ordering::SeqCst);
+            if let Packet::Input { .. } = *packet {
+                self.inputs.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        fn on_complete(&self, _: &PacketSummary) {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
 
-    // give it some time to propagate
+    let counter = Arc::new(Counter::default());
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params("observes_every_packet"));
+    builder.set_packet_observer(counter.clone());
+    let mut g = builder.start_local().await.unwrap().0;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    for i in 0..3 {
+        muta.insert(vec![i.into(), i.into()]).await.unwrap();
+    }
+
+    // a write held back while the domain is paused is only observed once it is processed
+    g.pause_domain(a).await.unwrap();
+    let write = tokio::spawn(async move {
+        muta.insert(vec![3.into(), 3.into()]).await.unwrap();
+    });
+    sleep().await;
+    assert_eq!(counter.inputs.load(Ordering::SeqCst), 3);
+    g.resume_domain(a).await.unwrap();
+    write.await.unwrap();
     sleep().await;
 
-    // // send a query to c
-    // assert_eq!(
-    //     cq.lookup(&[id.clone()], true).await,
-    //     Ok(vec![vec![1.into(), 4.into()]])
-    // );
+    assert_eq!(counter.inputs.load(Ordering::SeqCst), 4);
+    let received = counter.received.load(Ordering::SeqCst);
+    assert!(received > 3);
+    assert_eq!(counter.completed.load(Ordering::SeqCst), received);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn remove_query() {
-    let r_txt = "CREATE TABLE b (a int, c text, x text);\n
-                 QUERY qa: SELECT a FROM b;\n
-                 QUERY qb: SELECT a, c FROM b WHERE a = 42;";
-
-    let r2_txt = "CREATE TABLE b (a int, c text, x text);\n
-                  QUERY qa: SELECT a FROM b;";
-
-    let mut g = start_simple("remove_query").await;
-    g.install_recipe(r_txt).await.unwrap();
-    assert_eq!(g.inputs().await.unwrap().len(), 1);
-    assert_eq!(g.outputs().await.unwrap().len(), 2);
+async fn step_paused_domain() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(None);
+    b.set_persistence(get_persistence_params("step_paused_domain"));
+    let mut g = b.start_local().await.unwrap().0;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+            mig.maintain_anonymous(b, &[0]);
+            a
+        })
+        .await;
+    let mut getter = g.view("b").await.unwrap();
 
-    let mut mutb = g.table("b").await.unwrap();
-    let mut qa = g.view("qa").await.unwrap();
-    let mut qb = g.view("qb").await.unwrap();
+    // stepping is only possible while paused
+    assert!(g.step_domain(a).await.is_err());
+    g.pause_domain(a).await.unwrap();
+    g.step_domain(a).await.unwrap();
 
-    mutb.insert(vec![42.into(), "2".into(), "3".into()])
-        .await
-        .unwrap();
-    mutb.insert(vec![1.into(), "4".into(), "5".into()])
-        .await
-        .unwrap();
+    // use a table handle per write, so that each write arrives as a separate packet
+    let mut writes = Vec::new();
+    for i in 0..3 {
+        let mut muta = g.table("a").await.unwrap();
+        writes.push(tokio::spawn(async move {
+            muta.insert(vec![1.into(), i.into()]).await.unwrap();
+        }));
+    }
     sleep().await;
+    assert!(getter.lookup(&[1.into()], true).await.unwrap().is_empty());
 
-    assert_eq!(qa.lookup(&[0.into()], true).await.unwrap().len(), 2);
-    assert_eq!(qb.lookup(&[0.into()], true).await.unwrap().len(), 1);
-
-    // Remove qb and check that the graph still functions as expected.
-    g.install_recipe(r2_txt).await.unwrap();
-    assert_eq!(g.inputs().await.unwrap().len(), 1);
-    assert_eq!(g.outputs().await.unwrap().len(), 1);
-    assert!(g.view("qb").await.is_err());
-
-    mutb.insert(vec![42.into(), "6".into(), "7".into()])
-        .await
-        .unwrap();
+    g.step_domain(a).await.unwrap();
+    g.step_domain(a).await.unwrap();
     sleep().await;
+    assert_eq!(getter.lookup(&[1.into()], true).await.unwrap().len(), 2);
 
-    match qb.lookup(&[0.into()], true).await.unwrap_err() {
-        noria::error::ViewError::NotYetAvailable => {}
-        e => unreachable!("{:?}", e),
+    g.resume_domain(a).await.unwrap();
+    for w in writes {
+        w.await.unwrap();
     }
-}
-
-macro_rules! get {
-    ($private:ident, $public:ident, $uid:expr, $aid:expr) => {{
-        // combine private and public results
-        // also, there's currently a bug where MIR doesn't guarantee the order of parameters, so we try both O:)
-        let v: Vec<_> = $private
-            .lookup(&[$uid.into(), $aid.into()], true)
-            .await
-            .unwrap()
-            .into_iter()
-            .chain(
-                $private
-                    .lookup(&[$aid.into(), $uid.into()], true)
-                    .await
-                    .unwrap(),
-            )
-            .chain($public.lookup(&[$aid.into()], true).await.unwrap())
-            .collect();
-        eprintln!("check {} as {}: {:?}", $aid, $uid, v);
-        v
-    }}
+    sleep().await;
+    assert_eq!(getter.lookup(&[1.into()], true).await.unwrap().len(), 3);
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn albums() {
-    let mut g = start_simple_unsharded("albums").await;
-    g.install_recipe(
-        "CREATE TABLE friend (usera int, userb int);
-                 CREATE TABLE album (a_id text, u_id int, public tinyint(1));
-                 CREATE TABLE photo (p_id text, album text);",
-    )
-    .await
-    .unwrap();
-    g.extend_recipe("VIEW album_friends: \
-                   (SELECT album.a_id AS aid, friend.userb AS uid FROM album JOIN friend ON (album.u_id = friend.usera) WHERE album.public = 0) \
-                   UNION \
-                   (SELECT album.a_id AS aid, friend.usera AS uid FROM album JOIN friend ON (album.u_id = friend.userb) WHERE album.public = 0) \
-                   UNION \
-                   (SELECT album.a_id AS aid, album.u_id AS uid FROM album WHERE album.public = 0);
-QUERY private_photos: \
-SELECT photo.p_id FROM photo JOIN album_friends ON (photo.album = album_friends.aid) WHERE album_friends.uid = ? AND photo.album = ?;
-QUERY public_photos: \
-SELECT photo.p_id FROM photo JOIN album ON (photo.album = album.a_id) WHERE album.public = 1 AND album.a_id = ?;").await.unwrap();
-
-    let mut friends = g.table("friend").await.unwrap();
-    let mut albums = g.table("album").await.unwrap();
-    let mut photos = g.table("photo").await.unwrap();
+async fn add_node_to_existing_domain() {
+    let mut g = start_simple_unsharded("add_node_to_existing_domain").await;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["a", "b"], Base::default()))
+        .await;
 
-    // four users: 1, 2, 3, and 4
-    // 1 and 2 are friends, 3 is a friend of 1 but not 2
-    // 4 isn't friends with anyone
-    //
-    // four albums: x, y, z, and q; one authored by each user
-    // z is public.
-    //
-    // there's one photo in each album
-    //
-    // what should each user be able to see?
-    //
-    //  - 1 should be able to see albums x, y, and z
-    //  - 2 should be able to see albums x, y, and z
-    //  - 3 should be able to see albums x and z
-    //  - 4 should be able to see albums z and q
-    friends
-        .perform_all(vec![vec![1.into(), 2.into()], vec![3.into(), 1.into()]])
-        .await
-        .unwrap();
-    albums
-        .perform_all(vec![
-            vec!["x".into(), 1.into(), 0.into()],
-            vec!["y".into(), 2.into(), 0.into()],
-            vec!["z".into(), 3.into(), 1.into()],
-            vec!["q".into(), 4.into(), 0.into()],
-        ])
-        .await
-        .unwrap();
-    photos
-        .perform_all(vec![
-            vec!["a".into(), "x".into()],
-            vec!["b".into(), "y".into()],
-            vec!["c".into(), "z".into()],
-            vec!["d".into(), "q".into()],
-        ])
-        .await
-        .unwrap();
+    // b ends up in a's domain, which has to install the new union next to a
+    let _ = g
+        .migrate(move |mig| {
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let c = mig.add_ingredient("c", &["a", "b"], Union::new(emits));
+            mig.maintain_anonymous(c, &[0]);
+            c
+        })
+        .await;
 
-    let mut private = g.view("private_photos").await.unwrap();
-    let mut public = g.view("public_photos").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+    let mut cq = g.view("c").await.unwrap();
+    let id: DataType = 1.into();
 
+    muta.insert(vec![id.clone(), 2.into()]).await.unwrap();
+    mutb.insert(vec![id.clone(), 4.into()]).await.unwrap();
     sleep().await;
 
-    assert_eq!(get!(private, public, 1, "x").len(), 1);
-    assert_eq!(get!(private, public, 1, "y").len(), 1);
-    assert_eq!(get!(private, public, 1, "z").len(), 1);
-    assert_eq!(get!(private, public, 1, "q").len(), 0);
+    let res = cq.lookup(&[id.clone()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+}
 
-    assert_eq!(get!(private, public, 2, "x").len(), 1);
-    assert_eq!(get!(private, public, 2, "y").len(), 1);
-    assert_eq!(get!(private, public, 2, "z").len(), 1);
-    assert_eq!(get!(private, public, 2, "q").len(), 0);
+#[tokio::test(threaded_scheduler)]
+async fn ping_busy_domains() {
+    let mut g = start_simple("ping_busy_domains").await;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
 
-    assert_eq!(get!(private, public, 3, "x").len(), 1);
-    assert_eq!(get!(private, public, 3, "y").len(), 0);
-    assert_eq!(get!(private, public, 3, "z").len(), 1);
-    assert_eq!(get!(private, public, 3, "q").len(), 0);
+    // keep the domains busy with a large batch of writes
+    let mut muta = g.table("a").await.unwrap();
+    muta.perform_all((0..10_000i32).map(|i| vec![i.into(), i.into()]))
+        .await
+        .unwrap();
 
-    assert_eq!(get!(private, public, 4, "x").len(), 0);
-    assert_eq!(get!(private, public, 4, "y").len(), 0);
-    assert_eq!(get!(private, public, 4, "z").len(), 1);
-    assert_eq!(get!(private, public, 4, "q").len(), 1);
+    // every domain answers, even while busy
+    let pings = g.ping_domains().await.unwrap();
+    assert!(!pings.is_empty());
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn correct_nested_view_schema() {
-    use nom_sql::{ColumnSpecification, SqlType};
+async fn reader_reports_applied_offset() {
+    let mut g = start_simple_unsharded("reader_reports_applied_offset").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], Identity::new(a));
+        mig.maintain_anonymous(b, &[0]);
+    })
+    .await;
 
-    let r_txt = "CREATE TABLE votes (story int, user int);
-                 CREATE TABLE stories (id int, content text);
-                 VIEW swvc: SELECT stories.id, stories.content, COUNT(votes.user) AS vc \
-                     FROM stories \
-                     JOIN votes ON (stories.id = votes.story) \
-                     WHERE stories.id = ? GROUP BY votes.story;";
+    let mut muta = g.table("a").await.unwrap();
+    let mut getter = g.view("b").await.unwrap();
+    assert_eq!(getter.applied_offset().await.unwrap(), None);
 
-    let mut b = Builder::default();
-    // need to disable partial due to lack of support for key subsumption (#99)
-    b.disable_partial();
-    b.set_sharding(None);
-    let mut g = b.start_local().await.unwrap().0;
-    g.install_recipe(r_txt).await.unwrap();
+    // writes without an offset leave the applied offset alone
+    muta.insert(vec![0.into(), 0.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(getter.applied_offset().await.unwrap(), None);
 
-    let q = g.view("swvc").await.unwrap();
+    for i in 1..=3u64 {
+        muta.insert_at(vec![i.into(), i.into()], i * 10)
+            .await
+            .unwrap();
+    }
+    sleep().await;
+    assert_eq!(getter.applied_offset().await.unwrap(), Some(30));
 
-    let expected_schema = vec![
-        ColumnSpecification::new("swvc.id".into(), SqlType::Int(32)),
-        ColumnSpecification::new("swvc.content".into(), SqlType::Text),
-        ColumnSpecification::new("swvc.vc".into(), SqlType::Bigint(64)),
-    ];
-    assert_eq!(q.schema(), Some(&expected_schema[..]));
+    // the offset never goes backwards
+    muta.insert_at(vec![4.into(), 4.into()], 5).await.unwrap();
+    sleep().await;
+    assert_eq!(getter.applied_offset().await.unwrap(), Some(30));
+    assert_eq!(getter.len().await.unwrap(), 5);
 }
@@ -380,7 +380,7 @@ pub enum ReuseConfigType {
 pub use crate::builder::Builder;
 pub use crate::handle::Handle;
 pub use controller::migrate::materialization::FrontierStrategy;
-pub use dataflow::{DurabilityMode, PersistenceParameters};
+pub use dataflow::{DurabilityMode, PacketObserver, PersistenceParameters};
 pub use noria::consensus::LocalAuthority;
 pub use noria::*;
 pub use petgraph::graph::NodeIndex;
@@ -400,6 +400,7 @@ pub(crate) struct Config {
     pub(crate) sharding: Option<usize>,
     pub(crate) partial_enabled: bool,
     pub(crate) frontier_strategy: FrontierStrategy,
+    pub(crate) compress_replays: bool,
     pub(crate) domain_config: DomainConfig,
     pub(crate) persistence: PersistenceParameters,
     pub(crate) heartbeat_every: time::Duration,
@@ -417,9 +418,12 @@ impl Default for Config {
             sharding: None,
             partial_enabled: true,
             frontier_strategy: Default::default(),
+            compress_replays: false,
             domain_config: DomainConfig {
                 concurrent_replays: 512,
+                max_pending_replays: None,
                 replay_batch_timeout: time::Duration::new(0, 100_000),
+                check_link_order: false,
             },
             persistence: Default::default(),
             heartbeat_every: time::Duration::from_secs(1),
@@ -1,6 +1,7 @@
 use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload};
 use async_bincode::AsyncBincodeReader;
+use dataflow::PacketObserver;
 use futures_util::{
     future::FutureExt,
     future::TryFutureExt,
@@ -71,6 +72,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
     log: slog::Logger,
+    packet_observer: Option<Arc<dyn PacketObserver>>,
 ) -> Result<(Handle<A>, impl Future<Output = ()> + Unpin + Send), failure::Error> {
     let (trigger, valve) = Valve::new();
     let (alive, done) = tokio::sync::mpsc::channel(1);
@@ -176,6 +178,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         memory_limit,
         memory_check_frequency,
         log.clone(),
+        packet_observer,
     ));
 
     let h = Handle::new(authority, tx, trigger).await?;
@@ -2,7 +2,7 @@ use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
 use crate::startup::Event;
 use async_bincode::AsyncBincodeWriter;
-use dataflow::{DomainBuilder, Packet};
+use dataflow::{payload::PACKET_VERSION, DomainBuilder, Packet, PacketObserver};
 use futures_util::{future::FutureExt, future::TryFutureExt, sink::SinkExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
 use noria::consensus::Epoch;
@@ -50,9 +50,10 @@ pub(super) async fn main(
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
     log: slog::Logger,
+    packet_observer: Option<Arc<dyn PacketObserver>>,
 ) {
     // shared df state
-    let coord = Arc::new(ChannelCoordinator::new());
+    let coord = Arc::new(ChannelCoordinator::with_version(PACKET_VERSION));
 
     let mut worker_state = InstanceState::Pining;
     let log = log.clone();
@@ -137,6 +138,7 @@ pub(super) async fn main(
                     valve,
                     log.clone(),
                     (memory_limit, memory_check_frequency),
+                    packet_observer.clone(),
                     &state,
                     &descriptor,
                     waddr,
@@ -176,6 +178,7 @@ async fn listen_df<'a>(
     valve: Valve,
     log: slog::Logger,
     (memory_limit, evict_every): (Option<usize>, Option<Duration>),
+    packet_observer: Option<Arc<dyn PacketObserver>>,
     state: &'a ControllerState,
     desc: &'a ControllerDescriptor,
     waddr: SocketAddr,
@@ -301,6 +304,7 @@ async fn listen_df<'a>(
                     dcaddr,
                     &valve,
                     state_size.clone(),
+                    packet_observer.clone(),
                 );
 
                 let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
@@ -13,8 +13,10 @@ use futures_util::{
 use noria::{ReadQuery, ReadReply, Tagged};
 use pin_project::pin_project;
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::HashMap;
 use std::mem;
+use std::ops::Range;
 use std::time;
 use std::{
     future::Future,
@@ -33,6 +35,13 @@ const RETRY_TIMEOUT_MS: u64 = 1;
 /// while, waiting readers will use exponential backoff on this delay if they continue to miss.
 const TRIGGER_TIMEOUT_MS: u64 = 10;
 
+/// A connection keeps sorted copies of the rows of at most this many keys that are being read a
+/// page at a time. The copy read from least recently is dropped to make room for a new one.
+const MAX_PAGED_KEYS: usize = 16;
+
+/// A sorted copy of a key's rows is dropped once no page has been cut from it for this long.
+const PAGE_IDLE_TIMEOUT_MS: u64 = 30_000;
+
 thread_local! {
     static READERS: RefCell<HashMap<
         (NodeIndex, usize),
@@ -71,10 +80,11 @@ pub(super) async fn listen(
         stream.set_nodelay(true).expect("could not set TCP_NODELAY");
         let alive = alive.clone();
         let mut tx = tx.clone();
+        let mut pages = Pages::default();
         tokio::spawn(
             server::Server::new(
                 AsyncBincodeStream::from(stream).for_async(),
-                service_fn(move |req| handle_message(req, &readers, &mut tx)),
+                service_fn(move |req| handle_message(req, &readers, &mut tx, &mut pages)),
             )
             .map_err(|e| {
                 match e {
@@ -117,6 +127,185 @@ fn dup<'a>(rs: impl IntoIterator<Item = &'a Vec<DataType>>) -> Vec<Vec<DataType>
     outer
 }
 
+/// What to reply with once the rows for a lookup have been read.
+#[derive(Clone, Debug)]
+enum Shape {
+    /// All the rows for each key.
+    Rows,
+    /// Just how many rows there are.
+    Count,
+    /// The first `n` rows for each key when ordered by `order_col`.
+    TopN {
+        order_col: usize,
+        n: usize,
+        descending: bool,
+    },
+    /// The `n` rows for each key that follow `after` when ordered by all their columns.
+    Page {
+        after: Option<(Vec<DataType>, usize)>,
+        n: usize,
+    },
+    /// All the rows whose key starts with the one looked up.
+    Prefix,
+}
+
+/// Read the rows for `key`, or for every key that starts with it if that is the shape asked for.
+///
+/// A count only needs to know how many rows there are, so rather than copying the rows, an empty
+/// row stands in for each of them.
+fn find(
+    reader: &SingleReadHandle,
+    key: &[DataType],
+    shape: &Shape,
+) -> Result<Option<Vec<Vec<DataType>>>, ()> {
+    match *shape {
+        Shape::Count => reader
+            .try_find_and(key, |rs| vec![Vec::new(); rs.len()])
+            .map(|r| r.0),
+        Shape::Prefix => reader
+            .try_find_prefix_and(key, |rs| dup(rs))
+            .map(|rs| rs.map(|rs| rs.into_iter().flatten().collect())),
+        _ => reader.try_find_and(key, |rs| dup(rs)).map(|r| r.0),
+    }
+}
+
+/// Order `rows` by `order_col` and keep the first `n`.
+///
+/// Rows that tie on `order_col` are ordered by the rest of their columns, so the same rows always
+/// come back in the same order. Fails if the rows have no column `order_col`.
+fn top_n(
+    mut rows: Vec<Vec<DataType>>,
+    order_col: usize,
+    n: usize,
+    descending: bool,
+) -> Result<Vec<Vec<DataType>>, ()> {
+    if rows.iter().any(|r| order_col >= r.len()) {
+        return Err(());
+    }
+    rows.sort_by(|a, b| {
+        let o = a[order_col].cmp(&b[order_col]);
+        if descending { o.reverse() } else { o }.then_with(|| a.cmp(b))
+    });
+    rows.truncate(n);
+    Ok(rows)
+}
+
+/// Find the `n` rows of the sorted `rows` that come after `after`.
+///
+/// `after` is the last row of the previous page, along with how many copies of that row have
+/// been read so far, so that duplicate rows aren't skipped or repeated across pages.
+fn page(rows: &[Vec<DataType>], after: Option<&(Vec<DataType>, usize)>, n: usize) -> Range<usize> {
+    let start = match after {
+        Some(&(ref last, copies)) => {
+            let first = rows
+                .binary_search_by(|r| {
+                    if r < last {
+                        cmp::Ordering::Less
+                    } else {
+                        cmp::Ordering::Greater
+                    }
+                })
+                .unwrap_err();
+            let same = rows[first..].iter().take_while(|&r| r == last).count();
+            first + cmp::min(copies, same)
+        }
+        None => 0,
+    };
+    start..cmp::min(start + n, rows.len())
+}
+
+/// Sorted copies of the rows of keys that are being read a page at a time over one connection.
+///
+/// Without these, every page would copy and sort all the rows of its key. Instead, that is done
+/// once per stream, and later pages are cut from the copy until it runs out. A page that arrives
+/// over a connection that holds no copy of its key's rows makes one.
+///
+/// Each copy is kept along with when a page was last cut from it, so that copies of streams that
+/// are never read to the end don't pile up.
+#[derive(Default)]
+struct Pages(HashMap<((NodeIndex, usize), Vec<DataType>), (Vec<Vec<DataType>>, time::Instant)>);
+
+impl Pages {
+    /// Cut the `n` rows after `after` from the copy of the rows of `id`, if there is one.
+    fn next(
+        &mut self,
+        id: &((NodeIndex, usize), Vec<DataType>),
+        after: &(Vec<DataType>, usize),
+        n: usize,
+    ) -> Option<Vec<Vec<DataType>>> {
+        let (ref rows, ref mut used) = *self.0.get_mut(id)?;
+        *used = time::Instant::now();
+        let page = page(rows, Some(after), n);
+        let rows = rows[page.clone()].to_vec();
+        if page.len() < n {
+            // the stream ends with this page
+            self.0.remove(id);
+        }
+        Some(rows)
+    }
+
+    /// Keep a sorted copy of `rows`, the rows of `id`, and cut the `n` rows after `after` from it.
+    fn start(
+        &mut self,
+        id: ((NodeIndex, usize), Vec<DataType>),
+        mut rows: Vec<Vec<DataType>>,
+        after: Option<&(Vec<DataType>, usize)>,
+        n: usize,
+    ) -> Vec<Vec<DataType>> {
+        rows.sort();
+        let page = page(&rows, after, n);
+        let first = rows[page.clone()].to_vec();
+        if page.len() < n {
+            self.0.remove(&id);
+        } else {
+            let now = time::Instant::now();
+            let idle = time::Duration::from_millis(PAGE_IDLE_TIMEOUT_MS);
+            self.0
+                .retain(|_, &mut (_, used)| now.duration_since(used) < idle);
+            if self.0.len() >= MAX_PAGED_KEYS && !self.0.contains_key(&id) {
+                let oldest = self
+                    .0
+                    .iter()
+                    .min_by_key(|&(_, &(_, used))| used)
+                    .map(|(id, _)| id.clone())
+                    .unwrap();
+                self.0.remove(&oldest);
+            }
+            self.0.insert(id, (rows, now));
+        }
+        first
+    }
+}
+
+/// Reply with the rows read for each key, shaped as the query asked.
+fn rows_reply(rows: Result<Vec<Vec<Vec<DataType>>>, ()>, shape: Shape) -> ReadReply {
+    match shape {
+        Shape::Rows => ReadReply::Normal(rows),
+        Shape::Count => ReadReply::Count(rows.map(|rs| rs.iter().map(Vec::len).sum())),
+        Shape::TopN {
+            order_col,
+            n,
+            descending,
+        } => ReadReply::Normal(rows.and_then(|rs| {
+            rs.into_iter()
+                .map(|rs| top_n(rs, order_col, n, descending))
+                .collect()
+        })),
+        Shape::Page { after, n } => ReadReply::Normal(rows.map(|rs| {
+            rs.into_iter()
+                .map(|mut rs| {
+                    rs.sort();
+                    let page = page(&rs, after.as_ref(), n);
+                    rs.truncate(page.end);
+                    rs.drain(..page.start);
+                    rs
+                })
+                .collect()
+        })),
+        Shape::Prefix => ReadReply::Prefix(rows.map(Some)),
+    }
+}
+
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
@@ -124,9 +313,79 @@ fn handle_message(
         BlockingRead,
         tokio::sync::oneshot::Sender<Result<Tagged<ReadReply>, ()>>,
     )>,
+    pages: &mut Pages,
 ) -> impl Future<Output = Result<Tagged<ReadReply>, ()>> + Send {
     let tag = m.tag;
-    match m.v {
+
+    // a count is a lookup of a single key where only the number of rows is sent back, and
+    // top-n and page queries are ones where only some of the rows are
+    let mut shape = Shape::Rows;
+    let mut page_of = None;
+    let m = match m.v {
+        ReadQuery::Count { target, key, block } => {
+            shape = Shape::Count;
+            ReadQuery::Normal {
+                target,
+                keys: vec![key],
+                block,
+            }
+        }
+        ReadQuery::TopN {
+            target,
+            key,
+            order_col,
+            n,
+            descending,
+        } => {
+            shape = Shape::TopN {
+                order_col,
+                n,
+                descending,
+            };
+            ReadQuery::Normal {
+                target,
+                keys: vec![key],
+                block: true,
+            }
+        }
+        ReadQuery::Page {
+            target,
+            key,
+            after,
+            n,
+        } => {
+            let id = (target, key);
+            if let Some(rows) = after.as_ref().and_then(|after| pages.next(&id, after, n)) {
+                return Either::Right(future::ready(Ok(Tagged {
+                    tag,
+                    v: ReadReply::Normal(Ok(vec![rows])),
+                })));
+            }
+            let key = id.1.clone();
+            page_of = Some(id);
+            shape = Shape::Page { after, n };
+            ReadQuery::Normal {
+                target,
+                keys: vec![key],
+                block: true,
+            }
+        }
+        ReadQuery::Prefix {
+            target,
+            prefix,
+            block,
+        } => {
+            shape = Shape::Prefix;
+            ReadQuery::Normal {
+                target,
+                keys: vec![prefix],
+                block,
+            }
+        }
+        m => m,
+    };
+
+    match m {
         ReadQuery::Normal {
             target,
             mut keys,
@@ -139,6 +398,15 @@ fn handle_message(
                     readers.get(&target).unwrap().clone()
                 });
 
+                if let Shape::Prefix = shape {
+                    if !reader.supports_prefix(&keys[0]) {
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::Prefix(Ok(None)),
+                        });
+                    }
+                }
+
                 let mut ret = Vec::with_capacity(keys.len());
 
                 // first do non-blocking reads for all keys to see if we can return immediately
@@ -151,7 +419,7 @@ fn handle_message(
                         ret.push(Vec::new());
                         return false;
                     }
-                    let rs = reader.try_find_and(key, |rs| dup(rs)).map(|r| r.0);
+                    let rs = find(reader, key, &shape);
                     match rs {
                         Ok(Some(rs)) => {
                             // immediate hit!
@@ -176,16 +444,24 @@ fn handle_message(
                 if !ready {
                     return Ok(Tagged {
                         tag,
-                        v: ReadReply::Normal(Err(())),
+                        v: rows_reply(Err(()), shape.clone()),
                     });
                 }
 
                 if keys.is_empty() {
                     // we hit on all the keys!
                     assert!(pending.is_empty());
+                    if let (Some(id), Shape::Page { ref after, n }) = (page_of, &shape) {
+                        // later pages of this stream are cut from the same rows
+                        let rows = pages.start(id, ret.pop().unwrap(), after.as_ref(), *n);
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::Normal(Ok(vec![rows])),
+                        });
+                    }
                     return Ok(Tagged {
                         tag,
-                        v: ReadReply::Normal(Ok(ret)),
+                        v: rows_reply(Ok(ret), shape.clone()),
                     });
                 }
 
@@ -201,7 +477,7 @@ fn handle_message(
                     if !block {
                         Either::Left(Either::Left(future::ready(Ok(Tagged {
                             tag,
-                            v: ReadReply::Normal(Ok(ret)),
+                            v: rows_reply(Ok(ret), shape),
                         }))))
                     } else {
                         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -215,6 +491,7 @@ fn handle_message(
                                 keys,
                                 pending,
                                 read: ret,
+                                shape,
                                 truth: s.clone(),
                                 retry: tokio::time::interval_at(
                                     tokio::time::Instant::from_std(now + retry),
@@ -254,6 +531,77 @@ fn handle_message(
                 v: ReadReply::Size(size),
             })))
         }
+        ReadQuery::Range { target, start, end } => {
+            let rows = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.try_find_range_and(&start, &end, |rs| dup(rs))
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Range(rows),
+            })))
+        }
+        ReadQuery::All { target } => {
+            let rows = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.try_find_all_and(|rs| dup(rs))
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::All(rows),
+            })))
+        }
+        ReadQuery::Peek { target, key } => {
+            let rows = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                // a hole is reported as such, and unlike in a lookup, isn't filled
+                reader.try_find_and(&key, |rs| dup(rs)).map(|r| r.0)
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Peek(rows),
+            })))
+        }
+        ReadQuery::AppliedOffset { target } => {
+            let offset = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.applied_offset()
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::AppliedOffset(offset),
+            })))
+        }
+        ReadQuery::Count { .. }
+        | ReadQuery::TopN { .. }
+        | ReadQuery::Page { .. }
+        | ReadQuery::Prefix { .. } => {
+            unreachable!("counts, top-n, page and prefix queries are handled as lookups")
+        }
     }
 }
 
@@ -267,6 +615,8 @@ struct BlockingRead {
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
     pending: Vec<usize>,
+    // what to reply with once all the rows have been read
+    shape: Shape,
     truth: Readers,
 
     #[pin]
@@ -285,7 +635,7 @@ impl Future for BlockingRead {
         loop {
             ready!(this.retry.as_mut().poll_next(cx));
 
-            READERS.with(|readers_cache| {
+            let supported = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
                 let s = &this.truth;
                 let target = &this.target;
@@ -294,6 +644,13 @@ impl Future for BlockingRead {
                     readers.get(target).unwrap().clone()
                 });
 
+                if let Shape::Prefix = this.shape {
+                    if !reader.supports_prefix(&this.keys[0]) {
+                        // the reader's sources could not list the keys with the prefix
+                        return Ok(false);
+                    }
+                }
+
                 let now = time::Instant::now();
                 let read = &mut this.read;
                 let next_trigger = *this.next_trigger;
@@ -307,7 +664,7 @@ impl Future for BlockingRead {
 
                 while let Some(read_i) = this.pending.pop() {
                     let key = this.keys.pop().expect("pending.len() == keys.len()");
-                    match reader.try_find_and(&key, |rs| dup(rs)).map(|r| r.0) {
+                    match find(reader, &key, this.shape) {
                         Ok(Some(rs)) => {
                             read[read_i] = rs;
                         }
@@ -349,13 +706,23 @@ impl Future for BlockingRead {
                     }
                 }
 
-                Ok(())
+                Ok(true)
             })?;
 
+            if !supported {
+                return Poll::Ready(Ok(Tagged {
+                    tag: *this.tag,
+                    v: ReadReply::Prefix(Ok(None)),
+                }));
+            }
+
             if this.keys.is_empty() {
                 return Poll::Ready(Ok(Tagged {
                     tag: *this.tag,
-                    v: ReadReply::Normal(Ok(mem::replace(&mut this.read, Vec::new()))),
+                    v: rows_reply(
+                        Ok(mem::replace(&mut this.read, Vec::new())),
+                        this.shape.clone(),
+                    ),
                 }));
             }
         }
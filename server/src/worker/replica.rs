@@ -8,7 +8,7 @@ use async_timer::Oneshot;
 use bincode;
 use dataflow::{
     payload::SourceChannelIdentifier,
-    prelude::{DataType, Executor},
+    prelude::{DataType, Executor, Link},
     Domain, Packet, PollEvent, ProcessResult,
 };
 use failure::{self, ResultExt};
@@ -17,8 +17,9 @@ use futures_util::{
     sink::Sink,
     stream::{futures_unordered::FuturesUnordered, Stream},
 };
-use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE};
+use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE, CONNECTION_FROM_DOMAIN};
 use noria::internal::DomainIndex;
+use noria::internal::LocalNodeIndex;
 use noria::internal::LocalOrNot;
 use noria::{Input, Tagged};
 use pin_project::pin_project;
@@ -40,15 +41,24 @@ use tokio::io::{AsyncReadExt, BufReader, BufStream, BufWriter};
 pub(super) type ReplicaAddr = (DomainIndex, usize);
 
 // https://github.com/rust-lang/rust/issues/64445
-type FirstByte = impl Future<Output = Result<(tokio::net::TcpStream, u8), tokio::io::Error>> + Send;
+type FirstByte =
+    impl Future<Output = Result<(tokio::net::TcpStream, u8, Option<u16>), tokio::io::Error>> + Send;
 
-/// Read the first byte of a stream.
+/// Read the first byte of a stream, and the packet version that follows it if the stream is from
+/// another domain.
 fn read_first_byte(mut stream: tokio::net::TcpStream) -> FirstByte {
     async move {
         let mut byte = [0; 1];
         let n = stream.read_exact(&mut byte[..]).await?;
         assert_eq!(n, 1);
-        Ok((stream, byte[0]))
+        let version = if byte[0] == CONNECTION_FROM_DOMAIN {
+            let mut version = [0; 2];
+            stream.read_exact(&mut version[..]).await?;
+            Some(u16::from_le_bytes(version))
+        } else {
+            None
+        };
+        Ok((stream, byte[0], version))
     }
 }
 
@@ -60,6 +70,7 @@ pub(super) struct Replica {
     coord: Arc<ChannelCoordinator>,
 
     retry: Option<Box<Packet>>,
+    inbox: Inbox,
 
     #[pin]
     valve: Valve,
@@ -82,11 +93,15 @@ pub(super) struct Replica {
         >,
     >,
 
+    /// Connections to other replicas, whether they have unflushed sends, whether they are remote,
+    /// and the sequence number of the next linked packet sent along each link over them.
     outputs: FnvHashMap<
         ReplicaAddr,
         (
             Box<dyn Sink<Box<Packet>, Error = bincode::Error> + Send + Unpin>,
             bool,
+            bool,
+            LinkSeqs,
         ),
     >,
 
@@ -114,6 +129,7 @@ impl Replica {
             coord: cc,
             domain,
             retry: None,
+            inbox: Default::default(),
             valve: valve.clone(),
             incoming: Strawpoll::from(on),
             first_byte: FuturesUnordered::new(),
@@ -257,6 +273,7 @@ impl Replica {
 
         let cc = this.coord;
         let outputs = this.outputs;
+        let domain = this.domain;
 
         // just like in try_acks:
         // first, queue up any additional writes we have to do
@@ -266,11 +283,13 @@ impl Replica {
                 continue;
             }
 
-            let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
-                while !cc.has(&ri) {}
-                let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
-                (tx, true)
-            });
+            let &mut (ref mut tx, ref mut pending, remote, ref mut seqs) =
+                outputs.entry(ri).or_insert_with(|| {
+                    while !cc.has(&ri) {}
+                    let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
+                    let remote = cc.is_local(&ri) != Some(true);
+                    (tx, true, remote, LinkSeqs::default())
+                });
 
             let mut tx = Pin::new(tx);
 
@@ -279,18 +298,28 @@ impl Replica {
                     Poll::Ready(Ok(())) => {}
                     Poll::Pending => break,
                     Poll::Ready(Err(e)) => {
+                        // none of the queued packets will make it
+                        ms.drain(..)
+                            .for_each(|m| domain.failed_to_send(m.is_control()));
                         err.push(e);
                         break;
                     }
                 }
 
-                let m = ms.pop_front().expect("!is_empty");
+                let mut m = ms.pop_front().expect("!is_empty");
+                seqs.sequence(&mut m);
+                // local channels cannot corrupt packets, so only checksum remote sends
+                if remote {
+                    m = Box::new((*m).checksummed());
+                }
+                let control = m.is_control();
                 match tx.as_mut().start_send(m) {
                     Ok(()) => {
                         // we queued something, so we'll need to send!
                         *pending = true;
                     }
                     Err(e) => {
+                        domain.failed_to_send(control);
                         err.push(e);
                         break;
                     }
@@ -303,7 +332,7 @@ impl Replica {
         }
 
         // then, try to do any sends that are still pending
-        for &mut (ref mut tx, ref mut pending) in outputs.values_mut() {
+        for &mut (ref mut tx, ref mut pending, _, _) in outputs.values_mut() {
             if !*pending {
                 continue;
             }
@@ -342,13 +371,21 @@ impl Replica {
             }
         }
 
-        while let Poll::Ready(Some((stream, tag))) = this.first_byte.as_mut().poll_next(cx)? {
+        while let Poll::Ready(Some((stream, tag, version))) =
+            this.first_byte.as_mut().poll_next(cx)?
+        {
             let is_base = tag == CONNECTION_FROM_BASE;
+            if let Some(Err(e)) = version.map(Packet::check_version) {
+                error!(this.log, "refusing connection from incompatible domain: {}", e;
+                       "from" => ?stream.peer_addr().unwrap());
+                continue;
+            }
 
             debug!(this.log, "established new connection"; "base" => ?is_base);
             let slot = this.inputs.stream_entry();
             let token = slot.token();
             let epoch = if let Some(e) = this.out.connections.get_mut(token) {
+                e.next_seq = Default::default();
                 e.epoch
             } else {
                 let epoch = 1;
@@ -357,6 +394,7 @@ impl Replica {
                     tag_acks: Vec::new(),
                     epoch,
                     pending_flush: false,
+                    next_seq: Default::default(),
                 });
                 assert_eq!(t, token);
                 epoch
@@ -424,6 +462,61 @@ struct ConnState {
 
     // do we have stuff to flush
     pending_flush: bool,
+
+    // sequence number of the next linked packet we expect along each link from a remote domain
+    next_seq: LinkSeqs,
+}
+
+impl ConnState {
+    /// Check that `link` carries the sequence number of the next linked packet sent along it.
+    ///
+    /// Any other number means that packets were lost or reordered, and processing the rest of
+    /// the stream would corrupt the dataflow.
+    fn saw_seq(&mut self, link: &Link) -> Result<(), failure::Error> {
+        let seq = match link.seq {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+        let next = self.next_seq.0.entry((link.src, link.dst)).or_insert(0);
+        if seq == *next {
+            *next += 1;
+            Ok(())
+        } else if seq > *next {
+            Err(format_err!(
+                "lost packets {}..{} on remote link {:?}",
+                *next,
+                seq,
+                link
+            ))
+        } else {
+            Err(format_err!(
+                "packet {} arrived out of order on remote link {:?} (expected {})",
+                seq,
+                link,
+                *next
+            ))
+        }
+    }
+}
+
+/// The sequence number of the next linked packet along each link between two replicas.
+#[derive(Debug, Default)]
+struct LinkSeqs(FnvHashMap<(LocalNodeIndex, LocalNodeIndex), u64>);
+
+impl LinkSeqs {
+    /// Stamp `m` with the next sequence number along its link.
+    ///
+    /// Packets that do not travel along a link are left alone.
+    fn sequence(&mut self, m: &mut Packet) {
+        let key = match m.try_link() {
+            Some(link) => (link.src, link.dst),
+            None => return,
+        };
+        let seq = self.0.entry(key).or_insert(0);
+        if m.sequence(*seq) {
+            *seq += 1;
+        }
+    }
 }
 
 struct Outboxes {
@@ -453,6 +546,7 @@ impl Outboxes {
             tag_acks: Vec::new(),
             epoch: 0,
             pending_flush: false,
+            next_seq: Default::default(),
         });
 
         Outboxes {
@@ -520,6 +614,53 @@ impl Executor for Outboxes {
     }
 }
 
+/// Packets received by a replica that have yet to be handed to its domain.
+///
+/// Packets that may overtake others (see `Packet::may_overtake`) are handed out first. All other
+/// packets, control and data alike, are handed out in the order they were received.
+///
+/// Once the domain has quit, the inbox is closed, and data packets are dropped. Control packets are
+/// still handed out, so that the domain can tell whoever sent them that it has quit.
+#[derive(Default)]
+struct Inbox {
+    overtaking: VecDeque<Box<Packet>>,
+    ordered: VecDeque<Box<Packet>>,
+    closed: bool,
+}
+
+impl Inbox {
+    fn push(&mut self, packet: Box<Packet>) {
+        if self.closed && !packet.is_control() {
+            return;
+        }
+        if packet.may_overtake() {
+            self.overtaking.push_back(packet);
+        } else {
+            self.ordered.push_back(packet);
+        }
+    }
+
+    /// Stop accepting data packets, and drop those that have yet to be handed out.
+    ///
+    /// Called once the domain has quit. Packets are handed out in the order they were received,
+    /// so any data still queued arrived after the packet the domain quit on.
+    fn close(&mut self) {
+        self.closed = true;
+        self.ordered.retain(|p| p.is_control());
+    }
+
+    fn pop(&mut self) -> Option<Box<Packet>> {
+        self.overtaking
+            .pop_front()
+            .or_else(|| self.ordered.pop_front())
+    }
+
+    /// True once the inbox has closed and everything received before that has been handed out.
+    fn is_drained(&self) -> bool {
+        self.closed && self.overtaking.is_empty() && self.ordered.is_empty()
+    }
+}
+
 impl Future for Replica {
     type Output = Result<(), failure::Error>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -557,6 +698,10 @@ impl Future for Replica {
             // channel and once from the set of remote channels. this biases slightly in favor of
             // local sends, without starving either. we also stop alternating once either source is
             // depleted.
+            //
+            // everything we receive in a round goes through the inbox, which hands out requests
+            // that only inspect the domain ahead of everything else, so that they aren't stuck
+            // behind a long backlog of writes.
             let mut local_done = false;
             let mut remote_done = false;
             let mut check_local = true;
@@ -596,8 +741,7 @@ impl Future for Replica {
                 if !local_done && (check_local || remote_done) {
                     match this.locals.poll_recv(cx) {
                         Poll::Ready(Some(packet)) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                            this.inbox.push(packet);
                         }
                         Poll::Ready(None) => {
                             // local input stream finished
@@ -613,9 +757,23 @@ impl Future for Replica {
 
                 if !remote_done && (!check_local || local_done) {
                     match this.inputs.as_mut().poll_next(cx) {
-                        Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                        Poll::Ready(Some((StreamYield::Item(Ok(packet)), streami))) => {
+                            if let Some(link) = packet.try_link() {
+                                out.connections[streami]
+                                    .saw_seq(link)
+                                    .context("remote input stream")?;
+                            }
+                            let link = packet.try_link().cloned();
+                            match (*packet).verify_checksum().and_then(Packet::decompress) {
+                                Ok(packet) => this.inbox.push(Box::new(packet)),
+                                Err(e) => {
+                                    // the rest of the stream is still good, so drop just this
+                                    // packet rather than feed it to the domain
+                                    d.saw_corrupt_packet();
+                                    warn!(this.log, "dropped corrupted packet: {}", e;
+                                          "link" => ?link);
+                                }
+                            }
                         }
                         Poll::Ready(Some((StreamYield::Finished(f), streami))) => {
                             if out.try_retire(streami) {
@@ -656,6 +814,14 @@ impl Future for Replica {
                 check_local = !check_local;
             }
 
+            while let Some(packet) = this.inbox.pop() {
+                process!(*this.retry, out, packet, |p| d
+                    .on_event(out, PollEvent::Process(p),));
+                if d.has_quit() {
+                    this.inbox.close();
+                }
+            }
+
             // send to downstream
             // TODO: send fail == exiting?
             self.as_mut()
@@ -665,6 +831,15 @@ impl Future for Replica {
             // send acks
             self.as_mut().try_acks(cx)?;
 
+            if self.inbox.is_drained()
+                && self.out.domains.values().all(|ms| ms.is_empty())
+                && self.outputs.values().all(|&(_, pending, _, _)| !pending)
+            {
+                // domain was asked to quit once it had processed all the data it had received,
+                // and everything it produced has now been sent along
+                return Poll::Ready(Ok(()));
+            }
+
             if !local_done || !remote_done {
                 // we're yielding voluntarily to not block the executor and must ensure we wake
                 // up again
@@ -714,3 +889,235 @@ impl Future for Replica {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dataflow::prelude::*;
+
+    #[test]
+    fn inbox_control_first() {
+        let data = |i: i32| {
+            Box::new(Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) },
+                data: vec![vec![i.into()]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            })
+        };
+
+        let mut inbox = Inbox::default();
+        inbox.push(data(1));
+        inbox.push(Box::new(Packet::GetStatistics));
+        inbox.push(data(2));
+        inbox.push(Box::new(Packet::Quit));
+        inbox.push(data(3));
+
+        match inbox.pop().map(|p| *p) {
+            Some(Packet::GetStatistics) => {}
+            p => panic!("expected statistics request first, got {:?}", p),
+        }
+        match inbox.pop().map(|p| *p) {
+            Some(Packet::Quit) => {}
+            p => panic!("expected quit second, got {:?}", p),
+        }
+        for i in 1..=3 {
+            match inbox.pop().map(|p| *p) {
+                Some(Packet::Message { data, .. }) => assert_eq!(data[0][0], DataType::from(i)),
+                p => panic!("expected data packet {}, got {:?}", i, p),
+            }
+        }
+        assert!(inbox.pop().is_none());
+    }
+
+    #[test]
+    fn inbox_keeps_changes_in_order() {
+        let data = |i: i32| {
+            Box::new(Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) },
+                data: vec![vec![i.into()]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            })
+        };
+
+        // dropping a column changes what the data around it means, so it must not overtake it
+        let mut inbox = Inbox::default();
+        inbox.push(data(1));
+        inbox.push(Box::new(Packet::DropBaseColumn {
+            node: unsafe { LocalNodeIndex::make(0) },
+            column: 1,
+            op_id: 0,
+        }));
+        inbox.push(Box::new(Packet::Ping {
+            sent: std::time::SystemTime::now(),
+        }));
+        inbox.push(data(2));
+
+        let order: Vec<_> = std::iter::from_fn(|| inbox.pop())
+            .map(|p| match *p {
+                Packet::Message { ref data, .. } => format!("data {}", data[0][0]),
+                Packet::DropBaseColumn { .. } => "drop".to_owned(),
+                Packet::Ping { .. } => "ping".to_owned(),
+                ref p => panic!("unexpected packet {:?}", p),
+            })
+            .collect();
+        assert_eq!(order, vec!["ping", "data 1", "drop", "data 2"]);
+    }
+
+    #[test]
+    fn inbox_flush_after_data() {
+        let n = 10;
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let mut inbox = Inbox::default();
+        for i in 0..n {
+            inbox.push(Box::new(Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) },
+                data: vec![vec![i.into()]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            }));
+        }
+        inbox.push(Box::new(Packet::FlushAndAck(
+            noria::channel::ChannelSender::from_local(tx),
+        )));
+        inbox.push(Box::new(Packet::GetStatistics));
+
+        let mut processed = 0;
+        while let Some(p) = inbox.pop() {
+            match *p {
+                Packet::Message { .. } => {
+                    assert!(
+                        rx.try_recv().is_err(),
+                        "flush acked before all data was processed"
+                    );
+                    processed += 1;
+                }
+                Packet::FlushAndAck(ack) => {
+                    assert_eq!(processed, n);
+                    ack.send(()).unwrap();
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(rx.try_recv(), Ok(()));
+    }
+
+    #[test]
+    fn remote_link_sequence() {
+        let data = |dst: u32, seq: u64| {
+            let mut p = Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(dst)) },
+                data: vec![vec![DataType::from(seq as i64)]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            };
+            assert!(p.sequence(seq));
+            p
+        };
+        let conn = || ConnState {
+            unacked: 0,
+            tag_acks: Vec::new(),
+            epoch: 1,
+            pending_flush: false,
+            next_seq: Default::default(),
+        };
+
+        // in order
+        let mut c = conn();
+        for seq in 0..3 {
+            assert!(c.saw_seq(data(1, seq).try_link().unwrap()).is_ok());
+        }
+
+        // a reordered pair is caught as soon as the later packet shows up first
+        let mut c = conn();
+        assert!(c.saw_seq(data(1, 0).try_link().unwrap()).is_ok());
+        assert!(c.saw_seq(data(1, 2).try_link().unwrap()).is_err());
+        let mut c = conn();
+        assert!(c.saw_seq(data(1, 0).try_link().unwrap()).is_ok());
+        assert!(c.saw_seq(data(1, 1).try_link().unwrap()).is_ok());
+        assert!(c.saw_seq(data(1, 1).try_link().unwrap()).is_err());
+
+        // each link sharing the connection is numbered on its own
+        let mut c = conn();
+        assert!(c.saw_seq(data(1, 0).try_link().unwrap()).is_ok());
+        assert!(c.saw_seq(data(2, 0).try_link().unwrap()).is_ok());
+        assert!(c.saw_seq(data(1, 1).try_link().unwrap()).is_ok());
+
+        // control packets are not numbered
+        assert!(!Packet::GetStatistics.sequence(0));
+    }
+
+    #[test]
+    fn link_seqs_per_link() {
+        let data = |dst: u32| {
+            Box::new(Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(dst)) },
+                data: vec![vec![DataType::from(1)]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            })
+        };
+        let seq = |seqs: &mut LinkSeqs, dst: u32| {
+            let mut m = data(dst);
+            seqs.sequence(&mut m);
+            m.try_link().unwrap().seq.unwrap()
+        };
+
+        let mut seqs = LinkSeqs::default();
+        assert_eq!(seq(&mut seqs, 1), 0);
+        assert_eq!(seq(&mut seqs, 1), 1);
+        // a link to a new destination starts over...
+        assert_eq!(seq(&mut seqs, 2), 0);
+        // ...without disturbing the count along the others
+        assert_eq!(seq(&mut seqs, 1), 2);
+
+        let mut ping = Box::new(Packet::GetStatistics);
+        seqs.sequence(&mut ping);
+        assert!(ping.try_link().is_none());
+    }
+
+    #[test]
+    fn inbox_closed_after_quit() {
+        let data = |i: i32| {
+            Box::new(Packet::Message {
+                link: unsafe { Link::new(LocalNodeIndex::make(0), LocalNodeIndex::make(1)) },
+                data: vec![vec![i.into()]].into(),
+                source_offset: None,
+                request_ids: Vec::new(),
+            })
+        };
+
+        let mut inbox = Inbox::default();
+        inbox.push(data(1));
+        inbox.push(Box::new(Packet::QuitDrain { upstreams: 0 }));
+        inbox.push(data(2));
+        assert!(!inbox.is_drained());
+
+        match inbox.pop().map(|p| *p) {
+            Some(Packet::Message { data, .. }) => assert_eq!(data[0][0], DataType::from(1)),
+            p => panic!("expected data packet 1, got {:?}", p),
+        }
+        match inbox.pop().map(|p| *p) {
+            Some(Packet::QuitDrain { .. }) => {}
+            p => panic!("expected draining quit, got {:?}", p),
+        }
+
+        // the domain has quit, so data that arrived after the quit is dropped
+        inbox.close();
+        assert!(inbox.is_drained());
+        inbox.push(data(3));
+        assert!(inbox.pop().is_none());
+        assert!(inbox.is_drained());
+
+        // control packets are still handed out after the quit, so that they can be refused
+        inbox.push(Box::new(Packet::GetStatistics));
+        assert!(!inbox.is_drained());
+        match inbox.pop().map(|p| *p) {
+            Some(Packet::GetStatistics) => {}
+            p => panic!("expected statistics request, got {:?}", p),
+        }
+        assert!(inbox.is_drained());
+    }
+}